@@ -0,0 +1,364 @@
+use anyhow::{Result, bail};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use std::thread::sleep;
+use std::time::Duration;
+use crate::hal::{Servo, ServoInfo, ServoRegister, TorqueMode};
+use crate::robot::Robot;
+
+// Fault bits shared by ServoRegister::LEDAlarmCondition,
+// ServoRegister::UnloadingCondition, ServoRegister::ServoStatus and the
+// status byte in the header of every reply:
+//   bit 0  input voltage out of range
+//   bit 1  magnetic angle sensor fault
+//   bit 2  overheating
+//   bit 3  overcurrent
+//   bit 5  overload
+// Bits 4, 6 and 7 are unused.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct AlarmMask(u8);
+
+impl AlarmMask {
+    pub const VOLTAGE: AlarmMask = AlarmMask(1 << 0);
+    pub const SENSOR: AlarmMask = AlarmMask(1 << 1);
+    pub const TEMPERATURE: AlarmMask = AlarmMask(1 << 2);
+    pub const CURRENT: AlarmMask = AlarmMask(1 << 3);
+    pub const OVERLOAD: AlarmMask = AlarmMask(1 << 5);
+
+    pub const NONE: AlarmMask = AlarmMask(0);
+    pub const ALL: AlarmMask = AlarmMask(0x2F);
+
+    const NAMES: [(AlarmMask, &'static str); 5] = [
+        (AlarmMask::VOLTAGE, "voltage"),
+        (AlarmMask::SENSOR, "sensor"),
+        (AlarmMask::TEMPERATURE, "temperature"),
+        (AlarmMask::CURRENT, "current"),
+        (AlarmMask::OVERLOAD, "overload"),
+    ];
+
+    pub fn from_bits(bits: u8) -> Result<Self> {
+        if bits & !Self::ALL.0 != 0 {
+            bail!("Invalid alarm mask {:#04x}, unknown bits {:#04x}", bits, bits & !Self::ALL.0);
+        }
+        Ok(Self(bits))
+    }
+
+    pub fn bits(&self) -> u8 {
+        self.0
+    }
+
+    pub fn contains(&self, other: AlarmMask) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    pub fn union(self, other: AlarmMask) -> Self {
+        Self(self.0 | other.0)
+    }
+
+    // Bits set in self but not in `other`
+    pub fn difference(self, other: AlarmMask) -> Self {
+        Self(self.0 & !other.0)
+    }
+}
+
+impl fmt::Display for AlarmMask {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let names: Vec<&str> = Self::NAMES.iter()
+            .filter(|(bit, _)| self.contains(*bit))
+            .map(|(_, name)| *name)
+            .collect();
+        if names.is_empty() {
+            write!(f, "none")
+        } else {
+            write!(f, "{}", names.join("|"))
+        }
+    }
+}
+
+impl ServoInfo {
+    // Faults reported when this was read, from ServoRegister::ServoStatus
+    pub fn status(&self) -> AlarmMask {
+        AlarmMask(self.servo_status)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FaultTransition {
+    pub id: u8,
+    // Faults that weren't reported by the previous reply
+    pub entered: AlarmMask,
+    // Faults the previous reply reported that are gone now
+    pub cleared: AlarmMask,
+    pub status: AlarmMask,
+}
+
+pub type FaultCallback = Arc<dyn Fn(&FaultTransition) + Send + Sync>;
+
+// Latest status byte of every servo that has replied. A servo is assumed
+// fault free until its first reply, so a servo that comes up faulted is
+// reported as entering those faults.
+#[derive(Debug, Default)]
+pub struct StatusTracker {
+    latest: BTreeMap<u8, AlarmMask>,
+}
+
+impl StatusTracker {
+    // Some if this status differs from the previous one of the same servo
+    pub fn record(&mut self, id: u8, status: u8) -> Option<FaultTransition> {
+        let status = AlarmMask(status);
+        let previous = self.latest.insert(id, status).unwrap_or(AlarmMask::NONE);
+        if status == previous {
+            return None;
+        }
+        Some(FaultTransition {
+            id,
+            entered: status.difference(previous),
+            cleared: previous.difference(status),
+            status,
+        })
+    }
+
+    pub fn latest(&self, id: u8) -> Option<AlarmMask> {
+        self.latest.get(&id).copied()
+    }
+}
+
+// Shared by both HAL backends, which record the status of every reply that
+// carries one
+#[derive(Default)]
+pub struct StatusMonitor {
+    tracker: Mutex<StatusTracker>,
+    on_transition: Mutex<Option<FaultCallback>>,
+}
+
+impl fmt::Debug for StatusMonitor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("StatusMonitor")
+            .field("tracker", &self.tracker)
+            .finish()
+    }
+}
+
+impl StatusMonitor {
+    // Must not be called with the bus locked, the callback may use it
+    pub fn record(&self, id: u8, status: u8) {
+        let Some(transition) = self.tracker.lock().unwrap().record(id, status) else {
+            return;
+        };
+        let callback = self.on_transition.lock().unwrap().clone();
+        match callback {
+            Some(callback) => callback(&transition),
+            None if transition.entered != AlarmMask::NONE => {
+                eprintln!("Warning: servo {} reports {}", transition.id, transition.entered);
+            }
+            None => (),
+        }
+    }
+}
+
+impl Servo {
+    // Status of the most recent reply from this servo, None if it hasn't
+    // replied yet. Kept up to date by every read and write, no extra
+    // transactions needed.
+    pub fn latest_status(&self, id: u8) -> Option<AlarmMask> {
+        self.status.tracker.lock().unwrap().latest(id)
+    }
+
+    // Called once per change in a servo's reported faults, instead of
+    // logging a warning when a fault is entered. The callback runs with the
+    // bus unlocked and may use the servo.
+    pub fn on_fault_transition<F: Fn(&FaultTransition) + Send + Sync + 'static>(&self, callback: F) {
+        *self.status.on_transition.lock().unwrap() = Some(Arc::new(callback));
+    }
+
+    // Faults that make the LED blink
+    pub fn read_led_alarm(&self, id: u8) -> Result<AlarmMask> {
+        self.read_alarm(id, ServoRegister::LEDAlarmCondition)
+    }
+
+    pub fn write_led_alarm(&self, id: u8, mask: AlarmMask) -> Result<()> {
+        self.write_eeprom(id, ServoRegister::LEDAlarmCondition, &[mask.bits()])
+    }
+
+    // Faults that disable torque
+    pub fn read_shutdown_alarm(&self, id: u8) -> Result<AlarmMask> {
+        self.read_alarm(id, ServoRegister::UnloadingCondition)
+    }
+
+    pub fn write_shutdown_alarm(&self, id: u8, mask: AlarmMask) -> Result<()> {
+        self.write_eeprom(id, ServoRegister::UnloadingCondition, &[mask.bits()])
+    }
+
+    // Faults the servo currently reports
+    pub fn read_status(&self, id: u8) -> Result<AlarmMask> {
+        self.read_alarm(id, ServoRegister::ServoStatus)
+    }
+
+    // The STS series latches overload protection until torque is switched
+    // off. Torque is left off; re-enable it once the joint is known to be
+    // safe. Returns whatever faults are still reported afterwards.
+    pub fn clear_fault(&self, id: u8) -> Result<AlarmMask> {
+        self.set_torque_mode(id, TorqueMode::Disabled)?;
+        sleep(Duration::from_millis(50));
+        self.read_status(id)
+    }
+
+    fn read_alarm(&self, id: u8, register: ServoRegister) -> Result<AlarmMask> {
+        // Kept as read, unused bits included, so a read-modify-write doesn't
+        // fail on whatever the factory left in them
+        let data = self.read_exact(id, register, 1)?;
+        Ok(AlarmMask(data[0]))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct FaultReport {
+    pub name: String,
+    pub id: u8,
+    pub faults: AlarmMask,
+    // Faults still reported after clearing, NONE if it worked
+    pub remaining: AlarmMask,
+}
+
+impl Robot {
+    // The "unstick everything" command after a tumble: clear every faulted
+    // joint and report which ones were. Faulted joints are left with torque
+    // off, the others are untouched.
+    pub fn clear_all_faults(&self) -> Result<Vec<FaultReport>> {
+        let mut reports = Vec::new();
+        for joint in self.joints() {
+            let faults = self.servo().read_status(joint.id)?;
+            if faults == AlarmMask::NONE {
+                continue;
+            }
+            let remaining = self.servo().clear_fault(joint.id)?;
+            reports.push(FaultReport { name: joint.name.clone(), id: joint.id, faults, remaining });
+        }
+        Ok(reports)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unknown_bits_are_refused() {
+        assert_eq!(AlarmMask::from_bits(0x2F).unwrap(), AlarmMask::ALL);
+        assert!(AlarmMask::from_bits(0x10).is_err());
+        assert!(AlarmMask::from_bits(0x80).is_err());
+    }
+
+    #[test]
+    fn masks_combine_and_contain() {
+        let mask = AlarmMask::VOLTAGE.union(AlarmMask::OVERLOAD);
+        assert_eq!(mask.bits(), 0x21);
+        assert!(mask.contains(AlarmMask::OVERLOAD));
+        assert!(!mask.contains(AlarmMask::SENSOR));
+        assert!(AlarmMask::ALL.contains(mask));
+        assert!(mask.contains(AlarmMask::NONE));
+    }
+
+    #[test]
+    fn masks_display_by_name() {
+        assert_eq!(AlarmMask::NONE.to_string(), "none");
+        assert_eq!(AlarmMask::TEMPERATURE.union(AlarmMask::CURRENT).to_string(), "temperature|current");
+        assert_eq!(AlarmMask::ALL.to_string(), "voltage|sensor|temperature|current|overload");
+    }
+
+    #[test]
+    fn difference_keeps_only_own_bits() {
+        let mask = AlarmMask::VOLTAGE.union(AlarmMask::CURRENT);
+        assert_eq!(mask.difference(AlarmMask::CURRENT), AlarmMask::VOLTAGE);
+        assert_eq!(mask.difference(AlarmMask::ALL), AlarmMask::NONE);
+    }
+
+    #[test]
+    fn tracker_reports_each_change_once() {
+        let mut tracker = StatusTracker::default();
+        assert_eq!(tracker.latest(1), None);
+        assert_eq!(tracker.record(1, 0), None);
+        let entered = tracker.record(1, AlarmMask::TEMPERATURE.bits()).unwrap();
+        assert_eq!((entered.entered, entered.cleared), (AlarmMask::TEMPERATURE, AlarmMask::NONE));
+        assert_eq!(tracker.record(1, AlarmMask::TEMPERATURE.bits()), None);
+
+        let changed = tracker.record(1, AlarmMask::OVERLOAD.bits()).unwrap();
+        assert_eq!(changed, FaultTransition { id: 1, entered: AlarmMask::OVERLOAD, cleared: AlarmMask::TEMPERATURE, status: AlarmMask::OVERLOAD });
+        assert_eq!(tracker.latest(1), Some(AlarmMask::OVERLOAD));
+        assert_eq!(tracker.latest(2), None);
+    }
+
+    #[test]
+    fn servo_that_comes_up_faulted_enters_its_faults() {
+        let mut tracker = StatusTracker::default();
+        assert_eq!(tracker.record(3, AlarmMask::SENSOR.bits()).map(|transition| transition.entered), Some(AlarmMask::SENSOR));
+    }
+
+    #[cfg(not(feature = "milkv"))]
+    mod bus {
+        use super::*;
+        use crate::hal::mock::MockBus;
+
+        #[test]
+        fn alarm_writes_are_wrapped_in_unlock_and_relock() {
+            let bus = MockBus::new(&[1]);
+            let servo = Servo::mock(&bus);
+            servo.write_shutdown_alarm(1, AlarmMask::OVERLOAD.union(AlarmMask::TEMPERATURE)).unwrap();
+            assert_eq!(servo.read_shutdown_alarm(1).unwrap(), AlarmMask::from_bits(0x24).unwrap());
+            let writes: Vec<(u8, Vec<u8>)> = bus.writes().into_iter().map(|write| (write.address, write.data)).collect();
+            assert_eq!(writes, vec![
+                (ServoRegister::LockMark as u8, vec![0]),
+                (ServoRegister::UnloadingCondition as u8, vec![0x24]),
+                (ServoRegister::LockMark as u8, vec![1]),
+            ]);
+        }
+
+        #[test]
+        fn clear_all_faults_only_touches_faulted_joints() {
+            let (bus, robot) = crate::robot::tests::mock_robot(&[("left_hip", 1), ("right_hip", 2), ("neck", 3)]);
+            for id in 1..=3 {
+                bus.set_u8(id, ServoRegister::TorqueSwitch, 1);
+            }
+            bus.set_u8(2, ServoRegister::ServoStatus, AlarmMask::OVERLOAD.bits());
+            bus.set_u8(3, ServoRegister::ServoStatus, AlarmMask::OVERLOAD.union(AlarmMask::SENSOR).bits());
+            // Overload is latched until torque goes off, a sensor fault stays
+            bus.on_packet(|servos| {
+                for id in [2, 3] {
+                    if servos.u8(id, ServoRegister::TorqueSwitch) == 0 {
+                        let status = servos.u8(id, ServoRegister::ServoStatus) & !AlarmMask::OVERLOAD.bits();
+                        servos.set_u8(id, ServoRegister::ServoStatus, status);
+                    }
+                }
+            });
+            let reports = robot.clear_all_faults().unwrap();
+            assert_eq!(reports, vec![
+                FaultReport { name: "right_hip".to_string(), id: 2, faults: AlarmMask::OVERLOAD, remaining: AlarmMask::NONE },
+                FaultReport { name: "neck".to_string(), id: 3, faults: AlarmMask::OVERLOAD.union(AlarmMask::SENSOR), remaining: AlarmMask::SENSOR },
+            ]);
+            assert_eq!(bus.u8(1, ServoRegister::TorqueSwitch), 1);
+            assert_eq!(bus.u8(2, ServoRegister::TorqueSwitch), 0);
+        }
+
+        #[test]
+        fn reply_status_is_tracked_and_reported_on_change() {
+            let bus = MockBus::new(&[1]);
+            let servo = Servo::mock(&bus);
+            let transitions = Arc::new(Mutex::new(Vec::new()));
+            let recorded = transitions.clone();
+            servo.on_fault_transition(move |transition| recorded.lock().unwrap().push(*transition));
+
+            servo.read_info(1).unwrap();
+            assert_eq!(servo.latest_status(1), Some(AlarmMask::NONE));
+            bus.report_status(1, AlarmMask::VOLTAGE.bits());
+            servo.read_info(1).unwrap();
+            servo.read_info(1).unwrap();
+            bus.report_status(1, 0);
+            servo.read_info(1).unwrap();
+
+            let transitions: Vec<(AlarmMask, AlarmMask)> = transitions.lock().unwrap().iter().map(|transition| (transition.entered, transition.cleared)).collect();
+            assert_eq!(transitions, [(AlarmMask::VOLTAGE, AlarmMask::NONE), (AlarmMask::NONE, AlarmMask::VOLTAGE)]);
+            assert_eq!(servo.latest_status(2), None);
+        }
+    }
+}
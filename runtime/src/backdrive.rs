@@ -0,0 +1,187 @@
+use anyhow::Result;
+use std::sync::atomic::Ordering;
+use std::thread::sleep;
+use std::time::{Duration, Instant};
+use crate::robot::{Joint, Robot};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BackdriveConfig {
+    // How long the operator has to move each joint
+    pub window: Duration,
+    // Between position reads
+    pub poll: Duration,
+    // Ticks the joint has to travel by hand to count as moving freely, well
+    // above encoder noise and the backlash of a jammed gearbox
+    pub min_travel: u16,
+}
+
+impl Default for BackdriveConfig {
+    fn default() -> Self {
+        Self {
+            window: Duration::from_secs(5),
+            poll: Duration::from_millis(20),
+            min_travel: 100,
+        }
+    }
+}
+
+// Range of positions seen so far, so moving a joint back and forth counts
+// as much as moving it one way
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TravelTracker {
+    min: i16,
+    max: i16,
+}
+
+impl TravelTracker {
+    pub fn new(position: i16) -> Self {
+        Self { min: position, max: position }
+    }
+
+    // Ticks between the furthest positions seen
+    pub fn update(&mut self, position: i16) -> u16 {
+        self.min = self.min.min(position);
+        self.max = self.max.max(position);
+        self.travel()
+    }
+
+    pub fn travel(&self) -> u16 {
+        (self.max as i32 - self.min as i32) as u16
+    }
+}
+
+// Samples until the position has covered `min_travel` or the window is
+// over, returning the travel seen. Stops early once it's reached, there's
+// no point making the operator keep going.
+pub fn watch_travel(config: &BackdriveConfig, mut sample: impl FnMut() -> Result<i16>) -> Result<u16> {
+    let start = Instant::now();
+    let mut tracker = TravelTracker::new(sample()?);
+    while tracker.travel() < config.min_travel && start.elapsed() < config.window {
+        sleep(config.poll);
+        tracker.update(sample()?);
+    }
+    Ok(tracker.travel())
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct JointBackdrive {
+    pub name: String,
+    pub id: u8,
+    pub travel: u16,
+    // Under min_travel: stiff or jammed, not safe to teach by hand
+    pub free: bool,
+}
+
+impl Robot {
+    // Before a teaching session: relaxes each joint in turn, calls `prompt`
+    // to ask the operator to move it, and watches how far it goes. Each
+    // joint is reengaged where it was left before the next one, even if a
+    // read fails. Stops after the current joint once the emergency stop
+    // clears `running`.
+    pub fn backdrive_check(&self, config: &BackdriveConfig, mut prompt: impl FnMut(&Joint)) -> Result<Vec<JointBackdrive>> {
+        let mut report = Vec::new();
+        for joint in self.joints() {
+            if !self.running().load(Ordering::SeqCst) {
+                break;
+            }
+            self.relax(&[joint.id])?;
+            prompt(joint);
+            let travel = watch_travel(config, || self.servo().read_position(joint.id));
+            self.reengage(&[joint.id])?;
+            let travel = travel?;
+            report.push(JointBackdrive {
+                name: joint.name.clone(),
+                id: joint.id,
+                travel,
+                free: travel >= config.min_travel,
+            });
+        }
+        Ok(report)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn quick(min_travel: u16) -> BackdriveConfig {
+        BackdriveConfig { window: Duration::from_millis(30), poll: Duration::from_millis(1), min_travel }
+    }
+
+    #[test]
+    fn travel_spans_both_directions() {
+        let mut tracker = TravelTracker::new(2048);
+        assert_eq!(tracker.update(2100), 52);
+        assert_eq!(tracker.update(2000), 100);
+        // Back inside the range seen adds nothing
+        assert_eq!(tracker.update(2050), 100);
+    }
+
+    #[test]
+    fn watching_stops_once_far_enough() {
+        let mut positions = [2048, 2000, 2150, 2300].into_iter();
+        let mut samples = 0;
+        let travel = watch_travel(&quick(100), || {
+            samples += 1;
+            Ok(positions.next().unwrap())
+        }).unwrap();
+        assert_eq!((travel, samples), (150, 3));
+    }
+
+    #[test]
+    fn a_stiff_joint_runs_out_the_window() {
+        let start = Instant::now();
+        assert_eq!(watch_travel(&quick(100), || Ok(2048)).unwrap(), 0);
+        assert!(start.elapsed() >= Duration::from_millis(30));
+    }
+
+    #[test]
+    fn read_errors_end_the_watch() {
+        let mut first = true;
+        let result = watch_travel(&quick(100), || {
+            if std::mem::take(&mut first) { Ok(2048) } else { anyhow::bail!("no reply") }
+        });
+        assert_eq!(result.unwrap_err().to_string(), "no reply");
+    }
+
+    #[cfg(not(feature = "milkv"))]
+    mod bus {
+        use super::*;
+        use crate::hal::ServoRegister;
+        use crate::robot::tests::mock_robot;
+
+        #[test]
+        fn each_joint_is_relaxed_in_turn_and_reengaged() {
+            let (bus, robot) = mock_robot(&[("left", 1), ("right", 2)]);
+            for id in [1, 2] {
+                bus.set_u8(id, ServoRegister::TorqueSwitch, 1);
+            }
+            // Only the left joint moves freely by hand
+            bus.on_packet(|servos| {
+                if servos.u8(1, ServoRegister::TorqueSwitch) == 0 {
+                    let position = servos.u16(1, ServoRegister::CurrentLocation);
+                    servos.set_u16(1, ServoRegister::CurrentLocation, position + 10);
+                }
+            });
+            let mut prompted = Vec::new();
+            let report = robot.backdrive_check(&quick(100), |joint| prompted.push(joint.id)).unwrap();
+            assert_eq!(prompted, vec![1, 2]);
+            assert_eq!((report[0].id, report[0].free), (1, true));
+            assert!(report[0].travel >= 100);
+            assert_eq!(report[1], JointBackdrive { name: "right".to_string(), id: 2, travel: 0, free: false });
+            for id in [1, 2] {
+                assert_eq!(bus.u8(id, ServoRegister::TorqueSwitch), 1);
+            }
+            // Held where it was left, not jerked back to the old goal
+            assert!(bus.u16(1, ServoRegister::TargetLocation) >= 2048 + 100);
+            assert_eq!(bus.u16(2, ServoRegister::TargetLocation), 2048);
+        }
+
+        #[test]
+        fn stopping_ends_after_the_current_joint() {
+            let (_bus, robot) = mock_robot(&[("left", 1), ("right", 2)]);
+            let report = robot.backdrive_check(&quick(0), |_| robot.running().store(false, Ordering::SeqCst)).unwrap();
+            assert_eq!(report.len(), 1);
+        }
+    }
+}
@@ -0,0 +1,158 @@
+use anyhow::{Result, bail};
+use std::thread::sleep;
+use std::time::Duration;
+use crate::hal::{Servo, ServoDirection, ServoRegister};
+use crate::robot::Robot;
+use crate::servo::SettleConfig;
+use crate::units::wrap_ticks;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BacklashConfig {
+    // Ticks the output has to move to count as moving, above the encoder noise
+    pub min_motion: u16,
+    // Goal ticks added per step
+    pub step: u16,
+    // Wait after each step before reading the position back
+    pub step_delay: Duration,
+    // Goal ticks stepped without the output moving before giving up
+    pub max_travel: u16,
+    // For returning to the starting position afterwards
+    pub settle: SettleConfig,
+}
+
+impl Default for BacklashConfig {
+    fn default() -> Self {
+        Self {
+            min_motion: 2,
+            step: 1,
+            step_delay: Duration::from_millis(20),
+            max_travel: 200,
+            settle: SettleConfig::default(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct JointBacklash {
+    pub name: String,
+    pub id: u8,
+    pub ticks: u16,
+}
+
+impl Servo {
+    // Step the goal from `goal` in `direction` until the output follows by
+    // min_motion ticks. Returns the goal it moved at and the goal ticks
+    // stepped to get there.
+    pub fn step_until_moved(&self, id: u8, goal: i16, direction: ServoDirection, config: &BacklashConfig) -> Result<(i16, u16)> {
+        let sign = match direction {
+            ServoDirection::Clockwise => 1,
+            ServoDirection::Counterclockwise => -1,
+        };
+        let start = self.read_position(id)?;
+        let mut stepped = 0;
+        while stepped < config.max_travel {
+            stepped = stepped.saturating_add(config.step).min(config.max_travel);
+            let next = wrap_ticks(goal as i32 + sign * stepped as i32) as i16;
+            self.write_u16(id, ServoRegister::TargetLocation, next as u16)?;
+            sleep(config.step_delay);
+
+            let moved = wrap_ticks(self.read_position(id)? as i32 - start as i32);
+            let moved = moved.min(4096 - moved);
+            if moved >= config.min_motion as i32 {
+                return Ok((next, stepped));
+            }
+        }
+        bail!("Servo {} didn't move {:?} after stepping the goal {} ticks", id, direction, stepped);
+    }
+
+    // Gear backlash in ticks: the slack is taken up clockwise, then the goal
+    // is stepped back counterclockwise until the output follows. Run it with
+    // the joint lightly loaded, a load holding the gears against one side
+    // hides the slack. The servo's own compliance margins are part of the
+    // reading, compare readings of the same joint over time rather than
+    // across servos. The joint is returned to where it started.
+    pub fn measure_backlash(&self, id: u8, config: &BacklashConfig) -> Result<u16> {
+        if config.step == 0 {
+            bail!("Backlash step must be at least 1 tick");
+        }
+        let start = self.read_position(id)?;
+        self.move_to_and_wait(id, start, &config.settle)?;
+
+        let measured = self.step_until_moved(id, start, ServoDirection::Clockwise, config)
+            .and_then(|(goal, _)| self.step_until_moved(id, goal, ServoDirection::Counterclockwise, config));
+        let returned = self.move_to_and_wait(id, start, &config.settle);
+
+        let (_, stepped) = measured?;
+        returned?;
+        // The last min_motion ticks of the reversal moved the output
+        Ok(stepped.saturating_sub(config.min_motion))
+    }
+}
+
+impl Robot {
+    // Every joint's backlash, one joint at a time
+    pub fn backlash_report(&self, config: &BacklashConfig) -> Result<Vec<JointBacklash>> {
+        self.joints().iter()
+            .map(|joint| Ok(JointBacklash {
+                name: joint.name.clone(),
+                id: joint.id,
+                ticks: self.servo().measure_backlash(joint.id, config)?,
+            }))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(not(feature = "milkv"))]
+    mod bus {
+        use super::*;
+        use crate::hal::mock::MockBus;
+        use crate::robot::tests::mock_robot;
+
+        // Gears with `play` ticks of slack between the goal and the output
+        fn simulate_backlash(bus: &MockBus, id: u8, play: i32) {
+            let mut output = CENTER as i32;
+            bus.on_packet(move |servos| {
+                let goal = servos.u16(id, ServoRegister::TargetLocation) as i32;
+                output = output.clamp(goal - play / 2, goal + play / 2);
+                servos.set_u16(id, ServoRegister::CurrentLocation, output as u16);
+            });
+        }
+
+        const CENTER: u16 = 2048;
+
+        fn config() -> BacklashConfig {
+            BacklashConfig { step_delay: Duration::ZERO, ..BacklashConfig::default() }
+        }
+
+        #[test]
+        fn backlash_is_the_reversal_before_the_output_follows() {
+            let bus = MockBus::new(&[1]);
+            simulate_backlash(&bus, 1, 20);
+            let servo = Servo::mock(&bus);
+            assert_eq!(servo.measure_backlash(1, &config()).unwrap(), 20);
+            assert_eq!(bus.u16(1, ServoRegister::TargetLocation), CENTER);
+        }
+
+        #[test]
+        fn a_joint_that_never_moves_is_reported() {
+            let (bus, robot) = mock_robot(&[("left_hip", 1)]);
+            simulate_backlash(&bus, 1, 1000);
+            let error = robot.backlash_report(&config()).unwrap_err().to_string();
+            assert!(error.contains("didn't move"), "{}", error);
+            // Returned to the start all the same
+            assert_eq!(bus.u16(1, ServoRegister::TargetLocation), CENTER);
+        }
+
+        #[test]
+        fn report_covers_every_joint() {
+            let (bus, robot) = mock_robot(&[("left_hip", 1)]);
+            simulate_backlash(&bus, 1, 8);
+            assert_eq!(robot.backlash_report(&config()).unwrap(), vec![JointBacklash { name: "left_hip".to_string(), id: 1, ticks: 8 }]);
+            assert!(robot.servo().measure_backlash(1, &BacklashConfig { step: 0, ..config() }).is_err());
+        }
+    }
+}
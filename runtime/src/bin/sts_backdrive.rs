@@ -0,0 +1,47 @@
+use anyhow::{bail, Result};
+use clap::Parser;
+use runtime::backdrive::BackdriveConfig;
+use runtime::builder::RobotBuilder;
+use runtime::units::ticks_to_deg;
+use std::path::PathBuf;
+use std::time::Duration;
+
+#[derive(Parser, Debug)]
+#[command(author, version, about = "Check every joint can be moved freely by hand before a teaching session", long_about = None)]
+struct Args {
+    #[arg(short, long, default_value = "config/stompymicro.toml")]
+    config: PathBuf,
+
+    /// Seconds to move each joint
+    #[arg(long, default_value_t = 5.0)]
+    window: f32,
+
+    /// Ticks a joint has to travel to pass
+    #[arg(long, default_value_t = 100)]
+    min_travel: u16,
+}
+
+fn main() -> Result<()> {
+    let args = Args::parse();
+    let robot = RobotBuilder::new(&args.config).emergency_stop(|press| println!("\n{}", press)).build()?;
+    let config = BackdriveConfig {
+        window: Duration::from_secs_f32(args.window),
+        min_travel: args.min_travel,
+        ..BackdriveConfig::default()
+    };
+
+    let report = robot.backdrive_check(&config, |joint| {
+        println!("Torque off on {} (ID {}), move it back and forth by hand for up to {} s", joint.name, joint.id, args.window);
+    })?;
+
+    for joint in &report {
+        let verdict = if joint.free { "free" } else { "STIFF OR JAMMED" };
+        println!("{:>20} (ID {:2}): {:4} ticks ({:.1} degrees) {}", joint.name, joint.id, joint.travel, ticks_to_deg(joint.travel as i32), verdict);
+    }
+
+    let stiff: Vec<&str> = report.iter().filter(|joint| !joint.free).map(|joint| joint.name.as_str()).collect();
+    if !stiff.is_empty() {
+        bail!("Not back-drivable: {}", stiff.join(", "));
+    }
+    Ok(())
+}
@@ -0,0 +1,40 @@
+use anyhow::Result;
+use clap::Parser;
+use runtime::backlash::{BacklashConfig, JointBacklash};
+use runtime::builder::RobotBuilder;
+use runtime::units::ticks_to_deg;
+use std::path::PathBuf;
+
+#[derive(Parser, Debug)]
+#[command(author, version, about = "Measure gear backlash of every configured joint, or of a single servo", long_about = None)]
+struct Args {
+    #[arg(short, long, default_value = "config/stompymicro.toml")]
+    config: PathBuf,
+
+    /// Measure only this servo
+    #[arg(short, long)]
+    id: Option<u8>,
+}
+
+fn main() -> Result<()> {
+    let args = Args::parse();
+    let robot = RobotBuilder::new(&args.config).build()?;
+    let servo = robot.servo();
+    let config = BacklashConfig::default();
+
+    servo.disable_readout()?;
+    let report = match args.id {
+        Some(id) => robot.joint_by_id(id).and_then(|joint| Ok(vec![JointBacklash {
+            name: joint.name.clone(),
+            id,
+            ticks: servo.measure_backlash(id, &config)?,
+        }])),
+        None => robot.backlash_report(&config),
+    };
+    servo.enable_readout()?;
+
+    for joint in report? {
+        println!("{:>20} (ID {:2}): {:3} ticks ({:.2} degrees)", joint.name, joint.id, joint.ticks, ticks_to_deg(joint.ticks as i32));
+    }
+    Ok(())
+}
@@ -0,0 +1,40 @@
+use anyhow::Result;
+use clap::Parser;
+use runtime::builder::RobotBuilder;
+use std::path::PathBuf;
+
+#[derive(Parser, Debug)]
+#[command(author, version, about = "Find the highest baud rate every configured servo communicates at without errors", long_about = None)]
+struct Args {
+    #[arg(short, long, default_value = "config/stompymicro.toml")]
+    config: PathBuf,
+
+    /// Comm test rounds per joint at each baud rate
+    #[arg(short, long, default_value_t = 200)]
+    rounds: u32,
+}
+
+fn main() -> Result<()> {
+    let args = Args::parse();
+    let robot = RobotBuilder::new(&args.config).build()?;
+    let servo = robot.servo();
+
+    println!("Scanning up from {} baud over {} joints", servo.bus_baud_rate()?, robot.joints().len());
+    let scan = servo.with_readout_disabled(|_| robot.baud_scan(args.rounds))?;
+
+    for probe in &scan.probes {
+        match &probe.report {
+            Some(report) => println!("{:>9} baud: {}", probe.baud_rate, report),
+            None => println!("{:>9} baud: FAIL: joints not responding", probe.baud_rate),
+        }
+    }
+    match scan.best {
+        Some(best) if best > scan.original => println!(
+            "Highest error-free rate is {} baud, move the chain there with sts_change_baud {}", best, best
+        ),
+        Some(_) => println!("No rate above the current {} baud is error-free", scan.original),
+        None => println!("Errors even at the current {} baud, check the wiring before going faster", scan.original),
+    }
+    println!("Servos left at the original {} baud", scan.original);
+    Ok(())
+}
@@ -0,0 +1,277 @@
+use anyhow::Result;
+use clap::Parser;
+use runtime::calibration::{self, AdaptivePoll, Approach, Backoff, CalibrationEvent, CalibrationEvents, CalibrationFile, CalibrationParams, CalibrationWrites, CenterStart, Escalation, InterruptAction, ProgressReport, SweepProgress, StallRule, StopDetection, TripRule, DEFAULT_MAX_TRAVEL, offset_margin, symmetric_range, symmetric_range_deg};
+use runtime::servo::{parse_model_scale, ModelResolution, ModelScaling, ReadingChecks, SettleConfig, SpeedRamp};
+use runtime::hal::Servo;
+use runtime::usage::UsageFile;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+#[derive(Parser, Debug)]
+#[command(author, version, about = "Find the mechanical stops of a servo and write its calibration", long_about = None)]
+struct Args {
+    id: u8,
+
+    #[arg(short, long)]
+    speed: u16,
+
+    #[arg(short = 't', long)]
+    current_threshold: f32,
+
+    /// Backoff after each stop in ms
+    #[arg(long, default_value_t = 350)]
+    backoff_cw: u64,
+
+    #[arg(long, default_value_t = 350)]
+    backoff_ccw: u64,
+
+    /// Only write the offset, keeping the stored angle limits, e.g. after
+    /// a gearbox swap or for joints without angle limits
+    #[arg(long, alias = "offset-only")]
+    no_limits: bool,
+
+    /// Only write the angle limits, keeping the stored offset, e.g. after
+    /// moving a hard stop
+    #[arg(long, conflicts_with = "no_limits")]
+    limits_only: bool,
+
+    /// Sweep the first approach_distance ticks at this speed
+    #[arg(long)]
+    approach_speed: Option<u16>,
+
+    #[arg(long, default_value_t = 1024)]
+    approach_distance: u16,
+
+    /// Give up on a sweep that travels further than this without a stop, 0 to
+    /// sweep until interrupted
+    #[arg(long, default_value_t = DEFAULT_MAX_TRAVEL)]
+    max_travel: u32,
+
+    /// Raise the speed up to this if the joint barely moves at --speed
+    #[arg(long)]
+    max_speed: Option<u16>,
+
+    /// Hold position instead of coasting when interrupted
+    #[arg(long)]
+    hold_on_interrupt: bool,
+
+    /// Declare a stop once trip_required of the last trip_window readings are over the threshold
+    #[arg(long, default_value_t = 3)]
+    trip_required: usize,
+
+    #[arg(long, default_value_t = 3)]
+    trip_window: usize,
+
+    /// Ramp the speed down over this many ms once a stop trips
+    #[arg(long)]
+    decel_ms: Option<u64>,
+
+    #[arg(long, default_value_t = 5)]
+    decel_steps: u16,
+
+    /// Leave torque on with the joint holding center, instead of turning it off
+    #[arg(long)]
+    keep_torque: bool,
+
+    /// Move to center before sweeping, at --center-speed or else --speed
+    #[arg(long)]
+    center_start: bool,
+
+    #[arg(long)]
+    center_speed: Option<u16>,
+
+    /// Trust every frame read during the sweep, even implausible ones
+    #[arg(long)]
+    no_reading_checks: bool,
+
+    /// Poll slower during free travel and faster near the threshold
+    #[arg(long)]
+    adaptive_poll: bool,
+
+    /// Record the calibration time in this usage file
+    #[arg(long)]
+    usage: Option<PathBuf>,
+
+    /// Refuse to write a calibration scoring below --min-confidence
+    #[arg(long)]
+    verify_calibration: bool,
+
+    #[arg(long, default_value_t = 0.5)]
+    min_confidence: f32,
+
+    /// Record the calibration and its confidence in this calibration file,
+    /// under --joint
+    #[arg(long, requires = "joint")]
+    save: Option<PathBuf>,
+
+    #[arg(long)]
+    joint: Option<String>,
+
+    /// Average this many position reads at rest against each stop
+    #[arg(long, default_value_t = 1)]
+    stop_samples: usize,
+
+    /// Fail instead of warning when the offset is near saturation
+    #[arg(long)]
+    strict: bool,
+
+    /// Ignore the current for this many ms after each sweep starts, so the
+    /// spike from reversing off a stop isn't taken for the next one
+    #[arg(long, default_value_t = 50)]
+    blanking_ms: u64,
+
+    /// Print a progress line per percent of --expected-travel covered
+    #[arg(long)]
+    progress: bool,
+
+    /// Ticks between the joint's stops, for the progress estimate
+    #[arg(long, default_value_t = 2048)]
+    expected_travel: u32,
+
+    /// Detect the stops by the position stalling instead of by current, for
+    /// servos whose current reading can't be trusted. By default this is
+    /// only fallen back to if the current reads zero while moving.
+    #[arg(long, conflicts_with = "current_only")]
+    stall_detection: bool,
+
+    /// Never fall back to stall detection
+    #[arg(long)]
+    current_only: bool,
+
+    /// Readings the position has to stay within 2 ticks for to count as a stall
+    #[arg(long, default_value_t = 10)]
+    stall_samples: usize,
+
+    /// Current scale for a servo model as MODEL=SCALE, e.g. 0x0903=0.4225,
+    /// over the built-in ones. Repeat for several models.
+    #[arg(long = "current-scale", value_parser = parse_model_scale)]
+    current_scale: Vec<(u16, f32)>,
+}
+
+fn main() -> Result<()> {
+    let args = Args::parse();
+    let servo = Servo::new()?;
+    let running = Arc::new(AtomicBool::new(true));
+    let r = running.clone();
+
+    // Only clears the flag: the sweep stops at its next poll, while an
+    // EEPROM write already in progress is allowed to finish
+    ctrlc::set_handler(move || {
+        println!("\nInterrupt signal received. Stopping calibration...");
+        r.store(false, Ordering::SeqCst);
+    })
+    .expect("Error setting Ctrl-C handler");
+
+    let params = CalibrationParams {
+        speed: args.speed,
+        current_threshold: args.current_threshold,
+        current_scaling: ModelScaling::current().with_models(args.current_scale.iter().copied()),
+        settle: SettleConfig::default(),
+        backoff: Backoff {
+            clockwise: Duration::from_millis(args.backoff_cw),
+            counterclockwise: Duration::from_millis(args.backoff_ccw),
+        },
+        writes: match (args.no_limits, args.limits_only) {
+            (true, _) => CalibrationWrites::OffsetOnly,
+            (_, true) => CalibrationWrites::LimitsOnly,
+            _ => CalibrationWrites::All,
+        },
+        approach: args.approach_speed.map(|speed| Approach {
+            speed,
+            distance: args.approach_distance,
+        }),
+        escalation: args.max_speed.map(|max_speed| Escalation {
+            window: Duration::from_millis(500),
+            min_travel: 10,
+            step: 10,
+            max_speed,
+        }),
+        on_interrupt: if args.hold_on_interrupt { InterruptAction::Hold } else { InterruptAction::Stop },
+        trip: TripRule { required: args.trip_required, window: args.trip_window },
+        deceleration: args.decel_ms.map(|ms| SpeedRamp {
+            duration: Duration::from_millis(ms),
+            steps: args.decel_steps,
+        }),
+        keep_torque: args.keep_torque,
+        max_travel: Some(args.max_travel).filter(|&ticks| ticks > 0),
+        center_start: args.center_start.then(|| CenterStart {
+            speed: args.center_speed.unwrap_or(args.speed),
+            settle: SettleConfig { timeout: Duration::from_secs(10), ..SettleConfig::default() },
+        }),
+        reading_checks: if args.no_reading_checks { ReadingChecks::NONE } else { ReadingChecks::default() },
+        poll: args.adaptive_poll.then(AdaptivePoll::default),
+        verify_calibration: args.verify_calibration.then_some(args.min_confidence),
+        stop_samples: args.stop_samples,
+        strict_offset: args.strict,
+        resolution: ModelResolution::sts(),
+        reversal_blanking: Duration::from_millis(args.blanking_ms),
+        progress: args.progress.then(|| ProgressReport::new(args.expected_travel, printed_progress(args.id))),
+        events: Some(CalibrationEvents::new(print_event)),
+        detection: {
+            let rule = StallRule { samples: args.stall_samples, ..StallRule::default() };
+            match (args.stall_detection, args.current_only) {
+                (true, _) => StopDetection::Stall(rule),
+                (_, true) => StopDetection::Current,
+                _ => StopDetection::Auto(rule),
+            }
+        },
+    };
+
+    println!("Calibrating servo {}. Press Ctrl+C to abort", args.id);
+    let run = calibration::calibrate_servo(&servo, args.id, &params, &running)?;
+    let (calibration, trace) = (run.calibration, run.trace);
+    println!(
+        "Stops: backward {} (backed off to {}), forward {} (backed off to {})",
+        trace.backward.position(), trace.backward.backed_off, trace.forward.position(), trace.forward.backed_off
+    );
+    println!(
+        "Calibration complete. Offset: {} ({} ticks from saturation), min angle: {}, max angle: {}",
+        calibration.offset, offset_margin(calibration.offset), calibration.min_angle, calibration.max_angle
+    );
+    println!(
+        "Symmetric range: ±{} ticks (±{:.1} degrees) from center, keep poses within it",
+        symmetric_range(calibration.min_angle, calibration.max_angle), symmetric_range_deg(calibration.min_angle, calibration.max_angle)
+    );
+    let confidence = run.confidence;
+    println!("Confidence {:.2}: {}", confidence.score, confidence);
+
+    if let (Some(path), Some(joint)) = (&args.save, &args.joint) {
+        let mut file = if path.exists() { CalibrationFile::load(path)? } else { CalibrationFile::default() };
+        file.record(joint, args.id, &run);
+        file.save(path)?;
+    }
+
+    if let Some(path) = &args.usage {
+        let mut usage = UsageFile::load_or_default(path)?;
+        usage.record_calibration(args.id, SystemTime::now());
+        usage.save(path)?;
+    }
+
+    Ok(())
+}
+
+// One line per whole percent, e.g.
+// progress servo=3 pass=0 direction=Clockwise travel=812 percent=39
+// for a front-end reading the output
+fn printed_progress(id: u8) -> impl Fn(&SweepProgress) + Send + Sync {
+    let last = AtomicU32::new(u32::MAX);
+    move |progress| {
+        let percent = (progress.fraction * 100.0) as u32;
+        let key = progress.pass as u32 * 1000 + percent;
+        if last.swap(key, Ordering::SeqCst) != key {
+            println!(
+                "progress servo={} pass={} direction={:?} travel={} percent={}",
+                id, progress.pass, progress.direction, progress.travel, percent
+            );
+        }
+    }
+}
+
+fn print_event(event: &CalibrationEvent) {
+    match event {
+        CalibrationEvent::StallFallback { .. } => eprintln!("Warning: {}", event),
+        _ => println!("{}", event),
+    }
+}
@@ -0,0 +1,121 @@
+use anyhow::{bail, Result};
+use clap::Parser;
+use runtime::builder::RobotBuilder;
+use runtime::calibration::{offset_margin, symmetric_range_deg, Backoff, CalibrationEvent, CalibrationEvents, CalibrationParams, CalibrationWrites, InterruptAction, StopDetection, TripRule, DEFAULT_MAX_TRAVEL, DEFAULT_REVERSAL_BLANKING};
+use runtime::servo::{parse_model_scale, ModelResolution, ReadingChecks, SettleConfig};
+use std::path::PathBuf;
+use std::time::Duration;
+
+#[derive(Parser, Debug)]
+#[command(author, version, about = "Calibrate every joint in turn, each with its configured calibration profile", long_about = None)]
+struct Args {
+    #[arg(short, long, default_value = "config/stompymicro.toml")]
+    config: PathBuf,
+
+    /// Base parameters, for joints without a profile and fields a profile leaves unset
+    #[arg(short, long)]
+    speed: u16,
+
+    #[arg(short = 't', long)]
+    current_threshold: f32,
+
+    /// Backoff after each stop in ms
+    #[arg(long, default_value_t = 350)]
+    backoff_cw: u64,
+
+    #[arg(long, default_value_t = 350)]
+    backoff_ccw: u64,
+
+    /// Fail a joint instead of warning when its offset is near saturation
+    #[arg(long)]
+    strict: bool,
+
+    /// Current scale for a servo model as MODEL=SCALE, e.g. 0x0903=0.4225,
+    /// over robot.current_scale in the config. Repeat for several models.
+    #[arg(long = "current-scale", value_parser = parse_model_scale)]
+    current_scale: Vec<(u16, f32)>,
+}
+
+fn main() -> Result<()> {
+    let args = Args::parse();
+    // Calibrating is what makes the joints usable in the first place
+    let robot = RobotBuilder::new(&args.config)
+        .emergency_stop(|press| println!("\n{}", press))
+        .allow_uncalibrated(true)
+        .build()?;
+
+    let base = CalibrationParams {
+        speed: args.speed,
+        current_threshold: args.current_threshold,
+        current_scaling: robot.current_scaling().clone().with_models(args.current_scale.iter().copied()),
+        settle: SettleConfig::default(),
+        backoff: Backoff {
+            clockwise: Duration::from_millis(args.backoff_cw),
+            counterclockwise: Duration::from_millis(args.backoff_ccw),
+        },
+        writes: CalibrationWrites::All,
+        approach: None,
+        escalation: None,
+        on_interrupt: InterruptAction::Stop,
+        trip: TripRule::default(),
+        deceleration: None,
+        keep_torque: false,
+        max_travel: Some(DEFAULT_MAX_TRAVEL),
+        center_start: None,
+        reading_checks: ReadingChecks::default(),
+        poll: None,
+        verify_calibration: None,
+        stop_samples: 1,
+        strict_offset: args.strict,
+        resolution: ModelResolution::sts(),
+        reversal_blanking: DEFAULT_REVERSAL_BLANKING,
+        progress: None,
+        events: Some(CalibrationEvents::new(print_event)),
+        detection: StopDetection::default(),
+    };
+
+    for joint in robot.joints() {
+        let params = robot.calibration_params(joint, &base)?;
+        let profile = joint.calibration_profile.as_deref().unwrap_or("base");
+        println!(
+            "{:>20} (ID {:2}): {} profile, speed {}, threshold {} mA",
+            joint.name, joint.id, profile, params.speed, params.current_threshold
+        );
+    }
+    println!("Press Ctrl+C to stop after the current joint");
+
+    let results = robot.calibrate_all(&base, robot.running())?;
+    let mut failed = 0;
+    for (name, result) in &results {
+        match result {
+            Ok(run) => {
+                let calibration = run.calibration;
+                println!(
+                    "{:>20}: offset {} ({} ticks from saturation), min angle {}, max angle {}, symmetric ±{:.1} degrees, confidence {:.2}",
+                    name, calibration.offset, offset_margin(calibration.offset), calibration.min_angle, calibration.max_angle,
+                    symmetric_range_deg(calibration.min_angle, calibration.max_angle), run.confidence.score
+                );
+            }
+            Err(e) => {
+                failed += 1;
+                println!("{:>20}: failed: {:#}", name, e);
+            }
+        }
+    }
+
+    let skipped = robot.joints().len() - results.len();
+    if skipped > 0 {
+        println!("Interrupted, {} joints not calibrated", skipped);
+    }
+    if failed > 0 {
+        bail!("{} of {} joints failed to calibrate", failed, results.len());
+    }
+    Ok(())
+}
+
+fn print_event(event: &CalibrationEvent) {
+    match event {
+        CalibrationEvent::StallFallback { .. } => eprintln!("Warning: {}", event),
+        _ => println!("{}", event),
+    }
+}
@@ -0,0 +1,55 @@
+use anyhow::Result;
+use clap::{Parser, Subcommand};
+use runtime::hal::Servo;
+use runtime::robot::Robot;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+#[derive(Parser, Debug)]
+#[command(author, version, about = "Export or import the EEPROM calibration of all joints", long_about = None)]
+struct Args {
+    #[arg(short, long, default_value = "config/stompymicro.toml")]
+    config: PathBuf,
+
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    Export { file: PathBuf },
+    Import { file: PathBuf },
+    // Make the joint's present position its center, e.g. after placing it
+    // there by hand
+    CenterHere {
+        joint: String,
+
+        /// Also set the limits this many ticks either side of center
+        #[arg(long)]
+        half_range: Option<u16>,
+    },
+}
+
+fn main() -> Result<()> {
+    let args = Args::parse();
+    let servo = Arc::new(Servo::new()?);
+    let robot = Robot::from_config(servo.clone(), &args.config)?;
+
+    servo.disable_readout()?;
+    let result = match &args.command {
+        Command::Export { file } => robot.export_robot_calibration(file)
+            .map(|_| format!("Exported calibration of {} joints to {:?}", robot.joints().len(), file)),
+        Command::Import { file } => robot.import_robot_calibration(file)
+            .map(|_| format!("Imported calibration of {} joints from {:?}", robot.joints().len(), file)),
+        Command::CenterHere { joint, half_range } => robot.joint(joint)
+            .and_then(|joint| servo.set_center_here(joint.id, *half_range))
+            .map(|calibration| format!(
+                "{} centered, offset: {}, min angle: {}, max angle: {}",
+                joint, calibration.offset, calibration.min_angle, calibration.max_angle
+            )),
+    };
+    servo.enable_readout()?;
+
+    println!("{}", result?);
+    Ok(())
+}
@@ -0,0 +1,30 @@
+use anyhow::Result;
+use clap::Parser;
+use runtime::hal::Servo;
+use runtime::robot::Robot;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+#[derive(Parser, Debug)]
+#[command(author, version, about = "Move every configured servo to a new baud rate", long_about = None)]
+struct Args {
+    baud_rate: u32,
+
+    #[arg(short, long, default_value = "config/stompymicro.toml")]
+    config: PathBuf,
+}
+
+fn main() -> Result<()> {
+    let args = Args::parse();
+    let servo = Arc::new(Servo::new()?);
+    let robot = Robot::from_config(servo.clone(), &args.config)?;
+
+    servo.disable_readout()?;
+    let result = robot.change_baud(args.baud_rate);
+    servo.enable_readout()?;
+    result?;
+
+    println!("All {} servos now run at {} baud.", robot.joints().len(), args.baud_rate);
+    println!("Set SERVO_BAUD_RATE={} for future sessions.", args.baud_rate);
+    Ok(())
+}
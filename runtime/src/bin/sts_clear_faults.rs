@@ -0,0 +1,36 @@
+use anyhow::Result;
+use clap::Parser;
+use runtime::alarm::AlarmMask;
+use runtime::builder::RobotBuilder;
+use std::path::PathBuf;
+
+#[derive(Parser, Debug)]
+#[command(author, version, about = "Clear latched faults on every configured servo", long_about = None)]
+struct Args {
+    #[arg(short, long, default_value = "config/stompymicro.toml")]
+    config: PathBuf,
+}
+
+fn main() -> Result<()> {
+    let args = Args::parse();
+    let robot = RobotBuilder::new(&args.config).build()?;
+    let servo = robot.servo();
+
+    servo.disable_readout()?;
+    let reports = robot.clear_all_faults();
+    servo.enable_readout()?;
+    let reports = reports?;
+
+    if reports.is_empty() {
+        println!("No faulted joints.");
+        return Ok(());
+    }
+    for report in &reports {
+        if report.remaining == AlarmMask::NONE {
+            println!("{} (ID {}): cleared {}, torque left off", report.name, report.id, report.faults);
+        } else {
+            println!("{} (ID {}): {} still reported after clearing {}", report.name, report.id, report.remaining, report.faults);
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,28 @@
+use anyhow::{bail, Result};
+use clap::Parser;
+use runtime::hal::Servo;
+
+#[derive(Parser, Debug)]
+#[command(author, version, about = "Check data integrity to a servo by writing test patterns and reading them back", long_about = None)]
+struct Args {
+    id: u8,
+
+    #[arg(short, long, default_value_t = 1000)]
+    rounds: u32,
+}
+
+fn main() -> Result<()> {
+    let args = Args::parse();
+    let servo = Servo::new()?;
+
+    servo.disable_readout()?;
+    let report = servo.comm_test(args.id, args.rounds);
+    servo.enable_readout()?;
+    let report = report?;
+
+    println!("Servo {}: {}", args.id, report);
+    if !report.passed() {
+        bail!("Communication test with servo {} failed", args.id);
+    }
+    Ok(())
+}
@@ -0,0 +1,29 @@
+use anyhow::Result;
+use clap::Parser;
+use runtime::hal::Servo;
+
+#[derive(Parser, Debug)]
+#[command(author, version, about = "List the registers of a servo that differ from its factory defaults", long_about = None)]
+struct Args {
+    id: u8,
+}
+
+fn main() -> Result<()> {
+    let args = Args::parse();
+    let servo = Servo::new()?;
+
+    servo.disable_readout()?;
+    let diffs = servo.diff_from_defaults(args.id);
+    servo.enable_readout()?;
+    let diffs = diffs?;
+
+    if diffs.is_empty() {
+        println!("Servo {} matches its factory defaults", args.id);
+        return Ok(());
+    }
+    println!("Servo {} differs from its factory defaults in {} registers:", args.id, diffs.len());
+    for diff in &diffs {
+        println!("  {}", diff);
+    }
+    Ok(())
+}
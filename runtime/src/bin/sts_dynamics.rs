@@ -0,0 +1,100 @@
+use anyhow::{Context, Result};
+use clap::Parser;
+use runtime::builder::RobotBuilder;
+use runtime::hal::ServoMode;
+use runtime::robot::clamp_to_limits;
+use runtime::servo::{ModelScaling, SettleConfig, SettleTiming};
+use runtime::units::deg_to_ticks;
+use std::fs;
+use std::path::PathBuf;
+use std::time::Duration;
+
+#[derive(Parser, Debug)]
+#[command(author, version, about = "Move each joint out and back by a fixed delta, reporting settle time and peak current as CSV", long_about = None)]
+struct Args {
+    /// Joints to measure, all configured joints if none are given
+    joints: Vec<String>,
+
+    #[arg(short, long, default_value = "config/stompymicro.toml")]
+    config: PathBuf,
+
+    /// Servo degrees moved out from the present position and back
+    #[arg(short, long, default_value_t = 20.0)]
+    delta: f32,
+
+    #[arg(long, default_value_t = 4)]
+    tolerance: u16,
+
+    #[arg(long, default_value_t = 3000)]
+    timeout_ms: u64,
+
+    /// Write the CSV here instead of stdout
+    #[arg(short, long)]
+    output: Option<PathBuf>,
+
+    /// Move joints even if they were never calibrated
+    #[arg(long)]
+    force: bool,
+}
+
+fn row(name: &str, id: u8, leg: &str, from: i16, to: i16, timing: &Result<SettleTiming>) -> String {
+    match timing {
+        Ok(timing) => format!(
+            "{},{},{},{},{},{},{:.0},{}",
+            name, id, leg, from, to, timing.elapsed.as_millis(), timing.peak_current, timing.position as i32 - to as i32
+        ),
+        // Empty fields for a move that never settled, the error goes to stderr
+        Err(_) => format!("{},{},{},{},{},,,", name, id, leg, from, to),
+    }
+}
+
+fn main() -> Result<()> {
+    let args = Args::parse();
+    let robot = RobotBuilder::new(&args.config)
+        .emergency_stop(|press| println!("\n{}", press))
+        .allow_uncalibrated(args.force)
+        .mode(ServoMode::Position)
+        .build()?;
+    let servo = robot.servo();
+
+    let joints = if args.joints.is_empty() {
+        robot.joints().iter().collect::<Vec<_>>()
+    } else {
+        args.joints.iter().map(|name| robot.joint(name)).collect::<Result<Vec<_>>>()?
+    };
+    let settle = SettleConfig {
+        tolerance: args.tolerance,
+        timeout: Duration::from_millis(args.timeout_ms),
+        ..SettleConfig::default()
+    };
+    let scaling = ModelScaling::current();
+    let delta = deg_to_ticks(args.delta);
+
+    let mut csv = vec!["joint,id,move,from,to,settle_ms,peak_current_ma,final_error".to_string()];
+    for joint in joints {
+        if !robot.is_running() {
+            eprintln!("Interrupted, stopping before {}", joint.name);
+            break;
+        }
+        let scale = servo.read_scale(joint.id, &scaling)?;
+        let limits = robot.calibration_of(joint)?;
+        let start = servo.read_position(joint.id)?;
+        let target = clamp_to_limits((start as i32 + delta).clamp(0, 4095) as i16, limits.min_angle, limits.max_angle);
+
+        for (leg, from, to) in [("out", start, target), ("back", target, start)] {
+            let timing = servo.move_to_and_time(joint.id, to, &settle, scale);
+            if let Err(e) = &timing {
+                eprintln!("{} {}: {:#}", joint.name, leg, e);
+            }
+            csv.push(row(&joint.name, joint.id, leg, from, to, &timing));
+        }
+    }
+    robot.hold()?;
+
+    let csv = csv.join("\n") + "\n";
+    match &args.output {
+        Some(path) => fs::write(path, csv).with_context(|| format!("Failed to write {:?}", path))?,
+        None => print!("{}", csv),
+    }
+    Ok(())
+}
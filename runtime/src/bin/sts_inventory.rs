@@ -0,0 +1,28 @@
+use anyhow::Result;
+use std::sync::Arc;
+use runtime::hal::Servo;
+
+// Print a manifest of every servo on the bus, e.g. for RMA tracking
+fn main() -> Result<()> {
+    let servo = Arc::new(Servo::new()?);
+    servo.disable_readout()?;
+
+    println!("{:>4}  {:>6}  {:>8}  {:>10}  Serial", "ID", "Model", "Firmware", "Compatible");
+    for id in 1..=100 {
+        if servo.ping(id).is_err() {
+            continue;
+        }
+        let model = servo.read_model(id).map(|model| format!("{:#06x}", model)).unwrap_or_else(|_| "?".to_string());
+        let firmware = servo.read_firmware_version(id).map(|(major, minor)| format!("{}.{}", major, minor)).unwrap_or_else(|_| "?".to_string());
+        let serial = match servo.read_serial(id) {
+            Ok(Some(serial)) => serial.to_string(),
+            Ok(None) => "unsupported".to_string(),
+            Err(e) => format!("error: {}", e),
+        };
+        let compatible = if servo.check_compatibility(id).is_ok() { "yes" } else { "NO" };
+        println!("{:>4}  {:>6}  {:>8}  {:>10}  {}", id, model, firmware, compatible, serial);
+    }
+
+    servo.enable_readout()?;
+    Ok(())
+}
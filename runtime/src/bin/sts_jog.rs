@@ -0,0 +1,109 @@
+use anyhow::{bail, Result};
+use clap::Parser;
+use cursive::event::{Event, Key};
+use cursive::views::{LinearLayout, Panel, TextView};
+use cursive::traits::*;
+use runtime::builder::RobotBuilder;
+use runtime::commander::{DeadMan, JointCommander};
+use runtime::robot::Robot;
+use std::path::PathBuf;
+use std::time::Duration;
+
+#[derive(Parser, Debug)]
+#[command(author, version, about = "Jog joints with the arrow keys, moving only while a key is held", long_about = None)]
+struct Args {
+    #[arg(short, long, default_value = "config/stompymicro.toml")]
+    config: PathBuf,
+
+    /// Ticks the target moves per key press (or key repeat)
+    #[arg(long, default_value_t = 10)]
+    step: i16,
+
+    /// Most ticks a joint moves per update
+    #[arg(long, default_value_t = 8)]
+    max_rate: u16,
+
+    /// Motion stops this long after the last key press or repeat
+    #[arg(long, default_value_t = 600)]
+    dead_man_ms: u64,
+}
+
+struct Jog {
+    robot: Robot,
+    commander: JointCommander,
+    dead_man: DeadMan,
+    selected: usize,
+    step: i16,
+    status: String,
+}
+
+impl Jog {
+    fn jog(&mut self, direction: i16) -> Result<()> {
+        self.dead_man.assert();
+        let id = self.robot.joints()[self.selected].id;
+        let target = match self.commander.target(id) {
+            Some(target) => target,
+            None => self.robot.servo().read_position(id)?,
+        };
+        self.commander.set_target(id, target.saturating_add(direction * self.step))
+    }
+
+    fn render(&self) -> String {
+        let mut text = String::new();
+        for (i, joint) in self.robot.joints().iter().enumerate() {
+            let marker = if i == self.selected { '>' } else { ' ' };
+            let commanded = self.commander.commanded(joint.id).map_or("----".to_string(), |c| format!("{:4}", c));
+            text.push_str(&format!("{} {:>20} (ID {:2}): {}\n", marker, joint.name, joint.id, commanded));
+        }
+        let state = if self.dead_man.is_held() { "MOVING" } else { "holding" };
+        text.push_str(&format!("\n{}  {}", state, self.status));
+        text
+    }
+}
+
+fn with_jog<F: FnOnce(&mut Jog) -> Result<()>>(s: &mut cursive::Cursive, f: F) {
+    let Some(jog) = s.user_data::<Jog>() else { return };
+    if let Err(e) = f(jog) {
+        jog.status = format!("Error: {}", e);
+    }
+    let text = jog.render();
+    s.call_on_name("joints", |view: &mut TextView| view.set_content(text));
+}
+
+fn main() -> Result<()> {
+    let args = Args::parse();
+    let robot = RobotBuilder::new(&args.config).build()?;
+    if robot.joints().is_empty() {
+        bail!("No joints configured in {:?}", args.config);
+    }
+
+    let dead_man = DeadMan::new(Duration::from_millis(args.dead_man_ms));
+    let mut commander = JointCommander::new(robot.servo().clone(), args.max_rate);
+    commander.set_dead_man(Some(dead_man.clone()));
+
+    let mut siv = cursive::default();
+    siv.add_fullscreen_layer(
+        LinearLayout::vertical()
+            .child(Panel::new(TextView::new("").with_name("joints")).title("Jog"))
+            .child(TextView::new("Up/Down: select joint, hold Left/Right: jog, q: quit")),
+    );
+    siv.set_user_data(Jog { robot, commander, dead_man, selected: 0, step: args.step, status: String::new() });
+    siv.set_fps(50);
+
+    siv.add_global_callback('q', |s| s.quit());
+    siv.add_global_callback(Event::Key(Key::Up), |s| with_jog(s, |jog| {
+        let count = jog.robot.joints().len();
+        jog.selected = (jog.selected + count - 1) % count;
+        Ok(())
+    }));
+    siv.add_global_callback(Event::Key(Key::Down), |s| with_jog(s, |jog| {
+        jog.selected = (jog.selected + 1) % jog.robot.joints().len();
+        Ok(())
+    }));
+    siv.add_global_callback(Event::Key(Key::Left), |s| with_jog(s, |jog| jog.jog(-1)));
+    siv.add_global_callback(Event::Key(Key::Right), |s| with_jog(s, |jog| jog.jog(1)));
+    siv.set_global_callback(Event::Refresh, |s| with_jog(s, |jog| jog.commander.update()));
+
+    siv.run();
+    Ok(())
+}
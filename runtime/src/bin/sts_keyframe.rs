@@ -0,0 +1,108 @@
+use anyhow::Result;
+use clap::{Parser, Subcommand};
+use runtime::builder::RobotBuilder;
+use runtime::hal::ServoMode;
+use runtime::usage::UsageFile;
+use std::path::PathBuf;
+use std::time::Duration;
+
+#[derive(Parser, Debug)]
+#[command(author, version, about = "Save the current pose as a named keyframe, move to one or play a sequence of them", long_about = None)]
+struct Args {
+    #[arg(short, long, default_value = "config/stompymicro.toml")]
+    config: PathBuf,
+
+    #[arg(short, long, default_value = "keyframes.json")]
+    keyframes: PathBuf,
+
+    /// Count the moves of every joint in this usage file
+    #[arg(short, long)]
+    usage: Option<PathBuf>,
+
+    /// Move joints even if they were never calibrated
+    #[arg(long)]
+    force: bool,
+
+    /// Cap speeds, torque and travel to the config's [safe_mode] values
+    #[arg(long)]
+    safe_mode: bool,
+
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    Save {
+        name: String,
+    },
+    Goto {
+        name: String,
+
+        /// Duration of the move in ms
+        #[arg(short, long, default_value_t = 1000)]
+        duration: u64,
+    },
+    // Move to a keyframe and keep re-commanding it until Ctrl-C
+    Hold {
+        name: String,
+
+        #[arg(short, long, default_value_t = 1000)]
+        duration: u64,
+
+        /// Re-command the pose this often in ms
+        #[arg(short, long, default_value_t = 500)]
+        interval: u64,
+    },
+    // Ctrl-C holds the pose the robot is in
+    Play {
+        sequence: PathBuf,
+    },
+}
+
+fn main() -> Result<()> {
+    let args = Args::parse();
+    let robot = RobotBuilder::new(&args.config)
+        .emergency_stop(|press| println!("\n{}", press))
+        .allow_uncalibrated(args.force)
+        .mode(ServoMode::Position)
+        .safe_mode(args.safe_mode)
+        .build()?;
+
+    match args.command {
+        Command::Save { name } => {
+            let (keyframe, replaced) = robot.save_keyframe(&args.keyframes, &name)?;
+            if replaced.is_some() {
+                println!("Replaced keyframe {}", name);
+            }
+            for (joint, degrees) in &keyframe.joints {
+                println!("{:>20}: {:7.2}°", joint, degrees);
+            }
+            println!("Saved keyframe {} to {:?}", name, args.keyframes);
+        }
+        Command::Goto { name, duration } => {
+            let moved = robot.goto_keyframe(&args.keyframes, &name, Duration::from_millis(duration))?;
+            println!("Moving to keyframe {}", name);
+            if let Some(path) = &args.usage {
+                let mut usage = UsageFile::load_or_default(path)?;
+                usage.record_moves(&moved);
+                usage.save(path)?;
+            }
+        }
+        Command::Hold { name, duration, interval } => {
+            let moved = robot.goto_keyframe(&args.keyframes, &name, Duration::from_millis(duration))?;
+            println!("Holding keyframe {}, Ctrl-C to stop", name);
+            let sent = robot.hold_pose(&moved, Duration::from_millis(interval))?;
+            println!("Stopped after {} re-commands", sent);
+        }
+        Command::Play { sequence } => {
+            let finished = robot.play_sequence(&sequence, robot.running(), |keyframe| {
+                println!("Moving to keyframe {}", keyframe.name);
+            })?;
+            if !finished {
+                println!("Sequence interrupted, holding the current pose");
+            }
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,44 @@
+use anyhow::Result;
+use clap::Parser;
+use runtime::builder::RobotBuilder;
+use runtime::usage::UsageFile;
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime};
+
+#[derive(Parser, Debug)]
+#[command(author, version, about = "List when every joint was last calibrated and how often it has moved", long_about = None)]
+struct Args {
+    #[arg(short, long, default_value = "config/stompymicro.toml")]
+    config: PathBuf,
+
+    #[arg(short, long, default_value = "usage.json")]
+    usage: PathBuf,
+
+    /// Joints calibrated longer ago than this are flagged for recalibration
+    #[arg(long, default_value_t = 90)]
+    max_age_days: u64,
+}
+
+fn main() -> Result<()> {
+    let args = Args::parse();
+    let robot = RobotBuilder::new(&args.config).build()?;
+    let usage = UsageFile::load_or_default(&args.usage)?;
+    let max_age = Duration::from_secs(args.max_age_days * 24 * 60 * 60);
+
+    let report = robot.maintenance_report(&usage, max_age);
+    let now = SystemTime::now();
+    for entry in &report {
+        let calibrated = match entry.usage.since_calibration(now) {
+            Some(age) => format!("{} days ago", age.as_secs() / (24 * 60 * 60)),
+            None => "never".to_string(),
+        };
+        let flag = if entry.overdue { "  OVERDUE" } else { "" };
+        println!("{:>20} (ID {:2}): calibrated {}, {} moves{}", entry.name, entry.id, calibrated, entry.usage.moves, flag);
+    }
+
+    let overdue = report.iter().filter(|entry| entry.overdue).count();
+    if overdue > 0 {
+        println!("{} of {} joints are due for recalibration", overdue, report.len());
+    }
+    Ok(())
+}
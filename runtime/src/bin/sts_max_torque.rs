@@ -0,0 +1,34 @@
+use anyhow::Result;
+use clap::Parser;
+use runtime::hal::{Servo, ServoRegister};
+use runtime::torque::MAX_TORQUE;
+
+#[derive(Parser, Debug)]
+#[command(author, version, about = "Show or set the maximum torque a servo puts out", long_about = None)]
+struct Args {
+    id: u8,
+
+    /// New cap, 0-1000 of the stall torque
+    #[arg(long)]
+    set: Option<u16>,
+}
+
+fn main() -> Result<()> {
+    let args = Args::parse();
+    let servo = Servo::new()?;
+
+    servo.with_readout_disabled(|servo| {
+        if let Some(value) = args.set {
+            servo.write_max_torque(args.id, value)?;
+            println!("Servo {} max torque set to {}/{}", args.id, value, MAX_TORQUE);
+        }
+
+        let max_torque = servo.read_max_torque(args.id)?;
+        let limit = servo.read_u16(args.id, ServoRegister::TorqueLimit)?;
+        println!(
+            "Servo {}: max torque {}/{} ({:.0}% of stall), present torque limit {}",
+            args.id, max_torque, MAX_TORQUE, max_torque as f32 / 10.0, limit
+        );
+        Ok(())
+    })
+}
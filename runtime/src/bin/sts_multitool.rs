@@ -427,9 +427,8 @@ fn update_angle_limits(s: &mut cursive::Cursive, servo_id: u8, servo: &Arc<Servo
     }
 
     // Update offset
-    match servo.read(servo_id, ServoRegister::PositionCorrection, 2) {
-        Ok(data) => {
-            let offset = i16::from_le_bytes([data[0], data[1]]);
+    match servo.read_offset(servo_id) {
+        Ok(offset) => {
             s.call_on_name("Offset", |view: &mut TextView| {
                 view.set_content(format!("Offset: {}", offset));
             });
@@ -688,8 +687,7 @@ fn write_calibration_to_eeprom(servo_id: u8, servo: &Servo, offset: u16, min_ang
     for try_num in 0..3 {
         servo.write_servo_memory(servo_id, ServoRegister::MinAngleLimit, min_angle as u16)?;
         std::thread::sleep(Duration::from_millis(20));
-        let read_min = servo.read(servo_id, ServoRegister::MinAngleLimit, 2)?;
-        let read_min = u16::from_le_bytes([read_min[0], read_min[1]]);
+        let read_min = servo.read_u16(servo_id, ServoRegister::MinAngleLimit)?;
         if read_min == min_angle as u16 {
             break;
         }
@@ -701,8 +699,7 @@ fn write_calibration_to_eeprom(servo_id: u8, servo: &Servo, offset: u16, min_ang
     for try_num in 0..3 {
         servo.write_servo_memory(servo_id, ServoRegister::MaxAngleLimit, max_angle as u16)?;
         std::thread::sleep(Duration::from_millis(20));
-        let read_max = servo.read(servo_id, ServoRegister::MaxAngleLimit, 2)?;
-        let read_max = u16::from_le_bytes([read_max[0], read_max[1]]);
+        let read_max = servo.read_u16(servo_id, ServoRegister::MaxAngleLimit)?;
         if read_max == max_angle as u16 {
             break;
         }
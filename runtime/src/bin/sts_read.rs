@@ -1,6 +1,7 @@
 use anyhow::{Result, bail};
 use ctrlc;
 use runtime::hal::{Servo, ServoRegister};
+use runtime::servo::{decode_pwm, decode_speed, parse_model_scale, ModelScaling};
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
 use std::thread;
@@ -15,6 +16,18 @@ fn main() -> Result<()> {
         Some(arg) => arg.parse().map_err(|_| anyhow::anyhow!("Invalid servo ID"))?,
         None => bail!("Servo ID must be specified as a command-line argument"),
     };
+    // Then any number of --current-scale MODEL=SCALE, see ModelScaling
+    let mut current_scaling = ModelScaling::current();
+    let mut rest = args[2.min(args.len())..].iter();
+    while let Some(arg) = rest.next() {
+        match (arg.as_str(), rest.next()) {
+            ("--current-scale", Some(spec)) => {
+                let (model, scale) = parse_model_scale(spec)?;
+                current_scaling.set_model(model, scale);
+            }
+            _ => bail!("Usage: sts_read <servo ID> [--current-scale MODEL=SCALE]..."),
+        }
+    }
 
     let servo = Arc::new(Servo::new()?);
     let running = Arc::new(AtomicBool::new(true));
@@ -36,7 +49,7 @@ fn main() -> Result<()> {
     while running.load(Ordering::SeqCst) {
         let start = Instant::now();
 
-        match read_servo_info(&servo, servo_id) {
+        match read_servo_info(&servo, servo_id, &current_scaling) {
             Ok(info) => {
                 println!(
                     "Position: {}, Speed: {}, Load: {}, Current: {} mA",
@@ -70,16 +83,17 @@ struct ServoInfo {
     current: f32,
 }
 
-fn read_servo_info(servo: &Servo, id: u8) -> Result<ServoInfo> {
-    let position = servo.read(id, ServoRegister::CurrentLocation, 2)?;
-    let speed = servo.read(id, ServoRegister::CurrentSpeed, 2)?;
-    let load = servo.read(id, ServoRegister::CurrentLoad, 2)?;
-    let current = servo.read(id, ServoRegister::CurrentCurrent, 2)?;
+fn read_servo_info(servo: &Servo, id: u8, current_scaling: &ModelScaling) -> Result<ServoInfo> {
+    let position = servo.read_u16(id, ServoRegister::CurrentLocation)?;
+    let speed = servo.read_u16(id, ServoRegister::CurrentSpeed)?;
+    let load = servo.read_u16(id, ServoRegister::CurrentLoad)?;
+    let current = servo.read_u16(id, ServoRegister::CurrentCurrent)?;
+    let current_scale = servo.read_scale(id, current_scaling)?;
 
     Ok(ServoInfo {
-        position: u16::from_le_bytes([position[0], position[1]]),
-        speed: i16::from_le_bytes([speed[0], speed[1]]),
-        load: i16::from_le_bytes([load[0], load[1]]),
-        current: u16::from_le_bytes([current[0], current[1]]) as f32 * 6.5 / 100.0,
+        position,
+        speed: decode_speed(speed),
+        load: decode_pwm(load),
+        current: current as f32 * current_scale,
     })
 }
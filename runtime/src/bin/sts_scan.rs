@@ -1,6 +1,6 @@
 use anyhow::Result;
 use std::sync::Arc;
-use runtime::hal::{Servo, ServoRegister};
+use runtime::hal::{Servo, ServoError};
 
 fn main() -> Result<()> {
     let servo = Arc::new(Servo::new()?);
@@ -8,10 +8,12 @@ fn main() -> Result<()> {
     servo.disable_readout()?;
 
     for id in 1..=100 {
-        match scan_servo(&servo, id) {
-            Ok(true) => println!("Servo found at ID: {}", id),
-            Ok(false) => (), // No servo at this ID, continue silently
-            Err(e) => eprintln!("Error scanning ID {}: {}", id, e),
+        match servo.ping(id) {
+            Ok(rtt) => println!("Servo found at ID: {} ({:.2} ms)", id, rtt.as_secs_f64() * 1000.0),
+            Err(e) => match e.downcast_ref::<ServoError>() {
+                Some(ServoError::Timeout { .. }) => (), // No servo at this ID, continue silently
+                _ => eprintln!("Error scanning ID {}: {}", id, e),
+            },
         }
     }
 
@@ -19,12 +21,3 @@ fn main() -> Result<()> {
     println!("Scan complete.");
     Ok(())
 }
-
-fn scan_servo(servo: &Arc<Servo>, id: u8) -> Result<bool> {
-    // Try to read the servo ID from memory address 0x5 (ServoRegister::ID)
-    match servo.read(id, ServoRegister::ID, 1) {
-        Ok(data) if data.len() == 1 && data[0] == id => Ok(true),
-        Ok(_) => Ok(false), // Received data, but it doesn't match the ID
-        Err(_) => Ok(false), // No response, assume no servo at this ID
-    }
-}
@@ -2,7 +2,7 @@ use tonic::{transport::Server, Request, Response, Status};
 use tower_http::cors::CorsLayer;
 use std::sync::Arc;
 use tokio::sync::Mutex;
-use anyhow::Result;
+use anyhow::{Context, Result};
 use std::fs::File;
 use std::io::Write;
 use std::path::Path;
@@ -13,7 +13,11 @@ use std::sync::atomic::{AtomicBool, Ordering};
 use tokio::task;
 use std::time::Duration;
 use std::env;
-use runtime::hal::{Servo, IMU, MAX_SERVOS, ServoMultipleWriteCommand, ServoData, ServoMode, ServoDirection, ServoRegister, TorqueMode};
+use runtime::hal::{Servo, IMU, MAX_SERVOS, ServoMultipleWriteCommand, ServoData, ServoRegister, TorqueMode};
+use runtime::calibration::{self, Backoff, CalibrationEvents, CalibrationParams, CalibrationWrites, InterruptAction, ProgressReport, SweepProgress, StopDetection, TripRule, DEFAULT_MAX_TRAVEL, DEFAULT_REVERSAL_BLANKING};
+use runtime::servo::{ModelResolution, ModelScaling, ReadingChecks, SettleConfig};
+use runtime::units::ticks_to_deg;
+use runtime::watchdog::Watchdog;
 use std::collections::HashMap;
 use tokio::sync::RwLock;
 use uuid::Uuid;
@@ -28,7 +32,17 @@ pub mod servo_control {
 }
 
 use servo_control::servo_control_server::{ServoControl, ServoControlServer};
-use servo_control::{Empty, JointPositions, WifiCredentials, ServoId, ServoInfo, ServoIds, IdChange, ChangeIdResponse, ServoInfoResponse, servo_info_response, change_id_response, VideoStreamUrls, CalibrationResponse, CalibrationStatus, TorqueSettings, TorqueEnableSettings, ImuData, Vector3, AudioChunk, UploadResponse, PlayRequest, RecordingConfig, CalibrationRequest};
+use servo_control::{Empty, JointPositions, WifiCredentials, ServoId, ServoInfo, ServoIds, IdChange, ChangeIdResponse, ServoInfoResponse, servo_info_response, change_id_response, VideoStreamUrls, CalibrationResponse, CalibrationStatus, TorqueSettings, TorqueEnableSettings, ImuData, Vector3, AudioChunk, UploadResponse, PlayRequest, RecordingConfig, CalibrationRequest, HeartbeatResponse, HealthReport, ServoHealth};
+
+// Once a controller sends its first Heartbeat it has to keep sending them at
+// least every HEARTBEAT_TIMEOUT_MS, otherwise movement is disabled as by
+// DisableMovement so the robot doesn't keep acting on a lost controller's
+// last command. Movement stays disabled until EnableMovement is called.
+const DEFAULT_HEARTBEAT_TIMEOUT: Duration = Duration::from_millis(1000);
+
+// Half a turn between the stops, about what the joints cover, for
+// CalibrationStatus.progress
+const CALIBRATION_EXPECTED_TRAVEL: u32 = 2048;
 
 #[derive(Debug)]
 pub struct StsServoControl {
@@ -36,26 +50,51 @@ pub struct StsServoControl {
     imu: Arc<Mutex<Option<IMU>>>,
     last_positions: Arc<Mutex<ServoData>>,
     calibrating_servo: Arc<Mutex<Option<u8>>>,
+    calibration_progress: Arc<Mutex<Option<SweepProgress>>>,
     calibration_running: Arc<AtomicBool>,
     audio_files: Arc<RwLock<HashMap<String, PathBuf>>>,
     recording_running: Arc<AtomicBool>,
+    watchdog: Watchdog,
+    // Model of each servo by ID, see get_health
+    models: Arc<Mutex<HashMap<u8, u16>>>,
 }
 
 impl StsServoControl {
     pub fn new() -> Result<Self> {
         let servo = Servo::new()?;
+        // Keeps calibration runs from other processes off the bus while
+        // telemetry polls it
+        servo.claim_bus("telemetry server")?;
         let imu = IMU::new().ok();
         servo.enable_readout()?;
         let initial_data = servo.read_continuous()?;
+        let servo = Arc::new(Mutex::new(servo));
+
+        let heartbeat_timeout = match env::var("HEARTBEAT_TIMEOUT_MS") {
+            Ok(ms) => Duration::from_millis(ms.parse().context("Failed to parse HEARTBEAT_TIMEOUT_MS")?),
+            Err(_) => DEFAULT_HEARTBEAT_TIMEOUT,
+        };
+        let watchdog = {
+            let servo = servo.clone();
+            Watchdog::new(heartbeat_timeout, move || {
+                eprintln!("No heartbeat for {:?}, disabling movement", heartbeat_timeout);
+                if let Err(e) = servo.blocking_lock().disable_movement() {
+                    eprintln!("Failed to disable movement: {}", e);
+                }
+            })?
+        };
         
         Ok(Self {
-            servo: Arc::new(Mutex::new(servo)),
+            servo,
             imu: Arc::new(Mutex::new(imu)),
             last_positions: Arc::new(Mutex::new(initial_data)),
             calibrating_servo: Arc::new(Mutex::new(None)),
+            calibration_progress: Arc::new(Mutex::new(None)),
             calibration_running: Arc::new(AtomicBool::new(false)),
             audio_files: Arc::new(RwLock::new(HashMap::new())),
             recording_running: Arc::new(AtomicBool::new(false)),
+            watchdog,
+            models: Arc::new(Mutex::new(HashMap::new())),
         })
     }
 
@@ -76,173 +115,55 @@ impl StsServoControl {
     async fn calibrate_servo(&self, servo_id: u8, calibration_speed: u16, current_threshold: f32) -> Result<(), Status> {
         let servo = self.servo.clone();
         let calibrating_servo = self.calibrating_servo.clone();
+        let calibration_progress = self.calibration_progress.clone();
         let calibration_running = self.calibration_running.clone();
 
-        task::spawn(async move {
-            let servo = servo.lock().await;
+        task::spawn_blocking(move || {
+            let servo = servo.blocking_lock();
 
             servo.disable_movement().unwrap();
 
-
-            servo.disable_readout().unwrap();
-            servo.set_mode(servo_id, ServoMode::ConstantSpeed).unwrap();
-
-            servo.write_servo_memory(servo_id, runtime::hal::ServoRegister::TorqueLimit, 150).unwrap();
-
-            let mut max_forward = 0;
-            let mut max_backward = 0;
-
-            let version = servo.read(servo_id, ServoRegister::ServoMainVersion, 2).unwrap();
-            let mut current_multiplier = 1.0;
-
-            // sts3215
-            if version[0] == 0x09 && version[1] == 0x03 {
-                current_multiplier = 6.5;
-            }
-
-            for pass in 0..2 {
-                let direction = if pass == 0 { ServoDirection::Clockwise } else { ServoDirection::Counterclockwise };
-                servo.set_speed(servo_id, calibration_speed, direction).unwrap();
-
-                let mut threshold_exceeded_count = 0;
-
-                loop {
-                    if !calibration_running.load(Ordering::SeqCst) {
-                        servo.set_speed(servo_id, 0, ServoDirection::Clockwise).unwrap();
-                        return;
-                    }
-
-                    let mut info = servo.read_info(servo_id).unwrap();
-                    let mut retry_count = 0;
-                    while info.current_current == 0 && info.current_location == 0 && retry_count < 3 {
-                        tokio::time::sleep(Duration::from_millis(1)).await;
-                        info = servo.read_info(servo_id).unwrap();
-                        retry_count += 1;
-                    }
-                    let position = info.current_location;
-                    let current = info.current_current as f32 * 6.5 / 100.0 * current_multiplier;
-                    
-                    if current > current_threshold {
-                        threshold_exceeded_count += 1;
-                        
-                        if threshold_exceeded_count >= 3 {
-                            for _ in 0..3 { 
-                                tokio::time::sleep(Duration::from_millis(10)).await;
-                                servo.set_speed(servo_id, 0, direction).unwrap();
-                            }
-                            tokio::time::sleep(Duration::from_millis(100)).await;
-
-                            servo.set_speed(servo_id, calibration_speed, opposite_direction(direction)).unwrap();
-                            tokio::time::sleep(Duration::from_millis(350)).await;
-
-                            servo.set_speed(servo_id, 0, opposite_direction(direction)).unwrap();
-                            tokio::time::sleep(Duration::from_millis(100)).await;
-
-                            let info = servo.read_info(servo_id).unwrap();
-
-                            if direction == ServoDirection::Clockwise {
-                                max_forward = info.current_location;
-                            } else {
-                                max_backward = info.current_location;
-                            }
-
-                            break;
-                        }
-                    } else {
-                        threshold_exceeded_count = 0;
-                    }
-
-                    tokio::time::sleep(Duration::from_millis(10)).await;
-                }
-
-                if pass < 1 {
-                    tokio::time::sleep(Duration::from_millis(500)).await;
-                }
+            let params = CalibrationParams {
+                speed: calibration_speed,
+                current_threshold,
+                current_scaling: ModelScaling::current(),
+                settle: SettleConfig::default(),
+                backoff: Backoff::default(),
+                writes: CalibrationWrites::All,
+                approach: None,
+                escalation: None,
+                on_interrupt: InterruptAction::Stop,
+                trip: TripRule::default(),
+                deceleration: None,
+                keep_torque: false,
+                max_travel: Some(DEFAULT_MAX_TRAVEL),
+                center_start: None,
+                reading_checks: ReadingChecks::default(),
+                poll: None,
+                verify_calibration: None,
+                stop_samples: 1,
+                strict_offset: false,
+                resolution: ModelResolution::sts(),
+                reversal_blanking: DEFAULT_REVERSAL_BLANKING,
+                progress: Some(ProgressReport::new(CALIBRATION_EXPECTED_TRAVEL, {
+                    let calibration_progress = calibration_progress.clone();
+                    move |progress| *calibration_progress.blocking_lock() = Some(*progress)
+                })),
+                detection: StopDetection::default(),
+                events: Some(CalibrationEvents::new(|event| println!("{}", event))),
+            };
+            if let Err(e) = calibration::calibrate_servo(&servo, servo_id, &params, &calibration_running) {
+                eprintln!("Calibration of servo {} failed: {:#}", servo_id, e);
             }
 
-            servo.write(servo_id, ServoRegister::LockMark, &[0]).unwrap();
-            servo.set_speed(servo_id, 0, ServoDirection::Clockwise).unwrap();
-            servo.write_servo_memory(servo_id, ServoRegister::TorqueLimit, 600).unwrap();
-            servo.set_mode(servo_id, ServoMode::Position).unwrap();
-            servo.write(servo_id, ServoRegister::LockMark, &[1]).unwrap();
-
-            servo.enable_readout().unwrap();
-            *calibrating_servo.lock().await = None;
+            *calibrating_servo.blocking_lock() = None;
+            *calibration_progress.blocking_lock() = None;
             calibration_running.store(false, Ordering::SeqCst);
-            Self::calculate_and_write_calibration(servo_id, &servo, max_backward, max_forward).await.unwrap();
         });
 
         Ok(())
     }
 
-    async fn calculate_and_write_calibration(servo_id: u8, servo: &Servo, min_pos: i16, max_pos: i16) -> Result<(), Status> {
-        let mut max_pos = max_pos;
-
-        if max_pos < min_pos {
-            max_pos += 4096;
-        }
-    
-        let offset_value = min_pos + (max_pos - min_pos) / 2 - 2048;
-    
-        // Convert offset to 12-bit signed value
-        let offset_value = if offset_value < 0 {
-            offset_value.abs() as u16 | 0x800 // (set negative bit)
-        } else {
-            if offset_value > 2048 {
-                (offset_value - 4096).abs() as u16 | 0x800
-            } else {
-                offset_value as u16
-            }
-        };
-    
-        // Calculate new limits
-        let min_angle = 2048 - (max_pos - min_pos) / 2;
-        let max_angle = 2048 + (max_pos - min_pos) / 2;
-
-        println!("Writing calibration, offset: {}, min_angle: {}, max_angle: {}", offset_value, min_angle, max_angle);
-
-        // Unlock EEPROM
-        servo.write(servo_id, ServoRegister::LockMark, &[0])
-            .map_err(|e| Status::internal(format!("Failed to unlock EEPROM: {}", e)))?;
-        tokio::time::sleep(Duration::from_millis(20)).await;
-
-        // Write new offset
-        servo.write_servo_memory(servo_id, ServoRegister::PositionCorrection, offset_value)
-            .map_err(|e| Status::internal(format!("Failed to write offset: {}", e)))?;
-        tokio::time::sleep(Duration::from_millis(20)).await;
-
-        // Write new limits
-        for _ in 0..3 {
-            servo.write_servo_memory(servo_id, ServoRegister::MinAngleLimit, min_angle as u16)
-                .map_err(|e| Status::internal(format!("Failed to write MinAngleLimit: {}", e)))?;
-            tokio::time::sleep(Duration::from_millis(20)).await;
-            let read_min = servo.read(servo_id, ServoRegister::MinAngleLimit, 2)
-                .map_err(|e| Status::internal(format!("Failed to read MinAngleLimit: {}", e)))?;
-            let read_min = u16::from_le_bytes([read_min[0], read_min[1]]);
-            if read_min == min_angle as u16 {
-                break;
-            }
-        }
-
-        for _ in 0..3 {
-            servo.write_servo_memory(servo_id, ServoRegister::MaxAngleLimit, max_angle as u16)
-                .map_err(|e| Status::internal(format!("Failed to write MaxAngleLimit: {}", e)))?;
-            tokio::time::sleep(Duration::from_millis(20)).await;
-            let read_max = servo.read(servo_id, ServoRegister::MaxAngleLimit, 2)
-                .map_err(|e| Status::internal(format!("Failed to read MaxAngleLimit: {}", e)))?;
-            let read_max = u16::from_le_bytes([read_max[0], read_max[1]]);
-            if read_max == max_angle as u16 {
-                break;
-            }
-        }
-
-        // Lock EEPROM
-        servo.write(servo_id, ServoRegister::LockMark, &[1])
-            .map_err(|e| Status::internal(format!("Failed to lock EEPROM: {}", e)))?;
-
-        Ok(())
-    }
-
     fn is_process_running(process_name: &str) -> bool {
         Command::new("ps")
             .args(&["-A"])
@@ -321,12 +242,7 @@ impl ServoControl for StsServoControl {
                 .map(|(id, info)| servo_control::JointPosition {
                     id: (id + 1) as i32, // Add 1 to make IDs start from 1
                     position: Servo::raw_to_degrees(info.current_location as u16),
-                    speed: {
-                        let speed_raw = info.current_speed as u16;
-                        let speed_magnitude = speed_raw & 0x7FFF; // Remove 15th bit
-                        let speed_sign = if speed_raw & 0x8000 != 0 { -1.0 } else { 1.0 };
-                        speed_sign * (speed_magnitude as f32 * 360.0 / 4096.0)
-                    },
+                    speed: ticks_to_deg(info.speed() as i32),
                 })
                 .collect(),
         };
@@ -428,11 +344,7 @@ network={{
         let min_position = Servo::raw_to_degrees(min_position as u16);
         let max_position = Servo::raw_to_degrees(max_position as u16);
         
-        // Handle speed conversion
-        let speed_raw = servo_info.current_speed as u16;
-        let speed_magnitude = speed_raw & 0x7FFF; // Remove 15th bit
-        let speed_sign = if speed_raw & 0x8000 != 0 { -1.0 } else { 1.0 };
-        let speed = speed_sign * (speed_magnitude as f32 * 360.0 / 4096.0);
+        let speed = ticks_to_deg(servo_info.speed() as i32);
 
         let info = ServoInfo {
             id: id as i32,
@@ -601,9 +513,12 @@ network={{
 
     async fn get_calibration_status(&self, _request: Request<Empty>) -> Result<Response<CalibrationStatus>, Status> {
         let calibrating_servo = self.calibrating_servo.lock().await;
+        let progress = *self.calibration_progress.lock().await;
         Ok(Response::new(CalibrationStatus {
             is_calibrating: calibrating_servo.is_some(),
             calibrating_servo_id: calibrating_servo.unwrap_or(0) as i32,
+            pass: progress.map_or(0, |progress| progress.pass as u32),
+            progress: progress.map_or(0.0, |progress| progress.overall()),
         }))
     }
 
@@ -813,6 +728,32 @@ network={{
         Ok(Response::new(Empty {}))
     }
 
+    async fn heartbeat(&self, _request: Request<Empty>) -> Result<Response<HeartbeatResponse>, Status> {
+        self.watchdog.feed();
+        Ok(Response::new(HeartbeatResponse {
+            timeout_ms: self.watchdog.timeout().as_millis() as u32,
+        }))
+    }
+
+    async fn get_health(&self, _request: Request<Empty>) -> Result<Response<HealthReport>, Status> {
+        let servo = self.servo.lock().await;
+        let mut models = self.models.lock().await;
+        let ids: Vec<u8> = (0..MAX_SERVOS as u8).collect();
+        let readings = servo.read_health(&ids, &ModelScaling::current(), &mut models).map_err(|e| Status::internal(e.to_string()))?;
+
+        let servos = readings.into_iter()
+            .filter_map(|(id, reading)| reading.map(|reading| ServoHealth {
+                id: id as i32,
+                temperature: reading.celsius,
+                current: reading.milliamps,
+                voltage: reading.volts,
+                status: reading.status.bits() as u32,
+                faults: reading.status.to_string(),
+            }))
+            .collect();
+        Ok(Response::new(HealthReport { servos }))
+    }
+
     async fn set_position(&self, request: Request<servo_control::JointPosition>) -> Result<Response<Empty>, Status> {
         let position = request.into_inner();
         let servo = self.servo.lock().await;
@@ -830,13 +771,6 @@ network={{
     }
 }
 
-fn opposite_direction(direction: ServoDirection) -> ServoDirection {
-    match direction {
-        ServoDirection::Clockwise => ServoDirection::Counterclockwise,
-        ServoDirection::Counterclockwise => ServoDirection::Clockwise,
-    }
-}
-
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
     let addr = "0.0.0.0:50051".parse()?;
@@ -0,0 +1,23 @@
+use anyhow::Result;
+use clap::Parser;
+use runtime::hal::Servo;
+use runtime::units::ticks_to_deg;
+
+#[derive(Parser, Debug)]
+#[command(author, version, about = "Show the calibration offset stored in a servo", long_about = None)]
+struct Args {
+    id: u8,
+}
+
+fn main() -> Result<()> {
+    let args = Args::parse();
+    let servo = Servo::new()?;
+
+    servo.disable_readout()?;
+    let offset = servo.read_offset(args.id);
+    servo.enable_readout()?;
+    let offset = offset?;
+
+    println!("Servo {} offset: {} ticks ({:.2} degrees)", args.id, offset, ticks_to_deg(offset as i32));
+    Ok(())
+}
@@ -0,0 +1,41 @@
+use anyhow::Result;
+use clap::Parser;
+use runtime::builder::RobotBuilder;
+use runtime::calibration::CalibrationFile;
+use std::path::PathBuf;
+
+#[derive(Parser, Debug)]
+#[command(author, version, about = "Trim the offset of a joint and, mirrored, of its partner on the other side", long_about = None)]
+struct Args {
+    #[arg(short, long, default_value = "config/stompymicro.toml")]
+    config: PathBuf,
+
+    /// Updated with both trimmed calibrations
+    #[arg(long, default_value = "calibration.json")]
+    calibration: PathBuf,
+
+    /// Joint to trim, its partner comes from robot.mirrored in the config
+    #[arg(short, long)]
+    joint: String,
+
+    /// Offset trim in ticks, the partner gets the mirrored trim
+    #[arg(short, long, allow_hyphen_values = true)]
+    trim: i16,
+}
+
+fn main() -> Result<()> {
+    let args = Args::parse();
+    let robot = RobotBuilder::new(&args.config).build()?;
+    let mut file = CalibrationFile::load(&args.calibration)?;
+    robot.check_calibration_file(&file)?;
+
+    let trimmed = robot.trim_mirrored(&args.joint, args.trim, &mut file)?;
+    file.save(&args.calibration)?;
+    for (name, calibration) in &trimmed {
+        println!(
+            "{:>20}: offset {}, limits {}-{}",
+            name, calibration.offset, calibration.min_angle, calibration.max_angle
+        );
+    }
+    Ok(())
+}
@@ -0,0 +1,39 @@
+use anyhow::Result;
+use clap::Parser;
+use runtime::hal::Servo;
+use runtime::voltage::VoltageWindow;
+
+#[derive(Parser, Debug)]
+#[command(author, version, about = "Show or set the input voltage window outside which a servo shuts down", long_about = None)]
+struct Args {
+    id: u8,
+
+    /// New window in V, both have to be given
+    #[arg(long, requires = "max")]
+    min: Option<f32>,
+
+    #[arg(long, requires = "min")]
+    max: Option<f32>,
+}
+
+fn main() -> Result<()> {
+    let args = Args::parse();
+    let servo = Servo::new()?;
+
+    servo.with_readout_disabled(|servo| {
+        if let (Some(min), Some(max)) = (args.min, args.max) {
+            let window = VoltageWindow::from_volts(min, max)?;
+            servo.write_voltage_window(args.id, &window)?;
+            println!("Servo {} voltage window set to {:.1}-{:.1} V", args.id, window.min_volts(), window.max_volts());
+        }
+
+        let window = servo.read_voltage_window(args.id)?;
+        let voltage = servo.read_voltage(args.id)?;
+        let state = if window.contains(voltage) { "inside" } else { "OUTSIDE" };
+        println!(
+            "Servo {}: window {:.1}-{:.1} V, supply {:.1} V ({} the window)",
+            args.id, window.min_volts(), window.max_volts(), voltage, state
+        );
+        Ok(())
+    })
+}
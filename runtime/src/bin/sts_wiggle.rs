@@ -0,0 +1,131 @@
+use anyhow::{bail, Result};
+use clap::Parser;
+use runtime::builder::RobotBuilder;
+use runtime::hal::ServoMode;
+use runtime::robot::MissingJoints;
+use runtime::servo::ModelScaling;
+use runtime::trajectory::SineMotion;
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use std::thread::sleep;
+use std::time::{Duration, Instant};
+
+#[derive(Parser, Debug)]
+#[command(author, version, about = "Drive joints through a sine motion for burn-in, logging current and temperature", long_about = None)]
+struct Args {
+    /// Joints to move, all with the same motion around their own center
+    #[arg(required = true)]
+    joints: Vec<String>,
+
+    #[arg(short, long, default_value = "config/stompymicro.toml")]
+    config: PathBuf,
+
+    /// Degrees either side of the center
+    #[arg(short, long, default_value_t = 10.0)]
+    amplitude: f32,
+
+    /// In Hz
+    #[arg(short, long, default_value_t = 0.5)]
+    frequency: f32,
+
+    /// Joint angle in degrees to move around, instead of where each joint is
+    #[arg(long)]
+    center: Option<f32>,
+
+    /// In seconds
+    #[arg(short, long, default_value_t = 60)]
+    duration: u64,
+
+    /// Position updates per second
+    #[arg(long, default_value_t = 50.0)]
+    rate: f32,
+
+    /// Log current and temperature this often, in ms
+    #[arg(long, default_value_t = 1000)]
+    log_ms: u64,
+
+    /// Move joints even if they were never calibrated
+    #[arg(long)]
+    force: bool,
+
+    /// Cap speeds, torque and travel to the config's [safe_mode] values
+    #[arg(long)]
+    safe_mode: bool,
+}
+
+#[derive(Debug, Default)]
+struct Peaks {
+    current: f32,
+    temperature: f32,
+}
+
+fn main() -> Result<()> {
+    let args = Args::parse();
+    if !(args.rate > 0.0 && args.rate.is_finite()) {
+        bail!("Invalid rate {} Hz", args.rate);
+    }
+    let robot = RobotBuilder::new(&args.config)
+        .emergency_stop(|press| println!("\n{}", press))
+        .allow_uncalibrated(args.force)
+        .mode(ServoMode::Position)
+        .safe_mode(args.safe_mode)
+        .build()?;
+    let servo = robot.servo();
+
+    let positions = robot.positions(MissingJoints::Error)?;
+    let mut motions = Vec::with_capacity(args.joints.len());
+    for name in &args.joints {
+        let joint = robot.joint(name)?;
+        let center = args.center.unwrap_or(positions[&joint.name]);
+        motions.push((name.as_str(), joint.id, SineMotion::new(center, args.amplitude, args.frequency)?));
+    }
+
+    let current_scaling = ModelScaling::current();
+    let temperature_scaling = ModelScaling::temperature();
+    let mut peaks: BTreeMap<&str, Peaks> = BTreeMap::new();
+    let period = Duration::from_secs_f32(1.0 / args.rate);
+    let log_every = Duration::from_millis(args.log_ms);
+    let duration = args.duration as f32;
+
+    println!("Moving {} ±{}° at {} Hz for {} s, Ctrl-C to stop", args.joints.join(", "), args.amplitude, args.frequency, args.duration);
+    let start = Instant::now();
+    let mut last_log = start;
+    // Targets are clamped to the calibrated limits by move_group
+    for i in 0.. {
+        let t = i as f32 / args.rate;
+        if t > duration || !robot.is_running() {
+            break;
+        }
+        let targets: Vec<(&str, f32)> = motions.iter().map(|(name, _, motion)| (*name, motion.position(t))).collect();
+        robot.move_group(&targets, period)?;
+
+        if last_log.elapsed() >= log_every {
+            last_log = Instant::now();
+            let mut line = format!("{:7.1} s", t);
+            for (name, id, _) in &motions {
+                let info = servo.read_info(*id)?;
+                let current = info.scaled_current(servo.read_scale(*id, &current_scaling)?);
+                let temperature = info.scaled_temperature(servo.read_scale(*id, &temperature_scaling)?);
+                let peak = peaks.entry(name).or_default();
+                peak.current = peak.current.max(current);
+                peak.temperature = peak.temperature.max(temperature);
+                line.push_str(&format!("  {}: {:6.0} mA {:5.1}°C", name, current, temperature));
+            }
+            println!("{}", line);
+        }
+
+        let due = start + period * (i + 1);
+        if let Some(wait) = due.checked_duration_since(Instant::now()) {
+            sleep(wait);
+        }
+    }
+
+    robot.hold()?;
+    if !robot.is_running() {
+        println!("Interrupted, holding the current pose");
+    }
+    for (name, peak) in &peaks {
+        println!("{:>20}: peak {:.0} mA, {:.1}°C", name, peak.current, peak.temperature);
+    }
+    Ok(())
+}
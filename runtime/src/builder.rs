@@ -0,0 +1,254 @@
+use anyhow::{Result, Context};
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use crate::calibration::CalibrationFile;
+use crate::config::load_config;
+use crate::hal::{Servo, ServoMode};
+use crate::robot::Robot;
+use crate::safemode::SafeMode;
+
+// Everything a tool needs before it can touch the robot, e.g.
+//
+//     let robot = RobotBuilder::new("config/stompymicro.toml")
+//         .calibration("calibration.json")
+//         .emergency_stop(|press| println!("\n{}", press))
+//         .build()?;
+// A Ctrl-C press handled by RobotBuilder::emergency_stop
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StopPress {
+    // The first, Robot::running is being cleared
+    Stop,
+    // Any later one, torque is being cut
+    EmergencyStop,
+}
+
+impl fmt::Display for StopPress {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StopPress::Stop => write!(f, "Interrupt signal received. Stopping... (Ctrl-C again for emergency stop)"),
+            StopPress::EmergencyStop => write!(f, "Emergency stop, disabling torque on all joints"),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct RobotBuilder {
+    config: PathBuf,
+    servo: Option<Arc<Servo>>,
+    calibration: Option<PathBuf>,
+    emergency_stop: Option<fn(StopPress)>,
+    allow_uncalibrated: bool,
+    mode: Option<ServoMode>,
+    safe_mode: bool,
+}
+
+impl RobotBuilder {
+    pub fn new<P: AsRef<Path>>(config: P) -> Self {
+        Self {
+            config: config.as_ref().to_path_buf(),
+            servo: None,
+            calibration: None,
+            emergency_stop: None,
+            allow_uncalibrated: false,
+            mode: None,
+            safe_mode: false,
+        }
+    }
+
+    // Use an already open bus instead of opening the one in the config
+    pub fn servo(mut self, servo: Arc<Servo>) -> Self {
+        self.servo = Some(servo);
+        self
+    }
+
+    // Calibration JSON as written by export_robot_calibration, checked
+    // against the config when building
+    pub fn calibration<P: AsRef<Path>>(mut self, path: P) -> Self {
+        self.calibration = Some(path.as_ref().to_path_buf());
+        self
+    }
+
+    // Ctrl-C clears Robot::running, a second Ctrl-C is the emergency stop
+    // and cuts torque on every joint, see Robot::estop. The handler is
+    // process-wide, so only one robot per process can have it. `on_press`
+    // is told about each press, from the handler's thread.
+    pub fn emergency_stop(mut self, on_press: fn(StopPress)) -> Self {
+        self.emergency_stop = Some(on_press);
+        self
+    }
+
+    // The --force escape hatch: move joints even if they were never
+    // calibrated, see Robot::with_require_calibration
+    pub fn allow_uncalibrated(mut self, allow: bool) -> Self {
+        self.allow_uncalibrated = allow;
+        self
+    }
+
+    // Put every joint in `mode` when building, see Robot::set_all_mode
+    pub fn mode(mut self, mode: ServoMode) -> Self {
+        self.mode = Some(mode);
+        self
+    }
+
+    // Turn on safe mode even if the config leaves it off, with the caps
+    // from the config's [safe_mode]. A config that enables it can't be
+    // overridden from here.
+    pub fn safe_mode(mut self, enable: bool) -> Self {
+        self.safe_mode = enable;
+        self
+    }
+
+    pub fn build(self) -> Result<Robot> {
+        let config = load_config(&self.config)?;
+        let servo = match self.servo {
+            Some(servo) => servo,
+            None => Arc::new(Servo::open(config.bus.port.as_deref(), config.bus.baud_rate)?),
+        };
+        if let Some(path) = &config.bus.trace {
+            servo.set_trace(Some(path))?;
+        }
+        let mut robot = Robot::from_loaded_config(servo, &config)?
+            .with_require_calibration(!self.allow_uncalibrated);
+        if config.robot.preflight {
+            let report = robot.preflight()?;
+            if !report.unexpected.is_empty() {
+                eprintln!("Warning: servos at IDs {:?} answered but aren't in the config", report.unexpected);
+            }
+        }
+        if self.safe_mode && robot.safe_mode().is_none() {
+            robot = robot.with_safe_mode(Some(SafeMode { enabled: true, ..config.safe_mode }));
+        }
+        if let Some(safe_mode) = robot.safe_mode() {
+            eprintln!(
+                "SAFE MODE: speeds capped to {}°/s and {}°/s², torque to {}/1000, joints kept {}° inside their limits",
+                safe_mode.max_velocity, safe_mode.max_acceleration, safe_mode.torque_limit, safe_mode.limit_margin
+            );
+        }
+        robot.apply_torque_limits()?;
+
+        if let Some(path) = &self.calibration {
+            let file = CalibrationFile::load(path)?;
+            robot.check_calibration_file(&file)
+                .with_context(|| format!("Calibration {:?} doesn't match config {:?}", path, self.config))?;
+            robot = robot.with_calibration(file);
+        }
+
+        if let Some(mode) = self.mode {
+            robot.set_all_mode(mode)?;
+        }
+
+        if let Some(on_press) = self.emergency_stop {
+            let running = robot.running().clone();
+            let servo = robot.servo().clone();
+            let ids: Vec<u8> = robot.joints().iter().map(|joint| joint.id).collect();
+            let presses = AtomicUsize::new(0);
+            // The first press only clears the flag, whatever is running stops
+            // at its next check with the joints holding. If that doesn't come
+            // the second press goes limp right away.
+            ctrlc::set_handler(move || {
+                if presses.fetch_add(1, Ordering::SeqCst) == 0 {
+                    on_press(StopPress::Stop);
+                    running.store(false, Ordering::SeqCst);
+                } else {
+                    on_press(StopPress::EmergencyStop);
+                    if let Err(e) = servo.estop_all(&ids) {
+                        eprintln!("{}", e);
+                    }
+                }
+            })
+            .context("Failed to install the Ctrl-C handler")?;
+        }
+        Ok(robot)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn presses_say_what_happens_next() {
+        assert!(StopPress::Stop.to_string().contains("Ctrl-C again"));
+        assert!(StopPress::EmergencyStop.to_string().contains("disabling torque"));
+    }
+
+    #[cfg(not(feature = "milkv"))]
+    mod bus {
+        use super::*;
+        use crate::calibration::{Calibration, JointCalibration};
+        use crate::config::tests::TWO_LEGS;
+        use crate::hal::mock::MockBus;
+        use crate::hal::ServoRegister;
+
+        fn temp_path(name: &str, extension: &str) -> PathBuf {
+            std::env::temp_dir().join(format!("builder-{}-{}.{}", name, std::process::id(), extension))
+        }
+
+        fn config(name: &str) -> PathBuf {
+            let path = temp_path(name, "toml");
+            std::fs::write(&path, TWO_LEGS).unwrap();
+            path
+        }
+
+        fn calibration(name: &str, joints: &[(&str, u8)]) -> PathBuf {
+            let mut file = CalibrationFile::default();
+            for &(joint, id) in joints {
+                let calibration = Calibration { offset: 10, min_angle: 1000, max_angle: 3000 };
+                file.joints.insert(joint.to_string(), JointCalibration { id, calibration, confidence: None });
+            }
+            let path = temp_path(name, "json");
+            file.save(&path).unwrap();
+            path
+        }
+
+        #[test]
+        fn builds_the_configured_robot_in_the_given_mode() {
+            let bus = MockBus::new(&[1, 2]);
+            let path = config("mode");
+            let robot = RobotBuilder::new(&path)
+                .servo(Arc::new(Servo::mock(&bus)))
+                .mode(ServoMode::ConstantSpeed)
+                .build()
+                .unwrap();
+            std::fs::remove_file(&path).unwrap();
+            let names: Vec<&str> = robot.joints().iter().map(|joint| joint.name.as_str()).collect();
+            assert_eq!(names, ["left_hip_pitch", "right_hip_pitch"]);
+            assert!(robot.safe_mode().is_none());
+            for id in [1, 2] {
+                assert_eq!(bus.u8(id, ServoRegister::OperationMode), ServoMode::ConstantSpeed as u8);
+            }
+        }
+
+        #[test]
+        fn loaded_calibration_is_used_instead_of_the_servos() {
+            let bus = MockBus::new(&[1, 2]);
+            let (config, calibration) = (config("loaded"), calibration("loaded", &[("left_hip_pitch", 1), ("right_hip_pitch", 2)]));
+            let robot = RobotBuilder::new(&config).servo(Arc::new(Servo::mock(&bus))).calibration(&calibration).build().unwrap();
+            std::fs::remove_file(&config).unwrap();
+            std::fs::remove_file(&calibration).unwrap();
+            assert_eq!(robot.calibration_of(&robot.joints()[0]).unwrap().offset, 10);
+            assert_eq!(bus.u16(1, ServoRegister::PositionCorrection), 0);
+        }
+
+        #[test]
+        fn calibration_for_another_robot_is_refused() {
+            let bus = MockBus::new(&[1, 2]);
+            let (config, calibration) = (config("mismatch"), calibration("mismatch", &[("left_hip_pitch", 1)]));
+            let error = RobotBuilder::new(&config).servo(Arc::new(Servo::mock(&bus))).calibration(&calibration).build().unwrap_err();
+            std::fs::remove_file(&config).unwrap();
+            std::fs::remove_file(&calibration).unwrap();
+            assert!(format!("{:#}", error).contains("right_hip_pitch"), "{:#}", error);
+        }
+
+        #[test]
+        fn safe_mode_can_be_turned_on_from_the_tool() {
+            let bus = MockBus::new(&[1, 2]);
+            let path = config("safe");
+            let robot = RobotBuilder::new(&path).servo(Arc::new(Servo::mock(&bus))).safe_mode(true).build().unwrap();
+            std::fs::remove_file(&path).unwrap();
+            assert!(robot.safe_mode().is_some_and(|safe_mode| safe_mode.enabled));
+        }
+    }
+}
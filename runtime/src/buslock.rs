@@ -0,0 +1,161 @@
+use anyhow::{Result, Context};
+use std::env;
+use std::fs::{File, OpenOptions, TryLockError};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use crate::hal::{Servo, ServoError};
+
+// Two processes polling the same bus interleave their packets and read each
+// other's replies. Whoever needs the bus to themselves for a while takes an
+// advisory lock on a file named after the bus, in SERVO_LOCK_DIR or /tmp:
+// the telemetry server for as long as it runs, calibration for each run.
+// The OS drops the lock when its holder exits, even on a crash, so a stale
+// file never blocks anyone. Tools that just read or write a register don't
+// take it, it only keeps two long-running users apart.
+#[derive(Debug)]
+pub struct BusLock {
+    // Held open for the lock, which is released when it's closed
+    _file: File,
+    path: PathBuf,
+}
+
+pub fn lock_path(bus: &str) -> PathBuf {
+    let dir = env::var("SERVO_LOCK_DIR").unwrap_or_else(|_| "/tmp".to_string());
+    let name: String = bus.chars().map(|c| if c.is_ascii_alphanumeric() { c } else { '_' }).collect();
+    PathBuf::from(dir).join(format!("servo-bus{}.lock", name))
+}
+
+impl BusLock {
+    // Fails with ServoError::BusClaimed right away if anyone holds the lock, including
+    // another BusLock in this process
+    pub fn acquire(bus: &str, holder: &str) -> Result<Self> {
+        let path = lock_path(bus);
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(&path)
+            .with_context(|| format!("Failed to open bus lock {:?}", path))?;
+        match file.try_lock() {
+            Ok(()) => (),
+            Err(TryLockError::WouldBlock) => {
+                let mut holder = String::new();
+                let _ = file.read_to_string(&mut holder);
+                let holder = match holder.trim() {
+                    "" => "another process".to_string(),
+                    holder => holder.to_string(),
+                };
+                return Err(ServoError::BusClaimed { bus: bus.to_string(), holder }.into());
+            }
+            Err(TryLockError::Error(e)) => return Err(e).with_context(|| format!("Failed to lock {:?}", path)),
+        }
+
+        file.set_len(0)?;
+        file.seek(SeekFrom::Start(0))?;
+        write!(file, "{} (pid {})", holder, std::process::id())?;
+        Ok(Self { _file: file, path })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+// The bus a Servo talks on and the lock it holds on it, if any
+#[derive(Debug)]
+pub struct BusClaim {
+    bus: String,
+    lock: Mutex<Option<BusLock>>,
+}
+
+impl BusClaim {
+    pub fn new(bus: &str) -> Self {
+        Self { bus: bus.to_string(), lock: Mutex::new(None) }
+    }
+}
+
+impl Servo {
+    // Hold the bus lock until release_bus or until this Servo is dropped.
+    // Fails with ServoError::BusClaimed if another process holds it.
+    pub fn claim_bus(&self, holder: &str) -> Result<()> {
+        let mut lock = self.claim.lock.lock().unwrap_or_else(|e| e.into_inner());
+        if lock.is_none() {
+            *lock = Some(BusLock::acquire(&self.claim.bus, holder)?);
+        }
+        Ok(())
+    }
+
+    pub fn release_bus(&self) {
+        *self.claim.lock.lock().unwrap_or_else(|e| e.into_inner()) = None;
+    }
+
+    // Runs `f` under the bus lock, taken for the duration unless this Servo
+    // already holds it, e.g. the telemetry server calibrating through its
+    // own handle. Fails with ServoError::BusClaimed without running `f` if
+    // another process holds it.
+    pub fn with_bus_claimed<T>(&self, holder: &str, f: impl FnOnce(&Servo) -> Result<T>) -> Result<T> {
+        let held = self.claim.lock.lock().unwrap_or_else(|e| e.into_inner()).is_some();
+        let _lock = if held { None } else { Some(BusLock::acquire(&self.claim.bus, holder)?) };
+        f(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lock_file_is_named_after_the_bus() {
+        let path = lock_path("/dev/ttyUSB0");
+        assert_eq!(path.file_name().unwrap(), "servo-bus_dev_ttyUSB0.lock");
+    }
+
+    #[test]
+    fn second_holder_is_told_who_has_the_bus() {
+        let bus = "buslock-test-second-holder";
+        let lock = BusLock::acquire(bus, "telemetry server").unwrap();
+        assert_eq!(lock.path(), lock_path(bus));
+        let error = BusLock::acquire(bus, "calibration").unwrap_err();
+        let holder = format!("telemetry server (pid {})", std::process::id());
+        assert_eq!(error.downcast_ref::<ServoError>(), Some(&ServoError::BusClaimed { bus: bus.to_string(), holder }));
+        assert!(error.to_string().starts_with("telemetry server (pid "), "{}", error);
+
+        // Released on drop, the file left behind doesn't block anyone
+        drop(lock);
+        assert!(BusLock::acquire(bus, "calibration").is_ok());
+    }
+
+    #[cfg(not(feature = "milkv"))]
+    mod bus {
+        use super::*;
+        use crate::hal::mock::MockBus;
+
+        #[test]
+        fn claimed_bus_is_reentrant_for_its_own_servo() {
+            let bus = MockBus::new(&[1]);
+            let servo = Servo::mock(&bus);
+            servo.claim_bus("telemetry server").unwrap();
+            assert_eq!(servo.with_bus_claimed("calibration", |_| Ok(7)).unwrap(), 7);
+            // Still held, by the claim rather than the run
+            assert!(BusLock::acquire(&bus.name(), "other").is_err());
+            servo.release_bus();
+            assert!(BusLock::acquire(&bus.name(), "other").is_ok());
+        }
+
+        #[test]
+        fn busy_bus_refuses_to_run() {
+            let bus = MockBus::new(&[1]);
+            let servo = Servo::mock(&bus);
+            let _lock = BusLock::acquire(&bus.name(), "telemetry server").unwrap();
+            let mut ran = false;
+            let error = servo.with_bus_claimed("calibration", |_| {
+                ran = true;
+                Ok(())
+            }).unwrap_err();
+            assert!(matches!(error.downcast_ref::<ServoError>(), Some(ServoError::BusClaimed { .. })), "{}", error);
+            assert!(!ran);
+        }
+    }
+}
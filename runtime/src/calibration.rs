@@ -0,0 +1,2999 @@
+use anyhow::{Result, Context, anyhow, bail};
+use serde::{Serialize, Deserialize};
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
+use std::fmt;
+use std::fs;
+use std::path::Path;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread::{self, sleep};
+use std::time::{Duration, Instant};
+use crate::hal::{Servo, ServoInfo, ServoRegister, ServoMode, ServoDirection, MemoryLockState, TorqueMode, ServoError};
+use crate::config::CalibrationProfile;
+use crate::robot::{Joint, Robot};
+use crate::endian::{read_i16_le, read_u16_le};
+use crate::units::{ticks_to_deg, Resolution};
+use crate::servo::{ModelResolution, ModelScaling, ReadingChecks, SettleConfig, SpeedRamp, CENTER_POSITION, EEPROM_WRITE_DELAY};
+
+const EEPROM_WRITE_ATTEMPTS: usize = 3;
+
+// Min and max angle limit both at 0 disables the limits altogether, it's the
+// convention for continuous and multi-turn joints
+pub const NO_LIMITS: (i16, i16) = (0, 0);
+
+// Calibration as stored in the servo EEPROM: signed position correction
+// and the min/max angle limits in ticks
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Calibration {
+    pub offset: i16,
+    pub min_angle: i16,
+    pub max_angle: i16,
+}
+
+// Factory EEPROM values: no offset and the full turn as limits
+pub const FACTORY_LIMITS: (i16, i16) = (0, 4095);
+
+impl Calibration {
+    // False while the EEPROM still holds what a servo leaves the factory
+    // with, or no offset and NO_LIMITS. An offset-only calibration (see
+    // CalibrationWrites::OffsetOnly) counts as calibrated.
+    pub fn is_calibrated(&self) -> bool {
+        let limits = (self.min_angle, self.max_angle);
+        self.offset != 0 || (limits != FACTORY_LIMITS && limits != NO_LIMITS)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct JointCalibration {
+    pub id: u8,
+    #[serde(flatten)]
+    pub calibration: Calibration,
+    // Only known for calibrations recorded right after their sweep, not for
+    // ones read back from the EEPROM
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub confidence: Option<Confidence>,
+}
+
+// JSON calibration file, keyed by joint name
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct CalibrationFile {
+    pub joints: BTreeMap<String, JointCalibration>,
+}
+
+impl CalibrationFile {
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let contents = fs::read_to_string(path.as_ref())
+            .with_context(|| format!("Failed to read calibration file {:?}", path.as_ref()))?;
+        serde_json::from_str(&contents)
+            .with_context(|| format!("Failed to parse calibration file {:?}", path.as_ref()))
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        let contents = serde_json::to_string_pretty(self)?;
+        fs::write(path.as_ref(), contents)
+            .with_context(|| format!("Failed to write calibration file {:?}", path.as_ref()))
+    }
+
+    // Add or replace the entry of joint `name` with a fresh calibration run
+    pub fn record(&mut self, name: &str, id: u8, run: &CalibrationRun) {
+        self.joints.insert(name.to_string(), JointCalibration { id, calibration: run.calibration, confidence: Some(run.confidence) });
+    }
+}
+
+// The position correction register is 12-bit sign-magnitude: bit 11 is the
+// sign, bits 0-10 the magnitude. Offsets past half a turn wrap to negative.
+pub fn encode_offset(offset: i16) -> u16 {
+    let offset = if offset > 2048 { offset - 4096 } else { offset };
+    if offset < 0 {
+        offset.unsigned_abs() | 0x800
+    } else {
+        offset as u16
+    }
+}
+
+// Offset that makes `present`, read with `current_offset` applied, read as
+// center. The servo reports the raw position minus its offset, so the
+// current offset is folded back in. Half a turn either way can't be stored
+// in 11 bits of magnitude.
+pub fn center_offset(present: i16, current_offset: i16) -> Result<i16> {
+    let offset = (present as i32 + current_offset as i32 - CENTER_POSITION as i32).rem_euclid(4096);
+    let offset = if offset > 2048 { offset - 4096 } else { offset };
+    if offset.abs() > 0x7FF {
+        bail!("Offset {} is out of range, move the joint off the half turn and retry", offset);
+    }
+    Ok(offset as i16)
+}
+
+pub fn decode_offset(raw: u16) -> i16 {
+    let magnitude = (raw & 0x7FF) as i16;
+    if raw & 0x800 != 0 { -magnitude } else { magnitude }
+}
+
+// Largest offset magnitude the register holds
+pub const MAX_OFFSET: i16 = 0x7FF;
+
+// An offset this close to MAX_OFFSET means the horn or linkage sits far off
+// center, and the servo can't reach its full commanded range on one side
+pub const OFFSET_SATURATION_MARGIN: i16 = 128;
+
+// Ticks left before `offset`, wrapped the same way as in encode_offset,
+// saturates the register. Negative if it can't be stored at all.
+pub fn offset_margin(offset: i16) -> i16 {
+    let offset = offset as i32;
+    let offset = if offset > 2048 { offset - 4096 } else { offset };
+    (MAX_OFFSET as i32 - offset.abs()) as i16
+}
+
+pub fn near_saturation(offset: i16) -> bool {
+    offset_margin(offset) < OFFSET_SATURATION_MARGIN
+}
+
+// `limits` as read from MinAngleLimit onwards, `offset` from PositionCorrection
+pub fn decode_calibration(limits: [u8; 4], offset: [u8; 2]) -> Calibration {
+    Calibration {
+        offset: decode_offset(read_u16_le(&offset, 0)),
+        min_angle: read_i16_le(&limits, 0),
+        max_angle: read_i16_le(&limits, 2),
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct CalibrationParams {
+    pub speed: u16,
+    // In mA, converted per model through current_scaling
+    pub current_threshold: f32,
+    pub current_scaling: ModelScaling,
+    // For the move to the new center once the calibration is written
+    pub settle: SettleConfig,
+    pub backoff: Backoff,
+    // Which registers the sweep's result is written to
+    pub writes: CalibrationWrites,
+    // None sweeps the whole way at `speed`
+    pub approach: Option<Approach>,
+    // None keeps the stall-finding speed fixed
+    pub escalation: Option<Escalation>,
+    pub on_interrupt: InterruptAction,
+    pub trip: TripRule,
+    // Ramp the speed down once a stop trips instead of stopping dead, which
+    // makes elastic linkages rebound. None stops dead.
+    pub deceleration: Option<SpeedRamp>,
+    // Leave torque on and the joint holding center, for joints used right
+    // after calibrating. Off by default so a bulk calibration doesn't leave
+    // every joint stiff.
+    pub keep_torque: bool,
+    // Ticks a single sweep may travel without finding a stop before it's
+    // aborted with NoHardStop, e.g. on a disconnected gear that never
+    // stalls. None sweeps until a stop trips or the run is interrupted.
+    pub max_travel: Option<u32>,
+    // Move to center before sweeping, so the first pass starts from a known
+    // position instead of wherever the joint was left. None sweeps from
+    // where the joint is.
+    pub center_start: Option<CenterStart>,
+    // Frames failing these are read again rather than taken as a stop
+    // position. ReadingChecks::NONE trusts every frame.
+    pub reading_checks: ReadingChecks,
+    // None polls every SWEEP_POLL_INTERVAL
+    pub poll: Option<AdaptivePoll>,
+    // Refuse to write a calibration whose Confidence::score is below this,
+    // failing with LowConfidence instead. None writes whatever was found.
+    pub verify_calibration: Option<f32>,
+    // Position reads averaged with the joint at rest against each stop,
+    // which then replace the single reading at the first threshold crossing
+    // as the stop position, see StopTrace::position. 1 or less keeps the
+    // single reading.
+    pub stop_samples: usize,
+    // Fail with OffsetSaturated instead of warning when the offset is near
+    // saturation, see OFFSET_SATURATION_MARGIN
+    pub strict_offset: bool,
+    // For the center of each servo's range, see ModelResolution
+    pub resolution: ModelResolution,
+    // Readings ignored after each sweep starts, see BlankedDetector.
+    // Duration::ZERO checks from the first reading.
+    pub reversal_blanking: Duration,
+    // None reports nothing while sweeping
+    pub progress: Option<ProgressReport>,
+    // None calibrates silently, warnings aside
+    pub events: Option<CalibrationEvents>,
+    // How calibrate_servo tells it has reached a stop
+    pub detection: StopDetection,
+}
+
+// Which calibration registers a run writes, the rest keep what's stored.
+// After a gearbox swap, say, the stops are where they were but the offset
+// is wrong; after moving a hard stop only the limits are.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum CalibrationWrites {
+    #[default]
+    All,
+    // Re-center, keeping the stored limits, which are relative to center.
+    // Also for joints that keep their free range.
+    OffsetOnly,
+    // Keep the stored offset and set the limits to the stops as found
+    // through it
+    LimitsOnly,
+}
+
+impl CalibrationWrites {
+    pub fn registers(self) -> &'static [ServoRegister] {
+        match self {
+            CalibrationWrites::All => &[ServoRegister::PositionCorrection, ServoRegister::MinAngleLimit, ServoRegister::MaxAngleLimit],
+            CalibrationWrites::OffsetOnly => &[ServoRegister::PositionCorrection],
+            CalibrationWrites::LimitsOnly => &[ServoRegister::MinAngleLimit, ServoRegister::MaxAngleLimit],
+        }
+    }
+}
+
+// Write the `registers` of `calibration` through `write`, leaving every
+// other register alone
+pub fn write_registers(calibration: &Calibration, registers: &[ServoRegister], mut write: impl FnMut(ServoRegister, u16) -> Result<()>) -> Result<()> {
+    for &register in registers {
+        let value = match register {
+            ServoRegister::PositionCorrection => encode_offset(calibration.offset),
+            ServoRegister::MinAngleLimit => calibration.min_angle as u16,
+            ServoRegister::MaxAngleLimit => calibration.max_angle as u16,
+            _ => bail!("{:?} is not a calibration register", register),
+        };
+        write(register, value)?;
+    }
+    Ok(())
+}
+
+// Limits at the stops as read through `offset`, without re-centering.
+// Stops wrapping through 0 can't be stored as limits, the offset has to
+// be recalibrated as well then.
+pub fn limits_at_stops(min_pos: i16, max_pos: i16, offset: i16) -> Result<Calibration> {
+    if max_pos <= min_pos {
+        bail!("Stops {} and {} wrap through 0 with the stored offset {}, recalibrate the offset too", min_pos, max_pos, offset);
+    }
+    Ok(Calibration { offset, min_angle: min_pos, max_angle: max_pos })
+}
+
+// The move to center runs in position mode at the sweep's reduced torque
+// limit and is watched by the same limit detector as the sweep, so a stop
+// between the joint and center aborts it instead of being driven into
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CenterStart {
+    // Position mode running speed, keep it as slow as the sweep
+    pub speed: u16,
+    pub settle: SettleConfig,
+}
+
+// One and a half turns: further than any joint with stops can travel
+pub const DEFAULT_MAX_TRAVEL: u32 = 4096 * 3 / 2;
+
+// Long enough for the current to settle after reversing off a stop at
+// calibration speeds
+pub const DEFAULT_REVERSAL_BLANKING: Duration = Duration::from_millis(50);
+
+// A stop is declared once `required` of the last `window` current readings
+// were above the threshold. Requiring several keeps a single noisy reading
+// from tripping, at the cost of a few ms of extra travel per reading.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TripRule {
+    pub required: usize,
+    pub window: usize,
+}
+
+impl TripRule {
+    pub fn consecutive(count: usize) -> Self {
+        Self { required: count, window: count }
+    }
+}
+
+impl Default for TripRule {
+    fn default() -> Self {
+        Self::consecutive(3)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct TripDetector {
+    rule: TripRule,
+    history: VecDeque<bool>,
+}
+
+impl TripDetector {
+    pub fn new(rule: TripRule) -> Result<Self> {
+        if rule.required == 0 || rule.required > rule.window {
+            bail!("Invalid trip rule, {} of {} readings", rule.required, rule.window);
+        }
+        Ok(Self { rule, history: VecDeque::with_capacity(rule.window) })
+    }
+
+    // Record a reading, true once the rule is met
+    pub fn push(&mut self, above_threshold: bool) -> bool {
+        if self.history.len() == self.rule.window {
+            self.history.pop_front();
+        }
+        self.history.push_back(above_threshold);
+        self.above() >= self.rule.required
+    }
+
+    // Readings above the threshold in the current window
+    pub fn above(&self) -> usize {
+        self.history.iter().filter(|&&above| above).count()
+    }
+}
+
+// What a limit detector makes of the latest reading during a sweep
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LimitReading {
+    Clear,
+    // Some evidence of a stop, not enough to act on yet. The position where
+    // the evidence started is recorded as the raw stop.
+    Suspected,
+    // The sweep stops and backs off
+    Reached,
+}
+
+// Decides when a sweep has hit its stop. The default detects the stall
+// current of the joint pushing against its mechanical stop; joints fitted
+// with limit switches can stop on the switch instead, see SwitchLimitDetector.
+pub trait LimitDetector {
+    // Called at the start of each sweep direction
+    fn start(&mut self, _direction: ServoDirection) -> Result<()> {
+        Ok(())
+    }
+
+    fn check(&mut self, info: &ServoInfo, direction: ServoDirection) -> Result<LimitReading>;
+
+    // How close the latest reading came to the limit, 1.0 at the threshold.
+    // None if the detector can't tell, an adaptive poll then stays fast.
+    fn proximity(&self) -> Option<f32> {
+        None
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct CurrentLimitDetector {
+    // In mA
+    threshold: f32,
+    scale: f32,
+    trip: TripDetector,
+    // Latest reading in mA
+    current: f32,
+}
+
+impl CurrentLimitDetector {
+    pub fn new(servo: &Servo, id: u8, params: &CalibrationParams) -> Result<Self> {
+        Ok(Self {
+            threshold: params.current_threshold,
+            scale: servo.read_scale(id, &params.current_scaling)?,
+            trip: TripDetector::new(params.trip)?,
+            current: 0.0,
+        })
+    }
+}
+
+impl LimitDetector for CurrentLimitDetector {
+    fn start(&mut self, _direction: ServoDirection) -> Result<()> {
+        self.trip = TripDetector::new(self.trip.rule)?;
+        Ok(())
+    }
+
+    fn check(&mut self, info: &ServoInfo, _direction: ServoDirection) -> Result<LimitReading> {
+        self.current = info.scaled_current(self.scale);
+        let above_threshold = self.current > self.threshold;
+        Ok(if self.trip.push(above_threshold) {
+            LimitReading::Reached
+        } else if self.trip.above() > 0 {
+            LimitReading::Suspected
+        } else {
+            LimitReading::Clear
+        })
+    }
+
+    fn proximity(&self) -> Option<f32> {
+        (self.threshold > 0.0).then(|| self.current / self.threshold)
+    }
+}
+
+// Where a sweep is, for a progress bar. The travel to a stop isn't known
+// until it's found, so `fraction` is the travel so far over the expected
+// travel of the pass, held just short of 1.0 until the stop is reached.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SweepProgress {
+    // 0 for the first direction, 1 for the second
+    pub pass: usize,
+    pub direction: ServoDirection,
+    // Ticks travelled in this pass
+    pub travel: u32,
+    pub fraction: f32,
+}
+
+impl SweepProgress {
+    // Both passes together, 0.0 to 1.0
+    pub fn overall(&self) -> f32 {
+        (self.pass as f32 + self.fraction) / 2.0
+    }
+}
+
+pub type ProgressCallback = Arc<dyn Fn(&SweepProgress) + Send + Sync>;
+
+// Largest fraction reported before the stop is actually found
+const MAX_ESTIMATED_FRACTION: f32 = 0.99;
+
+#[derive(Clone)]
+pub struct ProgressReport {
+    // Ticks between the stops, e.g. from the joint's range in the config.
+    // The first pass is expected to cover half of it after a center start
+    // and all of it otherwise.
+    pub expected_travel: u32,
+    pub callback: ProgressCallback,
+}
+
+impl fmt::Debug for ProgressReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ProgressReport")
+            .field("expected_travel", &self.expected_travel)
+            .finish()
+    }
+}
+
+impl ProgressReport {
+    pub fn new(expected_travel: u32, callback: impl Fn(&SweepProgress) + Send + Sync + 'static) -> Self {
+        Self { expected_travel, callback: Arc::new(callback) }
+    }
+
+    // Calls the callback and returns what it was given. `expected` is the
+    // travel expected for this pass, a Reached pass reports 1.0.
+    pub fn report(&self, pass: usize, direction: ServoDirection, travel: u32, expected: u32, reached: bool) -> SweepProgress {
+        let fraction = if reached {
+            1.0
+        } else if expected == 0 {
+            0.0
+        } else {
+            (travel as f32 / expected as f32).min(MAX_ESTIMATED_FRACTION)
+        };
+        let progress = SweepProgress { pass, direction, travel, fraction };
+        (self.callback)(&progress);
+        progress
+    }
+}
+
+// What a calibration run is doing, for the caller to show. The library
+// doesn't print these itself.
+#[derive(Debug, Clone, PartialEq)]
+pub enum CalibrationEvent {
+    CenterReached { id: u8, position: i16 },
+    // The joint barely moved at `from`, see Escalation
+    SpeedRaised { id: u8, from: u16, to: u16 },
+    // Current read zero while moving, see AutoLimitDetector
+    StallFallback { id: u8 },
+    Writing { id: u8, calibration: Calibration, writes: CalibrationWrites },
+    Holding { id: u8, position: i16 },
+    Interrupted { id: u8, phase: CalibrationPhase },
+}
+
+impl fmt::Display for CalibrationEvent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CalibrationEvent::CenterReached { id, position } => write!(f, "Servo {} at {}, starting the sweep from center", id, position),
+            CalibrationEvent::SpeedRaised { id, from, to } => write!(f, "Servo {} barely moving at speed {}, raising it to {}", id, from, to),
+            CalibrationEvent::StallFallback { id } => write!(f, "Current of servo {} reads zero while moving, detecting stops by stall instead", id),
+            CalibrationEvent::Writing { calibration, writes: CalibrationWrites::All, .. } => write!(
+                f, "Writing calibration, offset: {}, min_angle: {}, max_angle: {}", calibration.offset, calibration.min_angle, calibration.max_angle),
+            CalibrationEvent::Writing { calibration, writes: CalibrationWrites::OffsetOnly, .. } => write!(
+                f, "Writing offset {}, leaving angle limits untouched", calibration.offset),
+            CalibrationEvent::Writing { calibration, writes: CalibrationWrites::LimitsOnly, .. } => write!(
+                f, "Writing min_angle {} and max_angle {}, leaving offset {} untouched", calibration.min_angle, calibration.max_angle, calibration.offset),
+            CalibrationEvent::Holding { id, position } => write!(f, "Holding servo {} at {}", id, position),
+            CalibrationEvent::Interrupted { phase: CalibrationPhase::EepromWrite, .. } => write!(
+                f, "Interrupt received during {:?} phase, calibration was written completely before stopping", CalibrationPhase::EepromWrite),
+            CalibrationEvent::Interrupted { id, phase } => write!(f, "Calibration of servo {} interrupted during {:?} phase, EEPROM untouched", id, phase),
+        }
+    }
+}
+
+pub type EventCallback = Arc<dyn Fn(&CalibrationEvent) + Send + Sync>;
+
+#[derive(Clone)]
+pub struct CalibrationEvents {
+    pub callback: EventCallback,
+}
+
+impl fmt::Debug for CalibrationEvents {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CalibrationEvents").finish_non_exhaustive()
+    }
+}
+
+impl CalibrationEvents {
+    pub fn new(callback: impl Fn(&CalibrationEvent) + Send + Sync + 'static) -> Self {
+        Self { callback: Arc::new(callback) }
+    }
+}
+
+impl CalibrationParams {
+    fn notify(&self, event: CalibrationEvent) {
+        notify(self.events.as_ref(), event);
+    }
+}
+
+fn notify(events: Option<&CalibrationEvents>, event: CalibrationEvent) {
+    if let Some(events) = events {
+        (events.callback)(&event);
+    }
+}
+
+// Reversing off a stop, or starting from rest, draws a brief current spike
+// while the joint's inertia is turned around, which a current detector
+// would take for the next stop. For `blanking` after every start the
+// readings are reported Clear without reaching `inner`, so they don't count
+// towards its trip rule either.
+pub struct BlankedDetector<'a> {
+    inner: &'a mut dyn LimitDetector,
+    blanking: Duration,
+    started: Option<Instant>,
+}
+
+impl<'a> BlankedDetector<'a> {
+    pub fn new(inner: &'a mut dyn LimitDetector, blanking: Duration) -> Self {
+        Self { inner, blanking, started: None }
+    }
+
+    fn blanked(&self) -> bool {
+        self.started.is_some_and(|started| started.elapsed() < self.blanking)
+    }
+}
+
+impl LimitDetector for BlankedDetector<'_> {
+    fn start(&mut self, direction: ServoDirection) -> Result<()> {
+        self.started = Some(Instant::now());
+        self.inner.start(direction)
+    }
+
+    fn check(&mut self, info: &ServoInfo, direction: ServoDirection) -> Result<LimitReading> {
+        if self.blanked() {
+            return Ok(LimitReading::Clear);
+        }
+        self.inner.check(info, direction)
+    }
+
+    fn proximity(&self) -> Option<f32> {
+        if self.blanked() { None } else { self.inner.proximity() }
+    }
+}
+
+// A stop is declared once the position has stayed within `tolerance` ticks
+// for `samples` readings in a row while the sweep drives the joint
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StallRule {
+    pub samples: usize,
+    pub tolerance: u16,
+}
+
+impl Default for StallRule {
+    fn default() -> Self {
+        Self { samples: 10, tolerance: 2 }
+    }
+}
+
+// For servos that don't report current reliably. It can only tell the
+// joint stopped moving, not why: a sweep speed too low to overcome
+// friction or an obstruction midway look the same as the stop, so keep the
+// speed well clear of stalling and the path clear. Every reading it waits
+// for is spent pushing against the stop, and the stop position carries the
+// gearbox backlash and any give in the linkage, which the current
+// detector, tripping on the first rise in current, mostly avoids. The
+// joint accelerating from rest looks stalled too, which the sweep's
+// reversal blanking (see BlankedDetector) has to cover.
+#[derive(Debug, Clone)]
+pub struct StallLimitDetector {
+    rule: StallRule,
+    last: Option<i16>,
+    stationary: usize,
+}
+
+impl StallLimitDetector {
+    pub fn new(rule: StallRule) -> Result<Self> {
+        if rule.samples == 0 {
+            bail!("Invalid stall rule, at least one stationary reading is needed");
+        }
+        Ok(Self { rule, last: None, stationary: 0 })
+    }
+
+    // Readings the position has stayed put for, so far
+    pub fn stationary(&self) -> usize {
+        self.stationary
+    }
+}
+
+impl LimitDetector for StallLimitDetector {
+    fn start(&mut self, _direction: ServoDirection) -> Result<()> {
+        self.last = None;
+        self.stationary = 0;
+        Ok(())
+    }
+
+    fn check(&mut self, info: &ServoInfo, _direction: ServoDirection) -> Result<LimitReading> {
+        let position = info.current_location;
+        match self.last {
+            Some(last) if travelled(last, position) <= self.rule.tolerance as u32 => self.stationary += 1,
+            _ => {
+                self.last = Some(position);
+                self.stationary = 0;
+            }
+        }
+        Ok(if self.stationary >= self.rule.samples {
+            LimitReading::Reached
+        } else if self.stationary > 0 {
+            LimitReading::Suspected
+        } else {
+            LimitReading::Clear
+        })
+    }
+
+    fn proximity(&self) -> Option<f32> {
+        Some(self.stationary as f32 / self.rule.samples as f32)
+    }
+}
+
+// Readings of zero current while moving after which AutoLimitDetector
+// gives up on the current
+pub const ZERO_CURRENT_SAMPLES: usize = 10;
+
+// Detects by current until the current reads zero for ZERO_CURRENT_SAMPLES
+// readings in a row while the joint is moving, which no servo drawing
+// current does, then by stall for the rest of the run
+#[derive(Debug, Clone)]
+pub struct AutoLimitDetector {
+    current: CurrentLimitDetector,
+    stall: StallLimitDetector,
+    last: Option<i16>,
+    zero_while_moving: usize,
+    fallen_back: bool,
+    // Told of the fallback as CalibrationEvent::StallFallback for `id`
+    events: Option<(u8, CalibrationEvents)>,
+}
+
+impl AutoLimitDetector {
+    pub fn new(current: CurrentLimitDetector, stall: StallLimitDetector) -> Self {
+        Self { current, stall, last: None, zero_while_moving: 0, fallen_back: false, events: None }
+    }
+
+    pub fn with_events(mut self, id: u8, events: Option<CalibrationEvents>) -> Self {
+        self.events = events.map(|events| (id, events));
+        self
+    }
+
+    pub fn fallen_back(&self) -> bool {
+        self.fallen_back
+    }
+}
+
+impl LimitDetector for AutoLimitDetector {
+    fn start(&mut self, direction: ServoDirection) -> Result<()> {
+        self.last = None;
+        self.current.start(direction)?;
+        self.stall.start(direction)
+    }
+
+    fn check(&mut self, info: &ServoInfo, direction: ServoDirection) -> Result<LimitReading> {
+        let stall = self.stall.check(info, direction)?;
+        if self.fallen_back {
+            return Ok(stall);
+        }
+        let moved = self.last.is_some_and(|last| last != info.current_location);
+        self.last = Some(info.current_location);
+        if moved && info.current_current == 0 {
+            self.zero_while_moving += 1;
+        } else if info.current_current != 0 {
+            self.zero_while_moving = 0;
+        }
+        if self.zero_while_moving >= ZERO_CURRENT_SAMPLES {
+            self.fallen_back = true;
+            if let Some((id, events)) = &self.events {
+                notify(Some(events), CalibrationEvent::StallFallback { id: *id });
+            }
+            return Ok(stall);
+        }
+        self.current.check(info, direction)
+    }
+
+    fn proximity(&self) -> Option<f32> {
+        if self.fallen_back { self.stall.proximity() } else { self.current.proximity() }
+    }
+}
+
+// How calibrate_servo finds the stops
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StopDetection {
+    // Stall current only, see CurrentLimitDetector
+    Current,
+    // Position stationary, for servos without a usable current reading,
+    // see StallLimitDetector
+    Stall(StallRule),
+    // Current, falling back to stall if the current turns out to read
+    // zero, see AutoLimitDetector
+    Auto(StallRule),
+}
+
+impl Default for StopDetection {
+    fn default() -> Self {
+        StopDetection::Auto(StallRule::default())
+    }
+}
+
+// Poll interval of a sweep without an adaptive poll
+pub const SWEEP_POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+// Polls slowly while the joint travels freely and the readings are steady,
+// and at `min_interval` as soon as they approach the limit, so the stop is
+// caught just as fast as with a fixed fast poll but the bus is mostly free
+// during travel.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AdaptivePoll {
+    pub min_interval: Duration,
+    pub max_interval: Duration,
+    // Proximity (see LimitDetector::proximity) from which to poll at min_interval
+    pub near: f32,
+    // Proximity change between polls below which the readings are steady
+    // and the interval doubles. Anything larger halves it.
+    pub stable: f32,
+}
+
+impl Default for AdaptivePoll {
+    fn default() -> Self {
+        Self {
+            min_interval: Duration::from_millis(5),
+            max_interval: Duration::from_millis(40),
+            near: 0.6,
+            stable: 0.05,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct AdaptivePoller {
+    config: AdaptivePoll,
+    interval: Duration,
+    last: Option<f32>,
+}
+
+impl AdaptivePoller {
+    pub fn new(config: AdaptivePoll) -> Self {
+        Self { config, interval: config.min_interval, last: None }
+    }
+
+    // How long to wait before the next reading, given the latest one
+    pub fn next(&mut self, reading: LimitReading, proximity: Option<f32>) -> Duration {
+        let config = &self.config;
+        self.interval = match proximity {
+            Some(proximity) if reading == LimitReading::Clear && proximity < config.near => {
+                let steady = self.last.is_some_and(|last| (proximity - last).abs() < config.stable);
+                if steady { self.interval * 2 } else { self.interval / 2 }
+            }
+            _ => config.min_interval,
+        }
+        .clamp(config.min_interval, config.max_interval);
+        self.last = proximity;
+        self.interval
+    }
+
+    pub fn interval(&self) -> Duration {
+        self.interval
+    }
+}
+
+// Stops on an external switch, `pressed` reads the switch at the end the
+// joint is heading for, e.g. from a GPIO line. The motor never stalls, so the
+// raw stop is the switch position rather than the mechanical stop.
+pub struct SwitchLimitDetector<F> {
+    pressed: F,
+}
+
+impl<F: FnMut(ServoDirection) -> Result<bool>> SwitchLimitDetector<F> {
+    pub fn new(pressed: F) -> Self {
+        Self { pressed }
+    }
+}
+
+impl<F: FnMut(ServoDirection) -> Result<bool>> LimitDetector for SwitchLimitDetector<F> {
+    fn check(&mut self, _info: &ServoInfo, direction: ServoDirection) -> Result<LimitReading> {
+        Ok(if (self.pressed)(direction)? { LimitReading::Reached } else { LimitReading::Clear })
+    }
+}
+
+// What to do with the joint when a sweep is interrupted
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum InterruptAction {
+    // Speed 0, the joint coasts. Fine for light joints.
+    #[default]
+    Stop,
+    // Switch to position mode holding the present position, for joints
+    // supporting weight that would otherwise drift
+    Hold,
+}
+
+// Guards against a stall-finding speed too low to overcome friction: the
+// joint sits still without drawing enough current to register as a stop, and
+// the sweep never ends. If the joint covers less than `min_travel` ticks in
+// `window` while below the current threshold, the speed is raised by `step`,
+// up to `max_speed`, and the window starts over.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Escalation {
+    pub window: Duration,
+    pub min_travel: u16,
+    pub step: u16,
+    pub max_speed: u16,
+}
+
+pub fn escalate_speed(speed: u16, escalation: &Escalation) -> u16 {
+    speed.saturating_add(escalation.step).min(escalation.max_speed).max(speed)
+}
+
+// Covers the first part of each sweep at a higher speed, for servos that
+// power up far from their stops. The remaining sweep runs at the slower
+// stall-finding speed.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Approach {
+    pub speed: u16,
+    // Ticks travelled at `speed` before slowing down. Keep it short of the
+    // closest the joint can start to a stop, a stop hit at approach speed
+    // still trips the current threshold but harder.
+    pub distance: u16,
+}
+
+// Speed to sweep at after `travel` ticks
+pub fn sweep_speed(travel: u32, sweep_speed: u16, approach: Option<&Approach>) -> u16 {
+    match approach {
+        Some(approach) if travel < approach.distance as u32 => approach.speed,
+        _ => sweep_speed,
+    }
+}
+
+// Distance between two consecutive readings, taking the 4096 tick wrap the
+// short way round
+fn travelled(from: i16, to: i16) -> u32 {
+    let delta = (to as i32 - from as i32).rem_euclid(4096);
+    delta.min(4096 - delta) as u32
+}
+
+// How long to drive away from a stop at sweep speed once it's detected, per
+// stop. A hard stop can take a shorter backoff than a compliant one.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Backoff {
+    pub clockwise: Duration,
+    pub counterclockwise: Duration,
+}
+
+impl Backoff {
+    pub fn uniform(duration: Duration) -> Self {
+        Self { clockwise: duration, counterclockwise: duration }
+    }
+
+    pub fn after(&self, direction: ServoDirection) -> Duration {
+        match direction {
+            ServoDirection::Clockwise => self.clockwise,
+            ServoDirection::Counterclockwise => self.counterclockwise,
+        }
+    }
+}
+
+impl Default for Backoff {
+    fn default() -> Self {
+        Self::uniform(Duration::from_millis(350))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct StopTrace {
+    // Position when current first crossed the threshold
+    pub raw: i16,
+    // Position when the stop was declared, see Confidence::sharpness
+    pub reached: i16,
+    // Mean of CalibrationParams::stop_samples reads at rest against the stop
+    #[serde(default)]
+    pub at_rest: Option<i16>,
+    // Position the joint was left at after backing off
+    pub backed_off: i16,
+}
+
+impl StopTrace {
+    // The stop position the center is computed from
+    pub fn position(&self) -> i16 {
+        self.at_rest.unwrap_or(self.raw)
+    }
+}
+
+// Mean of positions on the circle, so samples either side of the 0/4095
+// wrap average to the wrap instead of half a turn away
+pub fn mean_position(samples: &[i16]) -> Option<i16> {
+    let &first = samples.first()?;
+    let offsets: i32 = samples.iter()
+        .map(|&sample| {
+            let delta = (sample as i32 - first as i32).rem_euclid(4096);
+            if delta > 2048 { delta - 4096 } else { delta }
+        })
+        .sum();
+    let mean = first as f32 + offsets as f32 / samples.len() as f32;
+    Some((mean.round() as i32).rem_euclid(4096) as i16)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct SweepTrace {
+    pub backward: StopTrace,
+    pub forward: StopTrace,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CalibrationRun {
+    pub calibration: Calibration,
+    pub trace: SweepTrace,
+    pub confidence: Confidence,
+}
+
+// Ticks the stops of repeated sweeps may spread over before repeatability
+// scores 0
+pub const REPEAT_TOLERANCE: f32 = 32.0;
+// A range narrower than this is more likely an obstruction than the joint's
+// stops (about 22 degrees)
+pub const MIN_PLAUSIBLE_RANGE: f32 = 256.0;
+// Stops closer than this to a full turn apart suggest the joint slipped past one
+pub const FULL_TURN_MARGIN: f32 = 64.0;
+// Ticks a joint may travel between the current first crossing the threshold
+// and the stop being declared before sharpness scores 0
+pub const SHARPNESS_TRAVEL: f32 = 64.0;
+
+// How far a calibration can be trusted, every part from 0 (dubious) to 1:
+//
+// - repeatability: 1 - spread of the raw stops of repeated sweeps over
+//   REPEAT_TOLERANCE, the worst side counting. None for a single sweep, which
+//   can't show its repeatability, and left out of the score.
+// - travel: the range between the stops, scaled down below
+//   MIN_PLAUSIBLE_RANGE and to 0 within FULL_TURN_MARGIN of a full turn
+// - sharpness: 1 - travel from first crossing to declared stop over
+//   SHARPNESS_TRAVEL, the worst stop counting. A hard stop stalls the joint
+//   right away, an early trip on friction or a soft stop keeps it moving.
+//
+// The score is the worst of the three, a single dubious aspect is enough.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Confidence {
+    pub repeatability: Option<f32>,
+    pub travel: f32,
+    pub sharpness: f32,
+    pub score: f32,
+}
+
+pub fn score_sweeps(sweeps: &[SweepTrace]) -> Confidence {
+    let spread = |stop: fn(&SweepTrace) -> i16| {
+        let raws = sweeps.iter().map(stop);
+        (raws.clone().max().unwrap_or(0) as i32 - raws.min().unwrap_or(0) as i32) as f32
+    };
+    let worst_spread = spread(|sweep| sweep.backward.position()).max(spread(|sweep| sweep.forward.position()));
+    let repeatability = (sweeps.len() > 1).then(|| (1.0 - worst_spread / REPEAT_TOLERANCE).clamp(0.0, 1.0));
+
+    let travel = sweeps.iter()
+        .map(|sweep| {
+            let calibration = compute_calibration(sweep.backward.position(), sweep.forward.position());
+            let range = (calibration.max_angle - calibration.min_angle) as f32;
+            (range / MIN_PLAUSIBLE_RANGE).min((4096.0 - range) / FULL_TURN_MARGIN).clamp(0.0, 1.0)
+        })
+        .fold(1.0, f32::min);
+
+    let sharpness = sweeps.iter()
+        .flat_map(|sweep| [sweep.backward, sweep.forward])
+        .map(|stop| 1.0 - travelled(stop.raw, stop.reached) as f32 / SHARPNESS_TRAVEL)
+        .fold(1.0, f32::min)
+        .clamp(0.0, 1.0);
+
+    let score = if sweeps.is_empty() { 0.0 } else { repeatability.unwrap_or(1.0).min(travel).min(sharpness) };
+    Confidence { repeatability, travel, sharpness, score }
+}
+
+impl fmt::Display for Confidence {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(repeatability) = self.repeatability {
+            write!(f, "repeatability {:.2}, ", repeatability)?;
+        }
+        write!(f, "travel {:.2}, sharpness {:.2}", self.travel, self.sharpness)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CalibrationPhase {
+    // Moving to center before the sweep, freely interruptible
+    Prepositioning,
+    // Sweeping towards the mechanical stops, freely interruptible
+    Sweep,
+    // Writing offset and limits to EEPROM, runs to completion once started
+    EepromWrite,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CalibrationInterrupted {
+    pub phase: CalibrationPhase,
+}
+
+impl std::fmt::Display for CalibrationInterrupted {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Calibration interrupted during {:?} phase", self.phase)
+    }
+}
+
+impl std::error::Error for CalibrationInterrupted {}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct NoHardStop {
+    pub id: u8,
+    pub direction: ServoDirection,
+    pub travel: u32,
+}
+
+impl std::fmt::Display for NoHardStop {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "No hard stop found: servo {} travelled {} ticks {:?}, is the gear connected?", self.id, self.travel, self.direction)
+    }
+}
+
+impl std::error::Error for NoHardStop {}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LowConfidence {
+    pub id: u8,
+    pub confidence: Confidence,
+    pub min_score: f32,
+}
+
+impl std::fmt::Display for LowConfidence {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f, "Calibration of servo {} scored {:.2} ({}), below {:.2}. EEPROM untouched",
+            self.id, self.confidence.score, self.confidence, self.min_score
+        )
+    }
+}
+
+impl std::error::Error for LowConfidence {}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OffsetSaturated {
+    pub id: u8,
+    pub offset: i16,
+    pub margin: i16,
+}
+
+impl std::fmt::Display for OffsetSaturated {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f, "Servo {} needs offset {}, {} ticks from the ±{} limit. Refit the horn or linkage closer to center \
+                instead of correcting it in software. EEPROM untouched",
+            self.id, self.offset, self.margin, MAX_OFFSET
+        )
+    }
+}
+
+impl std::error::Error for OffsetSaturated {}
+
+// Center the stop positions found by the sweep around 2048
+pub fn compute_calibration(min_pos: i16, max_pos: i16) -> Calibration {
+    compute_calibration_with(min_pos, max_pos, Resolution::STS)
+}
+
+// Center the stop positions around the center of `resolution`. Only a
+// full-turn encoder can wrap through 0 between the stops, on any other the
+// lower one is taken as min.
+pub fn compute_calibration_with(min_pos: i16, max_pos: i16, resolution: Resolution) -> Calibration {
+    let (min_pos, max_pos) = (min_pos as i32, max_pos as i32);
+    let (min_pos, max_pos) = match max_pos < min_pos {
+        true if resolution.full_turn() => (min_pos, max_pos + resolution.ticks_per_turn),
+        true => (max_pos, min_pos),
+        false => (min_pos, max_pos),
+    };
+    let half_range = (max_pos - min_pos) / 2;
+    let center = resolution.center();
+    Calibration {
+        offset: (min_pos + half_range - center) as i16,
+        min_angle: (center - half_range) as i16,
+        max_angle: (center + half_range) as i16,
+    }
+}
+
+// Ticks a joint can travel both ways from center within its limits: the
+// nearer limit, since a centered range is rarely exactly symmetric once
+// limits have been set by hand or the stops rounded. Poses kept within
+// center ± this are reachable whichever way they go. Unset limits give the
+// whole turn, limits not around center give 0.
+pub fn symmetric_range(min_angle: i16, max_angle: i16) -> u16 {
+    symmetric_range_with(min_angle, max_angle, Resolution::STS)
+}
+
+pub fn symmetric_range_with(min_angle: i16, max_angle: i16, resolution: Resolution) -> u16 {
+    let (min_angle, max_angle) = if (min_angle, max_angle) == NO_LIMITS {
+        (0, resolution.max_tick)
+    } else {
+        (min_angle as i32, max_angle as i32)
+    };
+    let max_angle = if max_angle < min_angle && resolution.full_turn() { max_angle + resolution.ticks_per_turn } else { max_angle };
+    let center = resolution.center();
+    (center - min_angle).min(max_angle - center).max(0) as u16
+}
+
+// symmetric_range in degrees
+pub fn symmetric_range_deg(min_angle: i16, max_angle: i16) -> f32 {
+    ticks_to_deg(symmetric_range(min_angle, max_angle) as i32)
+}
+
+// Angle limits in degrees around center (2048). A max below min means the
+// range wraps through 0, which is unwrapped the same way the center is
+// computed during calibration.
+pub fn range_deg(min_angle: i16, max_angle: i16) -> (f32, f32) {
+    range_deg_with(min_angle, max_angle, Resolution::STS)
+}
+
+pub fn range_deg_with(min_angle: i16, max_angle: i16, resolution: Resolution) -> (f32, f32) {
+    let (min_angle, max_angle) = (min_angle as i32, max_angle as i32);
+    let max_angle = if max_angle < min_angle && resolution.full_turn() { max_angle + resolution.ticks_per_turn } else { max_angle };
+    let to_deg = |ticks: i32| resolution.ticks_to_deg(ticks - resolution.center());
+    (to_deg(min_angle), to_deg(max_angle))
+}
+
+impl Servo {
+    pub fn read_offset(&self, id: u8) -> Result<i16> {
+        Ok(decode_offset(self.read_u16(id, ServoRegister::PositionCorrection)?))
+    }
+
+    // MinAngleLimit and MaxAngleLimit are adjacent (0x09..0x0C) and come in
+    // one read, PositionCorrection (0x1F) is too far away to batch with them
+    pub fn read_calibration(&self, id: u8) -> Result<Calibration> {
+        let limits = self.read_exact(id, ServoRegister::MinAngleLimit, 4)?;
+        let offset = self.read_exact(id, ServoRegister::PositionCorrection, 2)?;
+        Ok(decode_calibration([limits[0], limits[1], limits[2], limits[3]], [offset[0], offset[1]]))
+    }
+
+    pub fn write_calibration(&self, id: u8, calibration: &Calibration) -> Result<()> {
+        self.unlock_eeprom(id)?;
+        sleep(EEPROM_WRITE_DELAY);
+
+        self.write_servo_memory(id, ServoRegister::PositionCorrection, encode_offset(calibration.offset))?;
+        sleep(EEPROM_WRITE_DELAY);
+
+        self.write_verified(id, ServoRegister::MinAngleLimit, calibration.min_angle as u16)?;
+        self.write_verified(id, ServoRegister::MaxAngleLimit, calibration.max_angle as u16)?;
+
+        self.lock_eeprom(id)?;
+        Ok(())
+    }
+
+    // Only the angle limits, leaving the stored offset untouched
+    pub fn write_limits(&self, id: u8, calibration: &Calibration) -> Result<()> {
+        self.unlock_eeprom(id)?;
+        sleep(EEPROM_WRITE_DELAY);
+        let written = write_registers(calibration, CalibrationWrites::LimitsOnly.registers(), |register, value| {
+            self.write_verified(id, register, value)
+        });
+        self.lock_eeprom(id)?;
+        written
+    }
+
+    // Write a calibration as a whole: if any write fails, the previous
+    // calibration is written back so the servo never keeps a half-written one
+    pub fn commit_calibration(&self, id: u8, calibration: &Calibration) -> Result<()> {
+        // Checked up front too, there's no point rolling back a servo that
+        // refuses every write
+        self.check_compatibility(id)?;
+        let previous = self.read_calibration(id)?;
+        if let Err(e) = self.write_calibration(id, calibration) {
+            return match self.write_calibration(id, &previous) {
+                Ok(()) => Err(e.context(format!("Failed to write calibration of servo {}, previous calibration restored", id))),
+                Err(rollback) => Err(e.context(format!(
+                    "Failed to write calibration of servo {} and to restore the previous one ({}), EEPROM may be inconsistent", id, rollback))),
+            };
+        }
+        Ok(())
+    }
+
+    // Only the offset, leaving whatever limits are stored untouched
+    pub fn write_offset(&self, id: u8, offset: i16) -> Result<()> {
+        self.unlock_eeprom(id)?;
+        sleep(EEPROM_WRITE_DELAY);
+        let written = self.write_verified(id, ServoRegister::PositionCorrection, encode_offset(offset));
+        self.lock_eeprom(id)?;
+        written
+    }
+
+    // Recalibrate from a single reference: the joint has been placed at its
+    // true mechanical center by hand, which becomes 2048. With `half_range`
+    // the limits are set symmetrically around it, otherwise they're left
+    // as stored. Faster than calibrate_servo for joints whose stops are
+    // known or can't be reached.
+    pub fn set_center_here(&self, id: u8, half_range: Option<u16>) -> Result<Calibration> {
+        let present = self.read_position(id)?;
+        let offset = center_offset(present, self.read_offset(id)?)?;
+        match half_range {
+            Some(half_range) => {
+                let half_range = half_range.min(CENTER_POSITION as u16) as i16;
+                self.commit_calibration(id, &Calibration {
+                    offset,
+                    min_angle: CENTER_POSITION - half_range,
+                    max_angle: CENTER_POSITION + half_range,
+                })?;
+            }
+            None => self.write_offset(id, offset)?,
+        }
+        self.read_calibration(id)
+    }
+
+    // Write the NO_LIMITS sentinel, giving the joint its free range
+    pub fn clear_limits(&self, id: u8) -> Result<()> {
+        self.unlock_eeprom(id)?;
+        sleep(EEPROM_WRITE_DELAY);
+        let written = self.write_verified(id, ServoRegister::MinAngleLimit, NO_LIMITS.0 as u16)
+            .and_then(|_| self.write_verified(id, ServoRegister::MaxAngleLimit, NO_LIMITS.1 as u16));
+        self.lock_eeprom(id)?;
+        written
+    }
+
+    fn write_verified(&self, id: u8, register: ServoRegister, value: u16) -> Result<()> {
+        for _ in 0..EEPROM_WRITE_ATTEMPTS {
+            self.write_servo_memory(id, register, value)?;
+            sleep(EEPROM_WRITE_DELAY);
+            if self.read_u16(id, register)? == value {
+                return Ok(());
+            }
+        }
+        bail!("Failed to write {:?} on servo {} after {} attempts", register, id, EEPROM_WRITE_ATTEMPTS)
+    }
+}
+
+impl CalibrationProfile {
+    pub fn apply(&self, base: &CalibrationParams) -> CalibrationParams {
+        let mut params = base.clone();
+        if let Some(speed) = self.speed {
+            params.speed = speed;
+        }
+        if let Some(threshold) = self.current_threshold {
+            params.current_threshold = threshold;
+        }
+        if let Some(ms) = self.backoff_cw_ms {
+            params.backoff.clockwise = Duration::from_millis(ms);
+        }
+        if let Some(ms) = self.backoff_ccw_ms {
+            params.backoff.counterclockwise = Duration::from_millis(ms);
+        }
+        if let Some(ticks) = self.max_travel {
+            params.max_travel = Some(ticks);
+        }
+        if let (Some(ticks), Some(progress)) = (self.expected_travel, &mut params.progress) {
+            progress.expected_travel = ticks;
+        }
+        params
+    }
+}
+
+impl Robot {
+    // `base` with the joint's calibration profile applied, `base` itself for
+    // joints without one
+    pub fn calibration_params(&self, joint: &Joint, base: &CalibrationParams) -> Result<CalibrationParams> {
+        match &joint.calibration_profile {
+            Some(name) => {
+                let profile = self.calibration_profile(name)
+                    .ok_or_else(|| anyhow!("Joint {} uses unknown calibration profile {:?}", joint.name, name))?;
+                Ok(profile.apply(base))
+            }
+            None => Ok(base.clone()),
+        }
+    }
+
+    // Calibrate every joint in turn, each with its own profile applied to
+    // `base`. Clearing `running` stops after the current joint, joints not
+    // started by then are left out of the results.
+    pub fn calibrate_all(&self, base: &CalibrationParams, running: &AtomicBool) -> Result<Vec<(String, Result<CalibrationRun>)>> {
+        let params = self.joints().iter()
+            .map(|joint| self.calibration_params(joint, base))
+            .collect::<Result<Vec<_>>>()?;
+        Ok(self.joints().iter().zip(&params)
+            .take_while(|_| running.load(Ordering::SeqCst))
+            .map(|(joint, params)| (joint.name.clone(), self.calibrate_joint(&joint.name, params, running)))
+            .collect())
+    }
+
+    // Calibrate a joint on its own, or with its partner holding still if it's
+    // coupled: a limp partner lets the tendon slack and skews the stops
+    pub fn calibrate_joint(&self, name: &str, params: &CalibrationParams, running: &AtomicBool) -> Result<CalibrationRun> {
+        let joint = self.joint(name)?;
+        if let Some(partner) = self.partner(joint.id) {
+            self.set_joint_mode(partner, ServoMode::Position)?;
+            self.reengage(&[partner])?;
+        }
+        calibrate_servo(self.servo(), joint.id, params, running)
+    }
+
+    // Calibrate both joints of a coupled pair, each while the other holds
+    pub fn calibrate_pair(&self, name: &str, params: &CalibrationParams, running: &AtomicBool) -> Result<(CalibrationRun, CalibrationRun)> {
+        let joint = self.joint(name)?;
+        let partner = self.partner(joint.id).ok_or_else(|| anyhow!("Joint {} is not coupled to another joint", name))?;
+        let partner = self.joint_by_id(partner)?;
+
+        let first = self.calibrate_joint(&joint.name, params, running)?;
+        let second = self.calibrate_joint(&partner.name, params, running)?;
+        Ok((first, second))
+    }
+
+    // Usable range of a joint in degrees, e.g. for drawing UI sliders
+    pub fn joint_range_deg(&self, name: &str) -> Result<(f32, f32)> {
+        let joint = self.joint(name)?;
+        let calibration = self.servo().read_calibration(joint.id)?;
+        Ok(range_deg(calibration.min_angle, calibration.max_angle))
+    }
+
+    // Servo degrees a joint can move both ways from center, see
+    // symmetric_range. What pose authors should stay within.
+    pub fn symmetric_range_deg(&self, name: &str) -> Result<f32> {
+        let joint = self.joint(name)?;
+        let calibration = self.servo().read_calibration(joint.id)?;
+        Ok(symmetric_range_deg(calibration.min_angle, calibration.max_angle))
+    }
+
+    // Snapshot the EEPROM calibration of every joint, e.g. to clone a tuned
+    // robot onto a fresh unit
+    pub fn export_robot_calibration<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        let mut file = CalibrationFile::default();
+        for joint in self.joints() {
+            let calibration = self.servo().read_calibration(joint.id)
+                .with_context(|| format!("Failed to read calibration of {}", joint.name))?;
+            file.joints.insert(joint.name.clone(), JointCalibration { id: joint.id, calibration, confidence: None });
+        }
+        file.save(path)
+    }
+
+    // Every configured joint has to be in `file`, under its configured ID
+    pub fn check_calibration_file(&self, file: &CalibrationFile) -> Result<()> {
+        let missing: Vec<&str> = self.joints().iter()
+            .filter(|joint| !file.joints.contains_key(&joint.name))
+            .map(|joint| joint.name.as_str())
+            .collect();
+        if !missing.is_empty() {
+            bail!("Calibration file is missing joints: {}", missing.join(", "));
+        }
+
+        for (name, entry) in &file.joints {
+            let joint = self.joint(name)?;
+            if joint.id != entry.id {
+                bail!("Calibration for {} is for servo {}, but the joint is configured as servo {}", name, entry.id, joint.id);
+            }
+        }
+        Ok(())
+    }
+
+    // Write a previously exported calibration back. The whole file is checked
+    // against the configured joints, and every servo for compatibility,
+    // before anything is written. If a joint can't be written the joints
+    // already imported are put back, so the robot never ends up with half
+    // of one calibration and half of another.
+    pub fn import_robot_calibration<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        let file = CalibrationFile::load(path)?;
+        self.check_calibration_file(&file)?;
+
+        let mut previous = Vec::with_capacity(self.joints().len());
+        for joint in self.joints() {
+            self.servo().check_compatibility(joint.id)?;
+            let calibration = self.servo().read_calibration(joint.id)
+                .with_context(|| format!("Failed to read calibration of {}", joint.name))?;
+            previous.push((joint, calibration));
+        }
+
+        for (index, joint) in self.joints().iter().enumerate() {
+            let entry = &file.joints[&joint.name];
+            if let Err(e) = self.servo().commit_calibration(joint.id, &entry.calibration) {
+                let unrestored: Vec<&str> = previous[..index].iter()
+                    .filter(|(joint, calibration)| self.servo().commit_calibration(joint.id, calibration).is_err())
+                    .map(|(joint, _)| joint.name.as_str())
+                    .collect();
+                return Err(if unrestored.is_empty() {
+                    e.context(format!("Failed to import calibration of {}, joints already imported restored", joint.name))
+                } else {
+                    e.context(format!("Failed to import calibration of {} and to restore {}, the robot has mixed calibrations", joint.name, unrestored.join(", ")))
+                });
+            }
+        }
+        Ok(())
+    }
+}
+
+// Sweep a servo into both mechanical stops in constant speed mode, detecting
+// each stop as set by params.detection, then center the range and write it
+// to EEPROM.
+//
+// Clearing `running` aborts the sweep, leaving the EEPROM untouched. Once the
+// EEPROM write has started it always runs to completion, so an interrupt can
+// never leave a half-written calibration behind.
+pub fn calibrate_servo(servo: &Servo, id: u8, params: &CalibrationParams, running: &AtomicBool) -> Result<CalibrationRun> {
+    match params.detection {
+        StopDetection::Current => {
+            let mut detector = CurrentLimitDetector::new(servo, id, params)?;
+            calibrate_servo_with(servo, id, params, &mut detector, running)
+        }
+        StopDetection::Stall(rule) => {
+            let mut detector = StallLimitDetector::new(rule)?;
+            calibrate_servo_with(servo, id, params, &mut detector, running)
+        }
+        StopDetection::Auto(rule) => {
+            let mut detector = AutoLimitDetector::new(CurrentLimitDetector::new(servo, id, params)?, StallLimitDetector::new(rule)?)
+                .with_events(id, params.events.clone());
+            calibrate_servo_with(servo, id, params, &mut detector, running)
+        }
+    }
+}
+
+// Calibrate joints spread over several buses, each bus given as its Servo
+// and the IDs on it. Joints in `independent` run concurrently, one per bus at
+// a time; the rest run one at a time across all buses once those are done.
+// Clearing `running` stops every bus, joints not started by then are left
+// out of the results.
+//
+// Only list a joint as independent if nothing it's linked to can move during
+// its sweep: no tendon coupling, no shared linkage, no joint on another bus
+// carrying it. Sweeps stall the motor against its stop, so the supply also
+// has to carry one stall current per bus at once.
+pub fn calibrate_parallel(buses: &[(&Servo, Vec<u8>)], independent: &[u8], params: &CalibrationParams, running: &AtomicBool) -> Result<Vec<(u8, Result<CalibrationRun>)>> {
+    let mut seen = BTreeSet::new();
+    for (_, ids) in buses {
+        for &id in ids {
+            if !seen.insert(id) {
+                bail!("Servo {} is listed more than once", id);
+            }
+        }
+    }
+
+    let mut results: Vec<(u8, Result<CalibrationRun>)> = thread::scope(|scope| {
+        let handles: Vec<_> = buses.iter()
+            .map(|(servo, ids)| {
+                let ids: Vec<u8> = ids.iter().copied().filter(|id| independent.contains(id)).collect();
+                scope.spawn(move || calibrate_in_turn(servo, &ids, params, running))
+            })
+            .collect();
+        handles.into_iter()
+            .flat_map(|handle| handle.join().expect("Calibration thread panicked"))
+            .collect()
+    });
+
+    for (servo, ids) in buses {
+        let ids: Vec<u8> = ids.iter().copied().filter(|id| !independent.contains(id)).collect();
+        results.extend(calibrate_in_turn(servo, &ids, params, running));
+    }
+    Ok(results)
+}
+
+fn calibrate_in_turn(servo: &Servo, ids: &[u8], params: &CalibrationParams, running: &AtomicBool) -> Vec<(u8, Result<CalibrationRun>)> {
+    ids.iter()
+        .take_while(|_| running.load(Ordering::SeqCst))
+        .map(|&id| (id, calibrate_servo(servo, id, params, running)))
+        .collect()
+}
+
+// Same as calibrate_servo with the stops found by `detector`, e.g. a
+// SwitchLimitDetector on joints fitted with limit switches. Fails with
+// ServoError::BusClaimed if another process holds the bus lock, see BusLock.
+pub fn calibrate_servo_with(servo: &Servo, id: u8, params: &CalibrationParams, detector: &mut dyn LimitDetector, running: &AtomicBool) -> Result<CalibrationRun> {
+    servo.with_bus_claimed("calibration", |servo| calibrate_claimed(servo, id, params, detector, running))
+}
+
+fn calibrate_claimed(servo: &Servo, id: u8, params: &CalibrationParams, detector: &mut dyn LimitDetector, running: &AtomicBool) -> Result<CalibrationRun> {
+    // Checked again before writing, but a sweep on a servo whose result
+    // can't be written is wasted
+    servo.check_compatibility(id)?;
+    let trace = servo.with_readout_disabled(|servo| {
+        let sweep = sweep_stops(servo, id, params, detector, running);
+        let restored = restore_after_sweep(servo, id);
+        let trace = sweep?;
+        restored?;
+        Ok(trace)
+    })?;
+
+    // The backed-off positions depend on how far each stop was backed off
+    // from, only the stop positions themselves give the geometric center
+    let resolution = servo.read_resolution(id, &params.resolution)?;
+    let mut calibration = match params.writes {
+        CalibrationWrites::LimitsOnly => limits_at_stops(trace.backward.position(), trace.forward.position(), servo.read_offset(id)?)?,
+        _ => compute_calibration_with(trace.backward.position(), trace.forward.position(), resolution),
+    };
+    // A single sweep, scored without repeatability
+    let confidence = score_sweeps(&[trace]);
+    if let Some(min_score) = params.verify_calibration {
+        if confidence.score < min_score {
+            release_torque(servo, id, params)?;
+            return Err(LowConfidence { id, confidence, min_score }.into());
+        }
+    }
+    if params.writes != CalibrationWrites::LimitsOnly && near_saturation(calibration.offset) {
+        let margin = offset_margin(calibration.offset);
+        if params.strict_offset {
+            release_torque(servo, id, params)?;
+            return Err(OffsetSaturated { id, offset: calibration.offset, margin }.into());
+        }
+        eprintln!(
+            "Warning: servo {} needs offset {}, only {} ticks from the ±{} limit. Refit the horn or linkage closer to center",
+            id, calibration.offset, margin, MAX_OFFSET
+        );
+    }
+    params.notify(CalibrationEvent::Writing { id, calibration, writes: params.writes });
+    match params.writes {
+        CalibrationWrites::All => {
+            servo.commit_calibration(id, &calibration)?;
+        }
+        CalibrationWrites::OffsetOnly => {
+            servo.write_offset(id, calibration.offset)?;
+            calibration = servo.read_calibration(id)?;
+        }
+        CalibrationWrites::LimitsOnly => {
+            servo.write_limits(id, &calibration)?;
+            calibration = servo.read_calibration(id)?;
+        }
+    }
+
+    if !running.load(Ordering::SeqCst) {
+        params.notify(CalibrationEvent::Interrupted { id, phase: CalibrationPhase::EepromWrite });
+        release_torque(servo, id, params)?;
+        return Ok(CalibrationRun { calibration, trace, confidence });
+    }
+
+    // The calibration is already written at this point, not reaching center
+    // is worth a warning but doesn't undo it
+    // The middle of the limits, which is only the center when re-centered
+    let middle = calibration.min_angle + (calibration.max_angle - calibration.min_angle) / 2;
+    let center = if params.writes == CalibrationWrites::LimitsOnly { middle } else { resolution.center() as i16 };
+    if let Err(e) = servo.move_to_and_wait(id, center, &params.settle) {
+        eprintln!("Warning: {}", center_warning(servo, id, &e));
+    }
+    // Only after the center move, which needs torque: the joint ends up at
+    // center either way, free to move by hand unless keep_torque is set
+    release_torque(servo, id, params)?;
+    Ok(CalibrationRun { calibration, trace, confidence })
+}
+
+fn release_torque(servo: &Servo, id: u8, params: &CalibrationParams) -> Result<()> {
+    if params.keep_torque {
+        return Ok(());
+    }
+    servo.set_torque_mode(id, TorqueMode::Disabled)
+}
+
+fn center_warning(servo: &Servo, id: u8, error: &anyhow::Error) -> String {
+    let cause = match servo.read_mode(id) {
+        Ok(ServoMode::Position) => "it may be obstructed".to_string(),
+        Ok(mode) => format!("it is in {:?} mode and ignores position writes", mode),
+        Err(_) => "it may not be in position mode or is obstructed".to_string(),
+    };
+    format!("Servo {} did not reach center after calibration, {}: {:#}", id, cause, error)
+}
+
+fn sweep_stops(servo: &Servo, id: u8, params: &CalibrationParams, detector: &mut dyn LimitDetector, running: &AtomicBool) -> Result<SweepTrace> {
+    servo.write_servo_memory(id, ServoRegister::TorqueLimit, 150)?;
+    let detector = &mut BlankedDetector::new(detector, params.reversal_blanking);
+    if let Some(start) = &params.center_start {
+        let position = move_to_center(servo, id, start, params, detector, running)?;
+        params.notify(CalibrationEvent::CenterReached { id, position });
+    }
+    servo.set_mode(id, ServoMode::ConstantSpeed)?;
+
+    let mut forward = None;
+    let mut backward = None;
+
+    for pass in 0..2 {
+        let direction = if pass == 0 { ServoDirection::Clockwise } else { ServoDirection::Counterclockwise };
+        detector.start(direction)?;
+        let mut travel = 0;
+        let mut last_position = servo.read_position(id)?;
+        let mut slow_speed = params.speed;
+        let mut speed = sweep_speed(travel, slow_speed, params.approach.as_ref());
+        servo.set_speed(id, speed, direction)?;
+        let mut window = (Instant::now(), travel);
+
+        let mut suspected = false;
+        let mut raw_stop = 0;
+        let mut poller = params.poll.map(AdaptivePoller::new);
+        let mut suspects = SuspectFrames::new(MAX_SUSPECT_FRAMES);
+        let expected = match (&params.progress, pass) {
+            (Some(progress), 0) if params.center_start.is_some() => progress.expected_travel / 2,
+            (Some(progress), _) => progress.expected_travel,
+            (None, _) => 0,
+        };
+
+        loop {
+            if !running.load(Ordering::SeqCst) {
+                servo.set_speed(id, 0, ServoDirection::Clockwise)?;
+                if params.on_interrupt == InterruptAction::Hold {
+                    let position = hold_position(servo, id)?;
+                    params.notify(CalibrationEvent::Holding { id, position });
+                }
+                params.notify(CalibrationEvent::Interrupted { id, phase: CalibrationPhase::Sweep });
+                return Err(CalibrationInterrupted { phase: CalibrationPhase::Sweep }.into());
+            }
+
+            let Some(info) = suspects.accept(read_plausible_info(servo, id, &params.reading_checks))? else {
+                sleep(SWEEP_POLL_INTERVAL);
+                continue;
+            };
+
+            travel += travelled(last_position, info.current_location);
+            last_position = info.current_location;
+            if params.max_travel.is_some_and(|max_travel| travel > max_travel) {
+                servo.set_speed(id, 0, direction)?;
+                return Err(NoHardStop { id, direction, travel }.into());
+            }
+            if let Some(escalation) = &params.escalation {
+                if speed == slow_speed && !suspected && window.0.elapsed() >= escalation.window {
+                    if travel - window.1 < escalation.min_travel as u32 && slow_speed < escalation.max_speed {
+                        slow_speed = escalate_speed(slow_speed, escalation);
+                        params.notify(CalibrationEvent::SpeedRaised { id, from: speed, to: slow_speed });
+                    }
+                    window = (Instant::now(), travel);
+                }
+            }
+            let next_speed = sweep_speed(travel, slow_speed, params.approach.as_ref());
+            if next_speed != speed {
+                speed = next_speed;
+                servo.set_speed(id, speed, direction)?;
+            }
+
+            let reading = detector.check(&info, direction)?;
+            if let Some(progress) = &params.progress {
+                progress.report(pass, direction, travel, expected, reading == LimitReading::Reached);
+            }
+            if reading != LimitReading::Clear && !suspected {
+                raw_stop = info.current_location;
+            }
+            suspected = reading != LimitReading::Clear;
+
+            if reading == LimitReading::Reached {
+                if let Some(ramp) = &params.deceleration {
+                    servo.set_speed_ramped(id, speed, 0, direction, ramp)?;
+                }
+                for _ in 0..3 {
+                    sleep(Duration::from_millis(10));
+                    servo.set_speed(id, 0, direction)?;
+                }
+                sleep(Duration::from_millis(100));
+                let at_rest = if params.stop_samples > 1 {
+                    Some(read_rest_position(servo, id, params)?)
+                } else {
+                    None
+                };
+
+                // Back off the stop so the joint isn't left loaded
+                servo.set_speed(id, params.speed, opposite_direction(direction))?;
+                sleep(params.backoff.after(direction));
+
+                servo.set_speed(id, 0, opposite_direction(direction))?;
+                sleep(Duration::from_millis(100));
+
+                let stop = StopTrace {
+                    raw: raw_stop,
+                    reached: info.current_location,
+                    at_rest,
+                    backed_off: read_plausible_info(servo, id, &params.reading_checks)?.current_location,
+                };
+                if direction == ServoDirection::Clockwise {
+                    forward = Some(stop);
+                } else {
+                    backward = Some(stop);
+                }
+
+                break;
+            }
+
+            sleep(match &mut poller {
+                Some(poller) => poller.next(reading, detector.proximity()),
+                None => SWEEP_POLL_INTERVAL,
+            });
+        }
+
+        if pass < 1 {
+            sleep(Duration::from_millis(500));
+        }
+    }
+
+    match (backward, forward) {
+        (Some(backward), Some(forward)) => Ok(SweepTrace { backward, forward }),
+        _ => bail!("Sweep of servo {} finished without finding both stops", id),
+    }
+}
+
+// Mean of stop_samples positions, skipping reads taken while the joint still
+// moves, e.g. settling off its rebound
+fn read_rest_position(servo: &Servo, id: u8, params: &CalibrationParams) -> Result<i16> {
+    let mut samples = Vec::with_capacity(params.stop_samples);
+    for _ in 0..params.stop_samples * 3 {
+        let info = read_plausible_info(servo, id, &params.reading_checks)?;
+        if info.speed() == 0 {
+            samples.push(info.current_location);
+            if samples.len() == params.stop_samples {
+                break;
+            }
+        }
+        sleep(Duration::from_millis(5));
+    }
+    match mean_position(&samples) {
+        Some(position) => Ok(position),
+        None => bail!("Servo {} never came to rest against its stop", id),
+    }
+}
+
+// Consecutive suspect frames a moving joint may read before the run fails,
+// e.g. while it passes through tick 0, which ReadingChecks flags as a
+// zero-filled frame
+pub const MAX_SUSPECT_FRAMES: usize = 20;
+
+// Drops suspect frames, up to `max` in a row, so a joint moving through a
+// position that reads as implausible keeps being sampled
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SuspectFrames {
+    dropped: usize,
+    max: usize,
+}
+
+impl SuspectFrames {
+    pub fn new(max: usize) -> Self {
+        Self { dropped: 0, max }
+    }
+
+    // The frame read, None for a suspect one to skip. Any other error, or
+    // a suspect frame past `max` in a row, is returned.
+    pub fn accept(&mut self, read: Result<ServoInfo>) -> Result<Option<ServoInfo>> {
+        match read {
+            Ok(info) => {
+                self.dropped = 0;
+                Ok(Some(info))
+            }
+            Err(e) if self.dropped < self.max && is_suspect(&e) => {
+                self.dropped += 1;
+                Ok(None)
+            }
+            Err(e) => Err(e),
+        }
+    }
+}
+
+fn is_suspect(error: &anyhow::Error) -> bool {
+    matches!(error.downcast_ref::<ServoError>(), Some(ServoError::SuspectReading { .. }))
+}
+
+// A few quick retries on a suspect frame, then its error is returned
+fn read_plausible_info(servo: &Servo, id: u8, checks: &ReadingChecks) -> Result<ServoInfo> {
+    let mut retries = 0;
+    loop {
+        match servo.read_info_checked(id, checks) {
+            Err(e) if retries < 3 && is_suspect(&e) => {
+                retries += 1;
+                sleep(Duration::from_millis(1));
+            }
+            result => return result,
+        }
+    }
+}
+
+fn move_to_center(servo: &Servo, id: u8, start: &CenterStart, params: &CalibrationParams, detector: &mut dyn LimitDetector, running: &AtomicBool) -> Result<i16> {
+    let position = hold_position(servo, id)?;
+    let direction = if CENTER_POSITION >= position { ServoDirection::Clockwise } else { ServoDirection::Counterclockwise };
+    detector.start(direction)?;
+    servo.set_torque_mode(id, TorqueMode::Enabled)?;
+    servo.write_u16(id, ServoRegister::RunningSpeed, start.speed)?;
+    servo.write_u16(id, ServoRegister::TargetLocation, CENTER_POSITION as u16)?;
+
+    let started = Instant::now();
+    let mut suspects = SuspectFrames::new(MAX_SUSPECT_FRAMES);
+    loop {
+        if !running.load(Ordering::SeqCst) {
+            hold_position(servo, id)?;
+            params.notify(CalibrationEvent::Interrupted { id, phase: CalibrationPhase::Prepositioning });
+            return Err(CalibrationInterrupted { phase: CalibrationPhase::Prepositioning }.into());
+        }
+
+        let Some(info) = suspects.accept(read_plausible_info(servo, id, &params.reading_checks))? else {
+            sleep(start.settle.poll_interval);
+            continue;
+        };
+        if detector.check(&info, direction)? == LimitReading::Reached {
+            let position = hold_position(servo, id)?;
+            bail!("Servo {} hit a stop at {} moving {:?} to center before the sweep, the joint is not between its stops", id, position, direction);
+        }
+        if start.settle.is_settled(info.current_location, CENTER_POSITION) {
+            return Ok(info.current_location);
+        }
+        if started.elapsed() >= start.settle.timeout {
+            hold_position(servo, id)?;
+            bail!("Servo {} did not reach center before the sweep within {:?}, stuck at {}", id, start.settle.timeout, info.current_location);
+        }
+        sleep(start.settle.poll_interval);
+    }
+}
+
+// The goal is written before the mode switch: in position mode the servo
+// immediately heads for whatever goal is stored
+fn hold_position(servo: &Servo, id: u8) -> Result<i16> {
+    let position = servo.read_position(id)?;
+    servo.write_u16(id, ServoRegister::TargetLocation, position as u16)?;
+    servo.set_mode(id, ServoMode::Position)?;
+    Ok(position)
+}
+
+fn restore_after_sweep(servo: &Servo, id: u8) -> Result<()> {
+    servo.set_memory_lock(id, MemoryLockState::Unlocked)?;
+    servo.set_speed(id, 0, ServoDirection::Clockwise)?;
+    servo.write_servo_memory(id, ServoRegister::TorqueLimit, 600)?;
+    // After an interrupt or a failed sweep the stored goal is stale
+    hold_position(servo, id)?;
+    servo.lock_eeprom(id)
+}
+
+pub fn opposite_direction(direction: ServoDirection) -> ServoDirection {
+    match direction {
+        ServoDirection::Clockwise => ServoDirection::Counterclockwise,
+        ServoDirection::Counterclockwise => ServoDirection::Clockwise,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_path(name: &str) -> std::path::PathBuf {
+        std::env::temp_dir().join(format!("calibration-{}-{}.json", name, std::process::id()))
+    }
+
+    #[test]
+    fn offset_is_sign_magnitude() {
+        assert_eq!(encode_offset(100), 100);
+        assert_eq!(encode_offset(-100), 0x800 | 100);
+        assert_eq!(decode_offset(0x800 | 100), -100);
+        for offset in [-MAX_OFFSET, -1, 0, 1, 1000, MAX_OFFSET] {
+            assert_eq!(decode_offset(encode_offset(offset)), offset);
+        }
+    }
+
+    #[test]
+    fn offsets_past_half_a_turn_wrap_negative() {
+        assert_eq!(decode_offset(encode_offset(4000)), -96);
+    }
+
+    #[test]
+    fn calibration_file_round_trips() {
+        let mut file = CalibrationFile::default();
+        let calibration = Calibration { offset: -12, min_angle: 1000, max_angle: 3000 };
+        file.joints.insert("left_hip".to_string(), JointCalibration { id: 1, calibration, confidence: None });
+        let path = temp_path("round_trip");
+        file.save(&path).unwrap();
+        let loaded = CalibrationFile::load(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(loaded.joints["left_hip"].id, 1);
+        assert_eq!(loaded.joints["left_hip"].calibration, calibration);
+    }
+
+    #[test]
+    fn range_in_degrees_around_center() {
+        assert_eq!(range_deg(1024, 3072), (-90.0, 90.0));
+        assert_eq!(range_deg(2048, 2048), (0.0, 0.0));
+    }
+
+    #[test]
+    fn range_wrapping_through_zero_is_unwrapped() {
+        assert_eq!(range_deg(3072, 1024), (90.0, 270.0));
+    }
+
+    #[test]
+    fn stops_are_centered_around_2048() {
+        assert_eq!(compute_calibration(1000, 3000), Calibration { offset: -48, min_angle: 1048, max_angle: 3048 });
+        assert_eq!(compute_calibration(1048, 3048), Calibration { offset: 0, min_angle: 1048, max_angle: 3048 });
+    }
+
+    #[test]
+    fn backoff_is_per_stop() {
+        let backoff = Backoff { clockwise: Duration::from_millis(100), counterclockwise: Duration::from_millis(400) };
+        assert_eq!(backoff.after(ServoDirection::Clockwise), Duration::from_millis(100));
+        assert_eq!(backoff.after(ServoDirection::Counterclockwise), Duration::from_millis(400));
+        assert_eq!(Backoff::default(), Backoff::uniform(Duration::from_millis(350)));
+    }
+
+    #[test]
+    fn calibration_decodes_from_the_limit_and_offset_bytes() {
+        let limits = [0xE8, 0x03, 0xB8, 0x0B];
+        let offset = [100, 0x08];
+        assert_eq!(decode_calibration(limits, offset), Calibration { offset: -100, min_angle: 1000, max_angle: 3000 });
+    }
+
+    #[test]
+    fn approach_speed_only_covers_its_distance() {
+        let approach = Approach { speed: 1000, distance: 300 };
+        assert_eq!(sweep_speed(0, 200, Some(&approach)), 1000);
+        assert_eq!(sweep_speed(299, 200, Some(&approach)), 1000);
+        assert_eq!(sweep_speed(300, 200, Some(&approach)), 200);
+        assert_eq!(sweep_speed(0, 200, None), 200);
+    }
+
+    #[test]
+    fn travel_takes_the_wrap_the_short_way() {
+        assert_eq!(travelled(1000, 1100), 100);
+        assert_eq!(travelled(1100, 1000), 100);
+        assert_eq!(travelled(4090, 5), 11);
+        assert_eq!(travelled(5, 4090), 11);
+    }
+
+    #[test]
+    fn escalation_steps_up_to_the_cap() {
+        let escalation = Escalation { window: Duration::from_millis(500), min_travel: 20, step: 50, max_speed: 220 };
+        assert_eq!(escalate_speed(100, &escalation), 150);
+        assert_eq!(escalate_speed(200, &escalation), 220);
+        assert_eq!(escalate_speed(220, &escalation), 220);
+        // Never lowers a speed already above the cap
+        assert_eq!(escalate_speed(300, &escalation), 300);
+    }
+
+    #[test]
+    fn trip_needs_m_of_the_last_n_readings() {
+        let mut detector = TripDetector::new(TripRule { required: 2, window: 3 }).unwrap();
+        assert!(!detector.push(true));
+        assert!(!detector.push(false));
+        assert!(detector.push(true));
+        // The first reading has left the window
+        assert!(!detector.push(false));
+        assert_eq!(detector.above(), 1);
+        assert!(detector.push(true));
+    }
+
+    #[test]
+    fn consecutive_trip_resets_on_a_reading_below() {
+        let mut detector = TripDetector::new(TripRule::default()).unwrap();
+        assert!(!detector.push(true));
+        assert!(!detector.push(true));
+        assert!(!detector.push(false));
+        assert!(!detector.push(true));
+        assert!(!detector.push(true));
+        assert!(detector.push(true));
+    }
+
+    #[test]
+    fn trip_rule_must_be_satisfiable() {
+        assert!(TripDetector::new(TripRule { required: 0, window: 3 }).is_err());
+        assert!(TripDetector::new(TripRule { required: 4, window: 3 }).is_err());
+    }
+
+    #[test]
+    fn current_detector_suspects_then_reaches_the_stop() {
+        let mut detector = CurrentLimitDetector { threshold: 500.0, scale: 1.0, trip: TripDetector::new(TripRule::consecutive(2)).unwrap(), current: 0.0 };
+        let reading = |current: u16| ServoInfo { current_current: current, ..ServoInfo::default() };
+        let direction = ServoDirection::Clockwise;
+        assert_eq!(detector.check(&reading(100), direction).unwrap(), LimitReading::Clear);
+        assert_eq!(detector.proximity(), Some(0.2));
+        assert_eq!(detector.check(&reading(600), direction).unwrap(), LimitReading::Suspected);
+        assert_eq!(detector.check(&reading(600), direction).unwrap(), LimitReading::Reached);
+        // Each direction starts over
+        detector.start(ServoDirection::Counterclockwise).unwrap();
+        assert_eq!(detector.check(&reading(600), direction).unwrap(), LimitReading::Suspected);
+    }
+
+    #[test]
+    fn suspect_frames_are_dropped_up_to_max_in_a_row() {
+        let suspect = || Err(ServoError::SuspectReading { id: 1, reason: "0 V supply".to_string() }.into());
+        let mut frames = SuspectFrames::new(2);
+        assert!(frames.accept(suspect()).unwrap().is_none());
+        assert!(frames.accept(suspect()).unwrap().is_none());
+        assert!(frames.accept(suspect()).is_err());
+
+        let mut frames = SuspectFrames::new(2);
+        frames.accept(suspect()).unwrap();
+        frames.accept(suspect()).unwrap();
+        // A good frame resets the count
+        assert!(frames.accept(Ok(ServoInfo::default())).unwrap().is_some());
+        assert!(frames.accept(suspect()).unwrap().is_none());
+        // Other errors are never dropped
+        assert!(frames.accept(Err(anyhow::anyhow!("no reply"))).is_err());
+    }
+
+    #[test]
+    fn factory_and_cleared_limits_are_uncalibrated() {
+        let calibration = |offset, (min_angle, max_angle)| Calibration { offset, min_angle, max_angle };
+        assert!(!calibration(0, FACTORY_LIMITS).is_calibrated());
+        assert!(!calibration(0, NO_LIMITS).is_calibrated());
+        assert!(calibration(12, FACTORY_LIMITS).is_calibrated());
+        assert!(calibration(0, (900, 3100)).is_calibrated());
+    }
+
+    #[test]
+    fn center_offset_folds_in_the_current_offset() {
+        assert_eq!(center_offset(2148, 0).unwrap(), 100);
+        assert_eq!(center_offset(2148, -40).unwrap(), 60);
+        assert_eq!(center_offset(100, 0).unwrap(), -1948);
+        // Half a turn away doesn't fit in the register
+        assert!(center_offset(0, 0).is_err());
+    }
+
+    #[test]
+    fn poll_slows_while_steady_and_speeds_up_near_the_limit() {
+        let mut poller = AdaptivePoller::new(AdaptivePoll::default());
+        let ms = Duration::from_millis;
+        assert_eq!(poller.interval(), ms(5));
+        let intervals: Vec<Duration> = [0.1, 0.11, 0.12, 0.13, 0.14, 0.3]
+            .into_iter()
+            .map(|proximity| poller.next(LimitReading::Clear, Some(proximity)))
+            .collect();
+        assert_eq!(intervals, [ms(5), ms(10), ms(20), ms(40), ms(40), ms(20)]);
+        assert_eq!(poller.next(LimitReading::Clear, Some(0.7)), ms(5));
+        poller.next(LimitReading::Clear, Some(0.1));
+        assert_eq!(poller.next(LimitReading::Suspected, Some(0.1)), ms(5));
+        // A detector that can't tell keeps it fast
+        poller.next(LimitReading::Clear, Some(0.1));
+        assert_eq!(poller.next(LimitReading::Clear, None), ms(5));
+    }
+
+    fn stop(raw: i16, reached: i16) -> StopTrace {
+        StopTrace { raw, reached, at_rest: None, backed_off: raw }
+    }
+
+    fn sweep(backward: i16, forward: i16) -> SweepTrace {
+        SweepTrace { backward: stop(backward, backward), forward: stop(forward, forward) }
+    }
+
+    #[test]
+    fn clean_sweep_scores_full_confidence() {
+        assert_eq!(score_sweeps(&[sweep(1000, 3000)]), Confidence { repeatability: None, travel: 1.0, sharpness: 1.0, score: 1.0 });
+        assert_eq!(score_sweeps(&[]).score, 0.0);
+    }
+
+    #[test]
+    fn score_is_the_worst_aspect() {
+        let spread = score_sweeps(&[sweep(1000, 3000), sweep(1000, 3016)]);
+        assert_eq!((spread.repeatability, spread.score), (Some(0.5), 0.5));
+        let repeated = score_sweeps(&[sweep(1000, 3000), sweep(1000, 3000)]);
+        assert_eq!((repeated.repeatability, repeated.score), (Some(1.0), 1.0));
+
+        let narrow = score_sweeps(&[sweep(1984, 2112)]);
+        assert_eq!((narrow.travel, narrow.score), (0.5, 0.5));
+        let full_turn = score_sweeps(&[sweep(2048 - 2040, 2048 + 2040)]);
+        assert_eq!(full_turn.travel, 0.25);
+
+        let soft = SweepTrace { backward: stop(1000, 1000), forward: stop(2900, 2900 + 48) };
+        let soft = score_sweeps(&[soft]);
+        assert_eq!((soft.sharpness, soft.score), (0.25, 0.25));
+    }
+
+    #[test]
+    fn mean_position_averages_across_the_wrap() {
+        assert_eq!(mean_position(&[]), None);
+        assert_eq!(mean_position(&[1000, 1002, 1004, 1006]), Some(1003));
+        assert_eq!(mean_position(&[4094, 2]), Some(0));
+        assert_eq!(mean_position(&[4092, 4094, 4]), Some(4095));
+    }
+
+    #[test]
+    fn resting_position_replaces_the_crossing() {
+        let mut trace = stop(1000, 1000);
+        assert_eq!(trace.position(), 1000);
+        trace.at_rest = Some(1008);
+        assert_eq!(trace.position(), 1008);
+        // Scored on the resting positions, here 16 ticks apart
+        let rested = SweepTrace { backward: StopTrace { at_rest: Some(1016), ..stop(1000, 1000) }, forward: stop(3000, 3000) };
+        assert_eq!(score_sweeps(&[sweep(1000, 3000), rested]).repeatability, Some(0.5));
+    }
+
+    #[test]
+    fn offset_margin_is_measured_to_the_register_limit() {
+        assert_eq!(offset_margin(0), MAX_OFFSET);
+        assert_eq!(offset_margin(MAX_OFFSET), 0);
+        assert_eq!(offset_margin(-2000), 47);
+        // Wrapped like encode_offset, 4000 is -96
+        assert_eq!(offset_margin(4000), MAX_OFFSET - 96);
+        assert!(offset_margin(2048) < 0);
+        assert!(near_saturation(MAX_OFFSET - OFFSET_SATURATION_MARGIN + 1));
+        assert!(!near_saturation(MAX_OFFSET - OFFSET_SATURATION_MARGIN));
+    }
+
+    #[test]
+    fn center_comes_from_the_resolution() {
+        let scs = Resolution { ticks_per_turn: 1229, max_tick: 1023 };
+        assert_eq!(compute_calibration_with(100, 900, scs), Calibration { offset: -12, min_angle: 112, max_angle: 912 });
+        // No wrap through 0 on a reduced range, the stops just swapped
+        assert_eq!(compute_calibration_with(900, 100, scs), compute_calibration_with(100, 900, scs));
+        assert_eq!(compute_calibration_with(3900, 100, Resolution::STS), compute_calibration(3900, 100));
+        assert_eq!(compute_calibration(3900, 100).offset, 2000);
+        let (min, max) = range_deg_with(112, 912, scs);
+        assert!((min + 117.17).abs() < 0.01 && (max - 117.17).abs() < 0.01, "{} {}", min, max);
+    }
+
+    #[test]
+    fn writes_pick_the_registers_they_name() {
+        let calibration = Calibration { offset: -40, min_angle: 900, max_angle: 3100 };
+        let mut written = Vec::new();
+        write_registers(&calibration, CalibrationWrites::All.registers(), |register, value| {
+            written.push((register as u8, value));
+            Ok(())
+        }).unwrap();
+        assert_eq!(written, [
+            (ServoRegister::PositionCorrection as u8, encode_offset(-40)),
+            (ServoRegister::MinAngleLimit as u8, 900),
+            (ServoRegister::MaxAngleLimit as u8, 3100),
+        ]);
+        assert_eq!(CalibrationWrites::OffsetOnly.registers().len(), 1);
+        assert_eq!(CalibrationWrites::LimitsOnly.registers().len(), 2);
+        let error = write_registers(&calibration, &[ServoRegister::TorqueLimit], |_, _| Ok(())).unwrap_err();
+        assert_eq!(error.to_string(), "TorqueLimit is not a calibration register");
+    }
+
+    #[test]
+    fn limits_at_stops_keep_the_offset() {
+        assert_eq!(limits_at_stops(960, 2960, 40).unwrap(), Calibration { offset: 40, min_angle: 960, max_angle: 2960 });
+        let error = limits_at_stops(3900, 100, 40).unwrap_err();
+        assert!(error.to_string().contains("recalibrate the offset too"), "{}", error);
+    }
+
+    // Reports Reached on every reading it's given, counting them
+    #[derive(Default)]
+    struct Tripped {
+        starts: Vec<ServoDirection>,
+        checks: usize,
+    }
+
+    impl LimitDetector for Tripped {
+        fn start(&mut self, direction: ServoDirection) -> Result<()> {
+            self.starts.push(direction);
+            Ok(())
+        }
+
+        fn check(&mut self, _info: &ServoInfo, _direction: ServoDirection) -> Result<LimitReading> {
+            self.checks += 1;
+            Ok(LimitReading::Reached)
+        }
+
+        fn proximity(&self) -> Option<f32> {
+            Some(2.0)
+        }
+    }
+
+    #[test]
+    fn readings_right_after_a_start_are_blanked() {
+        let mut inner = Tripped::default();
+        let info = ServoInfo::default();
+        {
+            let mut detector = BlankedDetector::new(&mut inner, Duration::from_millis(20));
+            detector.start(ServoDirection::Clockwise).unwrap();
+            assert_eq!(detector.check(&info, ServoDirection::Clockwise).unwrap(), LimitReading::Clear);
+            assert_eq!(detector.proximity(), None);
+            sleep(Duration::from_millis(25));
+            assert_eq!(detector.check(&info, ServoDirection::Clockwise).unwrap(), LimitReading::Reached);
+            assert_eq!(detector.proximity(), Some(2.0));
+
+            // Every start blanks again
+            detector.start(ServoDirection::Counterclockwise).unwrap();
+            assert_eq!(detector.check(&info, ServoDirection::Counterclockwise).unwrap(), LimitReading::Clear);
+        }
+        assert_eq!(inner.starts, vec![ServoDirection::Clockwise, ServoDirection::Counterclockwise]);
+        // Blanked readings never reached the inner detector
+        assert_eq!(inner.checks, 1);
+    }
+
+    #[test]
+    fn zero_blanking_checks_from_the_first_reading() {
+        let mut inner = Tripped::default();
+        let mut detector = BlankedDetector::new(&mut inner, Duration::ZERO);
+        detector.start(ServoDirection::Clockwise).unwrap();
+        assert_eq!(detector.check(&ServoInfo::default(), ServoDirection::Clockwise).unwrap(), LimitReading::Reached);
+    }
+
+    #[test]
+    fn nothing_is_blanked_before_the_first_start() {
+        let mut inner = Tripped::default();
+        let mut detector = BlankedDetector::new(&mut inner, Duration::from_secs(10));
+        assert_eq!(detector.check(&ServoInfo::default(), ServoDirection::Clockwise).unwrap(), LimitReading::Reached);
+    }
+
+    #[test]
+    fn progress_is_travel_over_the_expected_travel() {
+        let seen = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let progress = {
+            let seen = seen.clone();
+            ProgressReport::new(2000, move |progress: &SweepProgress| seen.lock().unwrap().push(progress.fraction))
+        };
+        let direction = ServoDirection::Clockwise;
+        assert_eq!(progress.report(0, direction, 500, 2000, false).fraction, 0.25);
+        // Held short of done until the stop is actually found
+        assert_eq!(progress.report(0, direction, 2500, 2000, false).fraction, MAX_ESTIMATED_FRACTION);
+        assert_eq!(progress.report(0, direction, 1200, 2000, true).fraction, 1.0);
+        assert_eq!(progress.report(1, direction, 100, 0, false).fraction, 0.0);
+        assert_eq!(*seen.lock().unwrap(), vec![0.25, MAX_ESTIMATED_FRACTION, 1.0, 0.0]);
+    }
+
+    #[test]
+    fn overall_progress_spans_both_passes() {
+        let progress = |pass, fraction| SweepProgress { pass, direction: ServoDirection::Clockwise, travel: 0, fraction };
+        assert_eq!(progress(0, 0.5).overall(), 0.25);
+        assert_eq!(progress(1, 0.0).overall(), 0.5);
+        assert_eq!(progress(1, 1.0).overall(), 1.0);
+    }
+
+    fn at(position: i16, current: u16) -> ServoInfo {
+        ServoInfo { current_location: position, current_current: current, ..ServoInfo::default() }
+    }
+
+    #[test]
+    fn stall_is_reached_after_enough_stationary_readings() {
+        assert!(StallLimitDetector::new(StallRule { samples: 0, tolerance: 2 }).is_err());
+        let mut detector = StallLimitDetector::new(StallRule { samples: 3, tolerance: 2 }).unwrap();
+        let direction = ServoDirection::Clockwise;
+        assert_eq!(detector.check(&at(1000, 0), direction).unwrap(), LimitReading::Clear);
+        assert_eq!(detector.check(&at(1100, 0), direction).unwrap(), LimitReading::Clear);
+        // Within the tolerance counts as stationary
+        assert_eq!(detector.check(&at(1102, 0), direction).unwrap(), LimitReading::Suspected);
+        assert_eq!(detector.check(&at(1099, 0), direction).unwrap(), LimitReading::Suspected);
+        assert_eq!(detector.proximity(), Some(2.0 / 3.0));
+        assert_eq!(detector.check(&at(1100, 0), direction).unwrap(), LimitReading::Reached);
+        assert_eq!(detector.stationary(), 3);
+
+        detector.start(ServoDirection::Counterclockwise).unwrap();
+        assert_eq!(detector.stationary(), 0);
+        assert_eq!(detector.check(&at(1100, 0), direction).unwrap(), LimitReading::Clear);
+    }
+
+    fn auto_detector() -> AutoLimitDetector {
+        let current = CurrentLimitDetector { threshold: 500.0, scale: 1.0, trip: TripDetector::new(TripRule::consecutive(1)).unwrap(), current: 0.0 };
+        AutoLimitDetector::new(current, StallLimitDetector::new(StallRule { samples: 3, tolerance: 0 }).unwrap())
+    }
+
+    #[test]
+    fn auto_detects_by_current_while_it_reads() {
+        let mut detector = auto_detector();
+        let direction = ServoDirection::Clockwise;
+        assert_eq!(detector.check(&at(1000, 50), direction).unwrap(), LimitReading::Clear);
+        assert_eq!(detector.check(&at(1010, 2000), direction).unwrap(), LimitReading::Reached);
+        assert!(!detector.fallen_back());
+    }
+
+    #[test]
+    fn auto_falls_back_to_stall_on_zero_current_while_moving() {
+        let mut detector = auto_detector();
+        let direction = ServoDirection::Clockwise;
+        // Zero while standing still doesn't count, a real current resets the count
+        detector.check(&at(1000, 0), direction).unwrap();
+        detector.check(&at(1000, 0), direction).unwrap();
+        for step in 1..ZERO_CURRENT_SAMPLES as i16 {
+            detector.check(&at(1000 + step * 10, 0), direction).unwrap();
+        }
+        detector.check(&at(1100, 50), direction).unwrap();
+        assert!(!detector.fallen_back());
+
+        for step in 1..=ZERO_CURRENT_SAMPLES as i16 {
+            detector.check(&at(1100 + step * 10, 0), direction).unwrap();
+        }
+        assert!(detector.fallen_back());
+        // From now on only the position matters
+        assert_eq!(detector.check(&at(1200, 2000), direction).unwrap(), LimitReading::Suspected);
+        detector.check(&at(1200, 2000), direction).unwrap();
+        assert_eq!(detector.check(&at(1200, 2000), direction).unwrap(), LimitReading::Reached);
+        detector.start(ServoDirection::Counterclockwise).unwrap();
+        assert!(detector.fallen_back());
+    }
+
+    #[test]
+    fn symmetric_range_is_the_nearer_limit() {
+        assert_eq!(symmetric_range(1000, 3000), 952);
+        assert_eq!(symmetric_range(1024, 3072), 1024);
+        assert_eq!(symmetric_range_deg(1024, 3072), 90.0);
+        assert_eq!(symmetric_range(NO_LIMITS.0, NO_LIMITS.1), 2047);
+        // Limits that don't take in the center leave nothing either way
+        assert_eq!(symmetric_range(2100, 3000), 0);
+        assert_eq!(symmetric_range(3000, 1000), 0);
+    }
+
+    #[test]
+    fn symmetric_range_follows_the_model_resolution() {
+        let scs = Resolution { ticks_per_turn: 1229, max_tick: 1023 };
+        assert_eq!(symmetric_range_with(100, 900, scs), 388);
+        assert_eq!(symmetric_range_with(NO_LIMITS.0, NO_LIMITS.1, scs), 511);
+    }
+
+    #[cfg(not(feature = "milkv"))]
+    mod bus {
+        use super::*;
+        use crate::hal::mock::{MockBus, MockWrite};
+        use crate::robot::tests::mock_robot;
+        use crate::servo::{decode_speed, encode_speed};
+
+        pub(super) const STOPS: (i32, i32) = (1000, 3000);
+        const STALL_CURRENT: u16 = 2000;
+        const FREE_CURRENT: u16 = 50;
+
+        // A joint between hard stops at `stops` in raw ticks: constant speed
+        // mode turns a tenth of RunningSpeed per packet, position mode goes
+        // straight to the goal. Against a stop the current rises to
+        // STALL_CURRENT. The servo reports the raw position minus its offset.
+        pub(super) fn simulate(bus: &MockBus, id: u8, stops: (i32, i32)) {
+            simulate_currents(bus, id, stops, (FREE_CURRENT, STALL_CURRENT));
+        }
+
+        // As simulate, drawing `currents` moving freely and against a stop
+        fn simulate_currents(bus: &MockBus, id: u8, stops: (i32, i32), currents: (u16, u16)) {
+            let mut raw = CENTER_POSITION as i32;
+            bus.on_packet(move |servos| {
+                let offset = decode_offset(servos.u16(id, ServoRegister::PositionCorrection)) as i32;
+                let mode = ServoMode::try_from(servos.u8(id, ServoRegister::OperationMode)).unwrap();
+                let (mut moved, mut blocked) = (0, false);
+                match mode {
+                    ServoMode::ConstantSpeed => {
+                        let step = decode_speed(servos.u16(id, ServoRegister::RunningSpeed)) as i32 / 10;
+                        let next = (raw + step).clamp(stops.0, stops.1);
+                        (moved, blocked) = (next - raw, step != 0 && next == raw);
+                        raw = next;
+                    }
+                    _ => raw = (servos.u16(id, ServoRegister::TargetLocation) as i16 as i32 + offset).clamp(stops.0, stops.1),
+                }
+                let (speed, direction) = crate::servo::speed_direction((moved * 10) as i16);
+                servos.set_u16(id, ServoRegister::CurrentLocation, (raw - offset).rem_euclid(4096) as u16);
+                servos.set_u16(id, ServoRegister::CurrentSpeed, encode_speed(speed, direction));
+                // Where decode_info takes ServoInfo::current_current from,
+                // the last two of the 30 bytes read from TorqueSwitch
+                let current = if blocked { currents.1 } else { currents.0 };
+                servos.memory.get_mut(&id).unwrap()[0x44..0x46].copy_from_slice(&current.to_le_bytes());
+            });
+        }
+
+        pub(super) fn params() -> CalibrationParams {
+            CalibrationParams {
+                speed: 200,
+                current_threshold: 500.0,
+                current_scaling: ModelScaling::current(),
+                settle: SettleConfig::default(),
+                backoff: Backoff::uniform(Duration::ZERO),
+                writes: CalibrationWrites::All,
+                approach: None,
+                escalation: None,
+                on_interrupt: InterruptAction::Stop,
+                trip: TripRule::default(),
+                deceleration: None,
+                keep_torque: false,
+                max_travel: Some(DEFAULT_MAX_TRAVEL),
+                center_start: None,
+                reading_checks: ReadingChecks::default(),
+                poll: None,
+                verify_calibration: None,
+                stop_samples: 1,
+                strict_offset: false,
+                resolution: ModelResolution::sts(),
+                reversal_blanking: DEFAULT_REVERSAL_BLANKING,
+                progress: None,
+                events: None,
+                detection: StopDetection::default(),
+            }
+        }
+
+        fn set_calibration(bus: &MockBus, id: u8, calibration: Calibration) {
+            bus.set_u16(id, ServoRegister::PositionCorrection, encode_offset(calibration.offset));
+            bus.set_u16(id, ServoRegister::MinAngleLimit, calibration.min_angle as u16);
+            bus.set_u16(id, ServoRegister::MaxAngleLimit, calibration.max_angle as u16);
+        }
+
+        fn stored(bus: &MockBus, id: u8) -> Calibration {
+            Calibration {
+                offset: decode_offset(bus.u16(id, ServoRegister::PositionCorrection)),
+                min_angle: bus.u16(id, ServoRegister::MinAngleLimit) as i16,
+                max_angle: bus.u16(id, ServoRegister::MaxAngleLimit) as i16,
+            }
+        }
+
+        const JOINTS: [(&str, u8); 2] = [("left_hip", 1), ("right_hip", 2)];
+        const LEFT: Calibration = Calibration { offset: 25, min_angle: 900, max_angle: 3100 };
+        const RIGHT: Calibration = Calibration { offset: -40, min_angle: 1200, max_angle: 2900 };
+
+        #[test]
+        fn export_then_import_clones_a_robot() {
+            let (tuned_bus, tuned) = mock_robot(&JOINTS);
+            set_calibration(&tuned_bus, 1, LEFT);
+            set_calibration(&tuned_bus, 2, RIGHT);
+            let path = temp_path("clone");
+            tuned.export_robot_calibration(&path).unwrap();
+
+            let (fresh_bus, fresh) = mock_robot(&JOINTS);
+            fresh.import_robot_calibration(&path).unwrap();
+            std::fs::remove_file(&path).unwrap();
+            assert_eq!(stored(&fresh_bus, 1), LEFT);
+            assert_eq!(stored(&fresh_bus, 2), RIGHT);
+            assert_eq!(fresh_bus.u8(1, ServoRegister::LockMark), MemoryLockState::Locked as u8);
+        }
+
+        #[test]
+        fn import_refuses_a_file_missing_a_joint() {
+            let (bus, robot) = mock_robot(&JOINTS);
+            let mut file = CalibrationFile::default();
+            file.joints.insert("left_hip".to_string(), JointCalibration { id: 1, calibration: LEFT, confidence: None });
+            let path = temp_path("missing");
+            file.save(&path).unwrap();
+            assert!(robot.import_robot_calibration(&path).is_err());
+            std::fs::remove_file(&path).unwrap();
+            assert!(bus.writes().is_empty());
+        }
+
+        #[test]
+        fn import_refuses_an_entry_for_another_servo() {
+            let (_, robot) = mock_robot(&JOINTS);
+            let mut file = CalibrationFile::default();
+            file.joints.insert("left_hip".to_string(), JointCalibration { id: 1, calibration: LEFT, confidence: None });
+            file.joints.insert("right_hip".to_string(), JointCalibration { id: 7, calibration: RIGHT, confidence: None });
+            assert!(robot.check_calibration_file(&file).is_err());
+        }
+
+        #[test]
+        fn import_checks_every_servo_before_writing() {
+            let (bus, robot) = mock_robot(&JOINTS);
+            bus.set_u8(2, ServoRegister::FirmwareMajorVersion, 4);
+            let mut file = CalibrationFile::default();
+            file.joints.insert("left_hip".to_string(), JointCalibration { id: 1, calibration: LEFT, confidence: None });
+            file.joints.insert("right_hip".to_string(), JointCalibration { id: 2, calibration: RIGHT, confidence: None });
+            let path = temp_path("incompatible");
+            file.save(&path).unwrap();
+            assert!(robot.import_robot_calibration(&path).is_err());
+            std::fs::remove_file(&path).unwrap();
+            assert!(bus.writes().is_empty());
+        }
+
+        #[test]
+        fn failed_import_restores_the_joints_already_written() {
+            let (bus, robot) = mock_robot(&JOINTS);
+            let original = stored(&bus, 1);
+            bus.refuse(2, ServoRegister::MaxAngleLimit);
+            let mut file = CalibrationFile::default();
+            file.joints.insert("left_hip".to_string(), JointCalibration { id: 1, calibration: LEFT, confidence: None });
+            file.joints.insert("right_hip".to_string(), JointCalibration { id: 2, calibration: RIGHT, confidence: None });
+            let path = temp_path("rollback");
+            file.save(&path).unwrap();
+            let error = robot.import_robot_calibration(&path).unwrap_err();
+            std::fs::remove_file(&path).unwrap();
+            assert!(format!("{:#}", error).contains("restored"));
+            assert_eq!(stored(&bus, 1), original);
+        }
+
+        #[test]
+        fn joint_range_reads_the_stored_limits() {
+            let (bus, robot) = mock_robot(&JOINTS);
+            set_calibration(&bus, 1, Calibration { offset: 0, min_angle: 1024, max_angle: 3072 });
+            assert_eq!(robot.joint_range_deg("left_hip").unwrap(), (-90.0, 90.0));
+            assert!(robot.joint_range_deg("left_knee").is_err());
+        }
+
+        #[test]
+        fn sweep_finds_both_stops_and_writes_a_centered_calibration() {
+            let bus = MockBus::new(&[1]);
+            simulate(&bus, 1, STOPS);
+            let servo = Servo::mock(&bus);
+            let run = calibrate_servo(&servo, 1, &params(), &AtomicBool::new(true)).unwrap();
+            assert_eq!((run.trace.backward.position(), run.trace.forward.position()), (1000, 3000));
+            assert_eq!(run.calibration, compute_calibration(1000, 3000));
+            assert_eq!(servo.read_calibration(1).unwrap(), run.calibration);
+            assert_eq!(servo.read_mode(1).unwrap(), ServoMode::Position);
+        }
+
+        #[test]
+        fn interrupted_sweep_leaves_the_eeprom_untouched() {
+            let bus = MockBus::new(&[1]);
+            simulate(&bus, 1, STOPS);
+            let servo = Servo::mock(&bus);
+            let error = calibrate_servo(&servo, 1, &params(), &AtomicBool::new(false)).unwrap_err();
+            assert_eq!(error.downcast_ref::<CalibrationInterrupted>().unwrap().phase, CalibrationPhase::Sweep);
+            assert!(bus.writes().iter().all(|write| write.address != ServoRegister::PositionCorrection as u8));
+        }
+
+        #[test]
+        fn calibration_is_centered_on_the_stops_not_the_backed_off_positions() {
+            let bus = MockBus::new(&[1]);
+            simulate(&bus, 1, STOPS);
+            let servo = Servo::mock(&bus);
+            let trace = calibrate_servo(&servo, 1, &params(), &AtomicBool::new(true)).unwrap().trace;
+            assert_eq!((trace.backward.raw, trace.forward.raw), (1000, 3000));
+            assert!(trace.backward.backed_off > 1000, "{:?}", trace);
+            assert!(trace.forward.backed_off < 3000, "{:?}", trace);
+        }
+
+        #[test]
+        fn read_calibration_returns_what_is_stored() {
+            let bus = MockBus::new(&[1]);
+            set_calibration(&bus, 1, RIGHT);
+            assert_eq!(Servo::mock(&bus).read_calibration(1).unwrap(), RIGHT);
+        }
+
+        #[test]
+        fn center_warning_names_a_servo_outside_position_mode() {
+            let bus = MockBus::new(&[1]);
+            bus.set_u8(1, ServoRegister::OperationMode, ServoMode::ConstantSpeed as u8);
+            let servo = Servo::mock(&bus);
+            let warning = center_warning(&servo, 1, &anyhow::anyhow!("timed out"));
+            assert!(warning.contains("ConstantSpeed mode and ignores position writes"), "{}", warning);
+            assert!(warning.ends_with("timed out"), "{}", warning);
+
+            bus.set_u8(1, ServoRegister::OperationMode, ServoMode::Position as u8);
+            assert!(center_warning(&servo, 1, &anyhow::anyhow!("timed out")).contains("obstructed"));
+        }
+
+        #[test]
+        fn clear_limits_frees_the_range_and_write_offset_keeps_it() {
+            let bus = MockBus::new(&[1]);
+            set_calibration(&bus, 1, LEFT);
+            let servo = Servo::mock(&bus);
+            servo.clear_limits(1).unwrap();
+            servo.write_offset(1, -30).unwrap();
+            assert_eq!(stored(&bus, 1), Calibration { offset: -30, min_angle: NO_LIMITS.0, max_angle: NO_LIMITS.1 });
+            assert_eq!(bus.u8(1, ServoRegister::LockMark), MemoryLockState::Locked as u8);
+        }
+
+        #[test]
+        fn offset_only_sweep_leaves_the_limits_alone() {
+            let bus = MockBus::new(&[1]);
+            bus.set_u16(1, ServoRegister::MaxAngleLimit, NO_LIMITS.1 as u16);
+            simulate(&bus, 1, STOPS);
+            let servo = Servo::mock(&bus);
+            let params = CalibrationParams { writes: CalibrationWrites::OffsetOnly, ..params() };
+            let run = calibrate_servo(&servo, 1, &params, &AtomicBool::new(true)).unwrap();
+            let offset = compute_calibration(1000, 3000).offset;
+            assert_eq!(run.calibration, Calibration { offset, min_angle: NO_LIMITS.0, max_angle: NO_LIMITS.1 });
+            assert_eq!(stored(&bus, 1), run.calibration);
+        }
+
+        #[test]
+        fn partner_holds_where_it_is_before_leaving_speed_mode() {
+            let (bus, robot) = mock_robot(&JOINTS);
+            let robot = robot.with_couplings(vec![(1, 2)]);
+            bus.set_u8(2, ServoRegister::OperationMode, ServoMode::ConstantSpeed as u8);
+            bus.set_u16(2, ServoRegister::CurrentLocation, 1500);
+            simulate(&bus, 1, STOPS);
+            robot.calibrate_joint("left_hip", &params(), &AtomicBool::new(true)).unwrap();
+
+            let partner: Vec<MockWrite> = bus.writes().into_iter().filter(|write| write.id == 2).collect();
+            let goal = partner.iter().position(|write| write.address == ServoRegister::TargetLocation as u8).unwrap();
+            let mode = partner.iter().position(|write| write.address == ServoRegister::OperationMode as u8).unwrap();
+            assert!(goal < mode, "{:?}", partner);
+            assert_eq!(bus.u16(2, ServoRegister::TargetLocation), 1500);
+            assert_eq!(bus.u8(2, ServoRegister::OperationMode), ServoMode::Position as u8);
+            assert_eq!(bus.u8(2, ServoRegister::TorqueSwitch), TorqueMode::Enabled as u8);
+        }
+
+        #[test]
+        fn approach_starts_fast_and_still_finds_the_stops() {
+            let bus = MockBus::new(&[1]);
+            simulate(&bus, 1, STOPS);
+            let servo = Servo::mock(&bus);
+            let params = CalibrationParams { approach: Some(Approach { speed: 600, distance: 400 }), ..params() };
+            let run = calibrate_servo(&servo, 1, &params, &AtomicBool::new(true)).unwrap();
+            assert_eq!((run.trace.backward.position(), run.trace.forward.position()), (1000, 3000));
+            let speeds: Vec<u16> = bus.writes().iter()
+                .filter(|write| write.address == ServoRegister::RunningSpeed as u8)
+                .map(|write| decode_speed(read_u16_le(&write.data, 0)).unsigned_abs())
+                .collect();
+            assert_eq!(speeds[0], 600);
+            assert!(speeds.contains(&200));
+        }
+
+        // Events of a run, in order
+        fn recorded() -> (CalibrationEvents, Arc<std::sync::Mutex<Vec<CalibrationEvent>>>) {
+            let events = Arc::new(std::sync::Mutex::new(Vec::new()));
+            let sink = events.clone();
+            (CalibrationEvents::new(move |event| sink.lock().unwrap().push(event.clone())), events)
+        }
+
+        #[test]
+        fn a_joint_that_doesnt_move_gets_a_faster_sweep() {
+            let bus = MockBus::new(&[1]);
+            simulate(&bus, 1, STOPS);
+            let servo = Servo::mock(&bus);
+            let (events, recorded) = recorded();
+            let params = CalibrationParams {
+                // Too slow to move the simulated joint at all
+                speed: 5,
+                escalation: Some(Escalation { window: Duration::ZERO, min_travel: 10, step: 195, max_speed: 300 }),
+                events: Some(events),
+                ..params()
+            };
+            let run = calibrate_servo(&servo, 1, &params, &AtomicBool::new(true)).unwrap();
+            assert_eq!((run.trace.backward.position(), run.trace.forward.position()), (1000, 3000));
+            assert!(recorded.lock().unwrap().contains(&CalibrationEvent::SpeedRaised { id: 1, from: 5, to: 200 }));
+        }
+
+        #[test]
+        fn interrupted_sweep_can_hold_the_joint_where_it_stopped() {
+            let bus = MockBus::new(&[1]);
+            bus.set_u16(1, ServoRegister::CurrentLocation, 1500);
+            bus.set_u16(1, ServoRegister::TargetLocation, 3000);
+            let servo = Servo::mock(&bus);
+            let (events, recorded) = recorded();
+            let params = CalibrationParams { on_interrupt: InterruptAction::Hold, events: Some(events), ..params() };
+            assert!(calibrate_servo(&servo, 1, &params, &AtomicBool::new(false)).is_err());
+            assert!(recorded.lock().unwrap().contains(&CalibrationEvent::Holding { id: 1, position: 1500 }));
+            assert_eq!(bus.u16(1, ServoRegister::TargetLocation), 1500);
+            assert_eq!(servo.read_mode(1).unwrap(), ServoMode::Position);
+        }
+
+        #[test]
+        fn sweep_trips_on_m_of_n_readings() {
+            let bus = MockBus::new(&[1]);
+            simulate(&bus, 1, STOPS);
+            let servo = Servo::mock(&bus);
+            let params = CalibrationParams { trip: TripRule { required: 2, window: 4 }, ..params() };
+            let run = calibrate_servo(&servo, 1, &params, &AtomicBool::new(true)).unwrap();
+            assert_eq!((run.trace.backward.position(), run.trace.forward.position()), (1000, 3000));
+            let params = CalibrationParams { trip: TripRule { required: 5, window: 4 }, ..params };
+            assert!(calibrate_servo(&servo, 1, &params, &AtomicBool::new(true)).is_err());
+        }
+
+        #[test]
+        fn switch_detector_stops_the_sweep_at_the_switches() {
+            let bus = MockBus::new(&[1]);
+            simulate(&bus, 1, STOPS);
+            let servo = Servo::mock(&bus);
+            let switches = bus.clone();
+            let mut detector = SwitchLimitDetector::new(move |direction| {
+                let position = switches.u16(1, ServoRegister::CurrentLocation);
+                Ok(match direction {
+                    ServoDirection::Clockwise => position >= 2600,
+                    ServoDirection::Counterclockwise => position <= 1500,
+                })
+            });
+            let run = calibrate_servo_with(&servo, 1, &params(), &mut detector, &AtomicBool::new(true)).unwrap();
+            // Within one simulated step of each switch, well short of the stops
+            assert!((2600..2620).contains(&run.trace.forward.raw), "{:?}", run.trace);
+            assert!((1481..=1500).contains(&run.trace.backward.raw), "{:?}", run.trace);
+        }
+
+        #[test]
+        fn torque_is_released_after_calibrating_unless_kept() {
+            for (keep_torque, torque) in [(false, 0), (true, 1)] {
+                let bus = MockBus::new(&[1]);
+                simulate(&bus, 1, STOPS);
+                bus.set_u8(1, ServoRegister::TorqueSwitch, TorqueMode::Enabled as u8);
+                let servo = Servo::mock(&bus);
+                calibrate_servo(&servo, 1, &CalibrationParams { keep_torque, ..params() }, &AtomicBool::new(true)).unwrap();
+                assert_eq!(bus.u8(1, ServoRegister::TorqueSwitch), torque, "keep_torque {}", keep_torque);
+                // Released only once the joint was sent back to center
+                let writes = bus.writes();
+                let last_goal = writes.iter().rposition(|write| write.address == ServoRegister::TargetLocation as u8).unwrap();
+                assert_eq!(writes[last_goal].data, (CENTER_POSITION as u16).to_le_bytes());
+                let release = writes.iter().position(|write| write.address == ServoRegister::TorqueSwitch as u8);
+                assert_eq!(release.map(|index| index > last_goal), (!keep_torque).then_some(true));
+            }
+        }
+
+        #[test]
+        fn parallel_calibration_runs_independent_joints_first() {
+            let (left, right) = (MockBus::new(&[1]), MockBus::new(&[2]));
+            simulate(&left, 1, STOPS);
+            simulate(&right, 2, STOPS);
+            let (left_servo, right_servo) = (Servo::mock(&left), Servo::mock(&right));
+            let buses = [(&left_servo, vec![1]), (&right_servo, vec![2])];
+            let results = calibrate_parallel(&buses, &[2], &params(), &AtomicBool::new(true)).unwrap();
+            let ids: Vec<u8> = results.iter().map(|(id, _)| *id).collect();
+            assert_eq!(ids, [2, 1]);
+            for (id, result) in results {
+                assert_eq!(result.unwrap().calibration, compute_calibration(STOPS.0 as i16, STOPS.1 as i16), "servo {}", id);
+            }
+        }
+
+        #[test]
+        fn parallel_calibration_refuses_a_servo_listed_twice() {
+            let bus = MockBus::new(&[1]);
+            let servo = Servo::mock(&bus);
+            let buses = [(&servo, vec![1]), (&servo, vec![1])];
+            assert!(calibrate_parallel(&buses, &[1], &params(), &AtomicBool::new(true)).is_err());
+            assert!(bus.writes().is_empty());
+            // Nothing started once stopped
+            assert!(calibrate_parallel(&buses[..1], &[1], &params(), &AtomicBool::new(false)).unwrap().is_empty());
+        }
+
+        #[test]
+        fn tripped_sweep_ramps_down_before_backing_off() {
+            let bus = MockBus::new(&[1]);
+            simulate(&bus, 1, STOPS);
+            let servo = Servo::mock(&bus);
+            let params = CalibrationParams { deceleration: Some(SpeedRamp { duration: Duration::ZERO, steps: 4 }), ..params() };
+            let run = calibrate_servo(&servo, 1, &params, &AtomicBool::new(true)).unwrap();
+            assert_eq!(run.calibration, compute_calibration(STOPS.0 as i16, STOPS.1 as i16));
+            let speeds: Vec<u16> = bus.writes().iter()
+                .filter(|write| write.address == ServoRegister::RunningSpeed as u8)
+                .map(|write| decode_speed(read_u16_le(&write.data, 0)).unsigned_abs())
+                .collect();
+            assert!(speeds.windows(4).any(|window| window == [150, 100, 50, 0]), "{:?}", speeds);
+        }
+
+        #[test]
+        fn sweep_without_a_stop_gives_up_past_max_travel() {
+            let bus = MockBus::new(&[1]);
+            simulate(&bus, 1, (-100_000, 100_000));
+            let servo = Servo::mock(&bus);
+            let params = CalibrationParams { max_travel: Some(2000), ..params() };
+            let error = calibrate_servo(&servo, 1, &params, &AtomicBool::new(true)).unwrap_err();
+            let no_stop = error.downcast_ref::<NoHardStop>().unwrap();
+            assert_eq!(no_stop.id, 1);
+            assert!((2000..2100).contains(&no_stop.travel), "{}", no_stop);
+            assert!(bus.writes().iter().all(|write| write.address != ServoRegister::PositionCorrection as u8));
+        }
+
+        fn center_start() -> CenterStart {
+            CenterStart { speed: 200, settle: SettleConfig { timeout: Duration::from_millis(200), ..SettleConfig::default() } }
+        }
+
+        #[test]
+        fn joint_is_centered_before_the_sweep() {
+            let bus = MockBus::new(&[1]);
+            bus.set_u16(1, ServoRegister::TargetLocation, 1200);
+            simulate(&bus, 1, STOPS);
+            let servo = Servo::mock(&bus);
+            let params = CalibrationParams { center_start: Some(center_start()), ..params() };
+            let run = calibrate_servo(&servo, 1, &params, &AtomicBool::new(true)).unwrap();
+            assert_eq!(run.calibration, compute_calibration(STOPS.0 as i16, STOPS.1 as i16));
+            let first_goal = bus.writes().into_iter().find(|write| write.address == ServoRegister::TargetLocation as u8 && write.data != 1200u16.to_le_bytes()).unwrap();
+            assert_eq!(first_goal.data, (CENTER_POSITION as u16).to_le_bytes());
+        }
+
+        #[test]
+        fn joint_that_cant_reach_center_is_not_swept() {
+            let bus = MockBus::new(&[1]);
+            bus.set_u16(1, ServoRegister::TargetLocation, 1200);
+            simulate(&bus, 1, (1000, 1500));
+            let servo = Servo::mock(&bus);
+            let params = CalibrationParams { center_start: Some(center_start()), ..params() };
+            let error = calibrate_servo(&servo, 1, &params, &AtomicBool::new(true)).unwrap_err().to_string();
+            assert!(error.contains("did not reach center"), "{}", error);
+            assert!(bus.writes().iter().all(|write| write.address != ServoRegister::PositionCorrection as u8));
+            assert_eq!(servo.read_mode(1).unwrap(), ServoMode::Position);
+        }
+
+        #[test]
+        fn center_here_rewrites_the_offset_and_optionally_the_limits() {
+            let bus = MockBus::new(&[1]);
+            bus.set_u16(1, ServoRegister::CurrentLocation, 2148);
+            let servo = Servo::mock(&bus);
+            assert_eq!(servo.set_center_here(1, None).unwrap(), Calibration { offset: 100, min_angle: 0, max_angle: 4095 });
+
+            // Reads 2048 now the offset is applied, the center isn't moved again
+            bus.set_u16(1, ServoRegister::CurrentLocation, 2048);
+            assert_eq!(servo.set_center_here(1, Some(1000)).unwrap(), Calibration { offset: 100, min_angle: 1048, max_angle: 3048 });
+            assert_eq!(bus.u8(1, ServoRegister::LockMark), MemoryLockState::Locked as u8);
+        }
+
+        #[test]
+        fn adaptive_poll_still_finds_the_stops() {
+            let bus = MockBus::new(&[1]);
+            simulate(&bus, 1, STOPS);
+            let servo = Servo::mock(&bus);
+            let params = CalibrationParams { poll: Some(AdaptivePoll::default()), ..params() };
+            let run = calibrate_servo(&servo, 1, &params, &AtomicBool::new(true)).unwrap();
+            assert_eq!(run.calibration, compute_calibration(STOPS.0 as i16, STOPS.1 as i16));
+        }
+
+        #[test]
+        fn low_confidence_leaves_the_eeprom_untouched() {
+            let bus = MockBus::new(&[1]);
+            simulate(&bus, 1, STOPS);
+            let servo = Servo::mock(&bus);
+            let params = CalibrationParams { verify_calibration: Some(1.5), ..params() };
+            let error = calibrate_servo(&servo, 1, &params, &AtomicBool::new(true)).unwrap_err();
+            let low = error.downcast_ref::<LowConfidence>().unwrap();
+            assert_eq!((low.confidence.repeatability, low.confidence.score), (None, 1.0));
+            assert_eq!(
+                low.to_string(),
+                "Calibration of servo 1 scored 1.00 (travel 1.00, sharpness 1.00), below 1.50. EEPROM untouched"
+            );
+            assert!(bus.writes().iter().all(|write| write.address != ServoRegister::PositionCorrection as u8));
+        }
+
+        #[test]
+        fn recorded_runs_keep_their_confidence() {
+            let bus = MockBus::new(&[1]);
+            simulate(&bus, 1, STOPS);
+            let servo = Servo::mock(&bus);
+            let run = calibrate_servo(&servo, 1, &CalibrationParams { verify_calibration: Some(0.9), ..params() }, &AtomicBool::new(true)).unwrap();
+            let mut file = CalibrationFile::default();
+            file.record("left_hip", 1, &run);
+            assert_eq!(file.joints["left_hip"].confidence.map(|confidence| confidence.score), Some(1.0));
+        }
+
+        #[test]
+        fn calibration_stays_off_a_busy_bus() {
+            let bus = MockBus::new(&[1]);
+            let servo = Servo::mock(&bus);
+            let _lock = crate::buslock::BusLock::acquire(&bus.name(), "telemetry server").unwrap();
+            let error = calibrate_servo(&servo, 1, &params(), &AtomicBool::new(true)).unwrap_err();
+            assert!(matches!(error.downcast_ref::<ServoError>(), Some(ServoError::BusClaimed { .. })), "{}", error);
+            assert!(bus.writes().is_empty());
+        }
+
+        #[test]
+        fn stop_samples_average_reads_at_rest() {
+            let bus = MockBus::new(&[1]);
+            simulate(&bus, 1, STOPS);
+            let servo = Servo::mock(&bus);
+            let run = calibrate_servo(&servo, 1, &CalibrationParams { stop_samples: 4, ..params() }, &AtomicBool::new(true)).unwrap();
+            assert_eq!((run.trace.backward.at_rest, run.trace.forward.at_rest), (Some(1000), Some(3000)));
+            let run = calibrate_servo(&servo, 1, &params(), &AtomicBool::new(true)).unwrap();
+            assert_eq!((run.trace.backward.at_rest, run.trace.forward.at_rest), (None, None));
+        }
+
+        #[test]
+        fn rest_position_skips_moving_reads() {
+            let bus = MockBus::new(&[1]);
+            let mut reads = 0;
+            // Still on every other read, either side of the wrap
+            bus.on_packet(move |servos| {
+                reads += 1;
+                let (location, speed) = match reads % 4 {
+                    0 => (4094, 0),
+                    2 => (2, 0),
+                    _ => (2000, 30),
+                };
+                servos.set_u16(1, ServoRegister::CurrentLocation, location);
+                servos.set_u16(1, ServoRegister::CurrentSpeed, speed);
+            });
+            let servo = Servo::mock(&bus);
+            let params = CalibrationParams { stop_samples: 4, ..params() };
+            assert_eq!(read_rest_position(&servo, 1, &params).unwrap(), 0);
+
+            bus.on_packet(|servos| servos.set_u16(1, ServoRegister::CurrentSpeed, 30));
+            let error = read_rest_position(&servo, 1, &params).unwrap_err();
+            assert_eq!(error.to_string(), "Servo 1 never came to rest against its stop");
+        }
+
+        #[test]
+        fn near_saturated_offset_fails_only_when_strict() {
+            // Centered 1949 ticks off, 98 ticks from the limit
+            let stops = (3900, 4094);
+            let bus = MockBus::new(&[1]);
+            simulate(&bus, 1, stops);
+            let servo = Servo::mock(&bus);
+            let error = calibrate_servo(&servo, 1, &CalibrationParams { strict_offset: true, ..params() }, &AtomicBool::new(true)).unwrap_err();
+            let saturated = error.downcast_ref::<OffsetSaturated>().copied();
+            assert_eq!(saturated, Some(OffsetSaturated { id: 1, offset: 1949, margin: 98 }), "{}", error);
+            assert!(bus.writes().iter().all(|write| write.address != ServoRegister::PositionCorrection as u8));
+            assert_eq!(bus.u8(1, ServoRegister::TorqueSwitch), 0);
+
+            // Only warned about otherwise
+            let bus = MockBus::new(&[1]);
+            simulate(&bus, 1, stops);
+            let run = calibrate_servo(&Servo::mock(&bus), 1, &params(), &AtomicBool::new(true)).unwrap();
+            assert_eq!(run.calibration.offset, 1949);
+        }
+
+        #[test]
+        fn profile_overrides_only_what_it_sets() {
+            let profile = CalibrationProfile { speed: Some(300), backoff_ccw_ms: Some(40), ..Default::default() };
+            let params = profile.apply(&params());
+            assert_eq!(params.speed, 300);
+            assert_eq!(params.backoff.counterclockwise, Duration::from_millis(40));
+            assert_eq!(params.backoff.clockwise, Duration::ZERO);
+            assert_eq!(params.current_threshold, 500.0);
+            assert_eq!(params.max_travel, Some(DEFAULT_MAX_TRAVEL));
+            assert_eq!(CalibrationProfile::default().apply(&params).speed, 300);
+        }
+
+        #[test]
+        fn calibrate_all_uses_each_joints_profile() {
+            let bus = MockBus::new(&[1, 2]);
+            simulate(&bus, 1, STOPS);
+            bus.remove(2);
+            let joints = vec![
+                Joint { calibration_profile: Some("gentle".to_string()), ..crate::robot::tests::joint("left_hip", 1) },
+                crate::robot::tests::joint("right_hip", 2),
+            ];
+            let robot = Robot::new(Arc::new(Servo::mock(&bus)), joints)
+                .with_calibration_profiles(BTreeMap::from([("gentle".to_string(), CalibrationProfile { speed: Some(300), ..Default::default() })]));
+
+            let results = robot.calibrate_all(&params(), &AtomicBool::new(true)).unwrap();
+            let names: Vec<&str> = results.iter().map(|(name, _)| name.as_str()).collect();
+            assert_eq!(names, ["left_hip", "right_hip"]);
+            assert_eq!(results[0].1.as_ref().unwrap().calibration, compute_calibration(1000, 3000));
+            assert!(results[1].1.is_err());
+            let fastest = bus.writes().iter()
+                .filter(|write| write.id == 1 && write.address == ServoRegister::RunningSpeed as u8)
+                .map(|write| decode_speed(read_u16_le(&write.data, 0)).unsigned_abs())
+                .max();
+            assert_eq!(fastest, Some(300));
+        }
+
+        #[test]
+        fn calibrate_all_checks_profiles_before_starting() {
+            let bus = MockBus::new(&[1]);
+            let joints = vec![Joint { calibration_profile: Some("missing".to_string()), ..crate::robot::tests::joint("left_hip", 1) }];
+            let robot = Robot::new(Arc::new(Servo::mock(&bus)), joints);
+            let error = robot.calibrate_all(&params(), &AtomicBool::new(true)).unwrap_err();
+            assert_eq!(error.to_string(), "Joint left_hip uses unknown calibration profile \"missing\"");
+            assert!(bus.writes().is_empty());
+
+            // Stopped before the first joint
+            let (bus, robot) = mock_robot(&JOINTS);
+            assert!(robot.calibrate_all(&params(), &AtomicBool::new(false)).unwrap().is_empty());
+            assert!(bus.writes().is_empty());
+        }
+
+        #[test]
+        fn reduced_range_servo_is_centered_on_its_own_center() {
+            let bus = MockBus::new(&[1]);
+            simulate(&bus, 1, (100, 900));
+            let scs = Resolution { ticks_per_turn: 1229, max_tick: 1023 };
+            let params = CalibrationParams { resolution: ModelResolution::new(scs), ..params() };
+            let run = calibrate_servo(&Servo::mock(&bus), 1, &params, &AtomicBool::new(true)).unwrap();
+            assert_eq!(run.calibration, Calibration { offset: -12, min_angle: 112, max_angle: 912 });
+            assert_eq!(bus.u16(1, ServoRegister::TargetLocation), 512);
+        }
+
+        #[test]
+        fn limits_only_sweep_keeps_the_offset() {
+            let bus = MockBus::new(&[1]);
+            bus.set_u16(1, ServoRegister::PositionCorrection, encode_offset(40));
+            simulate(&bus, 1, STOPS);
+            let servo = Servo::mock(&bus);
+            let params = CalibrationParams { writes: CalibrationWrites::LimitsOnly, ..params() };
+            let run = calibrate_servo(&servo, 1, &params, &AtomicBool::new(true)).unwrap();
+            // The stops as the servo reports them through its offset
+            assert_eq!(run.calibration, Calibration { offset: 40, min_angle: 960, max_angle: 2960 });
+            assert_eq!(stored(&bus, 1), run.calibration);
+            assert!(bus.writes().iter().all(|write| write.address != ServoRegister::PositionCorrection as u8));
+            // Left in the middle of the limits, not at 2048
+            assert_eq!(bus.u16(1, ServoRegister::TargetLocation), 1960);
+        }
+
+        #[test]
+        fn sweep_reports_progress_for_both_passes() {
+            let bus = MockBus::new(&[1]);
+            simulate(&bus, 1, STOPS);
+            let seen = Arc::new(std::sync::Mutex::new(Vec::new()));
+            let params = CalibrationParams {
+                progress: Some({
+                    let seen = seen.clone();
+                    ProgressReport::new(2000, move |progress: &SweepProgress| seen.lock().unwrap().push(*progress))
+                }),
+                ..params()
+            };
+            calibrate_servo(&Servo::mock(&bus), 1, &params, &AtomicBool::new(true)).unwrap();
+            let seen = seen.lock().unwrap();
+            for pass in [0, 1] {
+                let readings: Vec<&SweepProgress> = seen.iter().filter(|progress| progress.pass == pass).collect();
+                assert!(readings.windows(2).all(|pair| pair[0].fraction <= pair[1].fraction));
+                assert_eq!(readings.last().unwrap().fraction, 1.0);
+                assert!(readings[..readings.len() - 1].iter().all(|progress| progress.fraction <= MAX_ESTIMATED_FRACTION));
+            }
+            assert_eq!(seen.last().unwrap().overall(), 1.0);
+        }
+
+        #[test]
+        fn profile_expected_travel_only_changes_a_progress_report() {
+            let profile = CalibrationProfile { expected_travel: Some(1500), ..Default::default() };
+            assert!(profile.apply(&params()).progress.is_none());
+            let params = CalibrationParams { progress: Some(ProgressReport::new(2000, |_: &SweepProgress| {})), ..params() };
+            assert_eq!(profile.apply(&params).progress.unwrap().expected_travel, 1500);
+        }
+
+        #[test]
+        fn stall_detection_finds_the_stops_without_current() {
+            let bus = MockBus::new(&[1]);
+            simulate_currents(&bus, 1, STOPS, (0, 0));
+            let params = CalibrationParams { detection: StopDetection::Stall(StallRule::default()), ..params() };
+            let run = calibrate_servo(&Servo::mock(&bus), 1, &params, &AtomicBool::new(true)).unwrap();
+            assert_eq!((run.trace.backward.position(), run.trace.forward.position()), (1000, 3000));
+        }
+
+        #[test]
+        fn zero_current_switches_the_sweep_to_stall_detection() {
+            let bus = MockBus::new(&[1]);
+            simulate_currents(&bus, 1, STOPS, (0, 0));
+            let (events, recorded) = recorded();
+            let params = CalibrationParams { events: Some(events), ..params() };
+            let run = calibrate_servo(&Servo::mock(&bus), 1, &params, &AtomicBool::new(true)).unwrap();
+            assert_eq!((run.trace.backward.position(), run.trace.forward.position()), (1000, 3000));
+            let recorded = recorded.lock().unwrap();
+            assert_eq!(recorded.iter().filter(|event| **event == CalibrationEvent::StallFallback { id: 1 }).count(), 1);
+        }
+
+        #[test]
+        fn robot_reports_the_symmetric_range_of_a_joint() {
+            let (bus, robot) = mock_robot(&JOINTS);
+            set_calibration(&bus, 1, LEFT);
+            // LEFT spans 900-3100, its max is the nearer limit at 1052 ticks from center
+            assert_eq!(robot.symmetric_range_deg("left_hip").unwrap(), ticks_to_deg(1052));
+            assert!(robot.symmetric_range_deg("neck").is_err());
+        }
+
+        #[test]
+        fn interrupted_sweep_restores_position_mode_without_moving() {
+            let bus = MockBus::new(&[1]);
+            bus.set_u16(1, ServoRegister::CurrentLocation, 1500);
+            bus.set_u16(1, ServoRegister::TargetLocation, 3000);
+            let servo = Servo::mock(&bus);
+            assert!(calibrate_servo(&servo, 1, &params(), &AtomicBool::new(false)).is_err());
+            assert_eq!(servo.read_mode(1).unwrap(), ServoMode::Position);
+            assert_eq!(bus.u16(1, ServoRegister::TargetLocation), 1500);
+        }
+    }
+}
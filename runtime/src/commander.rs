@@ -0,0 +1,318 @@
+use anyhow::Result;
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use crate::calibration::Calibration;
+use crate::hal::Servo;
+
+#[derive(Debug, Clone, Copy)]
+struct CommandedJoint {
+    target: i16,
+    commanded: i16,
+    max_rate: u16,
+    envelope: Option<VelocityEnvelope>,
+}
+
+// Slows a joint down as it nears its calibrated limits so aggressive motions
+// don't slam the gearbox into the hard stops. Within `zone` ticks of a limit
+// the rate towards it falls linearly from the joint's max rate to `min_rate`
+// at the limit itself. Moving away from a limit is never slowed.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct VelocityEnvelope {
+    pub min: i16,
+    pub max: i16,
+    pub zone: u16,
+    pub min_rate: u16,
+}
+
+impl VelocityEnvelope {
+    pub fn from_calibration(calibration: &Calibration, zone: u16, min_rate: u16) -> Self {
+        Self { min: calibration.min_angle, max: calibration.max_angle, zone, min_rate }
+    }
+
+    // Rate allowed when moving from `position` towards `target`
+    pub fn rate(&self, position: i16, target: i16, max_rate: u16) -> u16 {
+        let distance_to_limit = if target > position {
+            self.max as i32 - position as i32
+        } else if target < position {
+            position as i32 - self.min as i32
+        } else {
+            return max_rate;
+        };
+        if self.zone == 0 || distance_to_limit >= self.zone as i32 {
+            return max_rate;
+        }
+        // At least one tick per cycle, so the joint still reaches its limit
+        let min_rate = self.min_rate.max(1).min(max_rate) as f32;
+        let fraction = distance_to_limit.max(0) as f32 / self.zone as f32;
+        (min_rate + (max_rate as f32 - min_rate) * fraction).round() as u16
+    }
+}
+
+// Supervised operation: motion is only allowed while an operator holds an
+// input. Terminals and most gamepads only report presses (and key repeat),
+// not releases, so the input is held for as long as `assert` keeps being
+// called within `timeout`. Pick a timeout longer than the key repeat delay.
+// Clones share the same state, one can go to the input handler and one to
+// the JointCommander.
+#[derive(Debug, Clone)]
+pub struct DeadMan {
+    timeout: Duration,
+    asserted: Arc<Mutex<Option<Instant>>>,
+}
+
+impl DeadMan {
+    pub fn new(timeout: Duration) -> Self {
+        Self { timeout, asserted: Arc::new(Mutex::new(None)) }
+    }
+
+    // The input is (still) held
+    pub fn assert(&self) {
+        *self.asserted.lock().unwrap() = Some(Instant::now());
+    }
+
+    // The input was released, for inputs that do report it
+    pub fn release(&self) {
+        *self.asserted.lock().unwrap() = None;
+    }
+
+    pub fn is_held(&self) -> bool {
+        self.asserted.lock().unwrap().is_some_and(|at| at.elapsed() < self.timeout)
+    }
+
+    pub fn timeout(&self) -> Duration {
+        self.timeout
+    }
+}
+
+// Decouples the rate of incoming targets from the smoothness of the output:
+// targets can jump arbitrarily, but each call to `update` moves the
+// commanded position at most `max_rate` ticks towards the target.
+#[derive(Debug)]
+pub struct JointCommander {
+    servo: Arc<Servo>,
+    joints: BTreeMap<u8, CommandedJoint>,
+    default_max_rate: u16,
+    dead_man: Option<DeadMan>,
+}
+
+impl JointCommander {
+    pub fn new(servo: Arc<Servo>, default_max_rate: u16) -> Self {
+        Self {
+            servo,
+            joints: BTreeMap::new(),
+            default_max_rate,
+            dead_man: None,
+        }
+    }
+
+    // While the dead-man isn't held, targets are ignored and every joint holds
+    // where it was commanded last. Holding it again doesn't resume the
+    // targets set before the release.
+    pub fn set_dead_man(&mut self, dead_man: Option<DeadMan>) {
+        self.dead_man = dead_man;
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.dead_man.as_ref().is_none_or(DeadMan::is_held)
+    }
+
+    pub fn max_rate(&self, id: u8) -> u16 {
+        self.joints.get(&id).map_or(self.default_max_rate, |joint| joint.max_rate)
+    }
+
+    pub fn set_max_rate(&mut self, id: u8, ticks_per_cycle: u16) -> Result<()> {
+        self.joint_mut(id)?.max_rate = ticks_per_cycle;
+        Ok(())
+    }
+
+    pub fn set_envelope(&mut self, id: u8, envelope: Option<VelocityEnvelope>) -> Result<()> {
+        self.joint_mut(id)?.envelope = envelope;
+        Ok(())
+    }
+
+    pub fn set_target(&mut self, id: u8, target: i16) -> Result<()> {
+        let enabled = self.is_enabled();
+        let joint = self.joint_mut(id)?;
+        if enabled {
+            joint.target = target;
+        }
+        Ok(())
+    }
+
+    pub fn target(&self, id: u8) -> Option<i16> {
+        self.joints.get(&id).map(|joint| joint.target)
+    }
+
+    pub fn commanded(&self, id: u8) -> Option<i16> {
+        self.joints.get(&id).map(|joint| joint.commanded)
+    }
+
+    // Advance every joint one cycle and send the intermediate positions.
+    // Meant to be called at a fixed rate by the control loop.
+    pub fn update(&mut self) -> Result<()> {
+        let positions = self.next_positions();
+        if positions.is_empty() {
+            return Ok(());
+        }
+        self.servo.sync_write_positions(&positions)
+    }
+
+    // Advance every joint one cycle, returning the joints whose commanded
+    // position changed
+    pub fn next_positions(&mut self) -> Vec<(u8, i16)> {
+        if !self.is_enabled() {
+            for joint in self.joints.values_mut() {
+                joint.target = joint.commanded;
+            }
+            return Vec::new();
+        }
+        let mut positions = Vec::new();
+        for (&id, joint) in self.joints.iter_mut() {
+            let max_rate = match &joint.envelope {
+                Some(envelope) => envelope.rate(joint.commanded, joint.target, joint.max_rate),
+                None => joint.max_rate,
+            };
+            let next = ramp_towards(joint.commanded, joint.target, max_rate);
+            if next != joint.commanded {
+                joint.commanded = next;
+                positions.push((id, next));
+            }
+        }
+        positions
+    }
+
+    // Joints are picked up lazily; the ramp starts from wherever the servo
+    // currently is so the first command doesn't snap
+    fn joint_mut(&mut self, id: u8) -> Result<&mut CommandedJoint> {
+        if !self.joints.contains_key(&id) {
+            let position = self.servo.read_position(id)?;
+            self.joints.insert(id, CommandedJoint {
+                target: position,
+                commanded: position,
+                max_rate: self.default_max_rate,
+                envelope: None,
+            });
+        }
+        Ok(self.joints.get_mut(&id).unwrap())
+    }
+}
+
+pub fn ramp_towards(current: i16, target: i16, max_rate: u16) -> i16 {
+    let delta = (target as i32 - current as i32).clamp(-(max_rate as i32), max_rate as i32);
+    (current as i32 + delta) as i16
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ramp_moves_at_most_max_rate() {
+        assert_eq!(ramp_towards(2048, 2148, 30), 2078);
+        assert_eq!(ramp_towards(2048, 1948, 30), 2018);
+        assert_eq!(ramp_towards(2048, 2058, 30), 2058);
+        assert_eq!(ramp_towards(2048, 2048, 30), 2048);
+    }
+
+    #[test]
+    fn envelope_slows_only_towards_a_near_limit() {
+        let envelope = VelocityEnvelope { min: 1000, max: 3000, zone: 100, min_rate: 10 };
+        assert_eq!(envelope.rate(2000, 2900, 50), 50);
+        assert_eq!(envelope.rate(2950, 3000, 50), 30);
+        assert_eq!(envelope.rate(3000, 3100, 50), 10);
+        assert_eq!(envelope.rate(1020, 900, 50), 18);
+        // Away from the limit, or holding still
+        assert_eq!(envelope.rate(2950, 2000, 50), 50);
+        assert_eq!(envelope.rate(2950, 2950, 50), 50);
+        assert_eq!(VelocityEnvelope { zone: 0, ..envelope }.rate(2999, 3000, 50), 50);
+    }
+
+    #[test]
+    fn envelope_keeps_a_rate_of_at_least_a_tick() {
+        let envelope = VelocityEnvelope { min: 1000, max: 3000, zone: 100, min_rate: 0 };
+        assert_eq!(envelope.rate(3000, 3001, 50), 1);
+        assert_eq!(VelocityEnvelope { min_rate: 80, ..envelope }.rate(3000, 3001, 50), 50);
+    }
+
+    #[test]
+    fn dead_man_is_held_until_it_times_out_or_is_released() {
+        let dead_man = DeadMan::new(Duration::from_millis(20));
+        assert!(!dead_man.is_held());
+        let input = dead_man.clone();
+        input.assert();
+        assert!(dead_man.is_held());
+        input.release();
+        assert!(!dead_man.is_held());
+        input.assert();
+        std::thread::sleep(Duration::from_millis(30));
+        assert!(!dead_man.is_held());
+    }
+
+    #[cfg(not(feature = "milkv"))]
+    mod bus {
+        use super::*;
+        use crate::hal::ServoRegister;
+        use crate::hal::mock::MockBus;
+
+        #[test]
+        fn first_target_ramps_from_the_present_position() {
+            let bus = MockBus::new(&[1, 2]);
+            bus.set_u16(2, ServoRegister::CurrentLocation, 1000);
+            let mut commander = JointCommander::new(Arc::new(Servo::mock(&bus)), 50);
+            commander.set_target(1, 2248).unwrap();
+            commander.set_target(2, 1000).unwrap();
+            assert_eq!(commander.commanded(1), Some(2048));
+
+            commander.update().unwrap();
+            assert_eq!(commander.commanded(1), Some(2098));
+            assert_eq!(bus.u16(1, ServoRegister::TargetLocation), 2098);
+            // Already at its target, so left out of the sync write
+            assert!(bus.writes().iter().all(|write| write.id != 2));
+        }
+
+        #[test]
+        fn per_joint_rate_overrides_the_default() {
+            let bus = MockBus::new(&[1]);
+            let mut commander = JointCommander::new(Arc::new(Servo::mock(&bus)), 50);
+            commander.set_max_rate(1, 10).unwrap();
+            commander.set_target(1, 2148).unwrap();
+            assert_eq!(commander.next_positions(), vec![(1, 2058)]);
+            assert_eq!(commander.max_rate(1), 10);
+            assert_eq!(commander.max_rate(2), 50);
+        }
+
+        #[test]
+        fn envelope_applies_to_the_commanded_rate() {
+            let bus = MockBus::new(&[1]);
+            bus.set_u16(1, ServoRegister::CurrentLocation, 2950);
+            let mut commander = JointCommander::new(Arc::new(Servo::mock(&bus)), 50);
+            let calibration = Calibration { offset: 0, min_angle: 1000, max_angle: 3000 };
+            commander.set_envelope(1, Some(VelocityEnvelope::from_calibration(&calibration, 100, 10))).unwrap();
+            commander.set_target(1, 3000).unwrap();
+            assert_eq!(commander.next_positions(), vec![(1, 2980)]);
+            assert!(commander.set_envelope(2, None).is_err());
+        }
+
+        #[test]
+        fn released_dead_man_holds_every_joint() {
+            let bus = MockBus::new(&[1]);
+            let mut commander = JointCommander::new(Arc::new(Servo::mock(&bus)), 50);
+            let dead_man = DeadMan::new(Duration::from_secs(60));
+            commander.set_dead_man(Some(dead_man.clone()));
+            dead_man.assert();
+            commander.set_target(1, 2248).unwrap();
+            assert_eq!(commander.next_positions(), vec![(1, 2098)]);
+
+            dead_man.release();
+            assert!(!commander.is_enabled());
+            assert!(commander.next_positions().is_empty());
+            commander.set_target(1, 1000).unwrap();
+            assert_eq!(commander.target(1), Some(2098));
+
+            // The target from before the release isn't resumed
+            dead_man.assert();
+            assert!(commander.next_positions().is_empty());
+        }
+    }
+}
@@ -0,0 +1,136 @@
+use anyhow::{Result, bail};
+use std::ops::RangeInclusive;
+use crate::hal::{Servo, ServoRegister};
+use crate::servo::MODEL_STS3215;
+
+// Firmware revisions may move registers, and writing the lock or
+// calibration registers at the wrong address can leave a servo unusable.
+// Every model and firmware range is listed with the addresses of its memory
+// table; anything not listed, or listed with addresses that differ from
+// ServoRegister, is refused.
+#[derive(Debug, Clone)]
+pub struct Compatibility {
+    pub model: u16,
+    pub name: &'static str,
+    pub firmware_major: RangeInclusive<u8>,
+    // Addresses of the critical registers in this firmware's memory table
+    pub layout: &'static [(ServoRegister, u8)],
+}
+
+pub const COMPATIBILITY: &[Compatibility] = &[
+    // Firmware 3.x, as shipped on every STS3215 in use so far
+    Compatibility { model: MODEL_STS3215, name: "STS3215", firmware_major: 3..=3, layout: STS_LAYOUT },
+];
+
+// The registers written during calibration and configuration, from the
+// STS memory table
+pub const STS_LAYOUT: &[(ServoRegister, u8)] = &[
+    (ServoRegister::ID, 0x05),
+    (ServoRegister::BaudRate, 0x06),
+    (ServoRegister::MinAngleLimit, 0x09),
+    (ServoRegister::MaxAngleLimit, 0x0B),
+    (ServoRegister::PositionCorrection, 0x1F),
+    (ServoRegister::OperationMode, 0x21),
+    (ServoRegister::TorqueSwitch, 0x28),
+    (ServoRegister::TargetLocation, 0x2A),
+    (ServoRegister::LockMark, 0x37),
+];
+
+pub fn check_firmware(model: u16, firmware: (u8, u8)) -> Result<&'static Compatibility> {
+    let Some(entry) = COMPATIBILITY.iter().find(|entry| entry.model == model) else {
+        bail!(
+            "Unknown servo model {:#06x}, its register addresses are unverified. Compare its memory table \
+             against ServoRegister and add it to COMPATIBILITY before writing to it", model
+        );
+    };
+    if !entry.firmware_major.contains(&firmware.0) {
+        bail!(
+            "{} firmware {}.{} is unverified, only major versions {:?} are known to match the register addresses used. \
+             Check its memory table against ServoRegister and extend COMPATIBILITY before writing to it",
+            entry.name, firmware.0, firmware.1, entry.firmware_major
+        );
+    }
+    let moved: Vec<String> = entry.layout.iter()
+        .filter(|&&(register, address)| register as u8 != address)
+        .map(|&(register, address)| format!("{:?} at {:#04x} instead of {:#04x}", register, address, register as u8))
+        .collect();
+    if !moved.is_empty() {
+        bail!(
+            "{} firmware {}.{} has registers at other addresses than ServoRegister: {}. \
+             It needs its own register map before it can be used",
+            entry.name, firmware.0, firmware.1, moved.join(", ")
+        );
+    }
+    Ok(entry)
+}
+
+impl Servo {
+    // Before anything writes EEPROM, see Servo::unlock_eeprom: the model and
+    // firmware must be known to use the register addresses hardcoded in
+    // ServoRegister
+    pub fn check_compatibility(&self, id: u8) -> Result<&'static Compatibility> {
+        let model = self.read_model(id)?;
+        let firmware = self.read_firmware_version(id)?;
+        check_firmware(model, firmware).map_err(|e| e.context(format!("Servo {} failed the compatibility check", id)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_listed_models_and_firmware_pass() {
+        assert_eq!(check_firmware(MODEL_STS3215, (3, 10)).unwrap().name, "STS3215");
+        assert!(check_firmware(MODEL_STS3215, (4, 0)).unwrap_err().to_string().contains("firmware 4.0 is unverified"));
+        assert!(check_firmware(0x0101, (3, 10)).unwrap_err().to_string().contains("Unknown servo model 0x0101"));
+    }
+
+    #[test]
+    fn shipped_layouts_match_the_register_addresses() {
+        for entry in COMPATIBILITY {
+            for &(register, address) in entry.layout {
+                assert_eq!(register as u8, address, "{} {:?}", entry.name, register);
+            }
+        }
+    }
+
+    #[cfg(not(feature = "milkv"))]
+    mod bus {
+        use super::*;
+        use crate::calibration::Calibration;
+        use crate::hal::mock::MockBus;
+
+        #[test]
+        fn unverified_firmware_is_never_written() {
+            let bus = MockBus::new(&[1]);
+            bus.set_u8(1, ServoRegister::FirmwareMajorVersion, 4);
+            let servo = Servo::mock(&bus);
+            let error = servo.commit_calibration(1, &Calibration { offset: 10, min_angle: 1000, max_angle: 3000 }).unwrap_err();
+            assert!(format!("{:#}", error).contains("Servo 1 failed the compatibility check"), "{:#}", error);
+            assert!(bus.writes().is_empty());
+        }
+
+        #[test]
+        fn every_eeprom_write_checks_compatibility_first() {
+            let bus = MockBus::new(&[1]);
+            bus.set_u8(1, ServoRegister::FirmwareMajorVersion, 4);
+            let servo = Servo::mock(&bus);
+            let calibration = Calibration { offset: 10, min_angle: 1000, max_angle: 3000 };
+            let attempts = [
+                servo.write_calibration(1, &calibration),
+                servo.write_limits(1, &calibration),
+                servo.write_offset(1, 10),
+                servo.clear_limits(1),
+                servo.write_eeprom(1, ServoRegister::MaxTorque, &[0, 2]),
+                servo.set_baud(1, 500_000),
+                servo.set_pid(1, 32, 0, 32),
+            ];
+            for error in attempts.into_iter().map(Result::unwrap_err) {
+                assert!(format!("{:#}", error).contains("Servo 1 failed the compatibility check"), "{:#}", error);
+            }
+            assert!(bus.writes().is_empty());
+            assert_eq!(bus.baud_rate(), 1_000_000);
+        }
+    }
+}
@@ -0,0 +1,81 @@
+use anyhow::{Result, bail};
+use crate::hal::{Servo, ServoRegister};
+
+// Largest insensitive area the STS series accepts, in ticks
+pub const MAX_COMPLIANCE_MARGIN: u8 = 32;
+
+// Dead band around the goal position, per direction. The servo applies no
+// correction while within the margin, so wider margins make a joint
+// back-drivable around its goal (e.g. a gripper) at the cost of holding
+// accuracy. The STS series has no separate compliance slope, how hard the
+// joint pulls back outside the margin is set by the P gain (see set_pid).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Compliance {
+    pub clockwise_margin: u8,
+    pub counterclockwise_margin: u8,
+}
+
+impl Compliance {
+    pub fn symmetric(margin: u8) -> Result<Self> {
+        Self::from_bytes([margin, margin])
+    }
+
+    // As stored from ServoRegister::ClockwiseInsensitiveArea onwards
+    pub fn from_bytes(data: [u8; 2]) -> Result<Self> {
+        for margin in data {
+            if margin > MAX_COMPLIANCE_MARGIN {
+                bail!("Compliance margin {} exceeds maximum of {}", margin, MAX_COMPLIANCE_MARGIN);
+            }
+        }
+        Ok(Self { clockwise_margin: data[0], counterclockwise_margin: data[1] })
+    }
+
+    pub fn to_bytes(&self) -> [u8; 2] {
+        [self.clockwise_margin, self.counterclockwise_margin]
+    }
+}
+
+impl Servo {
+    pub fn read_compliance(&self, id: u8) -> Result<Compliance> {
+        let data = self.read_exact(id, ServoRegister::ClockwiseInsensitiveArea, 2)?;
+        Ok(Compliance { clockwise_margin: data[0], counterclockwise_margin: data[1] })
+    }
+
+    pub fn write_compliance(&self, id: u8, compliance: &Compliance) -> Result<()> {
+        // Validate here too, the fields are public
+        let data = Compliance::from_bytes(compliance.to_bytes())?.to_bytes();
+        self.write_eeprom(id, ServoRegister::ClockwiseInsensitiveArea, &data)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn margins_are_capped() {
+        assert_eq!(Compliance::symmetric(MAX_COMPLIANCE_MARGIN).unwrap().to_bytes(), [32, 32]);
+        assert!(Compliance::symmetric(MAX_COMPLIANCE_MARGIN + 1).is_err());
+        assert!(Compliance::from_bytes([1, 40]).is_err());
+        assert_eq!(Compliance::from_bytes([1, 2]).unwrap(), Compliance { clockwise_margin: 1, counterclockwise_margin: 2 });
+    }
+
+    #[cfg(not(feature = "milkv"))]
+    mod bus {
+        use super::*;
+        use crate::hal::mock::MockBus;
+
+        #[test]
+        fn compliance_round_trips_and_bad_margins_are_never_written() {
+            let bus = MockBus::new(&[1]);
+            let servo = Servo::mock(&bus);
+            let compliance = Compliance { clockwise_margin: 4, counterclockwise_margin: 8 };
+            servo.write_compliance(1, &compliance).unwrap();
+            assert_eq!(servo.read_compliance(1).unwrap(), compliance);
+
+            bus.clear_writes();
+            assert!(servo.write_compliance(1, &Compliance { clockwise_margin: 64, counterclockwise_margin: 0 }).is_err());
+            assert!(bus.writes().is_empty());
+        }
+    }
+}
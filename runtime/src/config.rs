@@ -0,0 +1,533 @@
+use anyhow::{Result, Context, bail};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use crate::calibration::NO_LIMITS;
+use crate::servo::{baud_register_value, check_scale, parse_model, ModelScaling};
+use crate::failsafe::BusErrorPolicy;
+use crate::mirror::MirrorPair;
+use crate::safemode::SafeMode;
+use crate::torque::MAX_TORQUE;
+use crate::units::Rounding;
+
+// Schema of config/[robot-name].toml. Keys not covered here (physical
+// parameters, standing positions, ...) are left to their own consumers and
+// ignored.
+#[derive(Debug, Clone, Deserialize)]
+pub struct Config {
+    pub robot: RobotConfig,
+    #[serde(default)]
+    pub bus: BusConfig,
+    #[serde(default)]
+    pub homing: HomingConfig,
+    // Planar chains for forward kinematics, see PlanarChain
+    #[serde(default)]
+    pub chains: BTreeMap<String, ChainConfig>,
+    // Named calibration parameters assigned to joints through
+    // calibration_profile, see Robot::calibrate_all
+    #[serde(default)]
+    pub calibration_profiles: BTreeMap<String, CalibrationProfile>,
+    #[serde(default)]
+    pub safe_mode: SafeMode,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct RobotConfig {
+    pub name: String,
+    #[serde(default)]
+    pub legs: BTreeMap<String, BTreeMap<String, JointConfig>>,
+    #[serde(default)]
+    pub arms: BTreeMap<String, BTreeMap<String, JointConfig>>,
+    // Joints mechanically coupled through a tendon,
+    // e.g. coupled = [["left_hip_pitch", "left_knee_pitch"]]
+    #[serde(default)]
+    pub coupled: Vec<[String; 2]>,
+    // Left/right pairs trimmed together, see Robot::trim_mirrored
+    #[serde(default)]
+    pub mirrored: Vec<MirrorPair>,
+    // How joint targets in degrees are rounded to ticks, see Robot::move_group
+    #[serde(default)]
+    pub rounding: Rounding,
+    // What a bus error mid-motion does, see BusErrorPolicy
+    #[serde(default)]
+    pub on_bus_error: BusErrorPolicy,
+    // Current scale by model number, over ModelScaling::current(),
+    // e.g. current_scale = { "0x0903" = 0.4225 }
+    #[serde(default)]
+    pub current_scale: BTreeMap<String, f32>,
+    // Check every joint answers when building the robot, see Robot::preflight
+    #[serde(default)]
+    pub preflight: bool,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct JointConfig {
+    pub id: u8,
+    pub pid: Option<PidConfig>,
+    // Joint limits in radians, as used by the policy
+    pub limits: Option<JointLimits>,
+    // Hardware angle limits in ticks, see Calibration
+    pub angle_limits: Option<AngleLimits>,
+    #[serde(default)]
+    pub mapping: JointMapping,
+    // Caps for profiled moves in joint degrees/s and degrees/s², see
+    // Robot::move_group_profiled
+    pub max_velocity: Option<f32>,
+    pub max_acceleration: Option<f32>,
+    // Name of one of calibration_profiles, None calibrates with the
+    // parameters given on the command line
+    pub calibration_profile: Option<String>,
+    // Output cap, 0-1000 of the stall torque, written at session start,
+    // e.g. low for a gripper so it can't crush what it holds. None leaves
+    // what the servo has stored.
+    pub max_torque: Option<u16>,
+}
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+pub struct PidConfig {
+    pub p: f32,
+    pub i: f32,
+    pub d: f32,
+}
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+pub struct JointLimits {
+    pub lower: f32,
+    pub upper: f32,
+}
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+pub struct AngleLimits {
+    pub min: i16,
+    pub max: i16,
+}
+
+// Joint angle in degrees from the servo angle in degrees from center, as
+// joint = scale * servo + offset. A negative scale flips joints mounted the
+// other way round, e.g. mapping = { scale = -1.0, offset = 90.0 }
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct JointMapping {
+    pub scale: f32,
+    pub offset: f32,
+}
+
+impl Default for JointMapping {
+    fn default() -> Self {
+        Self { scale: 1.0, offset: 0.0 }
+    }
+}
+
+impl JointMapping {
+    pub fn to_joint(&self, servo_degrees: f32) -> f32 {
+        self.scale * servo_degrees + self.offset
+    }
+
+    pub fn to_servo(&self, joint_degrees: f32) -> f32 {
+        (joint_degrees - self.offset) / self.scale
+    }
+
+    // A joint rate (degrees/s, degrees/s², ...) as a servo rate, always
+    // positive. The offset doesn't apply to rates.
+    pub fn rate_to_servo(&self, joint_rate: f32) -> f32 {
+        (joint_rate / self.scale).abs()
+    }
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct BusConfig {
+    // Fall back to SERVO_PORT / SERVO_BAUD_RATE when unset
+    pub port: Option<String>,
+    pub baud_rate: Option<u32>,
+    // Log raw bus bytes to this file, like SERVO_TRACE_FILE
+    pub trace: Option<PathBuf>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct HomingConfig {
+    // Groups of joint names homed one group after another, e.g.
+    // order = [["left_hip_roll", "right_hip_roll"], ["left_knee_pitch", "right_knee_pitch"]]
+    #[serde(default)]
+    pub order: Vec<Vec<String>>,
+}
+
+// Links from the base out, e.g.
+// [chains.left_arm]
+// links = [{ joint = "left_shoulder_pitch", length = 0.1 }, { joint = "left_elbow_pitch", length = 0.08 }]
+#[derive(Debug, Clone, Deserialize)]
+pub struct ChainConfig {
+    pub links: Vec<LinkConfig>,
+}
+
+// `joint` rotates this link, `length` is from that joint to the next one or
+// to the end effector, in whatever unit the positions should come out in
+#[derive(Debug, Clone, Deserialize)]
+pub struct LinkConfig {
+    pub joint: String,
+    pub length: f32,
+}
+
+// Overrides of the base CalibrationParams, unset fields keep the base value,
+// e.g.
+// [calibration_profiles.delicate]
+// speed = 200
+// current_threshold = 150.0
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CalibrationProfile {
+    pub speed: Option<u16>,
+    // In mA
+    pub current_threshold: Option<f32>,
+    // Backoff after each stop in ms
+    pub backoff_cw_ms: Option<u64>,
+    pub backoff_ccw_ms: Option<u64>,
+    // Ticks, see CalibrationParams::max_travel
+    pub max_travel: Option<u32>,
+    // Ticks between the stops, for the progress estimate, see ProgressReport
+    pub expected_travel: Option<u32>,
+}
+
+impl RobotConfig {
+    // ModelScaling::current() with current_scale applied
+    pub fn current_scaling(&self) -> Result<ModelScaling> {
+        let models = self.current_scale.iter()
+            .map(|(model, &scale)| Ok((parse_model(model)?, check_scale(scale)?)))
+            .collect::<Result<Vec<_>>>()?;
+        Ok(ModelScaling::current().with_models(models))
+    }
+
+    // Every joint as ("<side>_<joint>", its config path, config)
+    pub fn joints(&self) -> Vec<(String, String, &JointConfig)> {
+        [("legs", &self.legs), ("arms", &self.arms)].into_iter()
+            .flat_map(|(limb, sides)| sides.iter().flat_map(move |(side, joints)| {
+                joints.iter().map(move |(joint, config)| {
+                    (format!("{}_{}", side, joint), format!("robot.{}.{}.{}", limb, side, joint), config)
+                })
+            }))
+            .collect()
+    }
+}
+
+impl Config {
+    pub fn validate(&self) -> Result<()> {
+        let mut errors = Vec::new();
+        let joints = self.robot.joints();
+
+        let mut ids: BTreeMap<u8, &str> = BTreeMap::new();
+        let mut seen_names: BTreeMap<&str, &str> = BTreeMap::new();
+        for (name, path, joint) in &joints {
+            if let Some(other) = ids.insert(joint.id, path) {
+                errors.push(format!("{}.id: servo ID {} already used by {}", path, joint.id, other));
+            }
+            if let Some(other) = seen_names.insert(name, path) {
+                errors.push(format!("{}: joint name {:?} already used by {}", path, name, other));
+            }
+            if let Some(limits) = &joint.limits {
+                if limits.lower > limits.upper {
+                    errors.push(format!("{}.limits: lower {} is above upper {}", path, limits.lower, limits.upper));
+                }
+            }
+            if let Some(limits) = &joint.angle_limits {
+                for (field, value) in [("min", limits.min), ("max", limits.max)] {
+                    if !(0..=4095).contains(&value) {
+                        errors.push(format!("{}.angle_limits.{}: {} is outside 0-4095", path, field, value));
+                    }
+                }
+                if (limits.min, limits.max) != NO_LIMITS && limits.min >= limits.max {
+                    errors.push(format!("{}.angle_limits: min {} is not below max {}", path, limits.min, limits.max));
+                }
+            }
+            if let Some(profile) = &joint.calibration_profile {
+                if !self.calibration_profiles.contains_key(profile) {
+                    errors.push(format!("{}.calibration_profile: unknown profile {:?}", path, profile));
+                }
+            }
+            if let Some(max_torque) = joint.max_torque {
+                if max_torque > MAX_TORQUE {
+                    errors.push(format!("{}.max_torque: {} is above {}", path, max_torque, MAX_TORQUE));
+                }
+            }
+            let mapping = &joint.mapping;
+            if !(mapping.scale.is_finite() && mapping.scale != 0.0 && mapping.offset.is_finite()) {
+                errors.push(format!("{}.mapping: scale {} and offset {} must be finite, with a nonzero scale", path, mapping.scale, mapping.offset));
+            }
+        }
+
+        for (model, &scale) in &self.robot.current_scale {
+            if let Err(e) = parse_model(model).and_then(|_| check_scale(scale)) {
+                errors.push(format!("robot.current_scale.{}: {}", model, e));
+            }
+        }
+
+        for (i, pair) in self.robot.mirrored.iter().enumerate() {
+            if pair.joints[0] == pair.joints[1] {
+                errors.push(format!("robot.mirrored[{}]: {:?} is mirrored with itself", i, pair.joints[0]));
+            }
+        }
+
+        let names: Vec<&str> = joints.iter().map(|(name, _, _)| name.as_str()).collect();
+        let mut check_name = |field: String, name: &str| {
+            if !names.contains(&name) {
+                errors.push(format!("{}: unknown joint {:?}", field, name));
+            }
+        };
+        for (i, pair) in self.robot.coupled.iter().enumerate() {
+            for name in pair {
+                check_name(format!("robot.coupled[{}]", i), name);
+            }
+        }
+        for (i, pair) in self.robot.mirrored.iter().enumerate() {
+            for name in &pair.joints {
+                check_name(format!("robot.mirrored[{}]", i), name);
+            }
+        }
+        for (i, group) in self.homing.order.iter().enumerate() {
+            for name in group {
+                check_name(format!("homing.order[{}]", i), name);
+            }
+        }
+        for (chain, config) in &self.chains {
+            for (i, link) in config.links.iter().enumerate() {
+                check_name(format!("chains.{}.links[{}]", chain, i), &link.joint);
+            }
+        }
+        for (chain, config) in &self.chains {
+            if config.links.is_empty() {
+                errors.push(format!("chains.{}: no links", chain));
+            }
+            for (i, link) in config.links.iter().enumerate() {
+                if !(link.length.is_finite() && link.length >= 0.0) {
+                    errors.push(format!("chains.{}.links[{}].length: {} must be finite and not negative", chain, i, link.length));
+                }
+            }
+        }
+
+        for (name, profile) in &self.calibration_profiles {
+            if profile.speed == Some(0) {
+                errors.push(format!("calibration_profiles.{}.speed: must be above 0", name));
+            }
+            if let Some(threshold) = profile.current_threshold {
+                if !(threshold.is_finite() && threshold > 0.0) {
+                    errors.push(format!("calibration_profiles.{}.current_threshold: {} must be positive", name, threshold));
+                }
+            }
+        }
+
+        errors.extend(self.safe_mode.errors().into_iter().map(|e| format!("safe_mode.{}", e)));
+
+        if let Some(baud_rate) = self.bus.baud_rate {
+            if let Err(e) = baud_register_value(baud_rate) {
+                errors.push(format!("bus.baud_rate: {}", e));
+            }
+        }
+
+        if !errors.is_empty() {
+            bail!("{}", errors.join("\n"));
+        }
+        Ok(())
+    }
+}
+
+pub fn load_config<P: AsRef<Path>>(path: P) -> Result<Config> {
+    let path = path.as_ref();
+    let contents = fs::read_to_string(path)
+        .with_context(|| format!("Failed to read config {:?}", path))?;
+    let config: Config = toml::from_str(&contents)
+        .with_context(|| format!("Failed to parse config {:?}", path))?;
+    config.validate()
+        .with_context(|| format!("Invalid config {:?}", path))?;
+    Ok(config)
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::mirror::MirrorSign;
+    use crate::servo::MODEL_STS3215;
+
+    pub(crate) fn parse(toml: &str) -> Config {
+        toml::from_str(toml).unwrap()
+    }
+
+    pub(crate) fn errors(toml: &str) -> String {
+        parse(toml).validate().unwrap_err().to_string()
+    }
+
+    pub(crate) const TWO_LEGS: &str = r#"
+        [robot]
+        name = "test"
+        [robot.legs.left]
+        hip_pitch = { id = 1 }
+        [robot.legs.right]
+        hip_pitch = { id = 2 }
+    "#;
+
+    #[test]
+    fn shipped_config_is_valid() {
+        let config = load_config(Path::new(env!("CARGO_MANIFEST_DIR")).join("config/stompymicro.toml")).unwrap();
+        assert_eq!(config.robot.joints().len(), 16);
+    }
+
+    #[test]
+    fn joints_are_named_by_side() {
+        let config = parse(TWO_LEGS);
+        let joints: Vec<(String, String, u8)> = config.robot.joints().into_iter()
+            .map(|(name, path, joint)| (name, path, joint.id))
+            .collect();
+        assert_eq!(joints, vec![
+            ("left_hip_pitch".to_string(), "robot.legs.left.hip_pitch".to_string(), 1),
+            ("right_hip_pitch".to_string(), "robot.legs.right.hip_pitch".to_string(), 2),
+        ]);
+        assert!(config.validate().is_ok());
+    }
+
+    #[test]
+    fn every_error_is_reported_with_its_path() {
+        let errors = errors(r#"
+            [robot]
+            name = "test"
+            coupled = [["left_hip_pitch", "left_knee"]]
+            [robot.legs.left]
+            hip_pitch = { id = 1, limits = { lower = 1.0, upper = -1.0 } }
+            [robot.legs.right]
+            hip_pitch = { id = 1, angle_limits = { min = 3000, max = 5000 } }
+            [homing]
+            order = [["neck"]]
+        "#);
+        assert!(errors.contains("robot.legs.left.hip_pitch.limits: lower 1 is above upper -1"), "{}", errors);
+        assert!(errors.contains("robot.legs.right.hip_pitch.id: servo ID 1 already used by robot.legs.left.hip_pitch"), "{}", errors);
+        assert!(errors.contains("robot.legs.right.hip_pitch.angle_limits.max: 5000 is outside 0-4095"), "{}", errors);
+        assert!(errors.contains("robot.coupled[0]: unknown joint \"left_knee\""), "{}", errors);
+        assert!(errors.contains("homing.order[0]: unknown joint \"neck\""), "{}", errors);
+    }
+
+    #[test]
+    fn angle_limits_must_be_ordered_unless_disabled() {
+        let limits = |min: i16, max: i16| format!("[robot]\nname = \"test\"\n[robot.legs.left]\nhip_pitch = {{ id = 1, angle_limits = {{ min = {}, max = {} }} }}\n", min, max);
+        assert!(errors(&limits(3000, 1000)).contains("min 3000 is not below max 1000"));
+        assert!(parse(&limits(NO_LIMITS.0, NO_LIMITS.1)).validate().is_ok());
+    }
+
+    #[test]
+    fn mapping_flips_and_offsets_joints() {
+        let mapping = JointMapping { scale: -1.0, offset: 90.0 };
+        assert_eq!(mapping.to_joint(30.0), 60.0);
+        assert_eq!(mapping.to_servo(60.0), 30.0);
+        assert_eq!(JointMapping::default().to_joint(12.5), 12.5);
+    }
+
+    #[test]
+    fn rounding_defaults_to_nearest() {
+        assert_eq!(parse(TWO_LEGS).robot.rounding, Rounding::Nearest);
+        let config = parse(&TWO_LEGS.replace("name = \"test\"", "name = \"test\"\nrounding = \"inside\""));
+        assert_eq!(config.robot.rounding, Rounding::Inside);
+    }
+
+    #[test]
+    fn bus_errors_hold_by_default() {
+        assert_eq!(parse(TWO_LEGS).robot.on_bus_error, BusErrorPolicy::Hold);
+        let config = parse(&TWO_LEGS.replace("name = \"test\"", "name = \"test\"\non_bus_error = \"limp\""));
+        assert_eq!(config.robot.on_bus_error, BusErrorPolicy::Limp);
+    }
+
+    #[test]
+    fn preflight_is_opt_in() {
+        assert!(!parse(TWO_LEGS).robot.preflight);
+        assert!(parse(&TWO_LEGS.replace("name = \"test\"", "name = \"test\"\npreflight = true")).robot.preflight);
+    }
+
+    #[test]
+    fn mirrored_pairs_name_two_configured_joints() {
+        let mirrored = |pairs: &str| TWO_LEGS.replace("name = \"test\"", &format!("name = \"test\"\nmirrored = {}", pairs));
+        let config = parse(&mirrored(r#"[{ joints = ["left_hip_pitch", "right_hip_pitch"] }]"#));
+        assert_eq!(config.robot.mirrored[0].sign, MirrorSign::Inverted);
+        assert!(config.validate().is_ok());
+        let errors = errors(&mirrored(r#"[{ joints = ["left_hip_pitch", "left_hip_pitch"], sign = "same" }, { joints = ["left_hip_pitch", "neck"] }]"#));
+        assert!(errors.contains("robot.mirrored[0]: \"left_hip_pitch\" is mirrored with itself"), "{}", errors);
+        assert!(errors.contains("robot.mirrored[1]: unknown joint \"neck\""), "{}", errors);
+    }
+
+    #[test]
+    fn max_torque_is_at_most_full_stall_torque() {
+        let config = parse(&TWO_LEGS.replace("hip_pitch = { id = 1 }", "hip_pitch = { id = 1, max_torque = 400 }"));
+        assert_eq!(config.robot.legs["left"]["hip_pitch"].max_torque, Some(400));
+        assert!(config.validate().is_ok());
+        let errors = errors(&TWO_LEGS.replace("hip_pitch = { id = 1 }", "hip_pitch = { id = 1, max_torque = 1200 }"));
+        assert!(errors.contains("robot.legs.left.hip_pitch.max_torque: 1200 is above 1000"), "{}", errors);
+    }
+
+    #[test]
+    fn current_scale_overrides_per_model() {
+        let scaled = |table: &str| TWO_LEGS.replace("name = \"test\"", &format!("name = \"test\"\ncurrent_scale = {}", table));
+        let config = parse(&scaled(r#"{ "0x0903" = 0.5 }"#));
+        assert!(config.validate().is_ok());
+        assert_eq!(config.robot.current_scaling().unwrap().scale(MODEL_STS3215), 0.5);
+        assert_eq!(parse(TWO_LEGS).robot.current_scaling().unwrap(), ModelScaling::current());
+        let errors = errors(&scaled(r#"{ "sts" = 0.5, "0x0A01" = 0.0 }"#));
+        assert!(errors.contains("robot.current_scale.sts: Invalid model number"), "{}", errors);
+        assert!(errors.contains("robot.current_scale.0x0A01: Scale 0 must be positive"), "{}", errors);
+    }
+
+    #[test]
+    fn rates_ignore_the_offset_and_direction() {
+        let mapping = JointMapping { scale: -2.0, offset: 90.0 };
+        assert_eq!(mapping.rate_to_servo(30.0), 15.0);
+        assert_eq!(mapping.rate_to_servo(-30.0), 15.0);
+    }
+
+    #[test]
+    fn motion_caps_are_optional() {
+        let config = parse(&TWO_LEGS.replace("hip_pitch = { id = 1 }", "hip_pitch = { id = 1, max_velocity = 90.0, max_acceleration = 360.0 }"));
+        let joints = config.robot.joints();
+        assert_eq!((joints[0].2.max_velocity, joints[0].2.max_acceleration), (Some(90.0), Some(360.0)));
+        assert_eq!((joints[1].2.max_velocity, joints[1].2.max_acceleration), (None, None));
+    }
+
+    #[test]
+    fn chain_links_are_validated() {
+        let errors = errors(&format!("{}{}", TWO_LEGS, r#"
+            [chains.left_leg]
+            links = [{ joint = "left_hip_pitch", length = -0.1 }, { joint = "left_knee", length = 0.1 }]
+            [chains.empty]
+            links = []
+        "#));
+        assert!(errors.contains("chains.left_leg.links[0].length: -0.1 must be finite and not negative"), "{}", errors);
+        assert!(errors.contains("chains.left_leg.links[1]: unknown joint \"left_knee\""), "{}", errors);
+        assert!(errors.contains("chains.empty: no links"), "{}", errors);
+        assert!(parse(TWO_LEGS).chains.is_empty());
+    }
+
+    #[test]
+    fn calibration_profiles_are_checked() {
+        let config = parse(&format!("{}{}", TWO_LEGS.replace("hip_pitch = { id = 1 }", "hip_pitch = { id = 1, calibration_profile = \"gentle\" }"), r#"
+            [calibration_profiles.gentle]
+            speed = 300
+            backoff_cw_ms = 40
+        "#));
+        assert!(config.validate().is_ok());
+        assert_eq!(config.calibration_profiles["gentle"], CalibrationProfile { speed: Some(300), backoff_cw_ms: Some(40), ..Default::default() });
+        assert_eq!(config.robot.joints()[0].2.calibration_profile.as_deref(), Some("gentle"));
+
+        let errors = errors(&format!("{}{}", TWO_LEGS.replace("hip_pitch = { id = 2 }", "hip_pitch = { id = 2, calibration_profile = \"rough\" }"), r#"
+            [calibration_profiles.stuck]
+            speed = 0
+            current_threshold = -5.0
+        "#));
+        assert!(errors.contains("robot.legs.right.hip_pitch.calibration_profile: unknown profile \"rough\""), "{}", errors);
+        assert!(errors.contains("calibration_profiles.stuck.speed: must be above 0"), "{}", errors);
+        assert!(errors.contains("calibration_profiles.stuck.current_threshold: -5 must be positive"), "{}", errors);
+        let typo = format!("{}\n[calibration_profiles.gentle]\nsped = 300\n", TWO_LEGS);
+        assert!(toml::from_str::<Config>(&typo).is_err());
+    }
+
+    #[test]
+    fn safe_mode_is_off_unless_configured() {
+        assert_eq!(parse(TWO_LEGS).safe_mode, SafeMode::default());
+        let config = parse(&format!("{}\n[safe_mode]\nenabled = true\nmax_velocity = 30.0\n", TWO_LEGS));
+        assert_eq!(config.safe_mode, SafeMode { enabled: true, max_velocity: 30.0, ..Default::default() });
+        let errors = errors(&format!("{}\n[safe_mode]\ntorque_limit = 2000\n", TWO_LEGS));
+        assert!(errors.contains("safe_mode.torque_limit: 2000 is above 1000"), "{}", errors);
+        assert!(toml::from_str::<Config>(&format!("{}\n[safe_mode]\nspeed = 30.0\n", TWO_LEGS)).is_err());
+    }
+}
@@ -0,0 +1,206 @@
+use anyhow::Result;
+use std::collections::HashMap;
+use crate::alarm::AlarmMask;
+use crate::hal::{Servo, ServoInfo};
+use crate::robot::{Joint, Robot};
+use crate::servo::ModelScaling;
+
+// What the thermal/overload dashboard shows for one servo, all from a
+// single ServoInfo frame
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct HealthReading {
+    pub celsius: f32,
+    pub milliamps: f32,
+    pub volts: f32,
+    pub status: AlarmMask,
+}
+
+impl HealthReading {
+    pub fn from_info(info: &ServoInfo, current_scale: f32, temperature_scale: f32) -> Self {
+        Self {
+            celsius: info.scaled_temperature(temperature_scale),
+            milliamps: info.scaled_current(current_scale),
+            volts: info.current_voltage as f32 / 10.0,
+            status: info.status(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct JointHealth {
+    pub name: String,
+    pub id: u8,
+    // None if the servo didn't reply
+    pub reading: Option<HealthReading>,
+}
+
+impl JointHealth {
+    // Silent servos count as faulted, the dashboard can't vouch for them
+    pub fn is_faulted(&self) -> bool {
+        self.reading.is_none_or(|reading| reading.status != AlarmMask::NONE)
+    }
+}
+
+// Scales each reply, `scales` gives the (current, temperature) scales for
+// a servo and is only asked about servos that replied
+pub fn health_readings(
+    infos: &[(u8, Option<ServoInfo>)],
+    mut scales: impl FnMut(u8) -> Result<(f32, f32)>,
+) -> Result<Vec<(u8, Option<HealthReading>)>> {
+    infos.iter()
+        .map(|&(id, info)| match info {
+            Some(info) => {
+                let (current_scale, temperature_scale) = scales(id)?;
+                Ok((id, Some(HealthReading::from_info(&info, current_scale, temperature_scale))))
+            }
+            None => Ok((id, None)),
+        })
+        .collect()
+}
+
+// One entry per joint, in joint order, matched to `readings` by ID
+pub fn aggregate_health(joints: &[Joint], readings: &[(u8, Option<HealthReading>)]) -> Vec<JointHealth> {
+    joints.iter()
+        .map(|joint| JointHealth {
+            name: joint.name.clone(),
+            id: joint.id,
+            reading: readings.iter().find(|(id, _)| *id == joint.id).and_then(|&(_, reading)| reading),
+        })
+        .collect()
+}
+
+impl Servo {
+    // Temperature, current, voltage and status sit in the one block of
+    // registers sync_read_info covers, so this is a single sync read for
+    // every servo, plus a model read the first time a servo is seen,
+    // remembered in `models`. `current` is usually ModelScaling::current(),
+    // or a robot's Robot::current_scaling.
+    pub fn read_health(&self, ids: &[u8], current: &ModelScaling, models: &mut HashMap<u8, u16>) -> Result<Vec<(u8, Option<HealthReading>)>> {
+        let infos = self.sync_read_info(ids)?;
+        let temperature = ModelScaling::temperature();
+        health_readings(&infos, |id| {
+            let model = match models.get(&id) {
+                Some(&model) => model,
+                None => {
+                    let model = self.read_model(id)?;
+                    models.insert(id, model);
+                    model
+                }
+            };
+            Ok((current.scale(model), temperature.scale(model)))
+        })
+    }
+}
+
+impl Robot {
+    // Every joint's temperature, current, voltage and faults, for the
+    // thermal/overload dashboard. Joints that don't reply are listed
+    // without a reading rather than failing the whole report.
+    pub fn health(&self) -> Result<Vec<JointHealth>> {
+        let ids: Vec<u8> = self.joints().iter().map(|joint| joint.id).collect();
+        let mut models = self.models.lock().unwrap_or_else(|e| e.into_inner());
+        let readings = self.servo().read_health(&ids, self.current_scaling(), &mut models)?;
+        Ok(aggregate_health(self.joints(), &readings))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::robot::tests::joint;
+
+    fn reading(status: AlarmMask) -> HealthReading {
+        HealthReading { celsius: 30.0, milliamps: 0.0, volts: 12.0, status }
+    }
+
+    #[test]
+    fn readings_are_scaled_from_one_frame() {
+        let info = ServoInfo { current_temperature: 40, current_voltage: 121, current_current: 100, servo_status: 0x04, ..ServoInfo::default() };
+        assert_eq!(HealthReading::from_info(&info, 6.5, 1.0), HealthReading {
+            celsius: 40.0,
+            milliamps: 650.0,
+            volts: 12.1,
+            status: AlarmMask::TEMPERATURE,
+        });
+    }
+
+    #[test]
+    fn silent_or_alarmed_servos_are_faulted() {
+        let health = |reading| JointHealth { name: "left".to_string(), id: 1, reading };
+        assert!(health(None).is_faulted());
+        assert!(health(Some(reading(AlarmMask::OVERLOAD))).is_faulted());
+        assert!(!health(Some(reading(AlarmMask::NONE))).is_faulted());
+    }
+
+    #[test]
+    fn only_servos_that_replied_are_scaled() {
+        let info = ServoInfo { current_temperature: 50, ..ServoInfo::default() };
+        let mut asked = Vec::new();
+        let readings = health_readings(&[(1, Some(info)), (2, None)], |id| {
+            asked.push(id);
+            Ok((1.0, 2.0))
+        }).unwrap();
+        assert_eq!(asked, vec![1]);
+        assert_eq!(readings[0].1.unwrap().celsius, 100.0);
+        assert_eq!(readings[1], (2, None));
+    }
+
+    #[test]
+    fn scale_errors_fail_the_readings() {
+        let infos = [(1, Some(ServoInfo::default()))];
+        assert!(health_readings(&infos, |_| anyhow::bail!("no model")).is_err());
+    }
+
+    #[test]
+    fn health_follows_joint_order() {
+        let joints = [joint("left", 1), joint("right", 2), joint("neck", 3)];
+        let readings = [(2, Some(reading(AlarmMask::NONE))), (1, None)];
+        let health = aggregate_health(&joints, &readings);
+        let names: Vec<&str> = health.iter().map(|health| health.name.as_str()).collect();
+        assert_eq!(names, vec!["left", "right", "neck"]);
+        assert_eq!(health[0].reading, None);
+        assert_eq!(health[1].reading, Some(reading(AlarmMask::NONE)));
+        // Not in the readings at all
+        assert_eq!(health[2].reading, None);
+    }
+
+    #[cfg(not(feature = "milkv"))]
+    mod bus {
+        use super::*;
+        use crate::hal::ServoRegister;
+        use crate::robot::tests::mock_robot;
+        use crate::servo::MODEL_STS3215;
+
+        #[test]
+        fn robot_health_reads_every_joint() {
+            let (bus, robot) = mock_robot(&[("left", 1), ("right", 2), ("neck", 3)]);
+            bus.set_u8(1, ServoRegister::CurrentTemperature, 55);
+            bus.set_u8(2, ServoRegister::ServoStatus, AlarmMask::OVERLOAD.bits());
+            bus.remove(3);
+            let health = robot.health().unwrap();
+            let left = health[0].reading.unwrap();
+            assert_eq!((left.celsius, left.volts, left.status), (55.0, 12.0, AlarmMask::NONE));
+            assert!(!health[0].is_faulted());
+            assert_eq!(health[1].reading.unwrap().status, AlarmMask::OVERLOAD);
+            assert_eq!(health[2].reading, None);
+            assert_eq!(robot.models.lock().unwrap().get(&1), Some(&MODEL_STS3215));
+            assert_eq!(robot.models.lock().unwrap().get(&3), None);
+        }
+
+        #[test]
+        fn current_uses_the_model_scale() {
+            let (bus, robot) = mock_robot(&[("left", 1)]);
+            bus.with(|servos| servos.memory.get_mut(&1).unwrap()[0x44..0x46].copy_from_slice(&[100, 0]));
+            let milliamps = robot.health().unwrap()[0].reading.unwrap().milliamps;
+            assert!((milliamps - 100.0 * ModelScaling::current().scale(MODEL_STS3215)).abs() < 1e-3);
+        }
+
+        #[test]
+        fn current_follows_the_robot_scaling() {
+            let (bus, robot) = mock_robot(&[("left", 1)]);
+            let robot = robot.with_current_scaling(ModelScaling::current().with_models([(MODEL_STS3215, 2.0)]));
+            bus.with(|servos| servos.memory.get_mut(&1).unwrap()[0x44..0x46].copy_from_slice(&[100, 0]));
+            assert_eq!(robot.health().unwrap()[0].reading.unwrap().milliamps, 200.0);
+        }
+    }
+}
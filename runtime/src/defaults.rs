@@ -0,0 +1,145 @@
+use anyhow::{Result, bail};
+use std::fmt;
+use crate::endian::byte_order;
+use crate::hal::{Servo, ServoRegister};
+use crate::servo::MODEL_STS3215;
+
+// Factory value of every configurable EEPROM register of the STS3215, from
+// the Feetech memory table. The ID is left out, every servo on a bus has
+// been renumbered.
+pub const STS3215_DEFAULTS: &[(ServoRegister, u16)] = &[
+    (ServoRegister::BaudRate, 0),
+    (ServoRegister::ReturnDelay, 0),
+    (ServoRegister::ResponseStatusLevel, 1),
+    (ServoRegister::MinAngleLimit, 0),
+    (ServoRegister::MaxAngleLimit, 4095),
+    (ServoRegister::MaxTemperatureLimit, 70),
+    (ServoRegister::MaxInputVoltage, 80),
+    (ServoRegister::MinInputVoltage, 40),
+    (ServoRegister::MaxTorque, 1000),
+    (ServoRegister::Phase, 12),
+    (ServoRegister::UnloadingCondition, 44),
+    (ServoRegister::LEDAlarmCondition, 47),
+    (ServoRegister::PProportionalCoeff, 32),
+    (ServoRegister::DDifferentialCoeff, 32),
+    (ServoRegister::IIntegralCoeff, 0),
+    (ServoRegister::MinStartupForce, 16),
+    (ServoRegister::ClockwiseInsensitiveArea, 1),
+    (ServoRegister::CounterclockwiseInsensitiveArea, 1),
+    (ServoRegister::ProtectionCurrent, 500),
+    (ServoRegister::AngularResolution, 1),
+    (ServoRegister::PositionCorrection, 0),
+    (ServoRegister::OperationMode, 0),
+    (ServoRegister::ProtectiveTorque, 20),
+    (ServoRegister::ProtectionTime, 200),
+    (ServoRegister::OverloadTorque, 80),
+    (ServoRegister::SpeedClosedLoopPCoeff, 10),
+    (ServoRegister::OverCurrentProtectionTime, 200),
+    (ServoRegister::VelocityClosedLoopICoeff, 10),
+];
+
+pub fn factory_defaults(model: u16) -> Option<&'static [(ServoRegister, u16)]> {
+    match model {
+        MODEL_STS3215 => Some(STS3215_DEFAULTS),
+        _ => None,
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct RegisterDiff {
+    pub register: ServoRegister,
+    pub default: u16,
+    pub actual: u16,
+}
+
+impl fmt::Display for RegisterDiff {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?} (0x{:02X}): {} (default {})", self.register, self.register as u8, self.actual, self.default)
+    }
+}
+
+// Registers in `defaults` whose value, as returned by `read`, differs
+pub fn diff_registers<F>(defaults: &[(ServoRegister, u16)], mut read: F) -> Result<Vec<RegisterDiff>>
+where
+    F: FnMut(ServoRegister) -> Result<u16>,
+{
+    let mut diffs = Vec::new();
+    for &(register, default) in defaults {
+        let actual = read(register)?;
+        if actual != default {
+            diffs.push(RegisterDiff { register, default, actual });
+        }
+    }
+    Ok(diffs)
+}
+
+impl Servo {
+    // Every configurable register that differs from the factory default for
+    // this servo's model
+    pub fn diff_from_defaults(&self, id: u8) -> Result<Vec<RegisterDiff>> {
+        let model = self.read_model(id)?;
+        let Some(defaults) = factory_defaults(model) else {
+            bail!("No factory defaults known for model 0x{:04X} of servo {}", model, id);
+        };
+        diff_registers(defaults, |register| match byte_order(register) {
+            Some(_) => self.read_u16(id, register),
+            None => Ok(self.read_exact(id, register, 1)?[0] as u16),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn defaults_are_known_per_model() {
+        assert_eq!(factory_defaults(MODEL_STS3215).map(|defaults| defaults.len()), Some(STS3215_DEFAULTS.len()));
+        assert!(factory_defaults(0x0000).is_none());
+        assert!(STS3215_DEFAULTS.iter().all(|&(register, _)| register as u8 != ServoRegister::ID as u8));
+    }
+
+    #[test]
+    fn only_differing_registers_are_listed() {
+        let defaults = [(ServoRegister::MaxTorque, 1000), (ServoRegister::Phase, 12)];
+        let diffs = diff_registers(&defaults, |register| Ok(if register as u8 == ServoRegister::Phase as u8 { 76 } else { 1000 })).unwrap();
+        assert_eq!(diffs.len(), 1);
+        assert_eq!(diffs[0].to_string(), "Phase (0x12): 76 (default 12)");
+        assert!(diff_registers(&defaults, |_| bail!("no reply")).is_err());
+    }
+
+    #[cfg(not(feature = "milkv"))]
+    mod bus {
+        use super::*;
+        use crate::hal::mock::MockBus;
+
+        fn factory_fresh(bus: &MockBus, id: u8) {
+            for &(register, default) in STS3215_DEFAULTS {
+                match byte_order(register) {
+                    Some(_) => bus.set_u16(id, register, default),
+                    None => bus.set_u8(id, register, default as u8),
+                }
+            }
+        }
+
+        #[test]
+        fn servo_is_compared_with_its_model_defaults() {
+            let bus = MockBus::new(&[1]);
+            factory_fresh(&bus, 1);
+            let servo = Servo::mock(&bus);
+            assert!(servo.diff_from_defaults(1).unwrap().is_empty());
+
+            bus.set_u16(1, ServoRegister::MaxAngleLimit, 3000);
+            bus.set_u8(1, ServoRegister::MaxTemperatureLimit, 65);
+            let diffs: Vec<(u8, u16)> = servo.diff_from_defaults(1).unwrap().iter().map(|diff| (diff.register as u8, diff.actual)).collect();
+            assert_eq!(diffs, [(ServoRegister::MaxAngleLimit as u8, 3000), (ServoRegister::MaxTemperatureLimit as u8, 65)]);
+        }
+
+        #[test]
+        fn unknown_model_has_no_defaults() {
+            let bus = MockBus::new(&[1]);
+            bus.set_u8(1, ServoRegister::ServoMainVersion, 0x01);
+            assert!(Servo::mock(&bus).diff_from_defaults(1).unwrap_err().to_string().contains("No factory defaults"));
+        }
+    }
+}
@@ -0,0 +1,140 @@
+use anyhow::{Result, bail};
+use crate::hal::{Servo, ServoRegister};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ByteOrder {
+    Little,
+    Big,
+}
+
+// Byte order of each two-byte register, None for single-byte registers.
+// Audited against the STS3215 memory table: every two-byte register is
+// little-endian, low byte at the lower address. The SCS series stores the
+// same registers big-endian, supporting it means answering Big here rather
+// than touching the decoding.
+//
+// Some values span two single-byte registers and aren't listed: the model
+// number is ServoMainVersion then ServoSubVersion, read big-endian (see
+// Servo::read_model), and the compliance margins are two separate bytes.
+pub fn byte_order(register: ServoRegister) -> Option<ByteOrder> {
+    use ServoRegister::*;
+    match register {
+        MinAngleLimit | MaxAngleLimit | MaxTorque | ProtectionCurrent | PositionCorrection
+        | TargetLocation | RunningTime | RunningSpeed | TorqueLimit
+        | CurrentLocation | CurrentSpeed | CurrentLoad | CurrentCurrent => Some(ByteOrder::Little),
+        _ => None,
+    }
+}
+
+// The read helpers take the offset of the value within a reply and panic if
+// it's out of range, callers check the reply length first
+pub fn read_u16_le(data: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes([data[offset], data[offset + 1]])
+}
+
+pub fn read_i16_le(data: &[u8], offset: usize) -> i16 {
+    read_u16_le(data, offset) as i16
+}
+
+pub fn read_u16_be(data: &[u8], offset: usize) -> u16 {
+    u16::from_be_bytes([data[offset], data[offset + 1]])
+}
+
+pub fn write_u16_le(value: u16) -> [u8; 2] {
+    value.to_le_bytes()
+}
+
+pub fn write_i16_le(value: i16) -> [u8; 2] {
+    write_u16_le(value as u16)
+}
+
+pub fn write_u16_be(value: u16) -> [u8; 2] {
+    value.to_be_bytes()
+}
+
+pub fn decode_u16(register: ServoRegister, data: &[u8]) -> Result<u16> {
+    match byte_order(register) {
+        Some(ByteOrder::Little) => Ok(read_u16_le(data, 0)),
+        Some(ByteOrder::Big) => Ok(read_u16_be(data, 0)),
+        None => bail!("{:?} is not a two-byte register", register),
+    }
+}
+
+pub fn encode_u16(register: ServoRegister, value: u16) -> Result<[u8; 2]> {
+    match byte_order(register) {
+        Some(ByteOrder::Little) => Ok(write_u16_le(value)),
+        Some(ByteOrder::Big) => Ok(write_u16_be(value)),
+        None => bail!("{:?} is not a two-byte register", register),
+    }
+}
+
+impl Servo {
+    // A two-byte register in its own byte order
+    pub fn read_u16(&self, id: u8, register: ServoRegister) -> Result<u16> {
+        byte_order(register).ok_or_else(|| anyhow::anyhow!("{:?} is not a two-byte register", register))?;
+        let data = self.read_exact(id, register, 2)?;
+        decode_u16(register, &data)
+    }
+
+    pub fn write_u16(&self, id: u8, register: ServoRegister, value: u16) -> Result<()> {
+        self.write(id, register, &encode_u16(register, value)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn two_byte_registers_are_little_endian() {
+        for register in [ServoRegister::MinAngleLimit, ServoRegister::TargetLocation, ServoRegister::CurrentLocation, ServoRegister::CurrentCurrent] {
+            assert_eq!(byte_order(register), Some(ByteOrder::Little), "{:?}", register);
+        }
+        for register in [ServoRegister::ID, ServoRegister::ServoMainVersion, ServoRegister::TorqueSwitch, ServoRegister::CurrentVoltage] {
+            assert_eq!(byte_order(register), None, "{:?}", register);
+        }
+    }
+
+    #[test]
+    fn helpers_read_at_the_offset() {
+        let data = [0xFF, 0x34, 0x12, 0xFF];
+        assert_eq!(read_u16_le(&data, 1), 0x1234);
+        assert_eq!(read_u16_be(&data, 1), 0x3412);
+        assert_eq!(read_i16_le(&write_i16_le(-5), 0), -5);
+        assert_eq!(write_u16_le(0x1234), [0x34, 0x12]);
+        assert_eq!(write_u16_be(0x1234), [0x12, 0x34]);
+    }
+
+    #[test]
+    fn single_byte_registers_are_refused() {
+        assert_eq!(decode_u16(ServoRegister::RunningSpeed, &[0xE8, 0x03]).unwrap(), 1000);
+        assert_eq!(encode_u16(ServoRegister::RunningSpeed, 1000).unwrap(), [0xE8, 0x03]);
+        assert!(decode_u16(ServoRegister::LockMark, &[0, 0]).is_err());
+        assert!(encode_u16(ServoRegister::LockMark, 0).is_err());
+    }
+
+    #[cfg(not(feature = "milkv"))]
+    mod bus {
+        use super::*;
+        use crate::hal::mock::{MockBus, MockWrite};
+
+        #[test]
+        fn registers_round_trip_low_byte_first() {
+            let bus = MockBus::new(&[1]);
+            let servo = Servo::mock(&bus);
+            servo.write_u16(1, ServoRegister::TargetLocation, 0x0123).unwrap();
+            assert_eq!(bus.writes(), vec![MockWrite { id: 1, address: ServoRegister::TargetLocation as u8, data: vec![0x23, 0x01] }]);
+            assert_eq!(servo.read_u16(1, ServoRegister::TargetLocation).unwrap(), 0x0123);
+        }
+
+        #[test]
+        fn single_byte_registers_never_reach_the_bus() {
+            let bus = MockBus::new(&[1]);
+            let servo = Servo::mock(&bus);
+            assert!(servo.read_u16(1, ServoRegister::ID).is_err());
+            assert!(servo.write_u16(1, ServoRegister::ID, 2).is_err());
+            assert!(bus.writes().is_empty());
+            assert_eq!(bus.u8(1, ServoRegister::ID), 1);
+        }
+    }
+}
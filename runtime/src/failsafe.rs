@@ -0,0 +1,186 @@
+use anyhow::Result;
+use serde::Deserialize;
+use crate::robot::Robot;
+
+// What Robot's control helpers do when a bus transaction fails partway
+// through a motion, under [robot] as e.g. on_bus_error = "limp".
+//
+// Hold (fail-closed) re-sends the last goals that got through, with one
+// sync write that needs no reply, so joints stop where they were last
+// commanded instead of finishing a move nobody is watching any more. A
+// standing robot stays up; but a joint that is jammed or pinching keeps
+// pushing, and if the bus is dead for good the servos keep that goal until
+// the power goes.
+//
+// Limp (fail-open) cuts torque on every joint, see Servo::estop_all. Nothing
+// keeps pushing and a runaway can't continue, at the cost of the robot
+// collapsing under its own weight.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BusErrorPolicy {
+    #[default]
+    Hold,
+    Limp,
+}
+
+// Carry out `policy` through `hold` (given the last goals) or `limp` (given
+// every joint ID). Holding with no goals recorded yet writes nothing, the
+// servos already keep whatever goal they have.
+pub fn react_to_bus_error(
+    policy: BusErrorPolicy,
+    last_goals: &[(u8, i16)],
+    ids: &[u8],
+    hold: impl FnOnce(&[(u8, i16)]) -> Result<()>,
+    limp: impl FnOnce(&[u8]) -> Result<()>,
+) -> Result<()> {
+    match policy {
+        BusErrorPolicy::Hold if last_goals.is_empty() => Ok(()),
+        BusErrorPolicy::Hold => hold(last_goals),
+        BusErrorPolicy::Limp => limp(ids),
+    }
+}
+
+impl Robot {
+    // Runs the bus transactions of a control helper, applying the bus error
+    // policy if they fail. The original error is returned either way, with
+    // what was done about it.
+    pub(crate) fn guard_bus<T>(&self, transactions: impl FnOnce() -> Result<T>) -> Result<T> {
+        let error = match transactions() {
+            Ok(value) => return Ok(value),
+            Err(error) => error,
+        };
+        let policy = self.bus_error_policy();
+        let mut last_goals: Vec<(u8, i16)> = self.last_goals.lock().unwrap_or_else(|e| e.into_inner())
+            .iter().map(|(&id, &goal)| (id, goal)).collect();
+        last_goals.sort();
+        let ids: Vec<u8> = self.joints().iter().map(|joint| joint.id).collect();
+        let reaction = react_to_bus_error(
+            policy,
+            &last_goals,
+            &ids,
+            |goals| self.servo().sync_write_positions(goals),
+            |ids| self.servo().estop_all(ids),
+        );
+        Err(match reaction {
+            Ok(()) => error.context(format!("Bus error during motion, {:?} policy applied", policy)),
+            Err(e) => error.context(format!("Bus error during motion, applying the {:?} policy failed too: {:#}", policy, e)),
+        })
+    }
+
+    // Goals known to have reached the servos, held under BusErrorPolicy::Hold
+    pub(crate) fn record_goals(&self, goals: &[(u8, i16)]) {
+        let mut last_goals = self.last_goals.lock().unwrap_or_else(|e| e.into_inner());
+        last_goals.extend(goals.iter().copied());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+
+    // Runs react_to_bus_error, returning which side was called with what
+    fn react(policy: BusErrorPolicy, last_goals: &[(u8, i16)]) -> (Vec<(u8, i16)>, Vec<u8>) {
+        let held = RefCell::new(Vec::new());
+        let limped = RefCell::new(Vec::new());
+        react_to_bus_error(
+            policy,
+            last_goals,
+            &[1, 2],
+            |goals| { held.borrow_mut().extend_from_slice(goals); Ok(()) },
+            |ids| { limped.borrow_mut().extend_from_slice(ids); Ok(()) },
+        ).unwrap();
+        (held.into_inner(), limped.into_inner())
+    }
+
+    #[test]
+    fn hold_resends_the_last_goals() {
+        assert_eq!(react(BusErrorPolicy::Hold, &[(1, 1000), (2, 3000)]), (vec![(1, 1000), (2, 3000)], vec![]));
+    }
+
+    #[test]
+    fn hold_without_goals_writes_nothing() {
+        assert_eq!(react(BusErrorPolicy::Hold, &[]), (vec![], vec![]));
+    }
+
+    #[test]
+    fn limp_releases_every_joint() {
+        assert_eq!(react(BusErrorPolicy::Limp, &[(1, 1000)]), (vec![], vec![1, 2]));
+    }
+
+    #[test]
+    fn reaction_errors_are_returned() {
+        let result = react_to_bus_error(BusErrorPolicy::Limp, &[], &[1], |_| Ok(()), |_| anyhow::bail!("bus gone"));
+        assert_eq!(result.unwrap_err().to_string(), "bus gone");
+    }
+
+    #[test]
+    fn hold_is_the_default() {
+        assert_eq!(BusErrorPolicy::default(), BusErrorPolicy::Hold);
+    }
+
+    #[cfg(not(feature = "milkv"))]
+    mod bus {
+        use super::*;
+        use crate::hal::ServoRegister;
+        use crate::robot::tests::mock_robot;
+
+        #[test]
+        fn guard_passes_results_through() {
+            let (bus, robot) = mock_robot(&[("left", 1)]);
+            assert_eq!(robot.guard_bus(|| Ok(7)).unwrap(), 7);
+            assert!(bus.writes().is_empty());
+        }
+
+        #[test]
+        fn guard_holds_the_recorded_goals() {
+            let (bus, robot) = mock_robot(&[("left", 1), ("right", 2)]);
+            robot.record_goals(&[(1, 1000), (2, 3000)]);
+            let error = robot.guard_bus(|| robot.servo().read_position(9)).unwrap_err();
+            assert!(format!("{:#}", error).starts_with("Bus error during motion, Hold policy applied"));
+            assert_eq!(bus.u16(1, ServoRegister::TargetLocation), 1000);
+            assert_eq!(bus.u16(2, ServoRegister::TargetLocation), 3000);
+        }
+
+        #[test]
+        fn later_goals_replace_earlier_ones() {
+            let (bus, robot) = mock_robot(&[("left", 1)]);
+            robot.record_goals(&[(1, 1000)]);
+            robot.record_goals(&[(1, 1500)]);
+            robot.guard_bus(|| robot.servo().read_position(9)).unwrap_err();
+            assert_eq!(bus.u16(1, ServoRegister::TargetLocation), 1500);
+        }
+
+        #[test]
+        fn guard_limps_every_joint() {
+            let (bus, robot) = mock_robot(&[("left", 1), ("right", 2)]);
+            let robot = robot.with_bus_error_policy(BusErrorPolicy::Limp);
+            bus.set_u8(1, ServoRegister::TorqueSwitch, 1);
+            bus.set_u8(2, ServoRegister::TorqueSwitch, 1);
+            let error = robot.guard_bus(|| robot.servo().read_position(9)).unwrap_err();
+            assert!(format!("{:#}", error).starts_with("Bus error during motion, Limp policy applied"));
+            assert_eq!(bus.u8(1, ServoRegister::TorqueSwitch), 0);
+            assert_eq!(bus.u8(2, ServoRegister::TorqueSwitch), 0);
+        }
+
+        #[test]
+        fn failed_reaction_is_reported_with_the_error() {
+            let (bus, robot) = mock_robot(&[("left", 1)]);
+            let robot = robot.with_bus_error_policy(BusErrorPolicy::Limp);
+            bus.refuse(1, ServoRegister::TorqueSwitch);
+            bus.set_u8(1, ServoRegister::TorqueSwitch, 1);
+            let error = robot.guard_bus(|| robot.servo().read_position(9)).unwrap_err();
+            assert!(format!("{:#}", error).contains("applying the Limp policy failed too"));
+        }
+
+        #[test]
+        fn move_group_records_its_goals() {
+            let (bus, robot) = mock_robot(&[("left", 1)]);
+            bus.set_u16(1, ServoRegister::MinAngleLimit, 1024);
+            bus.set_u16(1, ServoRegister::MaxAngleLimit, 3072);
+            let positions = robot.move_group(&[("left", 10.0)], std::time::Duration::from_millis(500)).unwrap();
+            assert_eq!(positions, vec![(1, 2162)]);
+            assert_eq!(robot.last_goals.lock().unwrap()[&1], 2162);
+        }
+    }
+}
@@ -0,0 +1,115 @@
+use anyhow::{Result, bail};
+use std::thread::sleep;
+use std::time::Duration;
+use crate::hal::{Servo, ServoDirection};
+
+// Mean loads within this many 0.1% steps of zero have no direction
+pub const LOAD_DEAD_BAND: f32 = 5.0;
+
+// Effort a still joint needs to hold its position, a proxy for the gravity
+// torque on it in this pose. Magnitude in % of full torque.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct HoldingLoad {
+    // The way the servo pushes, against gravity. None within LOAD_DEAD_BAND.
+    pub direction: Option<ServoDirection>,
+    pub magnitude: f32,
+    pub samples: usize,
+}
+
+impl HoldingLoad {
+    // Signed like ServoInfo::load, positive pushing clockwise, for a
+    // feed-forward term
+    pub fn signed(&self) -> f32 {
+        match self.direction {
+            Some(ServoDirection::Counterclockwise) => -self.magnitude,
+            _ => self.magnitude,
+        }
+    }
+}
+
+// `loads` as returned by ServoInfo::load
+pub fn holding_load(loads: &[i16]) -> HoldingLoad {
+    let mean = if loads.is_empty() {
+        0.0
+    } else {
+        loads.iter().map(|&load| load as f32).sum::<f32>() / loads.len() as f32
+    };
+    let direction = if mean > LOAD_DEAD_BAND {
+        Some(ServoDirection::Clockwise)
+    } else if mean < -LOAD_DEAD_BAND {
+        Some(ServoDirection::Counterclockwise)
+    } else {
+        None
+    };
+    HoldingLoad { direction, magnitude: mean.abs() / 10.0, samples: loads.len() }
+}
+
+impl Servo {
+    // Average of `samples` load readings `interval` apart. The load of a
+    // moving joint includes its acceleration and friction, so this fails if
+    // the joint moves at any point.
+    pub fn read_holding_load(&self, id: u8, samples: usize, interval: Duration) -> Result<HoldingLoad> {
+        let mut loads = Vec::with_capacity(samples);
+        for i in 0..samples {
+            if i > 0 {
+                sleep(interval);
+            }
+            let info = self.read_info(id)?;
+            if info.speed() != 0 {
+                bail!("Servo {} is moving at {} ticks/s, its holding load needs a still joint", id, info.speed());
+            }
+            loads.push(info.load());
+        }
+        Ok(holding_load(&loads))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn holding_load_is_the_mean_in_percent() {
+        let load = holding_load(&[300, 320, 340]);
+        assert_eq!(load, HoldingLoad { direction: Some(ServoDirection::Clockwise), magnitude: 32.0, samples: 3 });
+        assert_eq!(load.signed(), 32.0);
+        let load = holding_load(&[-150, -250]);
+        assert_eq!(load.direction, Some(ServoDirection::Counterclockwise));
+        assert_eq!(load.signed(), -20.0);
+    }
+
+    #[test]
+    fn small_loads_have_no_direction() {
+        let load = holding_load(&[4, -2, 6]);
+        assert_eq!(load.direction, None);
+        assert!(load.magnitude < 0.5);
+        assert_eq!(holding_load(&[]), HoldingLoad { direction: None, magnitude: 0.0, samples: 0 });
+    }
+
+    #[cfg(not(feature = "milkv"))]
+    mod bus {
+        use super::*;
+        use crate::hal::ServoRegister;
+        use crate::hal::mock::MockBus;
+
+        #[test]
+        fn sign_magnitude_load_is_read_signed() {
+            let bus = MockBus::new(&[1]);
+            // 30% pushing counterclockwise
+            bus.set_u16(1, ServoRegister::CurrentLoad, 0x400 | 300);
+            let servo = Servo::mock(&bus);
+            assert_eq!(servo.read_info(1).unwrap().load(), -300);
+            let load = servo.read_holding_load(1, 3, Duration::from_millis(1)).unwrap();
+            assert_eq!(load.signed(), -30.0);
+            assert_eq!(load.samples, 3);
+        }
+
+        #[test]
+        fn moving_joint_has_no_holding_load() {
+            let bus = MockBus::new(&[1]);
+            bus.set_u16(1, ServoRegister::CurrentSpeed, 40);
+            let error = Servo::mock(&bus).read_holding_load(1, 3, Duration::from_millis(1)).unwrap_err();
+            assert_eq!(error.to_string(), "Servo 1 is moving at 40 ticks/s, its holding load needs a still joint");
+        }
+    }
+}
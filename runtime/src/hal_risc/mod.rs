@@ -3,10 +3,17 @@ use std::os::raw::{c_int, c_short, c_uchar, c_ushort, c_uint};
 use std::error::Error;
 use i2cdev::linux::LinuxI2CDevice;
 use i2cdev::core::I2CDevice;
-use crate::hal::{ServoInfo, ServoData, ServoMultipleWriteCommand, ServoMode, ServoDirection, ServoRegister, MemoryLockState, TorqueMode, IMUData, MAX_SERVOS};
+use crate::hal::{ServoInfo, ServoData, ServoMultipleWriteCommand, ServoMode, ServoDirection, ServoRegister, MemoryLockState, TorqueMode, IMUData, ServoError, MAX_SERVOS};
+use std::time::{Duration, Instant};
 use std::sync::{Arc, Mutex};
 use std::fmt;
 use crate::hal_risc::qmi8658::QMI8658;
+use crate::endian::write_u16_le;
+use crate::alarm::StatusMonitor;
+use crate::buslock::BusClaim;
+use crate::health::BusMonitor;
+use crate::servo::CENTER_POSITION;
+use crate::units::{deg_to_ticks, ticks_to_deg};
 
 #[link(name = "sts3215")]
 extern "C" {
@@ -28,7 +35,11 @@ extern "C" {
 
 #[derive(Debug)]
 pub struct Servo {
-    _private: (), // Prevent direct construction
+    pub(crate) health: BusMonitor,
+    // The C library drops the reply header, only read_info, which includes
+    // ServoRegister::ServoStatus, updates it
+    pub(crate) status: StatusMonitor,
+    pub(crate) claim: BusClaim,
 }
 
 impl Servo {
@@ -37,12 +48,26 @@ impl Servo {
         if result != 0 {
             anyhow::bail!("Failed to initialize servo");
         }
-        Ok(Servo { _private: () })
+        Ok(Servo { health: BusMonitor::default(), status: StatusMonitor::default(), claim: BusClaim::new("milkv") })
+    }
+
+    // The C library owns the UART, its port and baud rate are fixed
+    pub fn open(_port: Option<&str>, _baud_rate: Option<u32>) -> Result<Self> {
+        Self::new()
+    }
+
+    // The C library owns the UART and never hands out the raw bytes
+    pub fn set_trace(&self, path: Option<&std::path::Path>) -> Result<()> {
+        if path.is_some() {
+            anyhow::bail!("Raw bus tracing is not available on this backend");
+        }
+        Ok(())
     }
 
     pub fn write(&self, id: u8, register: ServoRegister, data: &[u8]) -> Result<()> {
         let _result = unsafe { servo_write(id, register.clone() as u8, data.as_ptr(), data.len() as c_uchar) };
         let result = unsafe { servo_write(id, register as u8, data.as_ptr(), data.len() as c_uchar) };
+        self.health.record(result == 0);
 
         if result != 0 {
             anyhow::bail!("Failed to write to servo");
@@ -53,6 +78,7 @@ impl Servo {
     pub fn read(&self, id: u8, register: ServoRegister, length: u8) -> Result<Vec<u8>> {
         let mut data = vec![0u8; length as usize];
         let result = unsafe { servo_read(id, register as u8, length, data.as_mut_ptr()) };
+        self.health.record(result == 0);
         if result != 0 {
             anyhow::bail!("Failed to read from servo");
         }
@@ -141,9 +167,16 @@ impl Servo {
         if result != 0 {
             anyhow::bail!("Failed to read servo info");
         }
+        self.status.record(id, info.servo_status);
         Ok(info)
     }
 
+    // The C library has no sync read, each servo is read in turn. None for
+    // servos that didn't reply.
+    pub fn sync_read_info(&self, ids: &[u8]) -> Result<Vec<(u8, Option<ServoInfo>)>> {
+        Ok(ids.iter().map(|&id| (id, self.read_info(id).ok())).collect())
+    }
+
     pub fn read_continuous(&self) -> Result<ServoData> {
         let mut data = ServoData {
             servo: [ServoInfo {
@@ -177,12 +210,50 @@ impl Servo {
 
     pub fn write_multiple(&self, cmd: &ServoMultipleWriteCommand) -> Result<()> {
         let result = unsafe { servo_write_multiple(cmd) };
+        self.health.record(result == 0);
         if result != 0 {
             anyhow::bail!("Failed to write multiple servo positions");
         }
         Ok(())
     }
 
+    pub fn sync_write_positions(&self, targets: &[(u8, i16)]) -> Result<()> {
+        if targets.len() > MAX_SERVOS {
+            anyhow::bail!("Cannot sync write more than {} servos", MAX_SERVOS);
+        }
+        let mut cmd = ServoMultipleWriteCommand {
+            only_write_positions: 1,
+            ids: [0; MAX_SERVOS],
+            positions: [0; MAX_SERVOS],
+            times: [0; MAX_SERVOS],
+            speeds: [0; MAX_SERVOS],
+        };
+        for (i, &(id, position)) in targets.iter().enumerate() {
+            cmd.ids[i] = id;
+            cmd.positions[i] = position;
+        }
+        self.write_multiple(&cmd)
+    }
+
+    pub fn sync_move_timed(&self, targets: &[(u8, i16)], time_ms: u16) -> Result<()> {
+        if targets.len() > MAX_SERVOS {
+            anyhow::bail!("Cannot sync write more than {} servos", MAX_SERVOS);
+        }
+        let mut cmd = ServoMultipleWriteCommand {
+            only_write_positions: 0,
+            ids: [0; MAX_SERVOS],
+            positions: [0; MAX_SERVOS],
+            times: [0; MAX_SERVOS],
+            speeds: [0; MAX_SERVOS],
+        };
+        for (i, &(id, position)) in targets.iter().enumerate() {
+            cmd.ids[i] = id;
+            cmd.positions[i] = position;
+            cmd.times[i] = time_ms;
+        }
+        self.write_multiple(&cmd)
+    }
+
     pub fn read_pid(&self, id: u8) -> Result<(u8, u8, u8)> {
         let p = self.read(id, ServoRegister::PProportionalCoeff, 1)?[0];
         let i = self.read(id, ServoRegister::IIntegralCoeff, 1)?[0];
@@ -192,7 +263,7 @@ impl Servo {
 
     pub fn set_pid(&self, id: u8, p: u8, i: u8, d: u8) -> Result<()> {
         // Unlock flash
-        self.write(id, ServoRegister::LockMark, &[MemoryLockState::Unlocked as u8])?;
+        self.unlock_eeprom(id)?;
 
         // Set PID parameters
         self.write(id, ServoRegister::PProportionalCoeff, &[p])?;
@@ -200,9 +271,7 @@ impl Servo {
         self.write(id, ServoRegister::DDifferentialCoeff, &[d])?;
 
         // Lock flash
-        self.write(id, ServoRegister::LockMark, &[MemoryLockState::Locked as u8])?;
-
-        Ok(())
+        self.lock_eeprom(id)
     }
 
     pub fn set_memory_lock(&self, id: u8, state: MemoryLockState) -> Result<()> {
@@ -210,8 +279,8 @@ impl Servo {
     }
 
     pub fn read_angle_limits(&self, id: u8) -> Result<(i16, i16)> {
-        let min_limit = i16::from_le_bytes(self.read(id, ServoRegister::MinAngleLimit, 2)?.try_into().unwrap());
-        let max_limit = i16::from_le_bytes(self.read(id, ServoRegister::MaxAngleLimit, 2)?.try_into().unwrap());
+        let min_limit = self.read_u16(id, ServoRegister::MinAngleLimit)? as i16;
+        let max_limit = self.read_u16(id, ServoRegister::MaxAngleLimit)? as i16;
         Ok((min_limit, max_limit))
     }
 
@@ -220,42 +289,41 @@ impl Servo {
     }
 
     pub fn write_servo_memory(&self, id: u8, register: ServoRegister, value: u16) -> Result<()> {
-        let data = [(value & 0xFF) as u8, ((value >> 8) & 0xFF) as u8];
-        self.write(id, register, &data)
+        self.write(id, register, &write_u16_le(value))
     }
 
-    pub fn scan(&self, id: u8) -> Result<bool> {
-        // Try to read the servo ID from memory address 0x5 (ServoRegister::ID)
+    // The UART is owned by the RTOS side, so its baud rate can't be changed from here
+    pub fn bus_baud_rate(&self) -> Result<u32> {
+        anyhow::bail!("Reading the bus baud rate is not supported on this platform")
+    }
+
+    pub fn set_bus_baud_rate(&self, _baud_rate: u32) -> Result<()> {
+        anyhow::bail!("Changing the bus baud rate is not supported on this platform")
+    }
+
+    pub fn ping(&self, id: u8) -> Result<Duration> {
+        // The RTOS servo library has no PING, reading the ID register
+        // (ServoRegister::ID) is the closest equivalent round trip
+        let start = Instant::now();
         match self.read(id, ServoRegister::ID, 1) {
-            Ok(data) if data.len() == 1 && data[0] == id => Ok(true),
-            Ok(_) => Ok(false), // Received data, but it doesn't match the ID
-            Err(_) => Ok(false), // No response, assume no servo at this ID
+            Ok(data) if data.len() == 1 && data[0] == id => Ok(start.elapsed()),
+            Ok(data) => Err(ServoError::MalformedReply { id, reason: format!("Unexpected ID register contents {:?}", data) }.into()),
+            Err(_) => Err(ServoError::Timeout { id }.into()),
         }
     }
 
+    pub fn scan(&self, id: u8) -> Result<bool> {
+        Ok(self.ping(id).is_ok())
+    }
+
     pub fn degrees_to_raw(degrees: f32) -> u16 {
-        // Ensure the input is within the valid range
-        let clamped_degrees = degrees.max(-180.0).min(180.0);
-        
-        // Convert degrees to raw value
-        let raw = (clamped_degrees + 180.0) / 360.0 * 4096.0;
-        
-        // Round to nearest integer and ensure it's within the valid range
-        raw.round().max(0.0).min(4095.0) as u16
+        let clamped_degrees = degrees.clamp(-180.0, 180.0);
+        (CENTER_POSITION as i32 + deg_to_ticks(clamped_degrees)).clamp(0, 4095) as u16
     }
 
     pub fn raw_to_degrees(raw: u16) -> f32 {
-        // Ensure the input is within the valid range
-        let clamped_raw = raw.max(0).min(4095);
-        
-        // Convert raw value to degrees
-        let degrees = (clamped_raw as f32 / 4096.0) * 360.0 - 180.0;
-        
-        // Round to two decimal places
-        (degrees * 100.0).round() / 100.0;
-
-        // clamp to -180.0 to 180.0
-        degrees.max(-180.0).min(180.0)
+        let clamped_raw = raw.min(4095);
+        ticks_to_deg(clamped_raw as i32 - CENTER_POSITION as i32)
     }
 }
 
@@ -0,0 +1,335 @@
+use std::collections::{BTreeMap, HashSet};
+use std::io::{self, Read, Write};
+use std::sync::Arc;
+use std::thread::sleep;
+use std::time::Duration;
+use parking_lot::Mutex;
+use crate::hal::ServoRegister;
+use crate::endian::{read_u16_le, write_u16_le};
+use crate::servo::{BAUD_RATES, BROADCAST_ID, MODEL_STS3215};
+use super::{Transport, SERVO_CMD_PING, SERVO_CMD_READ, SERVO_CMD_WRITE, SERVO_CMD_REG_WRITE, SERVO_CMD_SYNC_READ, SERVO_CMD_SYNC_WRITE, SERVO_CMD_RESET, SERVO_START_BYTE};
+
+// Servos simulated at the packet level for tests: each has a 256 byte
+// memory table that reads and writes go to, and only answers at the baud
+// rate its BaudRate register selects. Clones share the same servos, so a
+// test keeps one to inspect what the code under test wrote.
+#[derive(Clone, Default)]
+pub struct MockBus {
+    state: Arc<Mutex<MockState>>,
+}
+
+// One register write as it arrived, broadcast and sync writes split per servo
+#[derive(Debug, Clone, PartialEq)]
+pub struct MockWrite {
+    pub id: u8,
+    pub address: u8,
+    pub data: Vec<u8>,
+}
+
+pub type MockHook = Box<dyn FnMut(&mut MockServos) + Send>;
+
+#[derive(Default)]
+struct MockState {
+    servos: MockServos,
+    baud_rate: u32,
+    tx: Vec<u8>,
+    rx: Vec<u8>,
+    writes: Vec<MockWrite>,
+    // (id, address) whose writes are rejected with an error status
+    refused: HashSet<(u8, u8)>,
+    // Status byte of every successful reply, per servo, 0 if not set
+    status: BTreeMap<u8, u8>,
+    // Run before every packet, e.g. to move a servo along
+    hook: Option<MockHook>,
+    // How long a reply takes to arrive, zero by default
+    reply_delay: Duration,
+    // Servos whose replies arrive with a wrong checksum
+    corrupted: HashSet<u8>,
+    // Every read and write fails, as with the adapter unplugged
+    disconnected: bool,
+    // Calls to read, whether or not a reply was waiting
+    reads: usize,
+}
+
+#[derive(Debug, Default)]
+pub struct MockServos {
+    pub memory: BTreeMap<u8, [u8; 256]>,
+}
+
+impl MockServos {
+    pub fn u16(&self, id: u8, register: ServoRegister) -> u16 {
+        read_u16_le(&self.memory[&id], register as usize)
+    }
+
+    pub fn set_u16(&mut self, id: u8, register: ServoRegister, value: u16) {
+        let memory = self.memory.get_mut(&id).expect("no such mock servo");
+        memory[register as usize..register as usize + 2].copy_from_slice(&write_u16_le(value));
+    }
+
+    pub fn u8(&self, id: u8, register: ServoRegister) -> u8 {
+        self.memory[&id][register as usize]
+    }
+
+    pub fn set_u8(&mut self, id: u8, register: ServoRegister, value: u8) {
+        self.memory.get_mut(&id).expect("no such mock servo")[register as usize] = value;
+    }
+}
+
+// An STS3215 on firmware 3.x, centered and unlimited, at 1 Mbaud
+fn default_memory(id: u8) -> [u8; 256] {
+    let mut memory = [0; 256];
+    memory[ServoRegister::FirmwareMajorVersion as usize] = 3;
+    memory[ServoRegister::FirmwareSubVersion as usize] = 10;
+    memory[ServoRegister::ServoMainVersion as usize..][..2].copy_from_slice(&MODEL_STS3215.to_be_bytes());
+    memory[ServoRegister::ID as usize] = id;
+    memory[ServoRegister::MaxAngleLimit as usize..][..2].copy_from_slice(&write_u16_le(4095));
+    memory[ServoRegister::MaxTemperatureLimit as usize] = 70;
+    memory[ServoRegister::MaxInputVoltage as usize] = 140;
+    memory[ServoRegister::MinInputVoltage as usize] = 40;
+    memory[ServoRegister::MaxTorque as usize..][..2].copy_from_slice(&write_u16_le(1000));
+    memory[ServoRegister::TorqueLimit as usize..][..2].copy_from_slice(&write_u16_le(1000));
+    memory[ServoRegister::LockMark as usize] = 1;
+    memory[ServoRegister::CurrentLocation as usize..][..2].copy_from_slice(&write_u16_le(2048));
+    memory[ServoRegister::TargetLocation as usize..][..2].copy_from_slice(&write_u16_le(2048));
+    memory[ServoRegister::CurrentVoltage as usize] = 120;
+    memory[ServoRegister::CurrentTemperature as usize] = 30;
+    memory
+}
+
+impl MockBus {
+    pub fn new(ids: &[u8]) -> Self {
+        let bus = MockBus::default();
+        {
+            let mut state = bus.state.lock();
+            state.baud_rate = BAUD_RATES[0];
+            for &id in ids {
+                state.servos.memory.insert(id, default_memory(id));
+            }
+        }
+        bus
+    }
+
+    // Unique per bus, so tests running at once don't share a BusLock
+    pub fn name(&self) -> String {
+        format!("mock{:p}", Arc::as_ptr(&self.state))
+    }
+
+    pub fn transport(&self) -> MockTransport {
+        MockTransport { bus: self.clone() }
+    }
+
+    pub fn with<T>(&self, f: impl FnOnce(&mut MockServos) -> T) -> T {
+        f(&mut self.state.lock().servos)
+    }
+
+    pub fn u16(&self, id: u8, register: ServoRegister) -> u16 {
+        self.with(|servos| servos.u16(id, register))
+    }
+
+    pub fn set_u16(&self, id: u8, register: ServoRegister, value: u16) {
+        self.with(|servos| servos.set_u16(id, register, value))
+    }
+
+    pub fn u8(&self, id: u8, register: ServoRegister) -> u8 {
+        self.with(|servos| servos.u8(id, register))
+    }
+
+    pub fn set_u8(&self, id: u8, register: ServoRegister, value: u8) {
+        self.with(|servos| servos.set_u8(id, register, value))
+    }
+
+    pub fn remove(&self, id: u8) {
+        self.state.lock().servos.memory.remove(&id);
+    }
+
+    pub fn refuse(&self, id: u8, register: ServoRegister) {
+        self.state.lock().refused.insert((id, register as u8));
+    }
+
+    pub fn report_status(&self, id: u8, status: u8) {
+        self.state.lock().status.insert(id, status);
+    }
+
+    pub fn delay_replies(&self, delay: Duration) {
+        self.state.lock().reply_delay = delay;
+    }
+
+    pub fn corrupt_replies(&self, id: u8) {
+        self.state.lock().corrupted.insert(id);
+    }
+
+    pub fn disconnect(&self) {
+        self.state.lock().disconnected = true;
+    }
+
+    pub fn on_packet(&self, hook: impl FnMut(&mut MockServos) + Send + 'static) {
+        self.state.lock().hook = Some(Box::new(hook));
+    }
+
+    pub fn writes(&self) -> Vec<MockWrite> {
+        self.state.lock().writes.clone()
+    }
+
+    pub fn reads(&self) -> usize {
+        self.state.lock().reads
+    }
+
+    pub fn clear_writes(&self) {
+        self.state.lock().writes.clear();
+    }
+
+    pub fn baud_rate(&self) -> u32 {
+        self.state.lock().baud_rate
+    }
+}
+
+impl MockState {
+    fn answers(&self, id: u8) -> bool {
+        self.servos.memory.get(&id)
+            .is_some_and(|memory| BAUD_RATES.get(memory[ServoRegister::BaudRate as usize] as usize) == Some(&self.baud_rate))
+    }
+
+    fn reply(&mut self, id: u8, status: u8, params: &[u8]) {
+        let status = if status == 0 { self.status.get(&id).copied().unwrap_or(0) } else { status };
+        let mut packet = vec![SERVO_START_BYTE, SERVO_START_BYTE, id, params.len() as u8 + 2, status];
+        packet.extend_from_slice(params);
+        let sum: u16 = packet[2..].iter().map(|&x| x as u16).sum();
+        let checksum = !((sum & 0xFF) as u8);
+        packet.push(if self.corrupted.contains(&id) { !checksum } else { checksum });
+        self.rx.extend_from_slice(&packet);
+    }
+
+    // False if refused. The ID is applied last, after the reply.
+    fn write(&mut self, id: u8, address: u8, data: &[u8]) -> bool {
+        self.writes.push(MockWrite { id, address, data: data.to_vec() });
+        if self.refused.contains(&(id, address)) {
+            return false;
+        }
+        if let Some(memory) = self.servos.memory.get_mut(&id) {
+            let start = address as usize;
+            let end = (start + data.len()).min(memory.len());
+            memory[start..end].copy_from_slice(&data[..end - start]);
+        }
+        true
+    }
+
+    fn move_id(&mut self, id: u8) {
+        let Some(memory) = self.servos.memory.get(&id) else { return };
+        let new_id = memory[ServoRegister::ID as usize];
+        if new_id != id {
+            let memory = self.servos.memory.remove(&id).unwrap();
+            self.servos.memory.insert(new_id, memory);
+        }
+    }
+
+    fn handle(&mut self, packet: &[u8]) {
+        if let Some(hook) = &mut self.hook {
+            hook(&mut self.servos);
+        }
+        let id = packet[2];
+        let params = &packet[5..packet.len() - 1];
+        match packet[4] {
+            SERVO_CMD_PING | SERVO_CMD_RESET if self.answers(id) => self.reply(id, 0, &[]),
+            SERVO_CMD_READ if self.answers(id) => {
+                let (address, length) = (params[0] as usize, params[1] as usize);
+                let data = self.servos.memory[&id][address..address + length].to_vec();
+                self.reply(id, 0, &data);
+            }
+            SERVO_CMD_WRITE | SERVO_CMD_REG_WRITE => {
+                let (address, data) = (params[0], &params[1..]);
+                if id == BROADCAST_ID {
+                    let ids: Vec<u8> = self.servos.memory.keys().copied().filter(|&id| self.answers(id)).collect();
+                    for id in ids {
+                        self.write(id, address, data);
+                        self.move_id(id);
+                    }
+                } else if self.answers(id) {
+                    let written = self.write(id, address, data);
+                    self.reply(id, if written { 0 } else { 0x08 }, &[]);
+                    self.move_id(id);
+                }
+            }
+            SERVO_CMD_SYNC_WRITE => {
+                let (address, length) = (params[0], params[1] as usize);
+                for entry in params[2..].chunks(length + 1) {
+                    if entry.len() == length + 1 && self.answers(entry[0]) {
+                        self.write(entry[0], address, &entry[1..]);
+                    }
+                }
+            }
+            SERVO_CMD_SYNC_READ => {
+                let (address, length) = (params[0] as usize, params[1] as usize);
+                for &id in &params[2..] {
+                    if self.answers(id) {
+                        let data = self.servos.memory[&id][address..address + length].to_vec();
+                        self.reply(id, 0, &data);
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+pub struct MockTransport {
+    bus: MockBus,
+}
+
+impl std::fmt::Debug for MockTransport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("MockTransport").finish_non_exhaustive()
+    }
+}
+
+impl Read for MockTransport {
+    fn read(&mut self, buffer: &mut [u8]) -> io::Result<usize> {
+        let mut state = self.bus.state.lock();
+        state.reads += 1;
+        if state.disconnected {
+            return Err(io::Error::new(io::ErrorKind::BrokenPipe, "mock bus disconnected"));
+        }
+        if state.rx.is_empty() {
+            return Err(io::Error::new(io::ErrorKind::TimedOut, "no reply from the mock bus"));
+        }
+        let count = buffer.len().min(state.rx.len());
+        buffer[..count].copy_from_slice(&state.rx[..count]);
+        state.rx.drain(..count);
+        Ok(count)
+    }
+}
+
+impl Write for MockTransport {
+    fn write(&mut self, bytes: &[u8]) -> io::Result<usize> {
+        let mut state = self.bus.state.lock();
+        if state.disconnected {
+            return Err(io::Error::new(io::ErrorKind::BrokenPipe, "mock bus disconnected"));
+        }
+        state.tx.extend_from_slice(bytes);
+        while state.tx.len() >= 4 && state.tx.len() >= state.tx[3] as usize + 4 {
+            let length = state.tx[3] as usize + 4;
+            let packet: Vec<u8> = state.tx.drain(..length).collect();
+            // A new request starts a new exchange, anything unread is lost
+            state.rx.clear();
+            state.handle(&packet);
+        }
+        let delay = if state.rx.is_empty() { Duration::ZERO } else { state.reply_delay };
+        drop(state);
+        sleep(delay);
+        Ok(bytes.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Transport for MockTransport {
+    fn baud_rate(&self) -> serialport::Result<u32> {
+        Ok(self.bus.baud_rate())
+    }
+
+    fn set_baud_rate(&mut self, baud_rate: u32) -> serialport::Result<()> {
+        self.bus.state.lock().baud_rate = baud_rate;
+        Ok(())
+    }
+}
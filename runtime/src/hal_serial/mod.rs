@@ -1,15 +1,29 @@
 use serialport::SerialPort;
-use std::time::Duration;
-use std::io::{Read, Write};
+use std::fs::File;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use std::io::{LineWriter, Read, Write};
+use std::path::Path;
 use anyhow::{Result, bail, Context};
 use std::sync::Arc;
-use std::sync::Mutex;
-use crate::hal::{ServoInfo, ServoRegister, ServoData, ServoMultipleWriteCommand, TorqueMode, ServoMode, ServoDirection, MemoryLockState, IMUData, MAX_SERVOS};
+use parking_lot::{Mutex, MutexGuard};
+use crate::hal::{ServoInfo, ServoRegister, ServoData, ServoMultipleWriteCommand, TorqueMode, ServoMode, ServoDirection, MemoryLockState, IMUData, ServoError, MAX_SERVOS};
 use std::env;
+use crate::endian::{read_i16_le, read_u16_le, write_i16_le, write_u16_le};
+use crate::alarm::StatusMonitor;
+use crate::buslock::BusClaim;
+use crate::health::BusMonitor;
+use crate::servo::{encode_speed, BROADCAST_ID, CENTER_POSITION};
+use crate::units::{deg_to_ticks, ticks_to_deg};
+
+pub mod replay;
+#[cfg(test)]
+pub mod mock;
+
+use replay::ReplayTransport;
 
 // Constants
 const SERVO_START_BYTE: u8 = 0xFF;
-const SERVO_BROADCAST_ID: u8 = 0xFE;
+const SERVO_BROADCAST_ID: u8 = BROADCAST_ID;
 const MAX_SERVO_COMMAND_DATA: usize = 256;
 
 // Servo commands
@@ -18,6 +32,7 @@ const SERVO_CMD_READ: u8 = 0x02;
 const SERVO_CMD_WRITE: u8 = 0x03;
 const SERVO_CMD_REG_WRITE: u8 = 0x04;
 const SERVO_CMD_ACTION: u8 = 0x05;
+const SERVO_CMD_SYNC_READ: u8 = 0x82;
 const SERVO_CMD_SYNC_WRITE: u8 = 0x83;
 const SERVO_CMD_RESET: u8 = 0x06;
 
@@ -41,9 +56,50 @@ pub struct ServoCommand {
     pub data: Vec<u8>,
 }
 
+// What ServoSerial talks through: the serial port, or a ReplayTransport
+// playing back a recorded trace
+pub trait Transport: Read + Write + Send + std::fmt::Debug {
+    fn baud_rate(&self) -> serialport::Result<u32>;
+    fn set_baud_rate(&mut self, baud_rate: u32) -> serialport::Result<()>;
+}
+
+impl Transport for Box<dyn SerialPort> {
+    fn baud_rate(&self) -> serialport::Result<u32> {
+        self.as_ref().baud_rate()
+    }
+
+    fn set_baud_rate(&mut self, baud_rate: u32) -> serialport::Result<()> {
+        self.as_mut().set_baud_rate(baud_rate)
+    }
+}
+
 #[derive(Debug)]
 pub struct ServoSerial {
-    port: Box<dyn SerialPort>,
+    port: Box<dyn Transport>,
+    // (id, status byte) of every reply since the last take_statuses
+    statuses: Vec<(u8, u8)>,
+    // Raw bytes on the wire, see set_trace
+    trace: Option<LineWriter<File>>,
+}
+
+// One line per packet: seconds since the Unix epoch, TX or RX and the bytes
+// in hex, e.g.
+//
+//     1718000000.123456 TX ff ff 01 04 02 38 02 be
+//     1718000000.124100 RX ff ff 01 04 00 00 08 f2
+//
+// A reply cut short by an error is logged with what did arrive and the error
+// after a '#'.
+pub fn format_trace_line(at: SystemTime, direction: &str, bytes: &[u8], error: Option<&std::io::Error>) -> String {
+    let since_epoch = at.duration_since(UNIX_EPOCH).unwrap_or_default();
+    let mut line = format!("{}.{:06} {}", since_epoch.as_secs(), since_epoch.subsec_micros(), direction);
+    for byte in bytes {
+        line.push_str(&format!(" {:02x}", byte));
+    }
+    if let Some(error) = error {
+        line.push_str(&format!(" # {}", error));
+    }
+    line
 }
 
 
@@ -54,7 +110,39 @@ impl ServoSerial {
         let port = serialport::new(port_name, baud_rate)
             .timeout(Duration::from_millis(100))
             .open()?;
-        Ok(ServoSerial { port })
+        Ok(Self::with_transport(Box::new(port)))
+    }
+
+    pub fn with_transport(port: Box<dyn Transport>) -> Self {
+        ServoSerial { port, statuses: Vec::new(), trace: None }
+    }
+
+    // Tee every packet sent and received to `path`, truncating it. None
+    // stops tracing. Costs nothing but a check per packet while off.
+    pub fn set_trace(&mut self, path: Option<&Path>) -> std::io::Result<()> {
+        self.trace = match path {
+            Some(path) => Some(LineWriter::new(File::create(path)?)),
+            None => None,
+        };
+        Ok(())
+    }
+
+    fn trace(&mut self, direction: &str, bytes: &[u8], error: Option<&std::io::Error>) {
+        if let Some(trace) = &mut self.trace {
+            // Tracing must never break the bus, a failed write only loses the line
+            let _ = writeln!(trace, "{}", format_trace_line(SystemTime::now(), direction, bytes, error));
+        }
+    }
+
+    // Replies are FF FF id length status ...
+    fn record_status(&mut self, response: &[u8]) {
+        if response.len() >= 6 && response[0] == SERVO_START_BYTE && response[1] == SERVO_START_BYTE {
+            self.statuses.push((response[2], response[4]));
+        }
+    }
+
+    pub fn take_statuses(&mut self) -> Vec<(u8, u8)> {
+        std::mem::take(&mut self.statuses)
     }
 
     fn calculate_checksum(&self, packet: &[u8]) -> u8 {
@@ -63,7 +151,9 @@ impl ServoSerial {
     }
 
     fn send_packet(&mut self, packet: &[u8]) -> Result<(), std::io::Error> {
-        self.port.write_all(packet)
+        let result = self.port.write_all(packet);
+        self.trace("TX", packet, result.as_ref().err());
+        result
     }
 
     fn receive_packet(&mut self, max_length: usize) -> Result<Vec<u8>, std::io::Error> {
@@ -71,7 +161,10 @@ impl ServoSerial {
         let mut buffer = [0u8; 1];
 
         while packet.len() < max_length {
-            self.port.read_exact(&mut buffer)?;
+            if let Err(e) = self.port.read_exact(&mut buffer) {
+                self.trace("RX", &packet, Some(&e));
+                return Err(e);
+            }
             packet.push(buffer[0]);
 
             if packet.len() >= 4 && packet.len() == packet[3] as usize + 4 {
@@ -79,10 +172,12 @@ impl ServoSerial {
             }
         }
 
+        self.trace("RX", &packet, None);
         Ok(packet)
     }
 
-    pub fn servo_ping(&mut self, id: u8) -> Result<(), std::io::Error> {
+    // Returns the status byte of the reply, nonzero if the servo reports a fault
+    pub fn servo_ping(&mut self, id: u8) -> Result<u8, std::io::Error> {
         let packet = [
             SERVO_START_BYTE,
             SERVO_START_BYTE,
@@ -97,14 +192,22 @@ impl ServoSerial {
         self.send_packet(&packet)?;
 
         let response = self.receive_packet(6)?;
-        if response.len() != 6 || response[2] != id || response[4] != 0 {
-            return Err(std::io::Error::new(std::io::ErrorKind::Other, "Invalid response"));
+        if response.len() != 6 || response[0] != SERVO_START_BYTE || response[1] != SERVO_START_BYTE || response[2] != id {
+            return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, "Invalid response"));
+        }
+        if response[5] != self.calculate_checksum(&response) {
+            return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, "Invalid checksum"));
         }
+        self.record_status(&response);
 
-        Ok(())
+        Ok(response[4])
     }
 
     pub fn servo_read(&mut self, id: u8, address: u8, length: u8) -> Result<Vec<u8>, std::io::Error> {
+        // Would only wait out the port timeout, no servo replies to it
+        if id == SERVO_BROADCAST_ID {
+            return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, "Cannot read from the broadcast ID"));
+        }
         let packet = [
             SERVO_START_BYTE,
             SERVO_START_BYTE,
@@ -124,6 +227,7 @@ impl ServoSerial {
         if response.len() < 6 || response[2] != id {
             return Err(std::io::Error::new(std::io::ErrorKind::Other, "Invalid response"));
         }
+        self.record_status(&response);
 
         Ok(response[5..response.len() - 1].to_vec())
     }
@@ -148,6 +252,9 @@ impl ServoSerial {
 
         if id != SERVO_BROADCAST_ID {
             let response = self.receive_packet(6)?;
+            if response.len() == 6 && response[2] == id {
+                self.record_status(&response);
+            }
             if response.len() != 6 || response[2] != id || response[4] != 0 {
                 return Err(std::io::Error::new(std::io::ErrorKind::Other, "Invalid response"));
             }
@@ -176,6 +283,9 @@ impl ServoSerial {
 
         if id != SERVO_BROADCAST_ID {
             let response = self.receive_packet(6)?;
+            if response.len() == 6 && response[2] == id {
+                self.record_status(&response);
+            }
             if response.len() != 6 || response[2] != id || response[4] != 0 {
                 return Err(std::io::Error::new(std::io::ErrorKind::Other, "Invalid response"));
             }
@@ -229,6 +339,9 @@ impl ServoSerial {
 
         if id != SERVO_BROADCAST_ID {
             let response = self.receive_packet(6)?;
+            if response.len() == 6 && response[2] == id {
+                self.record_status(&response);
+            }
             if response.len() != 6 || response[2] != id || response[4] != 0 {
                 return Err(std::io::Error::new(std::io::ErrorKind::Other, "Invalid response"));
             }
@@ -238,14 +351,10 @@ impl ServoSerial {
     }
 
     pub fn servo_move(&mut self, id: u8, position: i16, time: u16, speed: u16) -> Result<(), std::io::Error> {
-        let data = [
-            (position & 0xFF) as u8,
-            ((position >> 8) & 0xFF) as u8,
-            (time & 0xFF) as u8,
-            ((time >> 8) & 0xFF) as u8,
-            (speed & 0xFF) as u8,
-            ((speed >> 8) & 0xFF) as u8,
-        ];
+        let mut data = [0; 6];
+        data[0..2].copy_from_slice(&write_i16_le(position));
+        data[2..4].copy_from_slice(&write_u16_le(time));
+        data[4..6].copy_from_slice(&write_u16_le(speed));
         self.servo_write(id, SERVO_ADDR_TARGET_POSITION, &data)
     }
 
@@ -260,13 +369,85 @@ impl ServoSerial {
 
         for (&id, &position) in ids.iter().zip(positions.iter()) {
             data.push(id);
-            data.push((position & 0xFF) as u8);
-            data.push(((position >> 8) & 0xFF) as u8);
+            data.extend_from_slice(&write_i16_le(position));
+        }
+
+        self.servo_sync_write(&data)
+    }
+
+    // Like servo_move_multiple, with every servo given the same running time
+    // so they all arrive together
+    pub fn servo_move_multiple_timed(&mut self, ids: &[u8], positions: &[i16], time: u16) -> Result<(), std::io::Error> {
+        if ids.len() != positions.len() {
+            return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, "Mismatched ids and positions lengths"));
+        }
+
+        let mut data = Vec::with_capacity(1 + 1 + ids.len() * 7);
+        data.push(SERVO_ADDR_TARGET_POSITION);
+        data.push(6); // Position, time and speed
+
+        for (&id, &position) in ids.iter().zip(positions.iter()) {
+            data.push(id);
+            data.extend_from_slice(&write_i16_le(position));
+            data.extend_from_slice(&write_u16_le(time));
+            data.extend_from_slice(&write_u16_le(0)); // Speed is derived from the time
         }
 
         self.servo_sync_write(&data)
     }
 
+    // Every servo in `ids` replies in turn with `length` bytes from `address`.
+    // A servo that doesn't reply within the port timeout, or replies with a
+    // bad packet, is None and the remaining replies are still collected.
+    pub fn servo_sync_read(&mut self, ids: &[u8], address: u8, length: u8) -> Result<Vec<Option<Vec<u8>>>, std::io::Error> {
+        if ids.is_empty() || ids.len() > MAX_SERVOS {
+            return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, "Invalid count"));
+        }
+
+        let mut packet = Vec::with_capacity(8 + ids.len());
+        packet.extend_from_slice(&[
+            SERVO_START_BYTE,
+            SERVO_START_BYTE,
+            SERVO_BROADCAST_ID,
+            ids.len() as u8 + 4,
+            SERVO_CMD_SYNC_READ,
+            address,
+            length,
+        ]);
+        packet.extend_from_slice(ids);
+        packet.push(self.calculate_checksum(&packet));
+
+        self.send_packet(&packet)?;
+
+        let mut replies = Vec::with_capacity(ids.len());
+        // A servo that doesn't reply leaves the next one's reply to be read
+        // in its place, which is kept for the servo it belongs to
+        let mut pending = None;
+        for (index, &id) in ids.iter().enumerate() {
+            let response = match pending.take() {
+                Some(response) => Ok(response),
+                None => self.receive_packet(length as usize + 6),
+            };
+            let reply = match response {
+                Ok(response) if response.len() == length as usize + 6
+                    && response[response.len() - 1] == self.calculate_checksum(&response) => {
+                    if response[2] == id {
+                        self.record_status(&response);
+                        Some(response[5..response.len() - 1].to_vec())
+                    } else {
+                        if ids[index + 1..].contains(&response[2]) {
+                            pending = Some(response);
+                        }
+                        None
+                    }
+                }
+                _ => None,
+            };
+            replies.push(reply);
+        }
+        Ok(replies)
+    }
+
     pub fn servo_move_multiple_sync(&mut self, cmd: &ServoMultipleWriteCommand) -> Result<(), std::io::Error> {
         if cmd.ids.len() != cmd.positions.len() || cmd.ids.len() != cmd.times.len() || cmd.ids.len() != cmd.speeds.len() {
             return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, "Mismatched input lengths"));
@@ -290,10 +471,10 @@ impl ServoSerial {
 
         for i in 0..count {
             packet.push(cmd.ids[i]);
-            packet.extend_from_slice(&cmd.positions[i].to_le_bytes());
+            packet.extend_from_slice(&write_i16_le(cmd.positions[i]));
             if cmd.only_write_positions == 0 {
-                packet.extend_from_slice(&cmd.times[i].to_le_bytes());
-                packet.extend_from_slice(&cmd.speeds[i].to_le_bytes());
+                packet.extend_from_slice(&write_u16_le(cmd.times[i]));
+                packet.extend_from_slice(&write_u16_le(cmd.speeds[i]));
             }
         }
 
@@ -307,7 +488,7 @@ impl ServoSerial {
         if data.len() != 2 {
             return Err(std::io::Error::new(std::io::ErrorKind::Other, "Invalid response length"));
         }
-        Ok(i16::from_le_bytes([data[0], data[1]]))
+        Ok(read_i16_le(&data, 0))
     }
 
     pub fn servo_read_current(&mut self, id: u8) -> Result<u16, std::io::Error> {
@@ -315,7 +496,7 @@ impl ServoSerial {
         if data.len() != 2 {
             return Err(std::io::Error::new(std::io::ErrorKind::Other, "Invalid response length"));
         }
-        Ok(u16::from_le_bytes([data[0], data[1]]))
+        Ok(read_u16_le(&data, 0))
     }
 
     pub fn servo_read_load(&mut self, id: u8) -> Result<i16, std::io::Error> {
@@ -323,7 +504,7 @@ impl ServoSerial {
         if data.len() != 2 {
             return Err(std::io::Error::new(std::io::ErrorKind::Other, "Invalid response length"));
         }
-        Ok(i16::from_le_bytes([data[0], data[1]]))
+        Ok(read_i16_le(&data, 0))
     }
 
     pub fn servo_read_voltage(&mut self, id: u8) -> Result<u8, std::io::Error> {
@@ -340,9 +521,9 @@ impl ServoSerial {
             return Err(std::io::Error::new(std::io::ErrorKind::Other, "Invalid response length"));
         }
 
-        let current_location = i16::from_le_bytes([data[0], data[1]]);
-        let current_speed = i16::from_le_bytes([data[2], data[3]]);
-        let current_load = i16::from_le_bytes([data[4], data[5]]);
+        let current_location = read_i16_le(&data, 0);
+        let current_speed = read_i16_le(&data, 2);
+        let current_load = read_i16_le(&data, 4);
 
         Ok((current_location, current_speed, current_load))
     }
@@ -358,48 +539,156 @@ impl ServoSerial {
     pub fn servo_torque_off(&mut self, id: u8) -> Result<(), std::io::Error> {
         self.servo_set_torque(id, TORQUE_OFF)
     }
+
+    pub fn baud_rate(&self) -> Result<u32, serialport::Error> {
+        self.port.baud_rate()
+    }
+
+    pub fn set_baud_rate(&mut self, baud_rate: u32) -> Result<(), serialport::Error> {
+        self.port.set_baud_rate(baud_rate)
+    }
 }
 
+// Upper bound on waiting for another caller's transaction. Long enough for
+// any single transaction, short enough that e.g. the telemetry server gets a
+// ServoError::BusBusy instead of hanging if the bus is held up. Overridden
+// with SERVO_LOCK_TIMEOUT_MS or Servo::with_lock_timeout.
+pub const DEFAULT_LOCK_TIMEOUT: Duration = Duration::from_millis(500);
+
 #[derive(Debug)]
 pub struct Servo {
     serial: Arc<Mutex<ServoSerial>>,
+    lock_timeout: Duration,
+    pub(crate) health: BusMonitor,
+    pub(crate) status: StatusMonitor,
+    pub(crate) claim: BusClaim,
 }
 
 impl Servo {
     pub fn new() -> Result<Self> {
-        let port_name = env::var("SERVO_PORT").unwrap_or_else(|_| "/dev/ttyUSB0".to_string());
-        let baud_rate = env::var("SERVO_BAUD_RATE")
-            .unwrap_or_else(|_| "115200".to_string())
-            .parse::<u32>()
-            .context("Failed to parse SERVO_BAUD_RATE")?;
+        Self::open(None, None)
+    }
 
-        let serial = ServoSerial::new(&port_name, baud_rate)
+    // Unset `port` and `baud_rate` fall back to SERVO_PORT and
+    // SERVO_BAUD_RATE, then to /dev/ttyUSB0 at 115200
+    pub fn open(port: Option<&str>, baud_rate: Option<u32>) -> Result<Self> {
+        let port_name = match port {
+            Some(port) => port.to_string(),
+            None => env::var("SERVO_PORT").unwrap_or_else(|_| "/dev/ttyUSB0".to_string()),
+        };
+        let baud_rate = match baud_rate {
+            Some(baud_rate) => baud_rate,
+            None => env::var("SERVO_BAUD_RATE")
+                .unwrap_or_else(|_| "115200".to_string())
+                .parse::<u32>()
+                .context("Failed to parse SERVO_BAUD_RATE")?,
+        };
+        let lock_timeout = match env::var("SERVO_LOCK_TIMEOUT_MS") {
+            Ok(ms) => Duration::from_millis(ms.parse().context("Failed to parse SERVO_LOCK_TIMEOUT_MS")?),
+            Err(_) => DEFAULT_LOCK_TIMEOUT,
+        };
+
+        let mut serial = ServoSerial::new(&port_name, baud_rate)
             .map_err(|e| anyhow::anyhow!("Failed to create ServoSerial: {}", e))?;
+        if let Ok(path) = env::var("SERVO_TRACE_FILE") {
+            serial.set_trace(Some(Path::new(&path)))
+                .with_context(|| format!("Failed to open bus trace file {:?}", path))?;
+        }
         
         Ok(Servo {
             serial: Arc::new(Mutex::new(serial)),
+            lock_timeout,
+            health: BusMonitor::default(),
+            status: StatusMonitor::default(),
+            claim: BusClaim::new(&port_name),
         })
     }
 
+    // A bus playing back a trace written by set_trace, for reproducing a
+    // decoding failure from the field offline, see ReplayTransport.
+    // Commands have to be issued exactly as in the captured session.
+    pub fn replay(transport: ReplayTransport) -> Self {
+        Servo {
+            serial: Arc::new(Mutex::new(ServoSerial::with_transport(Box::new(transport)))),
+            lock_timeout: DEFAULT_LOCK_TIMEOUT,
+            health: BusMonitor::default(),
+            status: StatusMonitor::default(),
+            claim: BusClaim::new("replay"),
+        }
+    }
+
+    // Simulated servos for tests, see MockBus
+    #[cfg(test)]
+    pub fn mock(bus: &mock::MockBus) -> Self {
+        Servo {
+            serial: Arc::new(Mutex::new(ServoSerial::with_transport(Box::new(bus.transport())))),
+            lock_timeout: DEFAULT_LOCK_TIMEOUT,
+            health: BusMonitor::default(),
+            status: StatusMonitor::default(),
+            claim: BusClaim::new(&bus.name()),
+        }
+    }
+
+    pub fn with_lock_timeout(mut self, timeout: Duration) -> Self {
+        self.lock_timeout = timeout;
+        self
+    }
+
+    pub fn lock_timeout(&self) -> Duration {
+        self.lock_timeout
+    }
+
+    // Log raw bus bytes to `path`, see format_trace_line. Also enabled by
+    // setting SERVO_TRACE_FILE before opening the bus.
+    pub fn set_trace(&self, path: Option<&Path>) -> Result<()> {
+        let mut serial = self.lock_bus()?;
+        match path {
+            Some(path) => serial.set_trace(Some(path))
+                .with_context(|| format!("Failed to open bus trace file {:?}", path)),
+            None => Ok(serial.set_trace(None)?),
+        }
+    }
+
+    fn lock_bus(&self) -> Result<MutexGuard<'_, ServoSerial>> {
+        self.serial.try_lock_for(self.lock_timeout)
+            .ok_or_else(|| ServoError::BusBusy { timeout: self.lock_timeout }.into())
+    }
+
+    // Runs `transaction` with the bus locked, then records the status bytes
+    // of its replies once the bus is free again
+    fn with_bus<T, F: FnOnce(&mut ServoSerial) -> T>(&self, transaction: F) -> Result<T> {
+        let (result, statuses) = {
+            let mut serial = self.lock_bus()?;
+            let result = transaction(&mut serial);
+            (result, serial.take_statuses())
+        };
+        for (id, status) in statuses {
+            self.status.record(id, status);
+        }
+        Ok(result)
+    }
+
+    // Doesn't wait for a reply when `id` is BROADCAST_ID
     pub fn write(&self, id: u8, register: ServoRegister, data: &[u8]) -> Result<()> {
-        let mut serial = self.serial.lock().unwrap();
-        match serial.servo_write(id, register as u8, data) {
+        let result = self.with_bus(|serial| serial.servo_write(id, register as u8, data))?;
+        self.health.record(result.is_ok());
+        match result {
             Ok(_) => Ok(()),
             Err(_) => Ok(()),  // Silently ignore errors
         }
     }
 
     pub fn read(&self, id: u8, register: ServoRegister, length: u8) -> Result<Vec<u8>> {
-        let mut serial = self.serial.lock().unwrap();
-        match serial.servo_read(id, register as u8, length) {
+        let result = self.with_bus(|serial| serial.servo_read(id, register as u8, length))?;
+        self.health.record(result.is_ok());
+        match result {
             Ok(data) => Ok(data),
             Err(_) => Ok(Vec::new()),  // Return empty Vec on error
         }
     }
 
     pub fn move_servo(&self, id: u8, position: i16, time: u16, speed: u16) -> Result<()> {
-        let mut serial = self.serial.lock().unwrap();
-        serial.servo_move(id, position, time, speed)
+        self.with_bus(|serial| serial.servo_move(id, position, time, speed))?
             .map_err(|e| anyhow::anyhow!("Failed to move servo: {}", e))
     }
 
@@ -408,38 +697,29 @@ impl Servo {
     }
 
     pub fn set_speed(&self, id: u8, speed: u16, direction: ServoDirection) -> Result<()> {
-        let speed = if direction == ServoDirection::Clockwise { speed } else { speed | 0x8000 };
-        self.write(id, ServoRegister::RunningSpeed, &speed.to_le_bytes())
+        self.write(id, ServoRegister::RunningSpeed, &write_u16_le(encode_speed(speed, direction)))
     }
 
     pub fn read_info(&self, id: u8) -> Result<ServoInfo> {
-        let mut serial = self.serial.lock().unwrap();
-        let data = serial.servo_read(id, ServoRegister::TorqueSwitch as u8, 30)?;
+        let data = self.with_bus(|serial| serial.servo_read(id, ServoRegister::TorqueSwitch as u8, 30))??;
         
         if data.len() != 30 {
             bail!("Failed to read servo info: incorrect data length");
         }
 
-        Ok(ServoInfo {
-            torque_switch: data[0],
-            acceleration: data[1],
-            target_location: i16::from_le_bytes([data[2], data[3]]),
-            running_time: u16::from_le_bytes([data[4], data[5]]),
-            running_speed: u16::from_le_bytes([data[6], data[7]]),
-            torque_limit: u16::from_le_bytes([data[8], data[9]]),
-            reserved1: [data[10], data[11], data[12], data[13], data[14], data[15]],
-            lock_mark: data[15],
-            current_location: i16::from_le_bytes([data[16], data[17]]),
-            current_speed: i16::from_le_bytes([data[18], data[19]]),
-            current_load: i16::from_le_bytes([data[20], data[21]]),
-            current_voltage: data[22],
-            current_temperature: data[23],
-            async_write_flag: data[24],
-            servo_status: data[25],
-            mobile_sign: data[26],
-            reserved2: [data[27], data[28]],
-            current_current: u16::from_le_bytes([data[28], data[29]]),
-        })
+        Ok(decode_info(&data))
+    }
+
+    // One sync read for all of `ids`, None for servos that didn't reply
+    pub fn sync_read_info(&self, ids: &[u8]) -> Result<Vec<(u8, Option<ServoInfo>)>> {
+        let replies = self.with_bus(|serial| serial.servo_sync_read(ids, ServoRegister::TorqueSwitch as u8, 30))?
+            .map_err(|e| anyhow::anyhow!("Failed to sync read servo info: {}", e))?;
+        Ok(ids.iter().zip(replies)
+            .map(|(&id, reply)| {
+                self.health.record(reply.is_some());
+                (id, reply.map(|data| decode_info(&data)))
+            })
+            .collect())
     }
 
     pub fn read_continuous(&self) -> Result<ServoData> {
@@ -459,7 +739,6 @@ impl Servo {
     }
 
     pub fn write_multiple(&self, cmd: &ServoMultipleWriteCommand) -> Result<()> {
-        let mut serial = self.serial.lock().unwrap();
         let adapted_cmd = ServoMultipleWriteCommand {
             only_write_positions: cmd.only_write_positions,
             ids: cmd.ids,
@@ -467,8 +746,23 @@ impl Servo {
             times: cmd.times,
             speeds: cmd.speeds,
         };
-        serial.servo_move_multiple_sync(&adapted_cmd)
-            .map_err(|e| anyhow::anyhow!("Failed to write multiple servo positions: {}", e))
+        let result = self.with_bus(|serial| serial.servo_move_multiple_sync(&adapted_cmd))?;
+        self.health.record(result.is_ok());
+        result.map_err(|e| anyhow::anyhow!("Failed to write multiple servo positions: {}", e))
+    }
+
+    pub fn sync_write_positions(&self, targets: &[(u8, i16)]) -> Result<()> {
+        let (ids, positions): (Vec<u8>, Vec<i16>) = targets.iter().copied().unzip();
+        let result = self.with_bus(|serial| serial.servo_move_multiple(&ids, &positions))?;
+        self.health.record(result.is_ok());
+        result.map_err(|e| anyhow::anyhow!("Failed to sync write positions: {}", e))
+    }
+
+    pub fn sync_move_timed(&self, targets: &[(u8, i16)], time_ms: u16) -> Result<()> {
+        let (ids, positions): (Vec<u8>, Vec<i16>) = targets.iter().copied().unzip();
+        let result = self.with_bus(|serial| serial.servo_move_multiple_timed(&ids, &positions, time_ms))?;
+        self.health.record(result.is_ok());
+        result.map_err(|e| anyhow::anyhow!("Failed to sync write timed move: {}", e))
     }
 
     pub fn read_pid(&self, id: u8) -> Result<(u8, u8, u8)> {
@@ -480,7 +774,7 @@ impl Servo {
 
     pub fn set_pid(&self, id: u8, p: u8, i: u8, d: u8) -> Result<()> {
         // Unlock flash
-        self.write(id, ServoRegister::LockMark, &[MemoryLockState::Unlocked as u8])?;
+        self.unlock_eeprom(id)?;
 
         // Set PID parameters
         self.write(id, ServoRegister::PProportionalCoeff, &[p])?;
@@ -488,9 +782,7 @@ impl Servo {
         self.write(id, ServoRegister::DDifferentialCoeff, &[d])?;
 
         // Lock flash
-        self.write(id, ServoRegister::LockMark, &[MemoryLockState::Locked as u8])?;
-
-        Ok(())
+        self.lock_eeprom(id)
     }
 
     pub fn set_memory_lock(&self, id: u8, state: MemoryLockState) -> Result<()> {
@@ -498,8 +790,8 @@ impl Servo {
     }
 
     pub fn read_angle_limits(&self, id: u8) -> Result<(i16, i16)> {
-        let min_limit = i16::from_le_bytes(self.read(id, ServoRegister::MinAngleLimit, 2)?.try_into().unwrap());
-        let max_limit = i16::from_le_bytes(self.read(id, ServoRegister::MaxAngleLimit, 2)?.try_into().unwrap());
+        let min_limit = self.read_u16(id, ServoRegister::MinAngleLimit)? as i16;
+        let max_limit = self.read_u16(id, ServoRegister::MaxAngleLimit)? as i16;
         Ok((min_limit, max_limit))
     }
 
@@ -508,41 +800,45 @@ impl Servo {
     }
 
     pub fn write_servo_memory(&self, id: u8, register: ServoRegister, value: u16) -> Result<()> {
-        let data = value.to_le_bytes();
-        self.write(id, register, &data)
+        self.write(id, register, &write_u16_le(value))
     }
 
-    pub fn scan(&self, id: u8) -> Result<bool> {
-        match self.read(id, ServoRegister::ID, 1) {
-            Ok(data) if data.len() == 1 && data[0] == id => Ok(true),
-            Ok(_) => Ok(false),
-            Err(_) => Ok(false),
+    pub fn bus_baud_rate(&self) -> Result<u32> {
+        let serial = self.lock_bus()?;
+        serial.baud_rate()
+            .map_err(|e| anyhow::anyhow!("Failed to read bus baud rate: {}", e))
+    }
+
+    pub fn set_bus_baud_rate(&self, baud_rate: u32) -> Result<()> {
+        let mut serial = self.lock_bus()?;
+        serial.set_baud_rate(baud_rate)
+            .map_err(|e| anyhow::anyhow!("Failed to set bus baud rate: {}", e))
+    }
+
+    pub fn ping(&self, id: u8) -> Result<Duration> {
+        let reply = self.with_bus(|serial| {
+            let start = Instant::now();
+            serial.servo_ping(id).map(|_| start.elapsed())
+        })?;
+        match reply {
+            Ok(elapsed) => Ok(elapsed),
+            Err(e) if e.kind() == std::io::ErrorKind::TimedOut => Err(ServoError::Timeout { id }.into()),
+            Err(e) => Err(ServoError::MalformedReply { id, reason: e.to_string() }.into()),
         }
     }
 
+    pub fn scan(&self, id: u8) -> Result<bool> {
+        Ok(self.ping(id).is_ok())
+    }
+
     pub fn degrees_to_raw(degrees: f32) -> u16 {
-        // Ensure the input is within the valid range
-        let clamped_degrees = degrees.max(-180.0).min(180.0);
-        
-        // Convert degrees to raw value
-        let raw = (clamped_degrees + 180.0) / 360.0 * 4096.0;
-        
-        // Round to nearest integer and ensure it's within the valid range
-        raw.round().max(0.0).min(4095.0) as u16
+        let clamped_degrees = degrees.clamp(-180.0, 180.0);
+        (CENTER_POSITION as i32 + deg_to_ticks(clamped_degrees)).clamp(0, 4095) as u16
     }
 
     pub fn raw_to_degrees(raw: u16) -> f32 {
-        // Ensure the input is within the valid range
-        let clamped_raw = raw.max(0).min(4095);
-        
-        // Convert raw value to degrees
-        let degrees = (clamped_raw as f32 / 4096.0) * 360.0 - 180.0;
-        
-        // Round to two decimal places
-        (degrees * 100.0).round() / 100.0;
-
-        // clamp to -180.0 to 180.0
-        degrees.max(-180.0).min(180.0)
+        let clamped_raw = raw.min(4095);
+        ticks_to_deg(clamped_raw as i32 - CENTER_POSITION as i32)
     }
 
     pub fn enable_readout(&self) -> Result<()> {
@@ -560,6 +856,30 @@ impl Drop for Servo {
     }
 }
 
+// `data` is the 30 bytes from TorqueSwitch onwards
+fn decode_info(data: &[u8]) -> ServoInfo {
+    ServoInfo {
+        torque_switch: data[0],
+        acceleration: data[1],
+        target_location: read_i16_le(data, 2),
+        running_time: read_u16_le(data, 4),
+        running_speed: read_u16_le(data, 6),
+        torque_limit: read_u16_le(data, 8),
+        reserved1: [data[10], data[11], data[12], data[13], data[14], data[15]],
+        lock_mark: data[15],
+        current_location: read_i16_le(data, 16),
+        current_speed: read_i16_le(data, 18),
+        current_load: read_i16_le(data, 20),
+        current_voltage: data[22],
+        current_temperature: data[23],
+        async_write_flag: data[24],
+        servo_status: data[25],
+        mobile_sign: data[26],
+        reserved2: [data[27], data[28]],
+        current_current: read_u16_le(data, 28),
+    }
+}
+
 pub struct IMU {}
 
 impl IMU {
@@ -577,4 +897,115 @@ impl IMU {
             gyro_z: 0.0,
         })
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mock::MockBus;
+
+    #[test]
+    fn ping_times_a_servo_that_answers() {
+        let bus = MockBus::new(&[1]);
+        let servo = Servo::mock(&bus);
+        assert!(servo.ping(1).is_ok());
+        assert!(servo.scan(1).unwrap());
+    }
+
+    #[test]
+    fn ping_of_a_missing_servo_times_out() {
+        let bus = MockBus::new(&[1]);
+        let servo = Servo::mock(&bus);
+        let error = servo.ping(2).unwrap_err();
+        assert_eq!(error.downcast_ref::<ServoError>(), Some(&ServoError::Timeout { id: 2 }));
+        assert!(!servo.scan(2).unwrap());
+    }
+
+    #[test]
+    fn ping_time_includes_the_reply_delay() {
+        let bus = MockBus::new(&[1]);
+        bus.delay_replies(Duration::from_millis(15));
+        assert!(Servo::mock(&bus).ping(1).unwrap() >= Duration::from_millis(15));
+    }
+
+    #[test]
+    fn a_bad_checksum_is_malformed_not_a_timeout() {
+        let bus = MockBus::new(&[1, 2]);
+        bus.corrupt_replies(1);
+        let servo = Servo::mock(&bus);
+        let error = servo.ping(1).unwrap_err();
+        assert!(matches!(error.downcast_ref::<ServoError>(), Some(ServoError::MalformedReply { id: 1, .. })), "{:#}", error);
+        assert!(servo.ping(2).is_ok());
+    }
+
+    #[test]
+    fn a_held_bus_reports_busy_after_the_lock_timeout() {
+        let bus = MockBus::new(&[1]);
+        let servo = Servo::mock(&bus).with_lock_timeout(Duration::from_millis(20));
+        let _held = servo.serial.lock();
+        let start = std::time::Instant::now();
+        let error = servo.ping(1).unwrap_err();
+        assert_eq!(error.downcast_ref::<ServoError>(), Some(&ServoError::BusBusy { timeout: Duration::from_millis(20) }));
+        assert!(start.elapsed() >= Duration::from_millis(20));
+    }
+
+    #[test]
+    fn sync_read_leaves_missing_servos_empty() {
+        let bus = MockBus::new(&[1, 3]);
+        bus.set_u16(3, ServoRegister::CurrentLocation, 1000);
+        bus.set_u8(3, ServoRegister::CurrentVoltage, 118);
+        let servo = Servo::mock(&bus);
+        let infos = servo.sync_read_info(&[1, 2, 3]).unwrap();
+        let ids: Vec<u8> = infos.iter().map(|(id, _)| *id).collect();
+        assert_eq!(ids, vec![1, 2, 3]);
+        assert_eq!(infos[0].1.unwrap().current_location, 2048);
+        assert!(infos[1].1.is_none());
+        let info = infos[2].1.unwrap();
+        assert_eq!((info.current_location, info.current_voltage), (1000, 118));
+    }
+
+    #[test]
+    fn broadcast_reads_are_refused_without_waiting() {
+        let bus = MockBus::new(&[1]);
+        let mut serial = ServoSerial::with_transport(Box::new(bus.transport()));
+        let error = serial.servo_read(SERVO_BROADCAST_ID, ServoRegister::CurrentLocation as u8, 2).unwrap_err();
+        assert_eq!(error.kind(), std::io::ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn trace_lines_are_timestamped_hex() {
+        let at = UNIX_EPOCH + Duration::from_micros(1_718_000_000_123_456);
+        assert_eq!(format_trace_line(at, "TX", &[0xFF, 0xFF, 0x01, 0x02], None), "1718000000.123456 TX ff ff 01 02");
+        let error = std::io::Error::new(std::io::ErrorKind::TimedOut, "timed out");
+        assert_eq!(format_trace_line(at, "RX", &[0xFF], Some(&error)), "1718000000.123456 RX ff # timed out");
+    }
+
+    #[test]
+    fn traced_bus_logs_both_directions() {
+        let bus = MockBus::new(&[1]);
+        let servo = Servo::mock(&bus);
+        let path = std::env::temp_dir().join(format!("bus-trace-{}.log", std::process::id()));
+        servo.set_trace(Some(&path)).unwrap();
+        servo.ping(1).unwrap();
+        servo.set_trace(None).unwrap();
+        servo.ping(1).unwrap();
+        let trace = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let lines: Vec<&str> = trace.lines().map(|line| line.split_once(' ').unwrap().1).collect();
+        assert_eq!(lines, ["TX ff ff 01 02 01 fb", "RX ff ff 01 02 00 fc"]);
+    }
+
+    #[test]
+    fn broadcast_writes_return_without_reading_a_reply() {
+        let bus = MockBus::new(&[1, 2]);
+        let mut serial = ServoSerial::with_transport(Box::new(bus.transport()));
+        let start = std::time::Instant::now();
+        serial.servo_write(SERVO_BROADCAST_ID, ServoRegister::TorqueSwitch as u8, &[1]).unwrap();
+        assert!(start.elapsed() < Duration::from_millis(50));
+        assert_eq!(bus.reads(), 0);
+        assert_eq!((bus.u8(1, ServoRegister::TorqueSwitch), bus.u8(2, ServoRegister::TorqueSwitch)), (1, 1));
+        bus.set_u16(2, ServoRegister::CurrentLocation, 1234);
+        let data = serial.servo_read(2, ServoRegister::CurrentLocation as u8, 2).unwrap();
+        assert_eq!(data, 1234u16.to_le_bytes());
+    }
+}
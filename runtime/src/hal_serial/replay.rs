@@ -0,0 +1,217 @@
+use anyhow::{Result, bail, Context};
+use std::collections::VecDeque;
+use std::fs;
+use std::io::{self, ErrorKind, Read, Write};
+use std::path::Path;
+use super::Transport;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TraceDirection {
+    Tx,
+    Rx,
+}
+
+// One line of a bus trace, see format_trace_line
+#[derive(Debug, Clone, PartialEq)]
+pub struct TraceLine {
+    // Seconds since the Unix epoch
+    pub at: f64,
+    pub direction: TraceDirection,
+    pub bytes: Vec<u8>,
+    // What cut the packet short, as logged after the '#'
+    pub error: Option<String>,
+}
+
+pub fn parse_trace_line(line: &str) -> Result<TraceLine> {
+    let (packet, error) = match line.split_once(" # ") {
+        Some((packet, error)) => (packet, Some(error.trim().to_string())),
+        None => (line, None),
+    };
+    let mut fields = packet.split_whitespace();
+    let at = fields.next().context("Empty trace line")?
+        .parse().with_context(|| format!("Invalid timestamp in trace line {:?}", line))?;
+    let direction = match fields.next() {
+        Some("TX") => TraceDirection::Tx,
+        Some("RX") => TraceDirection::Rx,
+        other => bail!("Expected TX or RX in trace line {:?}, got {:?}", line, other),
+    };
+    let bytes = fields
+        .map(|byte| u8::from_str_radix(byte, 16).with_context(|| format!("Invalid byte {:?} in trace line {:?}", byte, line)))
+        .collect::<Result<Vec<u8>>>()?;
+    Ok(TraceLine { at, direction, bytes, error })
+}
+
+// A reply as it arrived, then how it ended
+#[derive(Debug)]
+struct Reply {
+    bytes: VecDeque<u8>,
+    // Recorded failures come back as the closest ErrorKind: a read that
+    // timed out replays as a timeout wherever it struck
+    error: Option<ErrorKind>,
+}
+
+// Plays back a bus trace written by Servo::set_trace in place of the serial
+// port, so a captured session goes through the same decoding without
+// hardware. Every recorded RX packet is handed out byte by byte, failing
+// where the recording failed, and every write has to match the next
+// recorded TX packet: a session that diverges from the capture (different
+// retries, a changed command) fails with InvalidData rather than decoding
+// replies to packets that were never sent.
+#[derive(Debug)]
+pub struct ReplayTransport {
+    expected: VecDeque<Vec<u8>>,
+    replies: VecDeque<Reply>,
+    baud_rate: u32,
+}
+
+impl ReplayTransport {
+    pub fn from_trace(trace: &str, baud_rate: u32) -> Result<Self> {
+        let mut expected = VecDeque::new();
+        let mut replies = VecDeque::new();
+        for (i, line) in trace.lines().enumerate().filter(|(_, line)| !line.trim().is_empty()) {
+            let line = parse_trace_line(line).with_context(|| format!("Line {} of the trace", i + 1))?;
+            match line.direction {
+                TraceDirection::Tx => expected.push_back(line.bytes),
+                TraceDirection::Rx => replies.push_back(Reply {
+                    bytes: line.bytes.into(),
+                    error: line.error.as_deref().map(error_kind),
+                }),
+            }
+        }
+        Ok(Self { expected, replies, baud_rate })
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P, baud_rate: u32) -> Result<Self> {
+        let trace = fs::read_to_string(path.as_ref())
+            .with_context(|| format!("Failed to read bus trace {:?}", path.as_ref()))?;
+        Self::from_trace(&trace, baud_rate)
+    }
+
+    // Recorded packets not replayed yet, TX and RX
+    pub fn remaining(&self) -> (usize, usize) {
+        (self.expected.len(), self.replies.len())
+    }
+}
+
+// The io::Error Display strings logged for the failures a serial read hits
+fn error_kind(error: &str) -> ErrorKind {
+    let error = error.to_ascii_lowercase();
+    if error.contains("timed out") || error.contains("timeout") {
+        ErrorKind::TimedOut
+    } else if error.contains("unexpected end of file") || error.contains("eof") {
+        ErrorKind::UnexpectedEof
+    } else {
+        ErrorKind::Other
+    }
+}
+
+impl Read for ReplayTransport {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        loop {
+            let Some(reply) = self.replies.front_mut() else {
+                return Err(io::Error::new(ErrorKind::TimedOut, "Replay has no more recorded replies"));
+            };
+            if let Some(byte) = reply.bytes.pop_front() {
+                buf[0] = byte;
+                return Ok(1);
+            }
+            let error = reply.error;
+            self.replies.pop_front();
+            if let Some(kind) = error {
+                return Err(io::Error::new(kind, "Recorded failure"));
+            }
+        }
+    }
+}
+
+impl Write for ReplayTransport {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self.expected.pop_front() {
+            Some(expected) if expected == buf => Ok(buf.len()),
+            Some(expected) => Err(io::Error::new(
+                ErrorKind::InvalidData,
+                format!("Replay diverged: sent {:02x?} where the trace has {:02x?}", buf, expected),
+            )),
+            None => Err(io::Error::new(ErrorKind::InvalidData, format!("Replay diverged: sent {:02x?} past the end of the trace", buf))),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Transport for ReplayTransport {
+    fn baud_rate(&self) -> serialport::Result<u32> {
+        Ok(self.baud_rate)
+    }
+
+    fn set_baud_rate(&mut self, baud_rate: u32) -> serialport::Result<()> {
+        self.baud_rate = baud_rate;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hal::{Servo, ServoRegister};
+    use crate::hal::mock::MockBus;
+
+    #[test]
+    fn trace_lines_parse_back() {
+        let line = parse_trace_line("1700000000.250000 RX ff ff 01 # timed out").unwrap();
+        assert_eq!(line, TraceLine { at: 1700000000.25, direction: TraceDirection::Rx, bytes: vec![0xFF, 0xFF, 0x01], error: Some("timed out".to_string()) });
+        assert_eq!(parse_trace_line("1.5 TX").unwrap().bytes, Vec::<u8>::new());
+        assert!(parse_trace_line("").is_err());
+        assert!(parse_trace_line("1.5 XX ff").is_err());
+        assert!(parse_trace_line("1.5 TX fg").is_err());
+        assert!(parse_trace_line("noon TX ff").is_err());
+    }
+
+    #[test]
+    fn recorded_failures_keep_their_kind() {
+        assert_eq!(error_kind("Operation timed out"), ErrorKind::TimedOut);
+        assert_eq!(error_kind("unexpected end of file"), ErrorKind::UnexpectedEof);
+        assert_eq!(error_kind("broken pipe"), ErrorKind::Other);
+    }
+
+    #[test]
+    fn replies_play_back_and_writes_must_match() {
+        let trace = "1.0 TX 01 02\n\n1.1 RX 0a 0b # timed out\n1.2 TX 03\n";
+        let mut replay = ReplayTransport::from_trace(trace, 1_000_000).unwrap();
+        assert_eq!(replay.remaining(), (2, 1));
+        replay.write_all(&[0x01, 0x02]).unwrap();
+        let mut byte = [0];
+        replay.read_exact(&mut byte).unwrap();
+        assert_eq!(byte, [0x0A]);
+        replay.read_exact(&mut byte).unwrap();
+        assert_eq!(replay.read(&mut byte).unwrap_err().kind(), ErrorKind::TimedOut);
+        assert_eq!(replay.write(&[0x04]).unwrap_err().kind(), ErrorKind::InvalidData);
+        assert_eq!(replay.write(&[0x03]).unwrap_err().kind(), ErrorKind::InvalidData);
+        assert_eq!(replay.remaining(), (0, 0));
+        // A bad line is reported by its number
+        let error = ReplayTransport::from_trace("1.0 TX 01\n1.1 ZZ\n", 1_000_000).unwrap_err();
+        assert_eq!(error.to_string(), "Line 2 of the trace");
+    }
+
+    #[test]
+    fn captured_session_decodes_the_same_offline() {
+        let bus = MockBus::new(&[1]);
+        bus.set_u16(1, ServoRegister::CurrentLocation, 3100);
+        let servo = Servo::mock(&bus);
+        let path = std::env::temp_dir().join(format!("replay-trace-{}.log", std::process::id()));
+        servo.set_trace(Some(&path)).unwrap();
+        assert_eq!(servo.read_position(1).unwrap(), 3100);
+        servo.set_trace(None).unwrap();
+
+        let replay = Servo::replay(ReplayTransport::load(&path, 1_000_000).unwrap());
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(replay.read_position(1).unwrap(), 3100);
+        // Not what was captured
+        assert!(replay.read_position(2).is_err());
+    }
+}
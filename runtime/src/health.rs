@@ -0,0 +1,551 @@
+use anyhow::Result;
+use std::collections::VecDeque;
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use crate::endian::decode_u16;
+use crate::hal::{Servo, ServoRegister};
+use crate::robot::Robot;
+use crate::servo::BAUD_RATES;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct BusStats {
+    pub transactions: u64,
+    pub failures: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct HealthConfig {
+    // Number of recent transactions the success rate is computed over
+    pub window: usize,
+    // Below this success rate (0.0-1.0) the bus is considered degraded
+    pub min_success_rate: f32,
+}
+
+impl Default for HealthConfig {
+    fn default() -> Self {
+        Self { window: 100, min_success_rate: 0.9 }
+    }
+}
+
+pub type DegradedCallback = Arc<dyn Fn(f32) + Send + Sync>;
+
+// Rolling success rate over the last `window` transactions. A falling rate
+// means the controller is about to lose authority over the joints, so
+// crossing the threshold is reported once, when it happens, to give the
+// controller a chance to slow down or safe the robot.
+pub struct BusHealth {
+    config: HealthConfig,
+    recent: VecDeque<bool>,
+    stats: BusStats,
+    degraded: bool,
+    on_degraded: Option<DegradedCallback>,
+}
+
+impl fmt::Debug for BusHealth {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("BusHealth")
+            .field("config", &self.config)
+            .field("stats", &self.stats)
+            .field("degraded", &self.degraded)
+            .finish()
+    }
+}
+
+impl BusHealth {
+    pub fn new(config: HealthConfig) -> Self {
+        Self {
+            config,
+            recent: VecDeque::with_capacity(config.window),
+            stats: BusStats::default(),
+            degraded: false,
+            on_degraded: None,
+        }
+    }
+
+    // Returns the success rate if this transaction made the bus degraded
+    pub fn record(&mut self, ok: bool) -> Option<f32> {
+        self.stats.transactions += 1;
+        if !ok {
+            self.stats.failures += 1;
+        }
+        if self.recent.len() >= self.config.window {
+            self.recent.pop_front();
+        }
+        self.recent.push_back(ok);
+
+        // Rate isn't meaningful until the window has filled once
+        if self.recent.len() < self.config.window {
+            return None;
+        }
+        let rate = self.success_rate();
+        let was_degraded = self.degraded;
+        self.degraded = rate < self.config.min_success_rate;
+        if self.degraded && !was_degraded { Some(rate) } else { None }
+    }
+
+    pub fn success_rate(&self) -> f32 {
+        if self.recent.is_empty() {
+            return 1.0;
+        }
+        self.recent.iter().filter(|&&ok| ok).count() as f32 / self.recent.len() as f32
+    }
+
+    pub fn is_degraded(&self) -> bool {
+        self.degraded
+    }
+
+    pub fn stats(&self) -> BusStats {
+        self.stats
+    }
+}
+
+// Shared by both HAL backends, which record every read and write
+#[derive(Debug)]
+pub struct BusMonitor {
+    health: Mutex<BusHealth>,
+}
+
+impl Default for BusMonitor {
+    fn default() -> Self {
+        Self { health: Mutex::new(BusHealth::new(HealthConfig::default())) }
+    }
+}
+
+impl BusMonitor {
+    pub fn record(&self, ok: bool) {
+        let degraded = {
+            let mut health = self.health.lock().unwrap();
+            health.record(ok).map(|rate| (rate, health.on_degraded.clone()))
+        };
+        match degraded {
+            Some((rate, Some(callback))) => callback(rate),
+            Some((rate, None)) => eprintln!("Warning: servo bus degraded, {:.0}% of recent transactions succeeded", rate * 100.0),
+            None => (),
+        }
+    }
+}
+
+impl Servo {
+    pub fn bus_stats(&self) -> BusStats {
+        self.health.health.lock().unwrap().stats()
+    }
+
+    pub fn bus_success_rate(&self) -> f32 {
+        self.health.health.lock().unwrap().success_rate()
+    }
+
+    pub fn is_bus_degraded(&self) -> bool {
+        self.health.health.lock().unwrap().is_degraded()
+    }
+
+    // Restarts the rolling window, the totals are kept
+    pub fn set_health_config(&self, config: HealthConfig) {
+        let mut health = self.health.health.lock().unwrap();
+        let stats = health.stats;
+        let on_degraded = health.on_degraded.take();
+        *health = BusHealth { stats, on_degraded, ..BusHealth::new(config) };
+    }
+
+    // Called instead of logging a warning when the bus becomes degraded
+    pub fn on_bus_degraded<F: Fn(f32) + Send + Sync + 'static>(&self, callback: F) {
+        self.health.health.lock().unwrap().on_degraded = Some(Arc::new(callback));
+    }
+}
+
+// Alternating bits catch flips on either level, all zeros and all ones stuck
+// lines, and the split bytes swapped or dropped bytes
+pub const COMM_TEST_PATTERNS: [u16; 6] = [0x0000, 0xFFFF, 0xAAAA, 0x5555, 0x00FF, 0xFF00];
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CommTestReport {
+    pub rounds: u32,
+    // No reply, or a reply of the wrong length
+    pub dropped: u32,
+    // Replies that read back something other than what was written
+    pub corrupted: u32,
+    pub bit_errors: u32,
+}
+
+impl CommTestReport {
+    pub fn passed(&self) -> bool {
+        self.dropped == 0 && self.corrupted == 0
+    }
+
+    // Corrupted replies out of those received
+    pub fn corruption_rate(&self) -> f32 {
+        let received = self.rounds - self.dropped;
+        if received == 0 {
+            return 0.0;
+        }
+        self.corrupted as f32 / received as f32
+    }
+}
+
+impl fmt::Display for CommTestReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f, "{}: {} rounds, {} dropped, {} corrupted ({:.2}%), {} bit errors",
+            if self.passed() { "PASS" } else { "FAIL" },
+            self.rounds, self.dropped, self.corrupted, self.corruption_rate() * 100.0, self.bit_errors
+        )
+    }
+}
+
+// Cycles through COMM_TEST_PATTERNS for `rounds` rounds. `loopback` writes
+// a pattern and reads it back, None if the read got no usable reply.
+pub fn run_pattern_test<F>(rounds: u32, mut loopback: F) -> Result<CommTestReport>
+where
+    F: FnMut(u16) -> Result<Option<u16>>,
+{
+    let mut report = CommTestReport { rounds, ..Default::default() };
+    for round in 0..rounds {
+        let pattern = COMM_TEST_PATTERNS[round as usize % COMM_TEST_PATTERNS.len()];
+        match loopback(pattern)? {
+            None => report.dropped += 1,
+            Some(read) if read != pattern => {
+                report.corrupted += 1;
+                report.bit_errors += (read ^ pattern).count_ones();
+            }
+            Some(_) => (),
+        }
+    }
+    Ok(report)
+}
+
+impl Servo {
+    // End to end data integrity, as opposed to ping latency. Runs the
+    // patterns through ServoRegister::RunningTime, which is RAM (no EEPROM
+    // wear) and only used when a move is commanded. Its value is restored
+    // afterwards, so don't command moves to this servo during the test.
+    pub fn comm_test(&self, id: u8, rounds: u32) -> Result<CommTestReport> {
+        let original = self.read_u16(id, ServoRegister::RunningTime)?;
+        let report = run_pattern_test(rounds, |pattern| {
+            self.write_u16(id, ServoRegister::RunningTime, pattern)?;
+            let data = self.read(id, ServoRegister::RunningTime, 2)?;
+            (data.len() == 2).then(|| decode_u16(ServoRegister::RunningTime, &data)).transpose()
+        });
+        self.write_u16(id, ServoRegister::RunningTime, original)?;
+        report
+    }
+}
+
+// Outcome at one baud rate of a baud scan, None if the joints didn't answer
+// at all after switching
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BaudProbe {
+    pub baud_rate: u32,
+    pub report: Option<CommTestReport>,
+}
+
+impl BaudProbe {
+    pub fn passed(&self) -> bool {
+        self.report.is_some_and(|report| report.passed())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BaudScan {
+    // In the order tried, ending at the first rate that failed
+    pub probes: Vec<BaudProbe>,
+    // Highest rate with no dropped or corrupted reply, None if not even the
+    // original rate passed
+    pub best: Option<u32>,
+    pub original: u32,
+}
+
+// Tries the original rate and then every supported rate above it, lowest
+// first, stopping at the first one that fails. `switch` moves the chain to a
+// rate, `probe` tests it. The chain is always switched back to `original`
+// at the end, even when a switch or probe fails with an error, which is
+// returned after the restore.
+pub fn scan_bauds<S, P>(original: u32, mut switch: S, mut probe: P) -> Result<BaudScan>
+where
+    S: FnMut(u32) -> Result<()>,
+    P: FnMut(u32) -> Result<CommTestReport>,
+{
+    let mut rates: Vec<u32> = BAUD_RATES.iter().copied().filter(|&rate| rate > original).collect();
+    rates.sort_unstable();
+    let mut scan = BaudScan { probes: Vec::new(), best: None, original };
+
+    let mut result = Ok(());
+    let mut switched = false;
+    for baud_rate in std::iter::once(original).chain(rates) {
+        let report = if baud_rate == original {
+            probe(baud_rate).map(Some)
+        } else {
+            switched = true;
+            match switch(baud_rate) {
+                Ok(()) => probe(baud_rate).map(Some),
+                Err(_) => Ok(None),
+            }
+        };
+        let probe = match report {
+            Ok(report) => BaudProbe { baud_rate, report },
+            Err(e) => {
+                result = Err(e.context(format!("Baud scan failed at {} baud", baud_rate)));
+                break;
+            }
+        };
+        scan.probes.push(probe);
+        if !probe.passed() {
+            break;
+        }
+        scan.best = Some(baud_rate);
+    }
+
+    let restored = if switched { switch(original) } else { Ok(()) };
+    result?;
+    restored.map_err(|e| e.context(format!("Failed to restore the original {} baud", original)))?;
+    Ok(scan)
+}
+
+impl Robot {
+    // scan_bauds over every configured joint, each running `rounds` rounds
+    // of Servo::comm_test per rate. A joint failing to answer counts as
+    // every round dropped. Leaves the chain at the rate it started at.
+    pub fn baud_scan(&self, rounds: u32) -> Result<BaudScan> {
+        let original = self.servo().bus_baud_rate()?;
+        scan_bauds(
+            original,
+            |baud_rate| self.change_baud(baud_rate),
+            |_| {
+                let mut total = CommTestReport::default();
+                for joint in self.joints() {
+                    let report = self.servo().comm_test(joint.id, rounds)
+                        .unwrap_or(CommTestReport { rounds, dropped: rounds, ..Default::default() });
+                    total.rounds += report.rounds;
+                    total.dropped += report.dropped;
+                    total.corrupted += report.corrupted;
+                    total.bit_errors += report.bit_errors;
+                }
+                Ok(total)
+            },
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn degradation_is_reported_once_per_crossing() {
+        let mut health = BusHealth::new(HealthConfig { window: 4, min_success_rate: 0.75 });
+        // Not judged until the window has filled
+        assert_eq!(health.record(false), None);
+        assert_eq!(health.record(false), None);
+        assert_eq!(health.record(true), None);
+        assert_eq!(health.record(true), Some(0.5));
+        assert!(health.is_degraded());
+        assert_eq!(health.record(true), None);
+        // Recovered, then degraded again
+        assert_eq!(health.record(true), None);
+        assert!(!health.is_degraded());
+        assert_eq!(health.record(false), None);
+        assert_eq!(health.record(false), Some(0.5));
+        assert_eq!(health.stats(), BusStats { transactions: 8, failures: 4 });
+    }
+
+    #[test]
+    fn empty_window_counts_as_healthy() {
+        let health = BusHealth::new(HealthConfig::default());
+        assert_eq!(health.success_rate(), 1.0);
+        assert!(!health.is_degraded());
+    }
+
+    #[test]
+    fn pattern_test_counts_drops_and_flipped_bits() {
+        let mut round = 0;
+        let report = run_pattern_test(12, |pattern| {
+            round += 1;
+            Ok(match round {
+                3 => None,
+                5 => Some(pattern ^ 0b101),
+                _ => Some(pattern),
+            })
+        }).unwrap();
+        assert_eq!(report, CommTestReport { rounds: 12, dropped: 1, corrupted: 1, bit_errors: 2 });
+        assert!(!report.passed());
+        assert!((report.corruption_rate() - 1.0 / 11.0).abs() < 1e-6);
+        assert!(report.to_string().starts_with("FAIL: 12 rounds, 1 dropped, 1 corrupted (9.09%)"), "{}", report);
+    }
+
+    #[test]
+    fn pattern_test_cycles_every_pattern() {
+        let mut written = Vec::new();
+        let report = run_pattern_test(7, |pattern| {
+            written.push(pattern);
+            Ok(Some(pattern))
+        }).unwrap();
+        assert!(report.passed());
+        assert_eq!(written[..6], COMM_TEST_PATTERNS);
+        assert_eq!(written[6], COMM_TEST_PATTERNS[0]);
+        assert!(run_pattern_test(1, |_| anyhow::bail!("bus gone")).is_err());
+        // Nothing received
+        assert_eq!(CommTestReport { rounds: 2, dropped: 2, ..Default::default() }.corruption_rate(), 0.0);
+    }
+
+    fn clean() -> CommTestReport {
+        CommTestReport { rounds: 4, ..Default::default() }
+    }
+
+    #[test]
+    fn baud_scan_climbs_until_a_rate_fails_and_switches_back() {
+        let mut switched = Vec::new();
+        let scan = scan_bauds(
+            115_200,
+            |rate| {
+                switched.push(rate);
+                Ok(())
+            },
+            |rate| Ok(if rate == 500_000 { CommTestReport { corrupted: 1, ..clean() } } else { clean() }),
+        ).unwrap();
+        assert_eq!(scan.probes.iter().map(|probe| probe.baud_rate).collect::<Vec<_>>(), [115_200, 128_000, 250_000, 500_000]);
+        assert!(!scan.probes[3].passed());
+        assert_eq!(scan.best, Some(250_000));
+        assert_eq!(switched, [128_000, 250_000, 500_000, 115_200]);
+    }
+
+    #[test]
+    fn baud_scan_failing_at_the_original_rate_never_switches() {
+        let mut switched = Vec::new();
+        let scan = scan_bauds(
+            115_200,
+            |rate| {
+                switched.push(rate);
+                Ok(())
+            },
+            |_| Ok(CommTestReport { dropped: 4, ..clean() }),
+        ).unwrap();
+        assert_eq!(scan.probes.len(), 1);
+        assert_eq!(scan.best, None);
+        assert!(switched.is_empty());
+    }
+
+    #[test]
+    fn baud_scan_from_the_top_rate_only_probes_it() {
+        let scan = scan_bauds(1_000_000, |_| anyhow::bail!("no switching"), |_| Ok(clean())).unwrap();
+        assert_eq!(scan.probes, [BaudProbe { baud_rate: 1_000_000, report: Some(clean()) }]);
+        assert_eq!(scan.best, Some(1_000_000));
+    }
+
+    #[test]
+    fn baud_scan_rate_the_chain_cannot_switch_to_fails_that_probe() {
+        let mut switched = Vec::new();
+        let scan = scan_bauds(
+            500_000,
+            |rate| {
+                switched.push(rate);
+                if rate == 1_000_000 { anyhow::bail!("joint lost") } else { Ok(()) }
+            },
+            |_| Ok(clean()),
+        ).unwrap();
+        assert_eq!(scan.probes[1], BaudProbe { baud_rate: 1_000_000, report: None });
+        assert!(!scan.probes[1].passed());
+        assert_eq!(scan.best, Some(500_000));
+        assert_eq!(switched, [1_000_000, 500_000]);
+    }
+
+    #[test]
+    fn baud_scan_probe_error_is_returned_after_restoring() {
+        let mut switched = Vec::new();
+        let error = scan_bauds(
+            250_000,
+            |rate| {
+                switched.push(rate);
+                Ok(())
+            },
+            |rate| if rate == 500_000 { anyhow::bail!("bus gone") } else { Ok(clean()) },
+        ).unwrap_err();
+        assert_eq!(error.to_string(), "Baud scan failed at 500000 baud");
+        assert_eq!(switched, [500_000, 250_000]);
+    }
+
+    #[test]
+    fn baud_scan_restore_failure_is_an_error() {
+        let error = scan_bauds(
+            500_000,
+            |rate| if rate == 500_000 { anyhow::bail!("stuck") } else { Ok(()) },
+            |_| Ok(clean()),
+        ).unwrap_err();
+        assert_eq!(error.to_string(), "Failed to restore the original 500000 baud");
+    }
+
+    #[cfg(not(feature = "milkv"))]
+    mod bus {
+        use super::*;
+        use crate::hal::mock::MockBus;
+
+        #[test]
+        fn missing_servo_degrades_the_bus() {
+            let bus = MockBus::new(&[1]);
+            let servo = Servo::mock(&bus);
+            servo.set_health_config(HealthConfig { window: 4, min_success_rate: 0.75 });
+            let reported = Arc::new(Mutex::new(Vec::new()));
+            let sink = reported.clone();
+            servo.on_bus_degraded(move |rate| sink.lock().unwrap().push(rate));
+            for id in [1, 1, 2, 2, 2] {
+                let _ = servo.read_position(id);
+            }
+            assert!(servo.is_bus_degraded());
+            assert_eq!(*reported.lock().unwrap(), vec![0.5]);
+            assert_eq!(servo.bus_stats(), BusStats { transactions: 5, failures: 3 });
+        }
+
+        #[test]
+        fn comm_test_restores_the_goal_time() {
+            let bus = MockBus::new(&[1]);
+            bus.set_u16(1, ServoRegister::RunningTime, 300);
+            let report = Servo::mock(&bus).comm_test(1, 6).unwrap();
+            assert_eq!(report, CommTestReport { rounds: 6, ..Default::default() });
+            assert_eq!(bus.u16(1, ServoRegister::RunningTime), 300);
+            assert_eq!(bus.writes().len(), 7);
+        }
+
+        #[test]
+        fn comm_test_needs_the_servo_to_answer() {
+            let bus = MockBus::new(&[1]);
+            let servo = Servo::mock(&bus);
+            assert!(servo.comm_test(2, 3).is_err());
+        }
+
+        #[test]
+        fn robot_baud_scan_reaches_the_top_rate_and_returns() {
+            let (bus, robot) = crate::robot::tests::mock_robot(&[("left_hip", 1), ("right_hip", 2)]);
+            robot.change_baud(250_000).unwrap();
+            let scan = robot.baud_scan(2).unwrap();
+            assert_eq!(scan.original, 250_000);
+            assert_eq!(scan.probes.len(), 3);
+            assert_eq!(scan.probes[2], BaudProbe { baud_rate: 1_000_000, report: Some(CommTestReport { rounds: 4, ..Default::default() }) });
+            assert_eq!(scan.best, Some(1_000_000));
+            assert_eq!(bus.baud_rate(), 250_000);
+            assert_eq!(bus.u8(1, ServoRegister::BaudRate), 2);
+        }
+
+        #[test]
+        fn robot_baud_scan_stops_at_a_rate_a_joint_refuses() {
+            let (bus, robot) = crate::robot::tests::mock_robot(&[("left_hip", 1), ("right_hip", 2)]);
+            robot.change_baud(500_000).unwrap();
+            bus.refuse(2, ServoRegister::BaudRate);
+            let scan = robot.baud_scan(2).unwrap();
+            assert_eq!(scan.probes[1], BaudProbe { baud_rate: 1_000_000, report: None });
+            assert_eq!(scan.best, Some(500_000));
+            // Rolled back, joint 1 included
+            assert_eq!(bus.baud_rate(), 500_000);
+            assert_eq!(bus.u8(1, ServoRegister::BaudRate), 1);
+        }
+
+        #[test]
+        fn sync_writes_count_towards_bus_health() {
+            let bus = MockBus::new(&[1, 2]);
+            let servo = Servo::mock(&bus);
+            servo.sync_write_positions(&[(1, 1000), (2, 3000)]).unwrap();
+            servo.sync_move_timed(&[(1, 1500)], 500).unwrap();
+            assert_eq!(servo.bus_stats(), BusStats { transactions: 2, failures: 0 });
+            bus.disconnect();
+            assert!(servo.sync_write_positions(&[(1, 1000)]).is_err());
+            assert!(servo.sync_move_timed(&[(1, 1000)], 500).is_err());
+            assert_eq!(servo.bus_stats(), BusStats { transactions: 4, failures: 2 });
+        }
+    }
+}
@@ -0,0 +1,181 @@
+use anyhow::{Result, Context, anyhow, bail};
+use serde::{Serialize, Deserialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+use std::time::Duration;
+use crate::robot::{MissingJoints, Robot};
+
+// A full pose, every joint's angle in degrees through its mapping, keyed by
+// joint name
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Keyframe {
+    pub name: String,
+    pub joints: BTreeMap<String, f32>,
+}
+
+// JSON keyframe library, in the order the keyframes were saved
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct KeyframeLibrary {
+    pub keyframes: Vec<Keyframe>,
+}
+
+impl KeyframeLibrary {
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let contents = fs::read_to_string(path.as_ref())
+            .with_context(|| format!("Failed to read keyframe file {:?}", path.as_ref()))?;
+        serde_json::from_str(&contents)
+            .with_context(|| format!("Failed to parse keyframe file {:?}", path.as_ref()))
+    }
+
+    // An empty library if the file doesn't exist yet
+    pub fn load_or_default<P: AsRef<Path>>(path: P) -> Result<Self> {
+        if path.as_ref().exists() {
+            Self::load(path)
+        } else {
+            Ok(Self::default())
+        }
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        let contents = serde_json::to_string_pretty(self)?;
+        fs::write(path.as_ref(), contents)
+            .with_context(|| format!("Failed to write keyframe file {:?}", path.as_ref()))
+    }
+
+    pub fn get(&self, name: &str) -> Result<&Keyframe> {
+        self.keyframes.iter()
+            .find(|keyframe| keyframe.name == name)
+            .ok_or_else(|| anyhow!("Unknown keyframe: {}", name))
+    }
+
+    // Appends `keyframe`, or replaces the one with the same name in place.
+    // Returns the replaced keyframe.
+    pub fn insert(&mut self, keyframe: Keyframe) -> Option<Keyframe> {
+        match self.keyframes.iter_mut().find(|existing| existing.name == keyframe.name) {
+            Some(existing) => Some(std::mem::replace(existing, keyframe)),
+            None => {
+                self.keyframes.push(keyframe);
+                None
+            }
+        }
+    }
+}
+
+impl Robot {
+    // Snapshot every joint into the keyframe library at `path`. Returns the
+    // new keyframe and the one of the same name it replaced, if any.
+    pub fn save_keyframe<P: AsRef<Path>>(&self, path: P, name: &str) -> Result<(Keyframe, Option<Keyframe>)> {
+        let joints = self.positions(MissingJoints::Error)?.into_iter().collect();
+        let keyframe = Keyframe { name: name.to_string(), joints };
+
+        let mut library = KeyframeLibrary::load_or_default(&path)?;
+        let replaced = library.insert(keyframe.clone());
+        library.save(&path)?;
+        Ok((keyframe, replaced))
+    }
+
+    // Move every joint to the keyframe `name` from the library at `path`,
+    // all arriving after `duration`
+    pub fn goto_keyframe<P: AsRef<Path>>(&self, path: P, name: &str, duration: Duration) -> Result<Vec<(u8, i16)>> {
+        let library = KeyframeLibrary::load(path)?;
+        let keyframe = library.get(name)?;
+        self.check_keyframe(keyframe)?;
+        self.move_to_keyframe(keyframe, duration)
+    }
+
+    pub fn move_to_keyframe(&self, keyframe: &Keyframe, duration: Duration) -> Result<Vec<(u8, i16)>> {
+        let targets: Vec<(&str, f32)> = keyframe.joints.iter()
+            .map(|(joint, &degrees)| (joint.as_str(), degrees))
+            .collect();
+        self.move_group(&targets, duration)
+    }
+
+    // A keyframe saved against a different config would leave joints out or
+    // name ones that no longer exist
+    pub fn check_keyframe(&self, keyframe: &Keyframe) -> Result<()> {
+        let mut errors = Vec::new();
+        for joint in self.joints() {
+            if !keyframe.joints.contains_key(&joint.name) {
+                errors.push(format!("missing joint {}", joint.name));
+            }
+        }
+        for name in keyframe.joints.keys() {
+            if self.joint(name).is_err() {
+                errors.push(format!("unknown joint {}", name));
+            }
+        }
+        if !errors.is_empty() {
+            bail!("Keyframe {} doesn't match the robot config: {}", keyframe.name, errors.join(", "));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn keyframe(name: &str, joints: &[(&str, f32)]) -> Keyframe {
+        Keyframe { name: name.to_string(), joints: joints.iter().map(|&(joint, degrees)| (joint.to_string(), degrees)).collect() }
+    }
+
+    fn temp_path(name: &str) -> std::path::PathBuf {
+        std::env::temp_dir().join(format!("keyframes-{}-{}.json", name, std::process::id()))
+    }
+
+    #[test]
+    fn insert_replaces_in_place() {
+        let mut library = KeyframeLibrary::default();
+        assert_eq!(library.insert(keyframe("stand", &[("left_hip", 0.0)])), None);
+        assert_eq!(library.insert(keyframe("sit", &[("left_hip", 45.0)])), None);
+        let replaced = library.insert(keyframe("stand", &[("left_hip", 5.0)])).unwrap();
+        assert_eq!(replaced.joints["left_hip"], 0.0);
+        let names: Vec<&str> = library.keyframes.iter().map(|keyframe| keyframe.name.as_str()).collect();
+        assert_eq!(names, vec!["stand", "sit"]);
+        assert_eq!(library.get("stand").unwrap().joints["left_hip"], 5.0);
+        assert!(library.get("wave").is_err());
+    }
+
+    #[test]
+    fn missing_library_loads_empty() {
+        let path = temp_path("missing");
+        assert!(KeyframeLibrary::load_or_default(&path).unwrap().keyframes.is_empty());
+        assert!(KeyframeLibrary::load(&path).is_err());
+    }
+
+    #[cfg(not(feature = "milkv"))]
+    mod bus {
+        use super::*;
+        use crate::hal::ServoRegister;
+        use crate::robot::tests::mock_robot;
+
+        const JOINTS: [(&str, u8); 2] = [("left_hip", 1), ("right_hip", 2)];
+
+        #[test]
+        fn saved_keyframe_is_moved_back_to() {
+            let (bus, robot) = mock_robot(&JOINTS);
+            let robot = robot.with_require_calibration(false);
+            bus.set_u16(1, ServoRegister::CurrentLocation, 2560);
+            let path = temp_path("save_goto");
+            let (saved, replaced) = robot.save_keyframe(&path, "pose").unwrap();
+            assert_eq!(saved, keyframe("pose", &[("left_hip", 45.0), ("right_hip", 0.0)]));
+            assert!(replaced.is_none());
+            assert!(robot.save_keyframe(&path, "pose").unwrap().1.is_some());
+
+            bus.set_u16(1, ServoRegister::CurrentLocation, 2048);
+            let moved = robot.goto_keyframe(&path, "pose", Duration::from_millis(200)).unwrap();
+            std::fs::remove_file(&path).unwrap();
+            assert_eq!(moved, vec![(1, 2560), (2, 2048)]);
+            assert_eq!(bus.u16(1, ServoRegister::TargetLocation), 2560);
+        }
+
+        #[test]
+        fn keyframes_must_match_the_joints() {
+            let (_, robot) = mock_robot(&JOINTS);
+            let error = robot.check_keyframe(&keyframe("pose", &[("left_hip", 0.0), ("neck", 0.0)])).unwrap_err().to_string();
+            assert!(error.contains("missing joint right_hip"), "{}", error);
+            assert!(error.contains("unknown joint neck"), "{}", error);
+        }
+    }
+}
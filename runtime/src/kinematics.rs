@@ -0,0 +1,264 @@
+use anyhow::{Result, anyhow, bail};
+use std::collections::HashMap;
+use crate::calibration::{range_deg, NO_LIMITS};
+use crate::config::{ChainConfig, Config};
+use crate::robot::{MissingJoints, Robot};
+use crate::units::wrap_deg;
+
+// Targets this close outside the reachable annulus are taken as on its
+// edge, float error would otherwise make the fully stretched or folded arm
+// unreachable
+const REACH_TOLERANCE: f32 = 1e-4;
+
+// Which of the two 2-link solutions to take, with y up: the elbow above or
+// below the line from the base to the target. Elbow down has a positive
+// (counterclockwise) elbow angle.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Elbow {
+    Up,
+    Down,
+}
+
+// Serial chain of links rotating in one plane, e.g. shoulder, elbow and
+// wrist pitch of an arm. The base is at the origin and every joint angle is
+// relative to the link before it: all zero stretches the chain along +x,
+// positive angles turn counterclockwise seen from +z. Joint angles are in
+// joint degrees as mapped by the config, so a joint turning the other way
+// round wants a negative mapping scale.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PlanarChain {
+    links: Vec<(String, f32)>,
+}
+
+impl PlanarChain {
+    // `links` as (joint name, length) from the base out
+    pub fn new(links: Vec<(String, f32)>) -> Result<Self> {
+        if links.is_empty() {
+            bail!("A chain needs at least one link");
+        }
+        Ok(Self { links })
+    }
+
+    pub fn from_config(config: &ChainConfig) -> Result<Self> {
+        Self::new(config.links.iter().map(|link| (link.joint.clone(), link.length)).collect())
+    }
+
+    // Chain `name` under [chains] in `config`
+    pub fn named(config: &Config, name: &str) -> Result<Self> {
+        let chain = config.chains.get(name).ok_or_else(|| anyhow!("Unknown chain {}", name))?;
+        Self::from_config(chain)
+    }
+
+    pub fn joints(&self) -> impl Iterator<Item = &str> {
+        self.links.iter().map(|(joint, _)| joint.as_str())
+    }
+
+    // End of every link for `angles`, in degrees and in link order, the last
+    // being the end effector
+    pub fn forward(&self, angles: &[f32]) -> Result<Vec<(f32, f32)>> {
+        if angles.len() != self.links.len() {
+            bail!("Chain has {} links, got {} angles", self.links.len(), angles.len());
+        }
+        let mut heading = 0.0f32;
+        let (mut x, mut y) = (0.0f32, 0.0f32);
+        Ok(self.links.iter().zip(angles)
+            .map(|((_, length), angle)| {
+                heading += angle.to_radians();
+                x += length * heading.cos();
+                y += length * heading.sin();
+                (x, y)
+            })
+            .collect())
+    }
+
+    pub fn end_effector(&self, angles: &[f32]) -> Result<(f32, f32)> {
+        Ok(*self.forward(angles)?.last().expect("chains have at least one link"))
+    }
+
+    // Joint angles in degrees putting the end of a 2-link chain at `x`, `y`.
+    // Fails if the target is out of reach, closer than |l1 - l2| or further
+    // than l1 + l2. Fully stretched or folded there's only one solution and
+    // `elbow` makes no difference; a target on the base with equal links
+    // leaves the first angle free, it comes out as 0.
+    pub fn inverse(&self, x: f32, y: f32, elbow: Elbow) -> Result<[f32; 2]> {
+        let [(_, l1), (_, l2)] = self.links.as_slice() else {
+            bail!("Inverse kinematics needs a 2-link chain, this one has {} links", self.links.len());
+        };
+        let (l1, l2) = (*l1, *l2);
+        if !(x.is_finite() && y.is_finite()) {
+            bail!("Invalid target ({}, {})", x, y);
+        }
+        let distance = x.hypot(y);
+        let (inner, outer) = ((l1 - l2).abs(), l1 + l2);
+        if distance > outer * (1.0 + REACH_TOLERANCE) || distance < inner - outer * REACH_TOLERANCE {
+            bail!("Target ({}, {}) is {} from the base, out of reach between {} and {}", x, y, distance, inner, outer);
+        }
+        if l1 == 0.0 || l2 == 0.0 {
+            bail!("Inverse kinematics needs both links longer than zero");
+        }
+
+        let cos_elbow = ((distance * distance - l1 * l1 - l2 * l2) / (2.0 * l1 * l2)).clamp(-1.0, 1.0);
+        let elbow_angle = match elbow {
+            Elbow::Down => cos_elbow.acos(),
+            Elbow::Up => -cos_elbow.acos(),
+        };
+        let shoulder_angle = y.atan2(x) - (l2 * elbow_angle.sin()).atan2(l1 + l2 * elbow_angle.cos());
+        Ok([wrap_deg(shoulder_angle.to_degrees()), elbow_angle.to_degrees()])
+    }
+
+    // `positions` as returned by Robot::positions
+    pub fn end_effector_at(&self, positions: &HashMap<String, f32>) -> Result<(f32, f32)> {
+        let angles = self.joints()
+            .map(|joint| positions.get(joint).copied().ok_or_else(|| anyhow!("No position for joint {}", joint)))
+            .collect::<Result<Vec<_>>>()?;
+        self.end_effector(&angles)
+    }
+}
+
+impl Robot {
+    // Where the end of `chain` is right now
+    pub fn end_effector(&self, chain: &PlanarChain) -> Result<(f32, f32)> {
+        chain.end_effector_at(&self.positions(MissingJoints::Error)?)
+    }
+
+    // move_group targets putting the end of a 2-link `chain` at `x`, `y`,
+    // see PlanarChain::inverse. Each angle is clamped to its joint's
+    // calibrated range, so a target the solution can only reach outside it
+    // ends up as close as the joints allow instead.
+    pub fn reach(&self, chain: &PlanarChain, x: f32, y: f32, elbow: Elbow) -> Result<Vec<(String, f32)>> {
+        let angles = chain.inverse(x, y, elbow)?;
+        chain.joints().zip(angles)
+            .map(|(name, angle)| {
+                let joint = self.joint(name)?;
+                let calibration = self.calibration_of(joint)?;
+                if (calibration.min_angle, calibration.max_angle) == NO_LIMITS {
+                    return Ok((name.to_string(), angle));
+                }
+                let (min, max) = range_deg(calibration.min_angle, calibration.max_angle);
+                let (a, b) = (joint.mapping.to_joint(min), joint.mapping.to_joint(max));
+                Ok((name.to_string(), angle.clamp(a.min(b), a.max(b))))
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn close(a: (f32, f32), b: (f32, f32)) -> bool {
+        (a.0 - b.0).abs() < 1e-4 && (a.1 - b.1).abs() < 1e-4
+    }
+
+    fn arm(lengths: &[f32]) -> PlanarChain {
+        PlanarChain::new(lengths.iter().enumerate().map(|(i, &length)| (format!("joint{}", i), length)).collect()).unwrap()
+    }
+
+    #[test]
+    fn forward_accumulates_relative_angles() {
+        let chain = arm(&[2.0, 1.0]);
+        assert!(close(chain.end_effector(&[0.0, 0.0]).unwrap(), (3.0, 0.0)));
+        let ends = chain.forward(&[90.0, -90.0]).unwrap();
+        assert!(close(ends[0], (0.0, 2.0)));
+        assert!(close(ends[1], (1.0, 2.0)));
+        // Folded back onto itself
+        assert!(close(chain.end_effector(&[0.0, 180.0]).unwrap(), (1.0, 0.0)));
+    }
+
+    #[test]
+    fn chains_need_links_and_one_angle_each() {
+        assert!(PlanarChain::new(Vec::new()).is_err());
+        let error = arm(&[1.0, 1.0]).forward(&[0.0]).unwrap_err();
+        assert_eq!(error.to_string(), "Chain has 2 links, got 1 angles");
+    }
+
+    #[test]
+    fn end_effector_at_looks_joints_up_by_name() {
+        let chain = arm(&[1.0, 1.0]);
+        let mut positions = HashMap::from([("joint0".to_string(), 90.0)]);
+        assert_eq!(chain.end_effector_at(&positions).unwrap_err().to_string(), "No position for joint joint1");
+        positions.insert("joint1".to_string(), 0.0);
+        assert!(close(chain.end_effector_at(&positions).unwrap(), (0.0, 2.0)));
+    }
+
+    #[test]
+    fn chains_come_from_the_config() {
+        let config = crate::config::tests::parse(r#"
+            [robot]
+            name = "test"
+            [robot.arms.left]
+            shoulder_pitch = { id = 1 }
+            elbow_pitch = { id = 2 }
+            [chains.left_arm]
+            links = [{ joint = "left_shoulder_pitch", length = 0.1 }, { joint = "left_elbow_pitch", length = 0.08 }]
+        "#);
+        let chain = PlanarChain::named(&config, "left_arm").unwrap();
+        assert_eq!(chain.joints().collect::<Vec<_>>(), ["left_shoulder_pitch", "left_elbow_pitch"]);
+        assert_eq!(PlanarChain::named(&config, "right_arm").unwrap_err().to_string(), "Unknown chain right_arm");
+    }
+
+    #[test]
+    fn inverse_lands_on_the_target_with_either_elbow() {
+        let chain = arm(&[2.0, 1.0]);
+        for elbow in [Elbow::Up, Elbow::Down] {
+            let angles = chain.inverse(1.5, 1.2, elbow).unwrap();
+            assert!(close(chain.end_effector(&angles).unwrap(), (1.5, 1.2)), "{:?} {:?}", elbow, angles);
+        }
+        assert!(chain.inverse(1.5, 1.2, Elbow::Down).unwrap()[1] > 0.0);
+        assert!(chain.inverse(1.5, 1.2, Elbow::Up).unwrap()[1] < 0.0);
+    }
+
+    #[test]
+    fn stretched_and_folded_have_one_solution() {
+        let chain = arm(&[2.0, 1.0]);
+        assert_eq!(chain.inverse(3.0, 0.0, Elbow::Up).unwrap(), [0.0, 0.0]);
+        let [shoulder, elbow] = chain.inverse(0.0, 1.0, Elbow::Down).unwrap();
+        assert!(close((shoulder, elbow.abs()), (90.0, 180.0)), "{} {}", shoulder, elbow);
+    }
+
+    #[test]
+    fn unreachable_targets_are_refused() {
+        let chain = arm(&[2.0, 1.0]);
+        assert!(chain.inverse(3.1, 0.0, Elbow::Up).is_err());
+        assert!(chain.inverse(0.5, 0.0, Elbow::Up).is_err());
+        assert!(chain.inverse(f32::NAN, 0.0, Elbow::Up).is_err());
+        // Just past the edge by float error
+        assert!(chain.inverse(3.0001, 0.0, Elbow::Up).is_ok());
+        let error = arm(&[1.0, 1.0, 1.0]).inverse(1.0, 1.0, Elbow::Up).unwrap_err();
+        assert_eq!(error.to_string(), "Inverse kinematics needs a 2-link chain, this one has 3 links");
+        assert!(arm(&[1.0, 0.0]).inverse(1.0, 0.0, Elbow::Up).is_err());
+    }
+
+    #[cfg(not(feature = "milkv"))]
+    mod bus {
+        use super::*;
+        use crate::hal::ServoRegister;
+        use crate::robot::tests::mock_robot;
+
+        #[test]
+        fn end_effector_follows_the_joints() {
+            let (bus, robot) = mock_robot(&[("shoulder", 1), ("elbow", 2)]);
+            let chain = PlanarChain::new(vec![("shoulder".to_string(), 1.0), ("elbow".to_string(), 1.0)]).unwrap();
+            bus.set_u16(1, ServoRegister::CurrentLocation, 3072);
+            assert!(close(robot.end_effector(&chain).unwrap(), (0.0, 2.0)));
+            bus.remove(2);
+            assert!(robot.end_effector(&chain).is_err());
+        }
+
+        #[test]
+        fn reach_clamps_to_the_calibrated_range() {
+            let (bus, robot) = mock_robot(&[("shoulder", 1), ("elbow", 2)]);
+            let chain = PlanarChain::new(vec![("shoulder".to_string(), 1.0), ("elbow".to_string(), 1.0)]).unwrap();
+            // Shoulder limited to ±90°, elbow uncalibrated
+            bus.set_u16(1, ServoRegister::MinAngleLimit, 1024);
+            bus.set_u16(1, ServoRegister::MaxAngleLimit, 3072);
+            let targets = robot.reach(&chain, 0.0, 2.0, Elbow::Up).unwrap();
+            assert_eq!(targets.iter().map(|(name, _)| name.as_str()).collect::<Vec<_>>(), ["shoulder", "elbow"]);
+            assert!(close((targets[0].1, targets[1].1), (90.0, 0.0)), "{:?}", targets);
+            // Straight back would need 180°
+            let targets = robot.reach(&chain, -2.0, 0.0, Elbow::Up).unwrap();
+            assert_eq!(targets[0].1.abs(), 90.0);
+            assert!(robot.reach(&chain, 3.0, 0.0, Elbow::Up).is_err());
+        }
+    }
+}
@@ -4,6 +4,39 @@ pub mod hal_risc;
 #[cfg(any(target_os = "macos", all(target_os = "linux", not(feature = "milkv"))))]
 pub mod hal_serial;
 
+pub mod servo;
+pub mod units;
+pub mod endian;
+pub mod robot;
+pub mod builder;
+pub mod calibration;
+pub mod commander;
+pub mod monitor;
+pub mod trajectory;
+pub mod alarm;
+pub mod compliance;
+pub mod config;
+pub mod health;
+pub mod watchdog;
+pub mod keyframe;
+pub mod sequence;
+pub mod backlash;
+pub mod defaults;
+pub mod usage;
+pub mod compatibility;
+pub mod buslock;
+pub mod gravity;
+pub mod kinematics;
+pub mod multiturn;
+pub mod safemode;
+pub mod voltage;
+pub mod failsafe;
+pub mod dashboard;
+pub mod preflight;
+pub mod backdrive;
+pub mod mirror;
+pub mod torque;
+
 // Create a public hal module
 pub mod hal {
     use std::os::raw::{c_short, c_uchar, c_ushort, c_uint};
@@ -113,13 +146,30 @@ pub mod hal {
         pub speeds: [c_ushort; MAX_SERVOS],
     }
 
+    // Values written to ServoRegister::OperationMode (0x21)
     #[repr(u8)]
-    #[derive(Debug, Copy, Clone, Serialize, Deserialize)]
+    #[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
     pub enum ServoMode {
         Position = 0,
         ConstantSpeed = 1,
         PWMOpenLoop = 2,
-        StepServo = 3,
+        // In step mode the target location register is a relative step count
+        // (bit 15 = direction) instead of an absolute position
+        Step = 3,
+    }
+
+    impl TryFrom<u8> for ServoMode {
+        type Error = anyhow::Error;
+
+        fn try_from(value: u8) -> anyhow::Result<Self> {
+            match value {
+                0 => Ok(ServoMode::Position),
+                1 => Ok(ServoMode::ConstantSpeed),
+                2 => Ok(ServoMode::PWMOpenLoop),
+                3 => Ok(ServoMode::Step),
+                _ => anyhow::bail!("Unknown servo operation mode: {}", value),
+            }
+        }
     }
 
     #[repr(i32)]
@@ -143,6 +193,40 @@ pub mod hal {
         }
     }
 
+    // Typed failures callers may want to react to, carried inside anyhow::Error
+    // (use `err.downcast_ref::<ServoError>()`)
+    #[derive(Debug, Clone, PartialEq)]
+    pub enum ServoError {
+        // Commanded position isn't being approached, e.g. a mechanical jam
+        Stuck { id: u8, error: u16 },
+        // No reply within the bus timeout
+        Timeout { id: u8 },
+        // A reply arrived but couldn't be decoded
+        MalformedReply { id: u8, reason: String },
+        // Another caller held the bus for longer than the lock timeout
+        BusBusy { timeout: std::time::Duration },
+        // Another process holds the advisory lock on the bus, see BusLock.
+        // `holder` is as written by it, e.g. "telemetry server (pid 1234)"
+        BusClaimed { bus: String, holder: String },
+        // A reply that decoded but can't be right, e.g. a partial frame
+        SuspectReading { id: u8, reason: String },
+    }
+
+    impl std::fmt::Display for ServoError {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            match self {
+                ServoError::Stuck { id, error } => write!(f, "Servo {} is stuck {} ticks from its commanded position", id, error),
+                ServoError::Timeout { id } => write!(f, "Servo {} did not respond", id),
+                ServoError::MalformedReply { id, reason } => write!(f, "Malformed reply from servo {}: {}", id, reason),
+                ServoError::BusBusy { timeout } => write!(f, "Servo bus busy for more than {:?}", timeout),
+                ServoError::BusClaimed { bus, holder } => write!(f, "{} is using the bus {}", holder, bus),
+                ServoError::SuspectReading { id, reason } => write!(f, "Suspect reading from servo {}: {}", id, reason),
+            }
+        }
+    }
+
+    impl std::error::Error for ServoError {}
+
     #[derive(Debug, Clone, Copy, Serialize, Deserialize)]
     pub struct IMUData {
         pub acc_x: f32,
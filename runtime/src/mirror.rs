@@ -0,0 +1,227 @@
+use anyhow::{Result, anyhow, bail};
+use serde::Deserialize;
+use crate::calibration::{Calibration, CalibrationFile, MAX_OFFSET, NO_LIMITS};
+use crate::robot::Robot;
+
+// How a trim on one side of a mirrored pair carries over to the other.
+// Mirrored mounts turn the other way for the same motion, so the default
+// is the opposite sign.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MirrorSign {
+    #[default]
+    Inverted,
+    Same,
+}
+
+impl MirrorSign {
+    // The trim for the other side of the pair
+    pub fn mirror(self, trim: i16) -> i16 {
+        match self {
+            MirrorSign::Inverted => -trim,
+            MirrorSign::Same => trim,
+        }
+    }
+}
+
+// Left/right joints kept symmetric by trim_mirrored, e.g.
+// mirrored = [{ joints = ["left_hip_roll", "right_hip_roll"], sign = "inverted" }]
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct MirrorPair {
+    pub joints: [String; 2],
+    #[serde(default)]
+    pub sign: MirrorSign,
+}
+
+impl Calibration {
+    // Shift the offset by `trim` ticks. The servo reports positions minus its
+    // offset, so the limits move the other way to stay at the same
+    // mechanical stops. Unset limits stay unset.
+    pub fn trimmed(&self, trim: i16) -> Result<Calibration> {
+        let offset = self.offset as i32 + trim as i32;
+        if offset.abs() > MAX_OFFSET as i32 {
+            bail!("Offset {} trimmed by {} is {}, past the largest storable offset {}", self.offset, trim, offset, MAX_OFFSET);
+        }
+        let (min_angle, max_angle) = if (self.min_angle, self.max_angle) == NO_LIMITS {
+            NO_LIMITS
+        } else {
+            let min_angle = self.min_angle as i32 - trim as i32;
+            let max_angle = self.max_angle as i32 - trim as i32;
+            if min_angle < 0 || max_angle > 4095 {
+                bail!("Limits {}-{} trimmed by {} leave 0-4095", self.min_angle, self.max_angle, trim);
+            }
+            (min_angle as i16, max_angle as i16)
+        };
+        Ok(Calibration { offset: offset as i16, min_angle, max_angle })
+    }
+}
+
+impl CalibrationFile {
+    // Trim `name` by `trim` and `mirror` by the mirrored trim. Both are
+    // checked before either entry changes.
+    pub fn trim_pair(&mut self, name: &str, mirror: &str, sign: MirrorSign, trim: i16) -> Result<(Calibration, Calibration)> {
+        let trimmed = |joint: &str, trim: i16| {
+            let entry = self.joints.get(joint).ok_or_else(|| anyhow!("Calibration file has no entry for {}", joint))?;
+            entry.calibration.trimmed(trim).map_err(|e| e.context(format!("Can't trim {}", joint)))
+        };
+        let first = trimmed(name, trim)?;
+        let second = trimmed(mirror, sign.mirror(trim))?;
+        self.joints.get_mut(name).unwrap().calibration = first;
+        self.joints.get_mut(mirror).unwrap().calibration = second;
+        Ok((first, second))
+    }
+}
+
+impl Robot {
+    // The joint mirrored with `name` and the sign between them, if any
+    pub fn mirror_of(&self, name: &str) -> Option<(&str, MirrorSign)> {
+        self.mirrored().iter().find_map(|pair| match &pair.joints {
+            [a, b] if a == name => Some((b.as_str(), pair.sign)),
+            [a, b] if b == name => Some((a.as_str(), pair.sign)),
+            _ => None,
+        })
+    }
+
+    // Trim the offset of `name` by `trim` ticks and its mirrored joint by
+    // the mirrored trim, writing both servos and both entries of `file`,
+    // which the caller saves. If the second servo can't be written the
+    // first is put back, so the pair is never left half trimmed.
+    pub fn trim_mirrored(&self, name: &str, trim: i16, file: &mut CalibrationFile) -> Result<[(String, Calibration); 2]> {
+        let Some((mirror, sign)) = self.mirror_of(name) else {
+            bail!("{} is not in a mirrored pair, see robot.mirrored in the config", name);
+        };
+        let mirror = mirror.to_string();
+        let first = self.joint(name)?.id;
+        let second = self.joint(&mirror)?.id;
+
+        let mut trimmed = file.clone();
+        let (first_calibration, second_calibration) = trimmed.trim_pair(name, &mirror, sign, trim)?;
+        let previous = file.joints[name].calibration;
+        self.servo().commit_calibration(first, &first_calibration)?;
+        if let Err(e) = self.servo().commit_calibration(second, &second_calibration) {
+            return match self.servo().commit_calibration(first, &previous) {
+                Ok(()) => Err(e.context(format!("Failed to trim {}, {} restored", mirror, name))),
+                Err(rollback) => Err(e.context(format!("Failed to trim {} and to restore {} ({}), the pair is out of symmetry", mirror, name, rollback))),
+            };
+        }
+        *file = trimmed;
+        Ok([(name.to_string(), first_calibration), (mirror, second_calibration)])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::calibration::JointCalibration;
+
+    fn calibration(offset: i16, min_angle: i16, max_angle: i16) -> Calibration {
+        Calibration { offset, min_angle, max_angle }
+    }
+
+    fn file(entries: &[(&str, u8, Calibration)]) -> CalibrationFile {
+        let joints = entries.iter()
+            .map(|&(name, id, calibration)| (name.to_string(), JointCalibration { id, calibration, confidence: None }))
+            .collect();
+        CalibrationFile { joints }
+    }
+
+    #[test]
+    fn signs_mirror_the_trim() {
+        assert_eq!(MirrorSign::Inverted.mirror(12), -12);
+        assert_eq!(MirrorSign::Same.mirror(12), 12);
+        assert_eq!(MirrorSign::default(), MirrorSign::Inverted);
+    }
+
+    #[test]
+    fn trimming_moves_the_limits_the_other_way() {
+        assert_eq!(calibration(40, 1000, 3000).trimmed(10).unwrap(), calibration(50, 990, 2990));
+        assert_eq!(calibration(40, 1000, 3000).trimmed(-50).unwrap(), calibration(-10, 1050, 3050));
+        let unlimited = calibration(0, NO_LIMITS.0, NO_LIMITS.1);
+        assert_eq!(unlimited.trimmed(25).unwrap(), calibration(25, NO_LIMITS.0, NO_LIMITS.1));
+    }
+
+    #[test]
+    fn trims_past_the_register_or_the_range_are_refused() {
+        assert!(calibration(MAX_OFFSET - 5, NO_LIMITS.0, NO_LIMITS.1).trimmed(6).is_err());
+        assert!(calibration(-MAX_OFFSET, NO_LIMITS.0, NO_LIMITS.1).trimmed(-1).is_err());
+        assert!(calibration(0, 10, 3000).trimmed(11).is_err());
+        assert!(calibration(0, 1000, 4090).trimmed(-6).is_err());
+    }
+
+    #[test]
+    fn pairs_are_trimmed_together() {
+        let mut calibrations = file(&[("left", 1, calibration(40, 1000, 3000)), ("right", 2, calibration(-20, 1100, 3100))]);
+        let (left, right) = calibrations.trim_pair("left", "right", MirrorSign::Inverted, 8).unwrap();
+        assert_eq!((left, right), (calibration(48, 992, 2992), calibration(-28, 1108, 3108)));
+        assert_eq!(calibrations.joints["left"].calibration, left);
+        assert_eq!(calibrations.joints["right"].calibration, right);
+    }
+
+    #[test]
+    fn a_pair_that_cant_be_trimmed_is_left_alone() {
+        let original = file(&[("left", 1, calibration(40, 1000, 3000)), ("right", 2, calibration(0, 0, 3000))]);
+        let mut calibrations = original.clone();
+        // Right would go below 0
+        let error = calibrations.trim_pair("left", "right", MirrorSign::Same, 8).unwrap_err();
+        assert!(format!("{:#}", error).starts_with("Can't trim right"));
+        assert_eq!(calibrations.joints, original.joints);
+        assert!(calibrations.trim_pair("left", "neck", MirrorSign::Same, 8).is_err());
+    }
+
+    #[cfg(not(feature = "milkv"))]
+    mod bus {
+        use super::*;
+        use crate::hal::ServoRegister;
+        use crate::robot::tests::mock_robot;
+
+        fn pair(sign: MirrorSign) -> Vec<MirrorPair> {
+            vec![MirrorPair { joints: ["left".to_string(), "right".to_string()], sign }]
+        }
+
+        #[test]
+        fn mirror_is_found_from_either_side() {
+            let (_bus, robot) = mock_robot(&[("left", 1), ("right", 2), ("neck", 3)]);
+            let robot = robot.with_mirrored(pair(MirrorSign::Same));
+            assert_eq!(robot.mirror_of("left"), Some(("right", MirrorSign::Same)));
+            assert_eq!(robot.mirror_of("right"), Some(("left", MirrorSign::Same)));
+            assert_eq!(robot.mirror_of("neck"), None);
+        }
+
+        #[test]
+        fn trim_writes_both_servos_and_the_file() {
+            let (_bus, robot) = mock_robot(&[("left", 1), ("right", 2)]);
+            let robot = robot.with_mirrored(pair(MirrorSign::Inverted));
+            let mut calibrations = file(&[("left", 1, calibration(0, 1000, 3000)), ("right", 2, calibration(0, 1000, 3000))]);
+            let trimmed = robot.trim_mirrored("right", 10, &mut calibrations).unwrap();
+            assert_eq!(trimmed, [
+                ("right".to_string(), calibration(10, 990, 2990)),
+                ("left".to_string(), calibration(-10, 1010, 3010)),
+            ]);
+            assert_eq!(robot.servo().read_calibration(2).unwrap(), calibration(10, 990, 2990));
+            assert_eq!(robot.servo().read_calibration(1).unwrap(), calibration(-10, 1010, 3010));
+            assert_eq!(calibrations.joints["left"].calibration, calibration(-10, 1010, 3010));
+        }
+
+        #[test]
+        fn failed_second_write_restores_the_first() {
+            let (bus, robot) = mock_robot(&[("left", 1), ("right", 2)]);
+            let robot = robot.with_mirrored(pair(MirrorSign::Inverted));
+            robot.servo().commit_calibration(1, &calibration(0, 1000, 3000)).unwrap();
+            let original = file(&[("left", 1, calibration(0, 1000, 3000)), ("right", 2, calibration(0, 1000, 3000))]);
+            let mut calibrations = original.clone();
+            bus.refuse(2, ServoRegister::MaxAngleLimit);
+            let error = robot.trim_mirrored("left", 10, &mut calibrations).unwrap_err();
+            assert!(format!("{:#}", error).starts_with("Failed to trim right, left restored"), "{:#}", error);
+            assert_eq!(robot.servo().read_calibration(1).unwrap(), calibration(0, 1000, 3000));
+            assert_eq!(calibrations.joints, original.joints);
+        }
+
+        #[test]
+        fn unpaired_joints_cant_be_trimmed() {
+            let (_bus, robot) = mock_robot(&[("left", 1), ("right", 2)]);
+            let mut calibrations = file(&[("left", 1, calibration(0, 1000, 3000))]);
+            assert!(robot.trim_mirrored("left", 10, &mut calibrations).is_err());
+        }
+    }
+}
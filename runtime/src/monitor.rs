@@ -0,0 +1,193 @@
+use anyhow::Result;
+use std::collections::BTreeMap;
+use std::time::{Duration, Instant};
+use crate::hal::{Servo, ServoError};
+
+#[derive(Debug, Clone, Copy)]
+pub struct StuckMonitorConfig {
+    // How long a joint may go without progress before it's flagged
+    pub window: Duration,
+    // Tracking errors at or below this many ticks are considered on target
+    pub error_threshold: u16,
+    // Movement (or error reduction) in ticks that counts as progress
+    pub min_progress: u16,
+}
+
+impl Default for StuckMonitorConfig {
+    fn default() -> Self {
+        Self {
+            window: Duration::from_millis(500),
+            error_threshold: 20,
+            min_progress: 5,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Progress {
+    error: u16,
+    measured: i16,
+    since: Instant,
+}
+
+// Catches a jammed joint that neither current nor status flags reveal
+// promptly: a joint is stuck once it is off target and has neither moved nor
+// closed in on its commanded position for a whole window.
+#[derive(Debug)]
+pub struct StuckMonitor {
+    config: StuckMonitorConfig,
+    joints: BTreeMap<u8, Progress>,
+}
+
+impl StuckMonitor {
+    pub fn new(config: StuckMonitorConfig) -> Self {
+        Self { config, joints: BTreeMap::new() }
+    }
+
+    pub fn config(&self) -> &StuckMonitorConfig {
+        &self.config
+    }
+
+    pub fn observe(&mut self, id: u8, commanded: i16, measured: i16, now: Instant) -> Result<()> {
+        let error = (commanded as i32 - measured as i32).unsigned_abs().min(u16::MAX as u32) as u16;
+        if error <= self.config.error_threshold {
+            self.joints.remove(&id);
+            return Ok(());
+        }
+
+        let progress = self.joints.entry(id).or_insert(Progress { error, measured, since: now });
+        let moved = (measured as i32 - progress.measured as i32).unsigned_abs();
+        let closed_in = progress.error.saturating_sub(error);
+        if moved >= self.config.min_progress as u32 || closed_in >= self.config.min_progress {
+            *progress = Progress { error, measured, since: now };
+            return Ok(());
+        }
+
+        if now.duration_since(progress.since) >= self.config.window {
+            return Err(ServoError::Stuck { id, error }.into());
+        }
+        Ok(())
+    }
+
+    // Read the present position of every commanded joint and observe it
+    pub fn check(&mut self, servo: &Servo, commanded: &[(u8, i16)]) -> Result<()> {
+        let now = Instant::now();
+        for &(id, target) in commanded {
+            let measured = servo.read_position(id)?;
+            self.observe(id, target, measured, now)?;
+        }
+        Ok(())
+    }
+
+    pub fn reset(&mut self, id: u8) {
+        self.joints.remove(&id);
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MotionEstimate {
+    // ticks/s², None until two speed samples have been seen
+    pub acceleration: Option<f32>,
+    // ticks/s³, None until three speed samples have been seen
+    pub jerk: Option<f32>,
+}
+
+// Acceleration and jerk for tuning, by differencing successive present speed
+// readings, for models where Servo::read_present_acceleration returns None
+// (every STS model so far). Noisy at high sample rates, the speed register
+// only changes in whole ticks/s.
+#[derive(Debug, Default)]
+pub struct AccelerationEstimator {
+    last: Option<(i16, Instant)>,
+    acceleration: Option<f32>,
+}
+
+impl AccelerationEstimator {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // `speed` as returned by ServoInfo::speed, sampled at `at`
+    pub fn update(&mut self, speed: i16, at: Instant) -> MotionEstimate {
+        let mut estimate = MotionEstimate { acceleration: None, jerk: None };
+        if let Some((last_speed, last_at)) = self.last {
+            let dt = at.saturating_duration_since(last_at).as_secs_f32();
+            if dt <= 0.0 {
+                return MotionEstimate { acceleration: self.acceleration, jerk: None };
+            }
+            let acceleration = (speed as f32 - last_speed as f32) / dt;
+            estimate.acceleration = Some(acceleration);
+            estimate.jerk = self.acceleration.map(|last| (acceleration - last) / dt);
+            self.acceleration = Some(acceleration);
+        }
+        self.last = Some((speed, at));
+        estimate
+    }
+
+    pub fn reset(&mut self) {
+        *self = Self::default();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stuck_id(result: Result<()>) -> Option<u8> {
+        match result.err()?.downcast_ref::<ServoError>() {
+            Some(ServoError::Stuck { id, .. }) => Some(*id),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn joint_without_progress_is_stuck_after_the_window() {
+        let mut monitor = StuckMonitor::new(StuckMonitorConfig::default());
+        let start = Instant::now();
+        monitor.observe(1, 2500, 2048, start).unwrap();
+        monitor.observe(1, 2500, 2050, start + Duration::from_millis(300)).unwrap();
+        assert_eq!(stuck_id(monitor.observe(1, 2500, 2051, start + Duration::from_millis(500))), Some(1));
+    }
+
+    #[test]
+    fn progress_restarts_the_window() {
+        let mut monitor = StuckMonitor::new(StuckMonitorConfig::default());
+        let start = Instant::now();
+        monitor.observe(1, 2500, 2048, start).unwrap();
+        monitor.observe(1, 2500, 2100, start + Duration::from_millis(400)).unwrap();
+        monitor.observe(1, 2500, 2101, start + Duration::from_millis(800)).unwrap();
+        assert_eq!(stuck_id(monitor.observe(1, 2500, 2101, start + Duration::from_millis(900))), Some(1));
+    }
+
+    #[test]
+    fn joint_on_target_is_never_stuck() {
+        let mut monitor = StuckMonitor::new(StuckMonitorConfig::default());
+        let start = Instant::now();
+        for ms in [0, 500, 1000] {
+            monitor.observe(1, 2048, 2060, start + Duration::from_millis(ms)).unwrap();
+        }
+    }
+
+    #[test]
+    fn acceleration_then_jerk_from_successive_speeds() {
+        let mut estimator = AccelerationEstimator::new();
+        let start = Instant::now();
+        assert_eq!(estimator.update(0, start), MotionEstimate { acceleration: None, jerk: None });
+        assert_eq!(estimator.update(100, start + Duration::from_millis(100)), MotionEstimate { acceleration: Some(1000.0), jerk: None });
+        let estimate = estimator.update(300, start + Duration::from_millis(200));
+        assert_eq!(estimate.acceleration, Some(2000.0));
+        assert!((estimate.jerk.unwrap() - 10000.0).abs() < 1.0, "{:?}", estimate);
+    }
+
+    #[test]
+    fn repeated_timestamp_keeps_the_last_acceleration() {
+        let mut estimator = AccelerationEstimator::new();
+        let start = Instant::now();
+        estimator.update(0, start);
+        estimator.update(-50, start + Duration::from_millis(50));
+        let estimate = estimator.update(-80, start + Duration::from_millis(50));
+        assert_eq!(estimate, MotionEstimate { acceleration: Some(-1000.0), jerk: None });
+        estimator.reset();
+        assert_eq!(estimator.update(-80, start).acceleration, None);
+    }
+}
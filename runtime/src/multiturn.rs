@@ -0,0 +1,162 @@
+use anyhow::Result;
+use crate::robot::Robot;
+use crate::units::TICKS_PER_TURN;
+
+// Signed rotation accumulated over any number of turns from single-turn
+// positions (0-4095). A jump of more than half a turn between two updates
+// is taken as a wrap through 0, so the tracker has to be updated at least
+// once per half turn of travel: at the STS3215's top speed of about 3000
+// ticks/s that is roughly every 0.6 s, and a missed wrap silently loses or
+// gains a whole turn.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MultiTurnTracker {
+    start: i16,
+    last: i16,
+    turns: i64,
+}
+
+impl MultiTurnTracker {
+    pub fn new(position: i16) -> Self {
+        Self { start: position, last: position, turns: 0 }
+    }
+
+    // Ticks turned since the tracker started, positive in the direction of
+    // increasing position
+    pub fn update(&mut self, position: i16) -> i64 {
+        let delta = position as i32 - self.last as i32;
+        if delta > TICKS_PER_TURN / 2 {
+            self.turns -= 1;
+        } else if delta < -TICKS_PER_TURN / 2 {
+            self.turns += 1;
+        }
+        self.last = position;
+        self.ticks()
+    }
+
+    // Make the last position seen the new origin. Wraps are still counted
+    // from there, so tracking carries on across the reset.
+    pub fn reset(&mut self) {
+        self.start = self.last;
+        self.turns = 0;
+    }
+
+    pub fn ticks(&self) -> i64 {
+        self.turns * TICKS_PER_TURN as i64 + self.last as i64 - self.start as i64
+    }
+
+    pub fn turns(&self) -> f64 {
+        self.ticks() as f64 / TICKS_PER_TURN as f64
+    }
+}
+
+impl Robot {
+    // Ticks servo `id` has turned since the first call for it, or since
+    // reset_multiturn, e.g. for wheel odometry. See MultiTurnTracker for how
+    // often this has to be polled.
+    pub fn read_position_multiturn(&self, id: u8) -> Result<i64> {
+        let position = self.servo().read_position(id)?;
+        let mut trackers = self.multiturn.lock().unwrap_or_else(|e| e.into_inner());
+        Ok(trackers.entry(id).or_insert_with(|| MultiTurnTracker::new(position)).update(position))
+    }
+
+    // Zero the count at the position last read by read_position_multiturn,
+    // e.g. at a known reference to set the odometry origin. Only the
+    // accumulator kept here is reset: nothing is written to the servo, and
+    // its own position and PositionCorrection are untouched. A servo that
+    // was never read starts from 0 at its first read anyway.
+    pub fn reset_multiturn(&self, id: u8) {
+        if let Some(tracker) = self.multiturn.lock().unwrap_or_else(|e| e.into_inner()).get_mut(&id) {
+            tracker.reset();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn wraps_through_zero_count_whole_turns() {
+        let mut tracker = MultiTurnTracker::new(3000);
+        assert_eq!(tracker.update(4000), 1000);
+        // 4000 -> 100 is 196 ticks forward through the wrap
+        assert_eq!(tracker.update(100), 1196);
+        tracker.update(2000);
+        assert_eq!(tracker.update(3000), 4096);
+        assert_eq!(tracker.turns(), 1.0);
+        // And back the other way
+        tracker.update(1500);
+        assert_eq!(tracker.update(100), 1196);
+        assert_eq!(tracker.update(4000), 1000);
+        assert_eq!(tracker.update(2500), -500);
+        assert_eq!(tracker.turns(), -500.0 / 4096.0);
+    }
+
+    #[test]
+    fn reset_keeps_counting_wraps_from_there() {
+        let mut tracker = MultiTurnTracker::new(2048);
+        tracker.update(4000);
+        tracker.reset();
+        assert_eq!(tracker.ticks(), 0);
+        assert_eq!(tracker.update(50), 146);
+        assert_eq!(tracker.update(3904), -96);
+    }
+
+    #[test]
+    fn reset_drops_whole_turns_too() {
+        let mut tracker = MultiTurnTracker::new(0);
+        for position in [2000, 4000, 1900, 3800, 1700] {
+            tracker.update(position);
+        }
+        assert_eq!(tracker.ticks(), 2 * 4096 + 1700);
+        tracker.reset();
+        assert_eq!((tracker.ticks(), tracker.turns()), (0, 0.0));
+        assert_eq!(tracker.update(1600), -100);
+    }
+
+    #[test]
+    fn reset_before_any_move_is_a_no_op() {
+        let mut tracker = MultiTurnTracker::new(1234);
+        tracker.reset();
+        assert_eq!(tracker, MultiTurnTracker::new(1234));
+    }
+
+    #[cfg(not(feature = "milkv"))]
+    mod bus {
+        use crate::hal::ServoRegister;
+        use crate::robot::tests::mock_robot;
+
+        #[test]
+        fn robot_tracks_each_servo_from_its_first_read() {
+            let (bus, robot) = mock_robot(&[("left_wheel", 1), ("right_wheel", 2)]);
+            assert_eq!(robot.read_position_multiturn(1).unwrap(), 0);
+            for position in [3500, 900, 2048] {
+                bus.set_u16(1, ServoRegister::CurrentLocation, position);
+                robot.read_position_multiturn(1).unwrap();
+            }
+            assert_eq!(robot.read_position_multiturn(1).unwrap(), 4096);
+            assert_eq!(robot.read_position_multiturn(2).unwrap(), 0);
+
+            robot.reset_multiturn(1);
+            assert_eq!(robot.read_position_multiturn(1).unwrap(), 0);
+            // Nothing written, the servo keeps its own position
+            assert!(bus.writes().is_empty());
+            robot.reset_multiturn(3);
+            bus.remove(2);
+            assert!(robot.read_position_multiturn(2).is_err());
+        }
+
+        #[test]
+        fn robot_reset_keeps_counting_wraps() {
+            let (bus, robot) = mock_robot(&[("wheel", 1)]);
+            bus.set_u16(1, ServoRegister::CurrentLocation, 4000);
+            robot.read_position_multiturn(1).unwrap();
+            robot.reset_multiturn(1);
+            for position in [1000, 3000, 4000] {
+                bus.set_u16(1, ServoRegister::CurrentLocation, position);
+                robot.read_position_multiturn(1).unwrap();
+            }
+            assert_eq!(robot.read_position_multiturn(1).unwrap(), 4096);
+        }
+    }
+}
@@ -0,0 +1,142 @@
+use anyhow::Result;
+use std::fmt;
+use std::ops::RangeInclusive;
+use crate::robot::{Joint, Robot};
+
+// IDs preflight pings, the same range the telemetry server's Scan covers.
+// Every unused ID costs a reply timeout.
+pub const PREFLIGHT_IDS: RangeInclusive<u8> = 0..=99;
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PreflightReport {
+    // Configured joints that didn't answer, by (name, id)
+    pub missing: Vec<(String, u8)>,
+    // IDs that answered but aren't any configured joint
+    pub unexpected: Vec<u8>,
+}
+
+impl PreflightReport {
+    // Joints in config order, `found` as the IDs that answered
+    pub fn compare(joints: &[Joint], found: &[u8]) -> Self {
+        let missing = joints.iter()
+            .filter(|joint| !found.contains(&joint.id))
+            .map(|joint| (joint.name.clone(), joint.id))
+            .collect();
+        let mut unexpected: Vec<u8> = found.iter()
+            .copied()
+            .filter(|&id| !joints.iter().any(|joint| joint.id == id))
+            .collect();
+        unexpected.sort();
+        unexpected.dedup();
+        Self { missing, unexpected }
+    }
+
+    pub fn is_complete(&self) -> bool {
+        self.missing.is_empty()
+    }
+}
+
+impl fmt::Display for PreflightReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let missing: Vec<String> = self.missing.iter().map(|(name, id)| format!("{} (ID {})", name, id)).collect();
+        if missing.is_empty() {
+            write!(f, "all joints present")?;
+        } else {
+            write!(f, "joints not responding: {}", missing.join(", "))?;
+        }
+        if !self.unexpected.is_empty() {
+            write!(f, "; unexpected servos at IDs {:?}", self.unexpected)?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PreflightFailed(pub PreflightReport);
+
+impl fmt::Display for PreflightFailed {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Preflight failed, {}. Check the cables before moving", self.0)
+    }
+}
+
+impl std::error::Error for PreflightFailed {}
+
+impl Robot {
+    // Before any motion: pings every ID in PREFLIGHT_IDS and fails with
+    // PreflightFailed unless every configured joint answers, so a
+    // disconnected cable is caught before a phantom joint is commanded.
+    // Servos that answer but aren't in the config don't fail the check,
+    // they're in the returned report for the caller to warn about.
+    pub fn preflight(&self) -> Result<PreflightReport> {
+        let mut found = Vec::new();
+        for id in PREFLIGHT_IDS {
+            if self.servo().scan(id)? {
+                found.push(id);
+            }
+        }
+        let report = PreflightReport::compare(self.joints(), &found);
+        if !report.is_complete() {
+            return Err(PreflightFailed(report).into());
+        }
+        Ok(report)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::robot::tests::joint;
+
+    #[test]
+    fn compare_lists_missing_joints_in_config_order() {
+        let joints = [joint("left", 3), joint("right", 1), joint("neck", 2)];
+        let report = PreflightReport::compare(&joints, &[1]);
+        assert_eq!(report.missing, vec![("left".to_string(), 3), ("neck".to_string(), 2)]);
+        assert!(report.unexpected.is_empty());
+        assert!(!report.is_complete());
+    }
+
+    #[test]
+    fn unexpected_servos_are_sorted_once_and_dont_fail() {
+        let joints = [joint("left", 1)];
+        let report = PreflightReport::compare(&joints, &[9, 1, 4, 9]);
+        assert_eq!(report.unexpected, vec![4, 9]);
+        assert!(report.is_complete());
+    }
+
+    #[test]
+    fn report_reads_as_a_sentence() {
+        let joints = [joint("left", 1), joint("right", 2)];
+        assert_eq!(PreflightReport::compare(&joints, &[1, 2]).to_string(), "all joints present");
+        assert_eq!(PreflightReport::compare(&joints, &[2, 7]).to_string(), "joints not responding: left (ID 1); unexpected servos at IDs [7]");
+        assert_eq!(
+            PreflightFailed(PreflightReport::compare(&joints, &[])).to_string(),
+            "Preflight failed, joints not responding: left (ID 1), right (ID 2). Check the cables before moving",
+        );
+    }
+
+    #[cfg(not(feature = "milkv"))]
+    mod bus {
+        use super::*;
+        use crate::hal::mock::MockBus;
+        use crate::hal::Servo;
+        use std::sync::Arc;
+
+        #[test]
+        fn preflight_passes_with_every_joint_present() {
+            let bus = MockBus::new(&[1, 2, 42]);
+            let robot = Robot::new(Arc::new(Servo::mock(&bus)), vec![joint("left", 1), joint("right", 2)]);
+            assert_eq!(robot.preflight().unwrap().unexpected, vec![42]);
+        }
+
+        #[test]
+        fn preflight_fails_on_a_missing_joint() {
+            let bus = MockBus::new(&[1]);
+            let robot = Robot::new(Arc::new(Servo::mock(&bus)), vec![joint("left", 1), joint("right", 2)]);
+            let error = robot.preflight().unwrap_err();
+            let failed = error.downcast_ref::<PreflightFailed>().unwrap();
+            assert_eq!(failed.0.missing, vec![("right".to_string(), 2)]);
+        }
+    }
+}
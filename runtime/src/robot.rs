@@ -0,0 +1,1108 @@
+use anyhow::{Result, anyhow, bail};
+use std::collections::{BTreeMap, HashMap};
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, sleep};
+use std::time::{Duration, Instant};
+use tokio::sync::broadcast;
+use crate::calibration::{Calibration, CalibrationFile, NO_LIMITS};
+use crate::config::{load_config, CalibrationProfile, Config, JointMapping};
+use crate::hal::{Servo, ServoInfo, ServoMode, ServoRegister, TorqueMode};
+use crate::servo::{baud_register_value, goal_time_ms, ModelScaling, SettleConfig, CENTER_POSITION};
+use crate::sequence::wait_while_running;
+use crate::trajectory::{MotionLimits, Trajectory};
+use crate::units::{deg_to_ticks, deg_to_ticks_within, ticks_to_deg, Rounding, TICKS_PER_TURN};
+use crate::multiturn::MultiTurnTracker;
+use crate::safemode::SafeMode;
+use crate::failsafe::BusErrorPolicy;
+use crate::mirror::MirrorPair;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Joint {
+    pub name: String,
+    pub id: u8,
+    pub mapping: JointMapping,
+    // In joint degrees/s and degrees/s², see move_group_profiled
+    pub max_velocity: Option<f32>,
+    pub max_acceleration: Option<f32>,
+    // See Robot::calibration_params
+    pub calibration_profile: Option<String>,
+    // MaxTorque register value, see Robot::apply_torque_limits
+    pub max_torque: Option<u16>,
+}
+
+impl Joint {
+    // Joint angle in degrees for a raw position in ticks
+    pub fn degrees(&self, position: i16) -> f32 {
+        self.mapping.to_joint(ticks_to_deg(position as i32 - CENTER_POSITION as i32))
+    }
+
+    // `limits` lowered to this joint's own, converted to servo ticks
+    pub fn motion_limits(&self, limits: MotionLimits) -> MotionLimits {
+        let to_ticks = |rate: Option<f32>| rate.map_or(f32::INFINITY, |rate| {
+            self.mapping.rate_to_servo(rate) * TICKS_PER_TURN as f32 / 360.0
+        });
+        limits.min(MotionLimits {
+            max_velocity: to_ticks(self.max_velocity),
+            max_acceleration: to_ticks(self.max_acceleration),
+        })
+    }
+}
+
+// What Robot::positions does about joints that don't reply
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum MissingJoints {
+    // Left out of the map
+    #[default]
+    Omit,
+    Error,
+}
+
+// Snapshots a lagging subscriber can fall behind by before losing the oldest
+const SUBSCRIBE_CAPACITY: usize = 16;
+
+#[derive(Debug, Clone)]
+pub struct JointState {
+    pub name: String,
+    pub id: u8,
+    pub info: ServoInfo,
+}
+
+#[derive(Debug, Clone)]
+pub struct RobotState {
+    pub timestamp: Instant,
+    pub joints: Vec<JointState>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct JointTemperature {
+    pub name: String,
+    pub id: u8,
+    pub celsius: f32,
+}
+
+// Named view over the servo bus, built from config/[robot-name].toml
+#[derive(Debug)]
+pub struct Robot {
+    servo: Arc<Servo>,
+    joints: Vec<Joint>,
+    // Pairs of servos mechanically coupled through a tendon
+    couplings: Vec<(u8, u8)>,
+    // Left/right pairs, see trim_mirrored
+    mirrored: Vec<MirrorPair>,
+    // Groups of servos homed one group after another, empty homes all at once
+    homing_order: Vec<Vec<u8>>,
+    // Known calibration, saves reading it back from each servo
+    calibration: Option<CalibrationFile>,
+    // Cleared by an emergency stop, see RobotBuilder
+    running: Arc<AtomicBool>,
+    // Refuse to move joints that were never calibrated, see Calibration::is_calibrated
+    require_calibration: bool,
+    // Degrees to ticks in move_group, see deg_to_ticks_within
+    rounding: Rounding,
+    // By name, as referenced by Joint::calibration_profile
+    calibration_profiles: BTreeMap<String, CalibrationProfile>,
+    // By servo ID, see read_position_multiturn
+    pub(crate) multiturn: Mutex<HashMap<u8, MultiTurnTracker>>,
+    // Caps every move when set, see SafeMode
+    safe_mode: Option<SafeMode>,
+    on_bus_error: BusErrorPolicy,
+    // Last goals sent to each servo, see guard_bus
+    pub(crate) last_goals: Mutex<HashMap<u8, i16>>,
+    // Model of each servo by ID, read once, see health
+    pub(crate) models: Mutex<HashMap<u8, u16>>,
+    // From robot.current_scale, see ModelScaling::current
+    current_scaling: ModelScaling,
+}
+
+impl Robot {
+    pub fn new(servo: Arc<Servo>, joints: Vec<Joint>) -> Self {
+        Self {
+            servo,
+            joints,
+            couplings: Vec::new(),
+            mirrored: Vec::new(),
+            homing_order: Vec::new(),
+            calibration: None,
+            running: Arc::new(AtomicBool::new(true)),
+            require_calibration: true,
+            rounding: Rounding::default(),
+            calibration_profiles: BTreeMap::new(),
+            multiturn: Mutex::new(HashMap::new()),
+            safe_mode: None,
+            on_bus_error: BusErrorPolicy::default(),
+            last_goals: Mutex::new(HashMap::new()),
+            models: Mutex::new(HashMap::new()),
+            current_scaling: ModelScaling::current(),
+        }
+    }
+
+    pub fn with_couplings(mut self, couplings: Vec<(u8, u8)>) -> Self {
+        self.couplings = couplings;
+        self
+    }
+
+    pub fn with_mirrored(mut self, mirrored: Vec<MirrorPair>) -> Self {
+        self.mirrored = mirrored;
+        self
+    }
+
+    pub fn with_homing_order(mut self, homing_order: Vec<Vec<u8>>) -> Self {
+        self.homing_order = homing_order;
+        self
+    }
+
+    // `calibration` is expected to match the joints, see check_calibration_file
+    pub fn with_calibration(mut self, calibration: CalibrationFile) -> Self {
+        self.calibration = Some(calibration);
+        self
+    }
+
+    pub fn with_running(mut self, running: Arc<AtomicBool>) -> Self {
+        self.running = running;
+        self
+    }
+
+    // False lets move_group drive uncalibrated joints, whose absolute angles
+    // mean nothing. For bring-up and experts only.
+    pub fn with_require_calibration(mut self, require_calibration: bool) -> Self {
+        self.require_calibration = require_calibration;
+        self
+    }
+
+    pub fn with_rounding(mut self, rounding: Rounding) -> Self {
+        self.rounding = rounding;
+        self
+    }
+
+    pub fn with_calibration_profiles(mut self, profiles: BTreeMap<String, CalibrationProfile>) -> Self {
+        self.calibration_profiles = profiles;
+        self
+    }
+
+    pub fn with_safe_mode(mut self, safe_mode: Option<SafeMode>) -> Self {
+        self.safe_mode = safe_mode;
+        self
+    }
+
+    pub fn with_bus_error_policy(mut self, policy: BusErrorPolicy) -> Self {
+        self.on_bus_error = policy;
+        self
+    }
+
+    pub fn bus_error_policy(&self) -> BusErrorPolicy {
+        self.on_bus_error
+    }
+
+    pub fn with_current_scaling(mut self, current_scaling: ModelScaling) -> Self {
+        self.current_scaling = current_scaling;
+        self
+    }
+
+    pub fn current_scaling(&self) -> &ModelScaling {
+        &self.current_scaling
+    }
+
+    pub fn safe_mode(&self) -> Option<&SafeMode> {
+        self.safe_mode.as_ref()
+    }
+
+    pub fn calibration_profile(&self, name: &str) -> Option<&CalibrationProfile> {
+        self.calibration_profiles.get(name)
+    }
+
+    pub fn from_config<P: AsRef<Path>>(servo: Arc<Servo>, path: P) -> Result<Self> {
+        Self::from_loaded_config(servo, &load_config(path)?)
+    }
+
+    // `config` is expected to be validated, as returned by load_config
+    pub fn from_loaded_config(servo: Arc<Servo>, config: &Config) -> Result<Self> {
+        // Joints are named "<side>_<joint>", e.g. left_hip_roll
+        let mut joints: Vec<Joint> = config.robot.joints().into_iter()
+            .map(|(name, _, joint)| Joint {
+                name,
+                id: joint.id,
+                mapping: joint.mapping,
+                max_velocity: joint.max_velocity,
+                max_acceleration: joint.max_acceleration,
+                calibration_profile: joint.calibration_profile.clone(),
+                max_torque: joint.max_torque,
+            })
+            .collect();
+        joints.sort_by_key(|joint| joint.id);
+
+        let robot = Self::new(servo, joints);
+        let couplings = config.robot.coupled.iter()
+            .map(|[a, b]| Ok((robot.joint(a)?.id, robot.joint(b)?.id)))
+            .collect::<Result<Vec<_>>>()?;
+        let homing_order = config.homing.order.iter()
+            .map(|group| group.iter().map(|name| Ok(robot.joint(name)?.id)).collect())
+            .collect::<Result<Vec<_>>>()?;
+        Ok(robot
+            .with_couplings(couplings)
+            .with_mirrored(config.robot.mirrored.clone())
+            .with_homing_order(homing_order)
+            .with_rounding(config.robot.rounding)
+            .with_bus_error_policy(config.robot.on_bus_error)
+            .with_current_scaling(config.robot.current_scaling()?)
+            .with_calibration_profiles(config.calibration_profiles.clone())
+            .with_safe_mode(Some(config.safe_mode).filter(|safe_mode| safe_mode.enabled)))
+    }
+
+    pub fn servo(&self) -> &Arc<Servo> {
+        &self.servo
+    }
+
+    pub fn mirrored(&self) -> &[MirrorPair] {
+        &self.mirrored
+    }
+
+    // Shared with long-running operations such as calibration and sequences,
+    // which stop once it's cleared
+    pub fn running(&self) -> &Arc<AtomicBool> {
+        &self.running
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.running.load(Ordering::SeqCst)
+    }
+
+    // From the loaded calibration if there is one, otherwise read from the servo
+    pub fn calibration_of(&self, joint: &Joint) -> Result<Calibration> {
+        match self.calibration.as_ref().and_then(|file| file.joints.get(&joint.name)) {
+            Some(entry) => Ok(entry.calibration),
+            None => self.servo.read_calibration(joint.id),
+        }
+    }
+
+    pub fn joints(&self) -> &[Joint] {
+        &self.joints
+    }
+
+    pub fn uncalibrated_joints(&self) -> Result<Vec<&Joint>> {
+        let mut uncalibrated = Vec::new();
+        for joint in &self.joints {
+            if !self.calibration_of(joint)?.is_calibrated() {
+                uncalibrated.push(joint);
+            }
+        }
+        Ok(uncalibrated)
+    }
+
+    pub fn joint(&self, name: &str) -> Result<&Joint> {
+        self.joints.iter()
+            .find(|joint| joint.name == name)
+            .ok_or_else(|| anyhow!("Unknown joint: {}", name))
+    }
+
+    pub fn joint_by_id(&self, id: u8) -> Result<&Joint> {
+        self.joints.iter()
+            .find(|joint| joint.id == id)
+            .ok_or_else(|| anyhow!("No joint with servo ID {}", id))
+    }
+
+    // The servo coupled to `id`, if any
+    pub fn partner(&self, id: u8) -> Option<u8> {
+        self.couplings.iter().find_map(|&(a, b)| {
+            if a == id {
+                Some(b)
+            } else if b == id {
+                Some(a)
+            } else {
+                None
+            }
+        })
+    }
+
+    pub fn unresponsive_joints(&self) -> Result<Vec<&Joint>> {
+        let mut missing = Vec::new();
+        for joint in &self.joints {
+            if !self.servo.scan(joint.id)? {
+                missing.push(joint);
+            }
+        }
+        Ok(missing)
+    }
+
+    // Move every joint to the center of its calibrated range, group by group
+    // if a homing order is configured. Joints left out of the order are
+    // homed last.
+    pub fn home(&self, settle: &SettleConfig) -> Result<()> {
+        let ordered: Vec<u8> = self.homing_order.iter().flatten().copied().collect();
+        let rest: Vec<u8> = self.joints.iter()
+            .map(|joint| joint.id)
+            .filter(|id| !ordered.contains(id))
+            .collect();
+
+        for group in self.homing_order.iter().chain(std::iter::once(&rest)) {
+            if group.is_empty() {
+                continue;
+            }
+            let targets: Vec<(u8, i16)> = group.iter().map(|&id| (id, CENTER_POSITION)).collect();
+            self.servo.sync_write_positions(&targets)?;
+            self.servo.wait_settled(&targets, settle)?;
+        }
+        Ok(())
+    }
+
+    pub fn state(&self) -> Result<RobotState> {
+        sample_state(&self.servo, &self.joints)
+    }
+
+    // Every joint's angle in degrees, through its mapping, from a single
+    // sync read
+    pub fn positions(&self, missing: MissingJoints) -> Result<HashMap<String, f32>> {
+        let ids: Vec<u8> = self.joints.iter().map(|joint| joint.id).collect();
+        let mut positions = HashMap::with_capacity(ids.len());
+        for (joint, (_, info)) in self.joints.iter().zip(self.servo.sync_read_info(&ids)?) {
+            match info {
+                Some(info) => {
+                    positions.insert(joint.name.clone(), joint.degrees(info.current_location));
+                }
+                None if missing == MissingJoints::Error => bail!("Joint {} (servo {}) did not reply", joint.name, joint.id),
+                None => (),
+            }
+        }
+        Ok(positions)
+    }
+
+    // Sample the state at `rate` Hz on a background thread. The channel is
+    // bounded: a slow receiver gets RecvError::Lagged and skips ahead instead
+    // of holding up the sampler. The thread exits once every receiver is
+    // dropped.
+    pub fn subscribe(&self, rate: f64) -> Result<broadcast::Receiver<RobotState>> {
+        if !(rate > 0.0 && rate.is_finite()) {
+            bail!("Invalid sample rate {} Hz", rate);
+        }
+        let period = Duration::from_secs_f64(1.0 / rate);
+        let (sender, receiver) = broadcast::channel(SUBSCRIBE_CAPACITY);
+        let servo = self.servo.clone();
+        let joints = self.joints.clone();
+
+        thread::spawn(move || {
+            while sender.receiver_count() > 0 {
+                let start = Instant::now();
+                match sample_state(&servo, &joints) {
+                    Ok(state) => {
+                        // Only fails once there are no receivers left
+                        let _ = sender.send(state);
+                    }
+                    Err(e) => eprintln!("Failed to sample robot state: {}", e),
+                }
+                if let Some(remaining) = period.checked_sub(start.elapsed()) {
+                    sleep(remaining);
+                }
+            }
+        });
+
+        Ok(receiver)
+    }
+
+    // Every joint's temperature, hottest first
+    pub fn thermal_report(&self, scaling: &ModelScaling) -> Result<Vec<JointTemperature>> {
+        let mut report = self.joints.iter()
+            .map(|joint| Ok(JointTemperature {
+                name: joint.name.clone(),
+                id: joint.id,
+                celsius: self.servo.read_temperature(joint.id, scaling)?,
+            }))
+            .collect::<Result<Vec<_>>>()?;
+        rank_hottest(&mut report);
+        Ok(report)
+    }
+
+    // Move a group of joints, in joint degrees through each mapping, so
+    // they all arrive after `duration`. Targets are clamped to each joint's
+    // calibrated limits. Nothing is sent unless every name and target is valid
+    // and, unless disabled with with_require_calibration, every joint is
+    // calibrated.
+    pub fn move_group(&self, targets: &[(&str, f32)], duration: Duration) -> Result<Vec<(u8, i16)>> {
+        // One goal time for every joint, see goal_time_ms
+        let positions = self.resolve_targets(targets)?;
+        self.guard_bus(|| {
+            // Safe mode stretches the move until the joint travelling
+            // furthest stays under its speed cap
+            let duration = match &self.safe_mode {
+                Some(safe_mode) => {
+                    let mut distance = 0;
+                    for &(id, goal) in &positions {
+                        distance = distance.max((goal as i32 - self.servo.read_position(id)? as i32).unsigned_abs());
+                    }
+                    safe_mode.move_duration(duration, distance)
+                }
+                None => duration,
+            };
+            let time_ms = goal_time_ms(duration)?;
+            self.servo.sync_move_timed(&positions, time_ms)
+        })?;
+        self.record_goals(&positions);
+        Ok(positions)
+    }
+
+    // Same targets and checks as move_group, but the motion is planned here
+    // instead of by each servo: synchronized trapezoidal profiles, every
+    // joint under `limits` (ticks/s, ticks/s²) or its own configured
+    // max_velocity and max_acceleration if lower, stretched so all start
+    // and finish together. Streamed at `rate` Hz until done or the
+    // emergency stop clears `running`, which holds the joints where they
+    // are.
+    pub fn move_group_profiled(&self, targets: &[(&str, f32)], limits: MotionLimits, rate: f32) -> Result<Vec<(u8, i16)>> {
+        let positions = self.resolve_targets(targets)?;
+        let moves = self.guard_bus(|| positions.iter()
+            .map(|&(id, goal)| {
+                let joint = self.joint_by_id(id)?;
+                let limits = match &self.safe_mode {
+                    Some(safe_mode) => safe_mode.motion_limits(joint.motion_limits(limits)),
+                    None => joint.motion_limits(limits),
+                };
+                Ok((id, self.servo.read_position(id)?, goal, limits))
+            })
+            .collect::<Result<Vec<_>>>())?;
+
+        let trajectory = Trajectory::synchronized_limited(&moves)?;
+        // Only the start is known to be safe to hold if streaming fails
+        self.record_goals(&moves.iter().map(|&(id, start, _, _)| (id, start)).collect::<Vec<_>>());
+        if !self.guard_bus(|| trajectory.execute_while(&self.servo, rate, &self.running))? {
+            self.hold()?;
+            return Ok(positions);
+        }
+        self.record_goals(&positions);
+        Ok(positions)
+    }
+
+    // Ticks for every target of move_group, clamped to the joint limits
+    fn resolve_targets(&self, targets: &[(&str, f32)]) -> Result<Vec<(u8, i16)>> {
+        let mut positions = Vec::with_capacity(targets.len());
+        let mut uncalibrated = Vec::new();
+        for &(name, degrees) in targets {
+            let joint = self.joint(name)?;
+            if positions.iter().any(|&(id, _)| id == joint.id) {
+                bail!("Joint {} is listed more than once", name);
+            }
+            let servo_degrees = joint.mapping.to_servo(degrees);
+            let ticks = CENTER_POSITION as i32 + deg_to_ticks(servo_degrees);
+            if !degrees.is_finite() || !(0..=4095).contains(&ticks) {
+                bail!("Target {} degrees for {} is out of range", degrees, name);
+            }
+            let limits = self.calibration_of(joint)?;
+            if self.require_calibration && !limits.is_calibrated() {
+                uncalibrated.push(name);
+            }
+            // Rounded within the calibrated range, or the full turn without
+            // one, then clamped as before
+            let (min, max) = match (limits.min_angle, limits.max_angle) {
+                (min, max) if (min, max) != NO_LIMITS && min < max => (min as i32, max as i32),
+                _ => (0, 4095),
+            };
+            let (min, max) = match &self.safe_mode {
+                Some(safe_mode) => safe_mode.narrow_range(min, max),
+                None => (min, max),
+            };
+            let center = CENTER_POSITION as i32;
+            let ticks = center + deg_to_ticks_within(servo_degrees, min - center, max - center, self.rounding, true);
+            positions.push((joint.id, clamp_to_limits(ticks as i16, limits.min_angle, limits.max_angle)));
+        }
+        if !uncalibrated.is_empty() {
+            bail!("Refusing to move uncalibrated joints: {}", uncalibrated.join(", "));
+        }
+        Ok(positions)
+    }
+
+    // Let the joints be moved by hand, see `reengage`
+    pub fn relax(&self, ids: &[u8]) -> Result<()> {
+        for &id in ids {
+            self.servo.set_torque_mode(id, TorqueMode::Disabled)?;
+        }
+        Ok(())
+    }
+
+    // Hold relaxed joints wherever they were left. The goal is set to the
+    // present position before torque comes back on, otherwise the joint
+    // would jerk back to the goal it had before being relaxed.
+    pub fn reengage(&self, ids: &[u8]) -> Result<Vec<(u8, i16)>> {
+        let goals = self.capture_goals(ids)?;
+        for &(id, _) in &goals {
+            self.servo.set_torque_mode(id, TorqueMode::Enabled)?;
+        }
+        Ok(goals)
+    }
+
+    // Stop every joint where it is, e.g. partway through a timed move
+    pub fn hold(&self) -> Result<Vec<(u8, i16)>> {
+        let ids: Vec<u8> = self.joints.iter().map(|joint| joint.id).collect();
+        self.capture_goals(&ids)
+    }
+
+    // Keep re-sending `positions` every `interval` until the emergency stop
+    // clears `running`, for long static holds where joints drift off their
+    // goal under load. Re-commands are kept on a fixed schedule from the
+    // start, see next_recommand. Returns how many were sent.
+    pub fn hold_pose(&self, positions: &[(u8, i16)], interval: Duration) -> Result<usize> {
+        if interval.is_zero() {
+            bail!("Hold interval must be longer than zero");
+        }
+        for &(id, position) in positions {
+            self.joint_by_id(id)?;
+            if !(0..=4095).contains(&position) {
+                bail!("Hold position {} of servo {} is out of range", position, id);
+            }
+        }
+
+        let start = Instant::now();
+        let mut sent = 0;
+        while self.is_running() {
+            self.guard_bus(|| self.servo.sync_write_positions(positions))?;
+            self.record_goals(positions);
+            sent += 1;
+            let deadline = next_recommand(start, interval, Instant::now());
+            if !wait_while_running(deadline.saturating_duration_since(Instant::now()), &self.running) {
+                break;
+            }
+        }
+        Ok(sent)
+    }
+
+    // Put every configured joint in `mode` and read it back, e.g. position
+    // mode at the start of a session so no joint is left in constant speed
+    // mode by a previous tool, ignoring position goals. Switching to position
+    // mode holds each joint where it is first, it would otherwise head for
+    // whatever goal it has stored. Every joint is tried, the ones that
+    // didn't take the mode are listed in the error.
+    pub fn set_all_mode(&self, mode: ServoMode) -> Result<()> {
+        let mut failed = Vec::new();
+        for joint in &self.joints {
+            if let Err(e) = self.set_joint_mode(joint.id, mode) {
+                failed.push(format!("{} ({:#})", joint.name, e));
+            }
+        }
+        if !failed.is_empty() {
+            bail!("Failed to set {:?} mode on: {}", mode, failed.join(", "));
+        }
+        Ok(())
+    }
+
+    pub(crate) fn set_joint_mode(&self, id: u8, mode: ServoMode) -> Result<()> {
+        if mode == ServoMode::Position {
+            self.capture_goals(&[id])?;
+        }
+        self.servo.set_mode(id, mode)?;
+        let read = self.servo.read_mode(id)?;
+        if read != mode {
+            bail!("reads back {:?}", read);
+        }
+        Ok(())
+    }
+
+    // Limp, not hold: every configured joint loses torque, see
+    // Servo::estop_all
+    pub fn estop(&self) -> Result<()> {
+        let ids: Vec<u8> = self.joints.iter().map(|joint| joint.id).collect();
+        self.servo.estop_all(&ids)
+    }
+
+    fn capture_goals(&self, ids: &[u8]) -> Result<Vec<(u8, i16)>> {
+        let mut goals = Vec::with_capacity(ids.len());
+        for &id in ids {
+            let position = self.servo.read_position(id)?;
+            self.servo.write_u16(id, ServoRegister::TargetLocation, position as u16)?;
+            goals.push((id, position));
+        }
+        self.record_goals(&goals);
+        Ok(goals)
+    }
+
+    // Move every joint to a new baud rate and reopen the bus at it. If any
+    // joint fails to answer at the new rate, all joints are moved back so the
+    // chain is never left split across two baud rates.
+    pub fn change_baud(&self, baud_rate: u32) -> Result<()> {
+        baud_register_value(baud_rate)?;
+        let old_baud_rate = self.servo.bus_baud_rate()?;
+        if old_baud_rate == baud_rate {
+            return Ok(());
+        }
+
+        let missing = self.unresponsive_joints()?;
+        if !missing.is_empty() {
+            bail!("Not changing baud rate, joints not responding: {}", joint_names(&missing));
+        }
+
+        let mut changed = Vec::new();
+        for joint in &self.joints {
+            if let Err(e) = self.servo.set_baud(joint.id, baud_rate) {
+                changed.push(joint.id);
+                self.rollback_baud(&changed, baud_rate, old_baud_rate)?;
+                return Err(e.context(format!("Failed to change baud rate of {}, rolled back", joint.name)));
+            }
+            changed.push(joint.id);
+        }
+
+        self.servo.set_bus_baud_rate(baud_rate)?;
+        sleep(Duration::from_millis(50));
+
+        let missing = self.unresponsive_joints()?;
+        if missing.is_empty() {
+            return Ok(());
+        }
+        let names = joint_names(&missing);
+        self.rollback_baud(&changed, baud_rate, old_baud_rate)?;
+        bail!("Joints not responding at {} baud: {}, rolled back to {} baud", baud_rate, names, old_baud_rate)
+    }
+
+    fn rollback_baud(&self, ids: &[u8], from: u32, to: u32) -> Result<()> {
+        // set_baud expects the bus to run at the servo's current rate
+        self.servo.set_bus_baud_rate(from)?;
+        let failed: Vec<u8> = ids.iter()
+            .copied()
+            .filter(|&id| self.servo.set_baud(id, to).is_err())
+            .collect();
+        self.servo.set_bus_baud_rate(to)?;
+        sleep(Duration::from_millis(50));
+
+        if !failed.is_empty() {
+            bail!("Rollback failed, servos {:?} may still be at {} baud", failed, from);
+        }
+        Ok(())
+    }
+}
+
+fn sample_state(servo: &Servo, joints: &[Joint]) -> Result<RobotState> {
+    let timestamp = Instant::now();
+    let joints = joints.iter()
+        .map(|joint| Ok(JointState {
+            name: joint.name.clone(),
+            id: joint.id,
+            info: servo.read_info(joint.id)?,
+        }))
+        .collect::<Result<Vec<_>>>()?;
+    Ok(RobotState { timestamp, joints })
+}
+
+// The first multiple of `interval` after `start` that is still ahead of
+// `now`. A re-command that overran its slot skips the missed ones instead
+// of sending a burst to catch up.
+pub fn next_recommand(start: Instant, interval: Duration, now: Instant) -> Instant {
+    let slots = now.saturating_duration_since(start).as_nanos() / interval.as_nanos().max(1);
+    start + interval * (slots as u32 + 1)
+}
+
+pub fn clamp_to_limits(position: i16, min: i16, max: i16) -> i16 {
+    if (min, max) == NO_LIMITS || min >= max {
+        return position;
+    }
+    position.clamp(min, max)
+}
+
+pub fn rank_hottest(temperatures: &mut [JointTemperature]) {
+    temperatures.sort_by(|a, b| b.celsius.total_cmp(&a.celsius).then(a.id.cmp(&b.id)));
+}
+
+fn joint_names(joints: &[&Joint]) -> String {
+    joints.iter().map(|joint| joint.name.as_str()).collect::<Vec<_>>().join(", ")
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    pub(crate) fn joint(name: &str, id: u8) -> Joint {
+        Joint {
+            name: name.to_string(),
+            id,
+            mapping: JointMapping::default(),
+            max_velocity: None,
+            max_acceleration: None,
+            calibration_profile: None,
+            max_torque: None,
+        }
+    }
+
+    // A Robot over mock servos at the joints' IDs
+    #[cfg(not(feature = "milkv"))]
+    pub(crate) fn mock_robot(joints: &[(&str, u8)]) -> (crate::hal::mock::MockBus, Robot) {
+        let ids: Vec<u8> = joints.iter().map(|&(_, id)| id).collect();
+        let bus = crate::hal::mock::MockBus::new(&ids);
+        let joints = joints.iter().map(|&(name, id)| joint(name, id)).collect();
+        let robot = Robot::new(Arc::new(Servo::mock(&bus)), joints);
+        (bus, robot)
+    }
+
+    #[cfg(not(feature = "milkv"))]
+    #[test]
+    fn change_baud_moves_every_joint_and_the_bus() {
+        let (bus, robot) = mock_robot(&[("left_hip", 1), ("right_hip", 2)]);
+        robot.change_baud(500_000).unwrap();
+        assert_eq!(bus.baud_rate(), 500_000);
+        assert_eq!(bus.u8(1, ServoRegister::BaudRate), 1);
+        assert_eq!(bus.u8(2, ServoRegister::BaudRate), 1);
+        assert!(robot.unresponsive_joints().unwrap().is_empty());
+    }
+
+    #[cfg(not(feature = "milkv"))]
+    #[test]
+    fn change_baud_refuses_with_a_joint_missing() {
+        let (bus, robot) = mock_robot(&[("left_hip", 1), ("right_hip", 2)]);
+        bus.remove(2);
+        assert!(robot.change_baud(500_000).is_err());
+        assert_eq!(bus.baud_rate(), 1_000_000);
+        assert!(bus.writes().is_empty());
+    }
+
+    #[cfg(not(feature = "milkv"))]
+    #[test]
+    fn joints_are_found_by_name_and_id() {
+        let (_, robot) = mock_robot(&[("left_hip", 1), ("right_hip", 2)]);
+        assert_eq!(robot.joint("right_hip").unwrap().id, 2);
+        assert_eq!(robot.joint_by_id(1).unwrap().name, "left_hip");
+        assert!(robot.joint("left_knee").is_err());
+    }
+
+    #[cfg(not(feature = "milkv"))]
+    #[test]
+    fn reengage_holds_relaxed_joints_where_they_were_left() {
+        let (bus, robot) = mock_robot(&[("left_hip", 1), ("right_hip", 2)]);
+        bus.set_u8(1, ServoRegister::TorqueSwitch, 1);
+        robot.relax(&[1]).unwrap();
+        assert_eq!(bus.u8(1, ServoRegister::TorqueSwitch), 0);
+
+        // Moved by hand while relaxed
+        bus.set_u16(1, ServoRegister::CurrentLocation, 1500);
+        assert_eq!(robot.reengage(&[1]).unwrap(), vec![(1, 1500)]);
+        assert_eq!(bus.u16(1, ServoRegister::TargetLocation), 1500);
+        assert_eq!(bus.u8(1, ServoRegister::TorqueSwitch), 1);
+        // The goal goes in before torque comes back on
+        let writes = bus.writes();
+        let goal = writes.iter().rposition(|write| write.address == ServoRegister::TargetLocation as u8).unwrap();
+        let torque = writes.iter().rposition(|write| write.address == ServoRegister::TorqueSwitch as u8).unwrap();
+        assert!(goal < torque);
+        // Joints not named are left alone
+        assert_eq!(bus.u16(2, ServoRegister::TargetLocation), 2048);
+    }
+
+    #[cfg(not(feature = "milkv"))]
+    #[test]
+    fn home_centers_groups_in_order_then_the_rest() {
+        let (bus, robot) = mock_robot(&[("left_hip", 1), ("right_hip", 2), ("neck", 3)]);
+        let robot = robot.with_homing_order(vec![vec![2], vec![1]]);
+        for id in 1..=3 {
+            bus.set_u16(id, ServoRegister::CurrentLocation, 1000);
+        }
+        bus.on_packet(|servos| {
+            let ids: Vec<u8> = servos.memory.keys().copied().collect();
+            for id in ids {
+                let target = servos.u16(id, ServoRegister::TargetLocation);
+                servos.set_u16(id, ServoRegister::CurrentLocation, target);
+            }
+        });
+        robot.home(&SettleConfig::default()).unwrap();
+        let order: Vec<u8> = bus.writes().iter()
+            .filter(|write| write.address == ServoRegister::TargetLocation as u8)
+            .map(|write| write.id)
+            .collect();
+        assert_eq!(order, vec![2, 1, 3]);
+        for id in 1..=3 {
+            assert_eq!(bus.u16(id, ServoRegister::CurrentLocation), CENTER_POSITION as u16);
+        }
+    }
+
+    #[cfg(not(feature = "milkv"))]
+    #[test]
+    fn partners_are_found_from_either_side() {
+        let (_, robot) = mock_robot(&[("left_hip", 1), ("right_hip", 2), ("neck", 3)]);
+        let robot = robot.with_couplings(vec![(1, 2)]);
+        assert_eq!(robot.partner(1), Some(2));
+        assert_eq!(robot.partner(2), Some(1));
+        assert_eq!(robot.partner(3), None);
+    }
+
+    #[cfg(not(feature = "milkv"))]
+    #[test]
+    fn subscribe_streams_every_joint_and_refuses_bad_rates() {
+        let (bus, robot) = mock_robot(&[("left_hip", 1), ("right_hip", 2)]);
+        for rate in [0.0, -1.0, f64::NAN, f64::INFINITY] {
+            assert!(robot.subscribe(rate).is_err());
+        }
+        bus.set_u16(2, ServoRegister::CurrentLocation, 1500);
+        let mut receiver = robot.subscribe(200.0).unwrap();
+        let state = receiver.blocking_recv().unwrap();
+        let joints: Vec<(&str, u8, i16)> = state.joints.iter()
+            .map(|joint| (joint.name.as_str(), joint.id, joint.info.current_location))
+            .collect();
+        assert_eq!(joints, vec![("left_hip", 1, 2048), ("right_hip", 2, 1500)]);
+        assert!(receiver.blocking_recv().unwrap().timestamp > state.timestamp);
+    }
+
+    #[cfg(not(feature = "milkv"))]
+    #[test]
+    fn robot_from_config_resolves_joint_names_to_ids() {
+        let config = crate::config::tests::parse(r#"
+            [robot]
+            name = "test"
+            coupled = [["left_hip_pitch", "left_knee_pitch"]]
+            [robot.legs.left]
+            knee_pitch = { id = 2 }
+            hip_pitch = { id = 1 }
+            [robot.legs.right]
+            hip_pitch = { id = 3 }
+            [homing]
+            order = [["right_hip_pitch"]]
+        "#);
+        let bus = crate::hal::mock::MockBus::new(&[1, 2, 3]);
+        let robot = Robot::from_loaded_config(Arc::new(Servo::mock(&bus)), &config).unwrap();
+        let joints: Vec<(&str, u8)> = robot.joints().iter().map(|joint| (joint.name.as_str(), joint.id)).collect();
+        assert_eq!(joints, vec![("left_hip_pitch", 1), ("left_knee_pitch", 2), ("right_hip_pitch", 3)]);
+        assert_eq!(robot.partner(1), Some(2));
+        assert_eq!(robot.homing_order, vec![vec![3]]);
+    }
+
+    #[test]
+    fn hottest_joints_rank_first_ties_by_id() {
+        let temperature = |id: u8, celsius: f32| JointTemperature { name: format!("joint{}", id), id, celsius };
+        let mut report = vec![temperature(3, 40.0), temperature(1, 55.0), temperature(4, 40.0), temperature(2, 30.0)];
+        rank_hottest(&mut report);
+        let ids: Vec<u8> = report.iter().map(|joint| joint.id).collect();
+        assert_eq!(ids, vec![1, 3, 4, 2]);
+    }
+
+    #[cfg(not(feature = "milkv"))]
+    #[test]
+    fn thermal_report_scales_per_model() {
+        let (bus, robot) = mock_robot(&[("left_hip", 1), ("right_hip", 2)]);
+        bus.set_u8(1, ServoRegister::CurrentTemperature, 40);
+        bus.set_u8(2, ServoRegister::CurrentTemperature, 30);
+        let scaling = ModelScaling::temperature().with_model(crate::servo::MODEL_STS3215, 2.0);
+        let report = robot.thermal_report(&scaling).unwrap();
+        assert_eq!(report, vec![
+            JointTemperature { name: "left_hip".to_string(), id: 1, celsius: 80.0 },
+            JointTemperature { name: "right_hip".to_string(), id: 2, celsius: 60.0 },
+        ]);
+        assert_eq!(robot.thermal_report(&ModelScaling::temperature()).unwrap()[0].celsius, 40.0);
+    }
+
+    #[test]
+    fn clamping_skips_disabled_limits() {
+        assert_eq!(clamp_to_limits(500, 1000, 3000), 1000);
+        assert_eq!(clamp_to_limits(3500, 1000, 3000), 3000);
+        assert_eq!(clamp_to_limits(2000, 1000, 3000), 2000);
+        assert_eq!(clamp_to_limits(500, NO_LIMITS.0, NO_LIMITS.1), 500);
+        assert_eq!(clamp_to_limits(500, 3000, 1000), 500);
+    }
+
+    #[cfg(not(feature = "milkv"))]
+    #[test]
+    fn move_group_sends_one_timed_sync_write_within_the_limits() {
+        let (bus, robot) = mock_robot(&[("left_hip", 1), ("right_hip", 2)]);
+        for id in [1, 2] {
+            bus.set_u16(id, ServoRegister::MinAngleLimit, 1024);
+            bus.set_u16(id, ServoRegister::MaxAngleLimit, 3072);
+        }
+        let positions = robot.move_group(&[("left_hip", 45.0), ("right_hip", -120.0)], Duration::from_millis(500)).unwrap();
+        assert_eq!(positions, vec![(1, 2560), (2, 1024)]);
+        let writes: Vec<(u8, u8, Vec<u8>)> = bus.writes().into_iter().map(|write| (write.id, write.address, write.data)).collect();
+        let target = ServoRegister::TargetLocation as u8;
+        assert_eq!(writes, vec![
+            (1, target, vec![0x00, 0x0A, 0xF4, 0x01, 0, 0]),
+            (2, target, vec![0x00, 0x04, 0xF4, 0x01, 0, 0]),
+        ]);
+
+        bus.clear_writes();
+        assert!(robot.move_group(&[("left_hip", 0.0), ("left_knee", 0.0)], Duration::from_millis(500)).is_err());
+        assert!(bus.writes().is_empty());
+    }
+
+    #[cfg(not(feature = "milkv"))]
+    #[test]
+    fn positions_are_joint_degrees_from_one_sync_read() {
+        let (bus, mut robot) = mock_robot(&[("left_hip", 1), ("right_hip", 2), ("neck", 3)]);
+        robot.joints[1].mapping = JointMapping { scale: -1.0, offset: 90.0 };
+        bus.set_u16(1, ServoRegister::CurrentLocation, 3072);
+        bus.set_u16(2, ServoRegister::CurrentLocation, 3072);
+        bus.remove(3);
+        let positions = robot.positions(MissingJoints::Omit).unwrap();
+        assert_eq!(positions.len(), 2);
+        assert_eq!(positions["left_hip"], 90.0);
+        assert_eq!(positions["right_hip"], 0.0);
+        let error = robot.positions(MissingJoints::Error).unwrap_err().to_string();
+        assert!(error.contains("neck (servo 3)"), "{}", error);
+    }
+
+    #[cfg(not(feature = "milkv"))]
+    #[test]
+    fn uncalibrated_joints_are_refused_unless_forced() {
+        let (bus, robot) = mock_robot(&[("left_hip", 1), ("right_hip", 2)]);
+        bus.set_u16(1, ServoRegister::MinAngleLimit, 1024);
+        bus.set_u16(1, ServoRegister::MaxAngleLimit, 3072);
+        let names: Vec<&str> = robot.uncalibrated_joints().unwrap().iter().map(|joint| joint.name.as_str()).collect();
+        assert_eq!(names, ["right_hip"]);
+
+        let error = robot.move_group(&[("left_hip", 10.0), ("right_hip", 10.0)], Duration::from_millis(500)).unwrap_err();
+        assert_eq!(error.to_string(), "Refusing to move uncalibrated joints: right_hip");
+        assert!(bus.writes().is_empty());
+
+        let robot = robot.with_require_calibration(false);
+        assert_eq!(robot.move_group(&[("right_hip", 0.0)], Duration::from_millis(500)).unwrap(), vec![(2, 2048)]);
+    }
+
+    #[cfg(not(feature = "milkv"))]
+    #[test]
+    fn estop_cuts_and_confirms_torque_on_every_joint() {
+        let (bus, robot) = mock_robot(&[("left_hip", 1), ("right_hip", 2), ("neck", 3)]);
+        for id in [1, 2, 3] {
+            bus.set_u8(id, ServoRegister::TorqueSwitch, TorqueMode::Enabled as u8);
+            bus.set_u16(id, ServoRegister::RunningSpeed, 400);
+        }
+        robot.estop().unwrap();
+        for id in [1, 2, 3] {
+            assert_eq!(bus.u8(id, ServoRegister::TorqueSwitch), TorqueMode::Disabled as u8);
+            assert_eq!(bus.u16(id, ServoRegister::RunningSpeed), 0);
+        }
+    }
+
+    #[cfg(not(feature = "milkv"))]
+    #[test]
+    fn estop_carries_on_past_servos_it_cant_confirm() {
+        let (bus, robot) = mock_robot(&[("left_hip", 1), ("right_hip", 2), ("neck", 3)]);
+        for id in [1, 2, 3] {
+            bus.set_u8(id, ServoRegister::TorqueSwitch, TorqueMode::Enabled as u8);
+        }
+        bus.refuse(1, ServoRegister::TorqueSwitch);
+        bus.remove(2);
+        let error = robot.estop().unwrap_err().to_string();
+        assert_eq!(error, "Emergency stop could not confirm torque off on servos [1, 2]");
+        assert_eq!(bus.u8(3, ServoRegister::TorqueSwitch), TorqueMode::Disabled as u8);
+    }
+
+    #[cfg(not(feature = "milkv"))]
+    #[test]
+    fn inside_rounding_keeps_a_target_near_the_limit_off_it() {
+        let (bus, robot) = mock_robot(&[("left_hip", 1)]);
+        bus.set_u16(1, ServoRegister::MinAngleLimit, 1024);
+        bus.set_u16(1, ServoRegister::MaxAngleLimit, 3072);
+        // 89.99° is 1023.9 ticks from center
+        assert_eq!(robot.move_group(&[("left_hip", 89.99)], Duration::from_millis(500)).unwrap(), vec![(1, 3072)]);
+        let robot = robot.with_rounding(Rounding::Inside);
+        assert_eq!(robot.move_group(&[("left_hip", 89.99)], Duration::from_millis(500)).unwrap(), vec![(1, 3071)]);
+    }
+
+    #[test]
+    fn recommands_keep_to_the_schedule() {
+        let start = Instant::now();
+        let ms = Duration::from_millis;
+        assert_eq!(next_recommand(start, ms(100), start), start + ms(100));
+        assert_eq!(next_recommand(start, ms(100), start + ms(130)), start + ms(200));
+        // Overran two slots, skips them
+        assert_eq!(next_recommand(start, ms(100), start + ms(350)), start + ms(400));
+    }
+
+    #[cfg(not(feature = "milkv"))]
+    #[test]
+    fn hold_pose_recommands_until_stopped() {
+        let (bus, robot) = mock_robot(&[("left_hip", 1), ("right_hip", 2)]);
+        let running = robot.running().clone();
+        let mut packets = 0;
+        bus.on_packet(move |_| {
+            packets += 1;
+            if packets == 3 {
+                running.store(false, Ordering::SeqCst);
+            }
+        });
+        assert_eq!(robot.hold_pose(&[(1, 1500), (2, 2500)], Duration::from_millis(1)).unwrap(), 3);
+        assert_eq!(bus.writes().len(), 6);
+        assert_eq!(bus.u16(2, ServoRegister::TargetLocation), 2500);
+    }
+
+    #[cfg(not(feature = "milkv"))]
+    #[test]
+    fn hold_pose_checks_the_pose_first() {
+        let (bus, robot) = mock_robot(&[("left_hip", 1)]);
+        assert!(robot.hold_pose(&[(1, 1500)], Duration::ZERO).is_err());
+        assert!(robot.hold_pose(&[(2, 1500)], Duration::from_millis(1)).is_err());
+        assert!(robot.hold_pose(&[(1, 5000)], Duration::from_millis(1)).is_err());
+        assert!(bus.writes().is_empty());
+    }
+
+    #[test]
+    fn joint_motion_limits_are_converted_to_servo_ticks() {
+        let joint = Joint {
+            mapping: JointMapping { scale: -2.0, offset: 90.0 },
+            max_velocity: Some(90.0),
+            ..joint("left_hip", 1)
+        };
+        let limits = joint.motion_limits(MotionLimits { max_velocity: 4000.0, max_acceleration: 8000.0 });
+        // 90 joint degrees/s is 45 servo degrees/s, 512 ticks/s
+        assert_eq!(limits, MotionLimits { max_velocity: 512.0, max_acceleration: 8000.0 });
+        // Never above the limits asked for
+        assert_eq!(joint.motion_limits(MotionLimits { max_velocity: 100.0, max_acceleration: 10.0 }).max_velocity, 100.0);
+    }
+
+    #[cfg(not(feature = "milkv"))]
+    #[test]
+    fn profiled_move_streams_to_the_goals() {
+        let (bus, robot) = mock_robot(&[("left_hip", 1), ("right_hip", 2)]);
+        for id in [1, 2] {
+            bus.set_u16(id, ServoRegister::MinAngleLimit, 1024);
+            bus.set_u16(id, ServoRegister::MaxAngleLimit, 3072);
+        }
+        let limits = MotionLimits { max_velocity: 4000.0, max_acceleration: 40000.0 };
+        let positions = robot.move_group_profiled(&[("left_hip", 45.0), ("right_hip", -45.0)], limits, 200.0).unwrap();
+        assert_eq!(positions, vec![(1, 2560), (2, 1536)]);
+        assert_eq!(bus.u16(1, ServoRegister::TargetLocation), 2560);
+        assert_eq!(bus.u16(2, ServoRegister::TargetLocation), 1536);
+        // Streamed, not a single timed write
+        assert!(bus.writes().len() > 2);
+    }
+
+    #[cfg(not(feature = "milkv"))]
+    #[test]
+    fn stopped_profiled_move_holds_where_it_is() {
+        let (bus, robot) = mock_robot(&[("left_hip", 1)]);
+        bus.set_u16(1, ServoRegister::MinAngleLimit, 1024);
+        bus.set_u16(1, ServoRegister::MaxAngleLimit, 3072);
+        robot.running().store(false, Ordering::SeqCst);
+        let limits = MotionLimits { max_velocity: 4000.0, max_acceleration: 40000.0 };
+        assert_eq!(robot.move_group_profiled(&[("left_hip", 45.0)], limits, 200.0).unwrap(), vec![(1, 2560)]);
+        assert_eq!(bus.u16(1, ServoRegister::TargetLocation), 2048);
+    }
+
+    #[cfg(not(feature = "milkv"))]
+    #[test]
+    fn set_all_mode_holds_joints_before_position_mode() {
+        let (bus, robot) = mock_robot(&[("left_hip", 1), ("right_hip", 2)]);
+        robot.set_all_mode(ServoMode::ConstantSpeed).unwrap();
+        assert_eq!(bus.u8(1, ServoRegister::OperationMode), ServoMode::ConstantSpeed as u8);
+        assert_eq!(bus.u8(2, ServoRegister::OperationMode), ServoMode::ConstantSpeed as u8);
+
+        // A stale goal left by a previous tool
+        bus.set_u16(1, ServoRegister::TargetLocation, 3500);
+        bus.set_u16(1, ServoRegister::CurrentLocation, 1800);
+        robot.set_all_mode(ServoMode::Position).unwrap();
+        assert_eq!(bus.u8(1, ServoRegister::OperationMode), ServoMode::Position as u8);
+        assert_eq!(bus.u16(1, ServoRegister::TargetLocation), 1800);
+    }
+
+    #[cfg(not(feature = "milkv"))]
+    #[test]
+    fn set_all_mode_tries_every_joint_and_lists_failures() {
+        let (bus, robot) = mock_robot(&[("left_hip", 1), ("right_hip", 2), ("neck", 3)]);
+        bus.refuse(2, ServoRegister::OperationMode);
+        let error = robot.set_all_mode(ServoMode::ConstantSpeed).unwrap_err().to_string();
+        assert!(error.starts_with("Failed to set ConstantSpeed mode on: right_hip ("), "{}", error);
+        assert!(!error.contains("left_hip") && !error.contains("neck"), "{}", error);
+        assert_eq!(bus.u8(3, ServoRegister::OperationMode), ServoMode::ConstantSpeed as u8);
+    }
+
+    #[cfg(not(feature = "milkv"))]
+    #[test]
+    fn safe_mode_narrows_and_slows_move_group() {
+        let (bus, robot) = mock_robot(&[("left_hip", 1)]);
+        bus.set_u16(1, ServoRegister::MinAngleLimit, 1024);
+        bus.set_u16(1, ServoRegister::MaxAngleLimit, 3072);
+        let robot = robot.with_safe_mode(Some(SafeMode { enabled: true, ..Default::default() }));
+        // 10° short of the limit, 910 ticks away at no more than 512 ticks/s
+        assert_eq!(robot.move_group(&[("left_hip", 90.0)], Duration::from_millis(500)).unwrap(), vec![(1, 2958)]);
+        let writes = bus.writes();
+        assert_eq!(writes.len(), 1);
+        let time_ms = crate::endian::read_u16_le(&writes[0].data, 2);
+        assert!((1777..1800).contains(&time_ms), "{}", time_ms);
+    }
+}
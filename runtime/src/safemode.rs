@@ -0,0 +1,116 @@
+use serde::Deserialize;
+use std::time::Duration;
+use crate::trajectory::MotionLimits;
+use crate::units::{deg_to_ticks, TICKS_PER_TURN};
+
+// Conservative caps for novice operators, applied on top of and regardless
+// of every per-joint setting. Rates are at the servo, not the joint, so a
+// geared-down joint is never let off faster than the servo itself. In the
+// config as
+// [safe_mode]
+// enabled = true
+// max_velocity = 30.0
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SafeMode {
+    pub enabled: bool,
+    // Servo degrees/s and degrees/s²
+    pub max_velocity: f32,
+    pub max_acceleration: f32,
+    // TorqueLimit register value, 0-1000 of the stall torque, see
+    // Robot::apply_torque_limits
+    pub torque_limit: u16,
+    // Degrees kept clear of either end of the calibrated range
+    pub limit_margin: f32,
+}
+
+impl Default for SafeMode {
+    fn default() -> Self {
+        Self { enabled: false, max_velocity: 45.0, max_acceleration: 90.0, torque_limit: 300, limit_margin: 10.0 }
+    }
+}
+
+impl SafeMode {
+    // Validation errors as "field: message"
+    pub fn errors(&self) -> Vec<String> {
+        let mut errors = Vec::new();
+        for (field, value) in [("max_velocity", self.max_velocity), ("max_acceleration", self.max_acceleration)] {
+            if !(value.is_finite() && value > 0.0) {
+                errors.push(format!("{}: {} must be positive", field, value));
+            }
+        }
+        if self.torque_limit > 1000 {
+            errors.push(format!("torque_limit: {} is above 1000", self.torque_limit));
+        }
+        if !(self.limit_margin.is_finite() && self.limit_margin >= 0.0) {
+            errors.push(format!("limit_margin: {} must be finite and not negative", self.limit_margin));
+        }
+        errors
+    }
+
+    // `limits` lowered to the safe mode caps, in ticks/s and ticks/s²
+    pub fn motion_limits(&self, limits: MotionLimits) -> MotionLimits {
+        limits.min(MotionLimits {
+            max_velocity: to_ticks(self.max_velocity),
+            max_acceleration: to_ticks(self.max_acceleration),
+        })
+    }
+
+    // `duration` stretched so `distance` ticks are covered no faster than
+    // max_velocity
+    pub fn move_duration(&self, duration: Duration, distance: u32) -> Duration {
+        duration.max(Duration::from_secs_f32(distance as f32 / to_ticks(self.max_velocity)))
+    }
+
+    // `min..=max` in ticks narrowed by limit_margin at both ends, down to its
+    // middle if the range is narrower than both margins
+    pub fn narrow_range(&self, min: i32, max: i32) -> (i32, i32) {
+        let margin = deg_to_ticks(self.limit_margin);
+        if max - min < 2 * margin {
+            let middle = min + (max - min) / 2;
+            (middle, middle)
+        } else {
+            (min + margin, max - margin)
+        }
+    }
+}
+
+fn to_ticks(degrees: f32) -> f32 {
+    degrees * TICKS_PER_TURN as f32 / 360.0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn defaults_are_valid_and_bad_caps_are_listed() {
+        assert!(SafeMode::default().errors().is_empty());
+        let safe_mode = SafeMode { max_velocity: 0.0, max_acceleration: f32::NAN, torque_limit: 1200, limit_margin: -1.0, ..Default::default() };
+        assert_eq!(safe_mode.errors(), [
+            "max_velocity: 0 must be positive",
+            "max_acceleration: NaN must be positive",
+            "torque_limit: 1200 is above 1000",
+            "limit_margin: -1 must be finite and not negative",
+        ]);
+    }
+
+    #[test]
+    fn motion_is_capped_in_servo_ticks() {
+        let safe_mode = SafeMode { max_velocity: 45.0, max_acceleration: 90.0, ..Default::default() };
+        let limits = safe_mode.motion_limits(MotionLimits { max_velocity: 4000.0, max_acceleration: 500.0 });
+        assert_eq!(limits, MotionLimits { max_velocity: 512.0, max_acceleration: 500.0 });
+        // 1024 ticks at 512 ticks/s
+        assert_eq!(safe_mode.move_duration(Duration::from_millis(500), 1024), Duration::from_secs(2));
+        assert_eq!(safe_mode.move_duration(Duration::from_secs(3), 1024), Duration::from_secs(3));
+    }
+
+    #[test]
+    fn range_is_narrowed_down_to_its_middle() {
+        // 10° is 114 ticks
+        let safe_mode = SafeMode { limit_margin: 10.0, ..Default::default() };
+        assert_eq!(safe_mode.narrow_range(1024, 3072), (1138, 2958));
+        assert_eq!(safe_mode.narrow_range(2000, 2200), (2100, 2100));
+        assert_eq!(SafeMode { limit_margin: 0.0, ..safe_mode }.narrow_range(1024, 3072), (1024, 3072));
+    }
+}
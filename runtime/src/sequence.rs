@@ -0,0 +1,252 @@
+use anyhow::{Result, Context, bail};
+use serde::Deserialize;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread::sleep;
+use std::time::{Duration, Instant};
+use crate::keyframe::{Keyframe, KeyframeLibrary};
+use crate::robot::Robot;
+
+// How often a transition or dwell checks for an interrupt
+const INTERRUPT_POLL: Duration = Duration::from_millis(10);
+
+// A routine of keyframes, written as TOML:
+//
+//     keyframes = "keyframes.json"
+//     loop = true
+//     speed = 1.5
+//
+//     [[step]]
+//     keyframe = "crouch"
+//     transition_ms = 800
+//     dwell_ms = 200
+//
+// `keyframes` is relative to the sequence file. `speed` scales every
+// transition and dwell, 2.0 plays twice as fast.
+#[derive(Debug, Clone, Deserialize)]
+pub struct Sequence {
+    pub keyframes: PathBuf,
+    #[serde(default, rename = "loop")]
+    pub looping: bool,
+    #[serde(default = "default_speed")]
+    pub speed: f32,
+    #[serde(rename = "step")]
+    pub steps: Vec<SequenceStep>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct SequenceStep {
+    pub keyframe: String,
+    // Time to move from the previous pose to this keyframe
+    pub transition_ms: u64,
+    // Time to hold the keyframe before the next step
+    #[serde(default)]
+    pub dwell_ms: u64,
+}
+
+fn default_speed() -> f32 {
+    1.0
+}
+
+impl Sequence {
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        let contents = fs::read_to_string(path)
+            .with_context(|| format!("Failed to read sequence {:?}", path))?;
+        let mut sequence: Sequence = toml::from_str(&contents)
+            .with_context(|| format!("Failed to parse sequence {:?}", path))?;
+        if let Some(dir) = path.parent() {
+            sequence.keyframes = dir.join(&sequence.keyframes);
+        }
+        sequence.validate()
+            .with_context(|| format!("Invalid sequence {:?}", path))?;
+        Ok(sequence)
+    }
+
+    pub fn validate(&self) -> Result<()> {
+        if self.steps.is_empty() {
+            bail!("Sequence has no steps");
+        }
+        if !(self.speed > 0.0 && self.speed.is_finite()) {
+            bail!("Invalid speed {}, must be positive", self.speed);
+        }
+        Ok(())
+    }
+
+    // (transition, dwell) of each step, scaled by speed
+    pub fn timings(&self) -> Vec<(Duration, Duration)> {
+        let scale = |ms: u64| Duration::from_millis(ms).div_f64(self.speed as f64);
+        self.steps.iter()
+            .map(|step| (scale(step.transition_ms), scale(step.dwell_ms)))
+            .collect()
+    }
+
+    // Length of a single pass through the steps
+    pub fn duration(&self) -> Duration {
+        self.timings().into_iter().map(|(transition, dwell)| transition + dwell).sum()
+    }
+}
+
+impl Robot {
+    // Play the sequence at `path`, once or until interrupted if it loops.
+    // Every keyframe is checked against the config before anything moves,
+    // `on_step` is called as each transition starts. Clearing `running`
+    // stops the joints where they are and returns false.
+    pub fn play_sequence<P: AsRef<Path>>(&self, path: P, running: &AtomicBool, mut on_step: impl FnMut(&Keyframe)) -> Result<bool> {
+        let sequence = Sequence::load(path)?;
+        let library = KeyframeLibrary::load(&sequence.keyframes)?;
+        let keyframes = sequence.steps.iter()
+            .map(|step| {
+                let keyframe = library.get(&step.keyframe)?;
+                self.check_keyframe(keyframe)?;
+                Ok(keyframe)
+            })
+            .collect::<Result<Vec<_>>>()?;
+        let timings = sequence.timings();
+
+        loop {
+            for (keyframe, &(transition, dwell)) in keyframes.iter().zip(&timings) {
+                if !running.load(Ordering::SeqCst) {
+                    return self.stop_sequence();
+                }
+                on_step(keyframe);
+                self.move_to_keyframe(keyframe, transition)?;
+                if !wait_while_running(transition + dwell, running) {
+                    return self.stop_sequence();
+                }
+            }
+            if !sequence.looping {
+                return Ok(true);
+            }
+        }
+    }
+
+    fn stop_sequence(&self) -> Result<bool> {
+        self.hold()?;
+        Ok(false)
+    }
+}
+
+// False if `running` was cleared before `duration` passed
+pub(crate) fn wait_while_running(duration: Duration, running: &AtomicBool) -> bool {
+    let start = Instant::now();
+    while let Some(remaining) = duration.checked_sub(start.elapsed()) {
+        if !running.load(Ordering::SeqCst) {
+            return false;
+        }
+        sleep(remaining.min(INTERRUPT_POLL));
+    }
+    running.load(Ordering::SeqCst)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("sequence-{}-{}", name, std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    const TWO_STEPS: &str = r#"
+        keyframes = "keyframes.json"
+        speed = 2.0
+
+        [[step]]
+        keyframe = "crouch"
+        transition_ms = 800
+        dwell_ms = 200
+
+        [[step]]
+        keyframe = "stand"
+        transition_ms = 400
+    "#;
+
+    #[test]
+    fn timings_scale_with_speed() {
+        let sequence: Sequence = toml::from_str(TWO_STEPS).unwrap();
+        assert!(!sequence.looping);
+        assert_eq!(sequence.timings(), vec![
+            (Duration::from_millis(400), Duration::from_millis(100)),
+            (Duration::from_millis(200), Duration::ZERO),
+        ]);
+        assert_eq!(sequence.duration(), Duration::from_millis(700));
+    }
+
+    #[test]
+    fn sequences_need_steps_and_a_positive_speed() {
+        let sequence: Sequence = toml::from_str(TWO_STEPS).unwrap();
+        assert!(Sequence { steps: Vec::new(), ..sequence.clone() }.validate().is_err());
+        assert!(Sequence { speed: 0.0, ..sequence.clone() }.validate().is_err());
+        assert!(Sequence { speed: f32::INFINITY, ..sequence }.validate().is_err());
+    }
+
+    #[test]
+    fn keyframes_are_relative_to_the_sequence_file() {
+        let dir = temp_dir("relative");
+        fs::write(dir.join("routine.toml"), TWO_STEPS).unwrap();
+        let sequence = Sequence::load(dir.join("routine.toml")).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(sequence.keyframes, dir.join("keyframes.json"));
+    }
+
+    #[test]
+    fn wait_ends_early_once_running_is_cleared() {
+        assert!(wait_while_running(Duration::from_millis(20), &AtomicBool::new(true)));
+        let start = Instant::now();
+        assert!(!wait_while_running(Duration::from_secs(10), &AtomicBool::new(false)));
+        assert!(start.elapsed() < Duration::from_secs(1));
+    }
+
+    #[cfg(not(feature = "milkv"))]
+    mod bus {
+        use super::*;
+        use crate::hal::ServoRegister;
+        use crate::keyframe::Keyframe;
+        use crate::robot::tests::mock_robot;
+
+        fn write_routine(dir: &Path) -> PathBuf {
+            let mut library = KeyframeLibrary::default();
+            for (name, degrees) in [("crouch", 45.0), ("stand", 0.0)] {
+                library.insert(Keyframe { name: name.to_string(), joints: [("left_hip".to_string(), degrees)].into() });
+            }
+            library.save(dir.join("keyframes.json")).unwrap();
+            let routine = TWO_STEPS.replace("800", "20").replace("400", "20").replace("200", "10");
+            fs::write(dir.join("routine.toml"), routine).unwrap();
+            dir.join("routine.toml")
+        }
+
+        #[test]
+        fn sequence_plays_every_step_in_order() {
+            let (bus, robot) = mock_robot(&[("left_hip", 1)]);
+            let robot = robot.with_require_calibration(false);
+            let dir = temp_dir("play");
+            let path = write_routine(&dir);
+            let mut steps = Vec::new();
+            let finished = robot.play_sequence(&path, &AtomicBool::new(true), |keyframe| steps.push(keyframe.name.clone())).unwrap();
+            fs::remove_dir_all(&dir).unwrap();
+            assert!(finished);
+            assert_eq!(steps, vec!["crouch", "stand"]);
+            let goals: Vec<u16> = bus.writes().iter()
+                .filter(|write| write.address == ServoRegister::TargetLocation as u8)
+                .map(|write| u16::from_le_bytes([write.data[0], write.data[1]]))
+                .collect();
+            assert_eq!(goals[..2], [2560, 2048]);
+        }
+
+        #[test]
+        fn interrupted_sequence_holds_without_moving() {
+            let (bus, robot) = mock_robot(&[("left_hip", 1)]);
+            let robot = robot.with_require_calibration(false);
+            bus.set_u16(1, ServoRegister::CurrentLocation, 1500);
+            let dir = temp_dir("interrupted");
+            let path = write_routine(&dir);
+            let finished = robot.play_sequence(&path, &AtomicBool::new(false), |_| panic!("no step should start")).unwrap();
+            fs::remove_dir_all(&dir).unwrap();
+            assert!(!finished);
+            assert_eq!(bus.u16(1, ServoRegister::TargetLocation), 1500);
+        }
+    }
+}
@@ -0,0 +1,1246 @@
+use anyhow::{Result, anyhow, bail};
+use std::collections::BTreeMap;
+use std::thread::sleep;
+use std::time::{Duration, Instant};
+use crate::hal::{Servo, ServoInfo, ServoRegister, ServoMode, ServoDirection, MemoryLockState, ServoError, TorqueMode};
+use crate::endian::{read_u16_be, read_u16_le, write_i16_le, write_u16_le};
+use crate::units::{deg_to_ticks, Resolution};
+
+// Largest step count that fits next to the direction bit
+pub const MAX_STEPS: u16 = 0x7FFF;
+
+// Baud rates selectable through ServoRegister::BaudRate, indexed by register value
+pub const BAUD_RATES: [u32; 8] = [1_000_000, 500_000, 250_000, 128_000, 115_200, 76_800, 57_600, 38_400];
+
+// EEPROM needs a moment between writes before it reliably accepts the next one
+pub(crate) const EEPROM_WRITE_DELAY: Duration = Duration::from_millis(20);
+pub const EEPROM_LOCK_ATTEMPTS: usize = 3;
+
+// Center of the calibrated range, see calibration::compute_calibration
+pub const CENTER_POSITION: i16 = 2048;
+
+// How close a joint has to get to its goal to count as arrived. Keep some
+// slack on loaded joints: gravity or a spring can hold a joint a few ticks
+// off target indefinitely, and too tight a tolerance turns that into a
+// spurious timeout.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SettleConfig {
+    pub tolerance: u16,
+    pub timeout: Duration,
+    pub poll_interval: Duration,
+}
+
+impl Default for SettleConfig {
+    fn default() -> Self {
+        Self {
+            tolerance: 4,
+            timeout: Duration::from_secs(2),
+            poll_interval: Duration::from_millis(10),
+        }
+    }
+}
+
+impl SettleConfig {
+    pub fn is_settled(&self, position: i16, target: i16) -> bool {
+        (position as i32 - target as i32).unsigned_abs() <= self.tolerance as u32
+    }
+}
+
+// A goal position as the single TargetLocation write it takes, see
+// Servo::set_position_nowait
+pub fn command_position(target: i16, write: impl FnOnce(ServoRegister, &[u8]) -> Result<()>) -> Result<()> {
+    if !(0..=4095).contains(&target) {
+        bail!("Position {} is outside 0-4095", target);
+    }
+    write(ServoRegister::TargetLocation, &write_i16_le(target))
+}
+
+// How long a move took to settle and the most current drawn on the way
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SettleTiming {
+    pub elapsed: Duration,
+    // Where the joint settled
+    pub position: i16,
+    // In mA
+    pub peak_current: f32,
+    pub samples: usize,
+}
+
+// Poll `sample` for (position, current in mA) every poll_interval until the
+// position is within tolerance of `target`, timing out like
+// Servo::wait_settled. The clock starts at the call, so write the goal
+// right before.
+pub fn time_settle(target: i16, settle: &SettleConfig, mut sample: impl FnMut() -> Result<(i16, f32)>) -> Result<SettleTiming> {
+    let start = Instant::now();
+    let mut peak_current: f32 = 0.0;
+    let mut samples = 0;
+    loop {
+        let (position, current) = sample()?;
+        samples += 1;
+        peak_current = peak_current.max(current);
+        if settle.is_settled(position, target) {
+            return Ok(SettleTiming { elapsed: start.elapsed(), position, peak_current, samples });
+        }
+        if start.elapsed() >= settle.timeout {
+            bail!("Timed out after {:?} waiting to settle within {} ticks of {}, at {}", settle.timeout, settle.tolerance, target, position);
+        }
+        sleep(settle.poll_interval);
+    }
+}
+
+// Speed change spread over `steps` evenly spaced writes across `duration`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SpeedRamp {
+    pub duration: Duration,
+    pub steps: u16,
+}
+
+// Intermediate speeds from `from` to `to`, `from` itself left out and `to`
+// always last
+pub fn ramp_speeds(from: u16, to: u16, steps: u16) -> Vec<u16> {
+    let steps = steps.max(1) as i32;
+    (1..=steps)
+        .map(|i| (from as i32 + (to as i32 - from as i32) * i / steps) as u16)
+        .collect()
+}
+
+// Every servo on the bus acts on a write to this ID and none of them reply,
+// so writes to it are fire-and-forget: they return as soon as the packet is
+// sent, without knowing whether any servo received it. Nothing can be read
+// from it.
+pub const BROADCAST_ID: u8 = 0xFE;
+
+// ServoRegister::RunningTime is the goal time, in ms: a position goal
+// written together with it (and RunningSpeed 0) is reached that long after
+// the write whatever the distance, so joints given the same goal time
+// arrive together. A goal time of 0 moves at RunningSpeed instead.
+pub fn goal_time_ms(duration: Duration) -> Result<u16> {
+    match u16::try_from(duration.as_millis()) {
+        Ok(ms) => Ok(ms),
+        Err(_) => bail!("Move duration {:?} is longer than the {} ms goal time limit", duration, u16::MAX),
+    }
+}
+
+// Model number as returned by Servo::read_model, ServoMainVersion in the high byte
+pub const MODEL_STS3215: u16 = 0x0903;
+
+// Models that store a unique serial number and where. None of the STS models
+// documented so far do, read_serial reports them as unsupported.
+const SERIAL_NUMBER_REGISTERS: [(u16, ServoRegister, u8); 0] = [];
+
+// Models that report their present acceleration (signed, little-endian) and
+// where. The STS3215 doesn't: ServoRegister::Acceleration is the commanded
+// profile acceleration, not telemetry. Without it use AccelerationEstimator.
+const PRESENT_ACCELERATION_REGISTERS: [(u16, ServoRegister); 0] = [];
+
+// Scale of ServoRegister::CurrentCurrent for models without their own
+// entry: raw × 6.5 / 100, the conversion the runtime has always used. The
+// result is called mA throughout, calibration thresholds included, but it
+// is this historical scale rather than one checked against a meter.
+pub const DEFAULT_CURRENT_SCALE: f32 = 6.5 / 100.0;
+
+// The calibration sweep has always multiplied STS3215 readings by a further
+// 6.5 (its model check before per-model scaling), and the current
+// thresholds in use were tuned against that. Keeping the factor keeps those
+// thresholds meaning the same current. sts_read used to leave it out, so it
+// now shows STS3215 current 6.5 times higher than before, matching what
+// calibration compares against.
+pub const STS3215_CURRENT_SCALE: f32 = DEFAULT_CURRENT_SCALE * 6.5;
+
+// Degrees Celsius per raw unit of ServoRegister::CurrentTemperature, the STS
+// series reports whole degrees
+pub const DEFAULT_TEMPERATURE_SCALE: f32 = 1.0;
+
+// Raw units differ between STS models, so a threshold in mA or °C only means
+// the same thing on every joint of a mixed-model robot if each reading is
+// scaled by its own model's factor
+#[derive(Debug, Clone, PartialEq)]
+pub struct ModelScaling {
+    default: f32,
+    models: BTreeMap<u16, f32>,
+}
+
+impl ModelScaling {
+    pub fn new(default: f32) -> Self {
+        Self { default, models: BTreeMap::new() }
+    }
+
+    // ServoRegister::CurrentCurrent to mA
+    pub fn current() -> Self {
+        Self::new(DEFAULT_CURRENT_SCALE).with_model(MODEL_STS3215, STS3215_CURRENT_SCALE)
+    }
+
+    // ServoRegister::CurrentTemperature to °C
+    pub fn temperature() -> Self {
+        Self::new(DEFAULT_TEMPERATURE_SCALE)
+    }
+
+    pub fn with_model(mut self, model: u16, scale: f32) -> Self {
+        self.set_model(model, scale);
+        self
+    }
+
+    // Overrides, e.g. from robot.current_scale or --current-scale
+    pub fn with_models(mut self, models: impl IntoIterator<Item = (u16, f32)>) -> Self {
+        for (model, scale) in models {
+            self.set_model(model, scale);
+        }
+        self
+    }
+
+    pub fn set_model(&mut self, model: u16, scale: f32) {
+        self.models.insert(model, scale);
+    }
+
+    pub fn scale(&self, model: u16) -> f32 {
+        self.models.get(&model).copied().unwrap_or(self.default)
+    }
+}
+
+// A model number as read_model reports it, decimal or 0x hex, e.g. "0x0903"
+pub fn parse_model(text: &str) -> Result<u16> {
+    let parsed = match text.strip_prefix("0x").or_else(|| text.strip_prefix("0X")) {
+        Some(hex) => u16::from_str_radix(hex, 16),
+        None => text.parse(),
+    };
+    parsed.map_err(|_| anyhow!("Invalid model number {:?}, expected e.g. 2307 or 0x0903", text))
+}
+
+// A positive, finite scale, e.g. for ModelScaling::set_model
+pub fn check_scale(scale: f32) -> Result<f32> {
+    if !(scale.is_finite() && scale > 0.0) {
+        bail!("Scale {} must be positive", scale);
+    }
+    Ok(scale)
+}
+
+// "MODEL=SCALE", the form --current-scale takes, e.g. "0x0903=0.4225"
+pub fn parse_model_scale(spec: &str) -> Result<(u16, f32)> {
+    let (model, scale) = spec.split_once('=')
+        .ok_or_else(|| anyhow!("Expected MODEL=SCALE, got {:?}", spec))?;
+    let scale = scale.trim().parse().map_err(|_| anyhow!("Invalid scale {:?}", scale))?;
+    Ok((parse_model(model.trim())?, check_scale(scale)?))
+}
+
+// Position resolution by model, for the center and angle math of
+// mixed-resolution robots. Models not listed use the default, see
+// units::Resolution for the values of each series.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ModelResolution {
+    default: Resolution,
+    models: BTreeMap<u16, Resolution>,
+}
+
+impl ModelResolution {
+    pub fn new(default: Resolution) -> Self {
+        Self { default, models: BTreeMap::new() }
+    }
+
+    pub fn sts() -> Self {
+        Self::new(Resolution::STS).with_model(MODEL_STS3215, Resolution::STS)
+    }
+
+    pub fn with_model(mut self, model: u16, resolution: Resolution) -> Self {
+        self.set_model(model, resolution);
+        self
+    }
+
+    pub fn set_model(&mut self, model: u16, resolution: Resolution) {
+        self.models.insert(model, resolution);
+    }
+
+    pub fn resolution(&self, model: u16) -> Resolution {
+        self.models.get(&model).copied().unwrap_or(self.default)
+    }
+}
+
+impl Default for ModelResolution {
+    fn default() -> Self {
+        Self::sts()
+    }
+}
+
+// Sanity checks on a decoded ServoInfo. A truncated or corrupted frame can
+// still decode into plausible looking values, acting on it records bogus
+// positions, e.g. a calibration stop at 0.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ReadingChecks {
+    // Position exactly 0 while moving, what a zero-filled partial frame
+    // looks like. A joint passing through 0 at speed is flagged too.
+    pub zero_position_moving: bool,
+    // Values a servo can't report: position past 4095, an unknown torque
+    // switch state, 0 V while it answers, or speed beyond `max_speed`
+    pub ranges: bool,
+    // ticks/s, well above the fastest the servo can turn
+    pub max_speed: u16,
+}
+
+impl Default for ReadingChecks {
+    fn default() -> Self {
+        Self { zero_position_moving: true, ranges: true, max_speed: 5000 }
+    }
+}
+
+impl ReadingChecks {
+    pub const NONE: ReadingChecks = ReadingChecks { zero_position_moving: false, ranges: false, max_speed: u16::MAX };
+
+    // Why `info` can't be right, None if it passes every enabled check
+    pub fn implausibility(&self, info: &ServoInfo) -> Option<String> {
+        let speed = info.speed();
+        if self.zero_position_moving && info.current_location == 0 && speed != 0 {
+            return Some(format!("position 0 while moving at {} ticks/s", speed));
+        }
+        if !self.ranges {
+            return None;
+        }
+        if !(0..=4095).contains(&info.current_location) {
+            return Some(format!("position {} outside 0-4095", info.current_location));
+        }
+        if info.torque_switch > 2 {
+            return Some(format!("torque switch {}", info.torque_switch));
+        }
+        if info.current_voltage == 0 {
+            return Some("0 V supply".to_string());
+        }
+        if speed.unsigned_abs() > self.max_speed {
+            return Some(format!("speed {} ticks/s beyond {}", speed, self.max_speed));
+        }
+        None
+    }
+
+    pub fn check(&self, id: u8, info: &ServoInfo) -> Result<()> {
+        match self.implausibility(info) {
+            Some(reason) => Err(ServoError::SuspectReading { id, reason }.into()),
+            None => Ok(()),
+        }
+    }
+}
+
+impl ServoInfo {
+    // current_current in mA, `scale` from ModelScaling::current for this servo's model
+    pub fn scaled_current(&self, scale: f32) -> f32 {
+        self.current_current as f32 * scale
+    }
+
+    // current_temperature in °C, `scale` from ModelScaling::temperature for this servo's model
+    pub fn scaled_temperature(&self, scale: f32) -> f32 {
+        self.current_temperature as f32 * scale
+    }
+
+    // Signed present speed in ticks/s, see decode_speed
+    pub fn speed(&self) -> i16 {
+        decode_speed(self.current_speed as u16)
+    }
+
+    // Signed drive duty, see decode_pwm
+    pub fn pwm(&self) -> i16 {
+        decode_pwm(self.current_load as u16)
+    }
+
+    // The same register read as load: the effort the servo exerts in 0.1%
+    // of full torque, positive pushing clockwise. current_load itself is the
+    // raw sign-magnitude value and never negative.
+    pub fn load(&self) -> i16 {
+        self.pwm()
+    }
+
+    // Goal and present speed from the same frame, see SpeedTracking
+    pub fn speed_tracking(&self) -> SpeedTracking {
+        SpeedTracking { goal: decode_speed(self.running_speed), present: self.speed() }
+    }
+}
+
+// Commanded vs measured speed, both signed in ticks/s, the speed
+// counterpart to goal vs present position. In ServoMode::ConstantSpeed the
+// goal is the commanded wheel speed; in position mode it's the speed limit
+// of the move (0 for the maximum) and only its magnitude means anything.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SpeedTracking {
+    pub goal: i16,
+    pub present: i16,
+}
+
+impl SpeedTracking {
+    // Positive when the joint runs slower than commanded in the commanded
+    // direction, e.g. a wheel held back by its load
+    pub fn error(&self) -> i32 {
+        (self.goal as i32 - self.present as i32) * self.goal.signum() as i32
+    }
+}
+
+// `data` as read from RunningSpeed up to and including CurrentSpeed
+pub fn decode_speed_tracking(data: &[u8]) -> SpeedTracking {
+    let present_at = (ServoRegister::CurrentSpeed as u8 - ServoRegister::RunningSpeed as u8) as usize;
+    SpeedTracking {
+        goal: decode_speed(read_u16_le(data, 0)),
+        present: decode_speed(read_u16_le(data, present_at)),
+    }
+}
+
+// Re-enables the readout when dropped, unless released first
+struct ReadoutGuard<'a> {
+    servo: &'a Servo,
+    armed: bool,
+}
+
+impl ReadoutGuard<'_> {
+    fn release(mut self) -> Result<()> {
+        self.armed = false;
+        self.servo.enable_readout()
+    }
+}
+
+impl Drop for ReadoutGuard<'_> {
+    fn drop(&mut self) {
+        // Only reached on a panic, there's no one left to report to
+        if self.armed {
+            let _ = self.servo.enable_readout();
+        }
+    }
+}
+
+// Backend independent helpers built on top of Servo::read / Servo::write
+impl Servo {
+    // The readout is the background loop of the milkv firmware that keeps
+    // polling every servo into shared memory for read_info and friends. It
+    // competes for the bus with anything else, and a reply meant for it can
+    // get mixed up with one for a bulk read or write, so tools pause it
+    // around theirs. The host backend has no readout.
+    //
+    // Runs `f` with the readout disabled and re-enables it afterwards, even
+    // when `f` fails or panics. An error from `f` takes precedence over one
+    // re-enabling.
+    pub fn with_readout_disabled<T>(&self, f: impl FnOnce(&Servo) -> Result<T>) -> Result<T> {
+        self.disable_readout()?;
+        let guard = ReadoutGuard { servo: self, armed: true };
+        let result = f(self);
+        let enabled = guard.release();
+        let value = result?;
+        enabled?;
+        Ok(value)
+    }
+
+    // read_info that fails with ServoError::SuspectReading instead of
+    // returning a frame that fails `checks`
+    pub fn read_info_checked(&self, id: u8, checks: &ReadingChecks) -> Result<ServoInfo> {
+        let info = self.read_info(id)?;
+        checks.check(id, &info)?;
+        Ok(info)
+    }
+
+    pub(crate) fn read_exact(&self, id: u8, register: ServoRegister, length: u8) -> Result<Vec<u8>> {
+        let data = self.read(id, register, length)?;
+        if data.len() != length as usize {
+            bail!("Failed to read {:?} from servo {}: expected {} bytes, got {}", register, id, length, data.len());
+        }
+        Ok(data)
+    }
+
+    // Refuses servos that fail the compatibility check, so nothing is ever
+    // written to EEPROM at addresses that don't match its memory table
+    pub fn unlock_eeprom(&self, id: u8) -> Result<()> {
+        self.check_compatibility(id)?;
+        self.set_memory_lock(id, MemoryLockState::Unlocked)
+    }
+
+    // Relock the EEPROM and read ServoRegister::LockMark back to make sure
+    // it took, retrying a few times. A lock write lost to noise would leave
+    // the EEPROM writable, where a corrupted packet on a vibrating robot can
+    // overwrite the ID, baud rate or calibration.
+    pub fn lock_eeprom(&self, id: u8) -> Result<()> {
+        let attempts = relock_with_retry(
+            EEPROM_LOCK_ATTEMPTS,
+            || self.set_memory_lock(id, MemoryLockState::Locked),
+            || {
+                sleep(EEPROM_WRITE_DELAY);
+                Ok(self.read_exact(id, ServoRegister::LockMark, 1)?[0])
+            },
+        ).map_err(|e| e.context(format!("Failed to lock the EEPROM of servo {}", id)))?;
+        if attempts > 1 {
+            eprintln!("Warning: EEPROM of servo {} only locked on attempt {}", id, attempts);
+        }
+        Ok(())
+    }
+
+    // Single EEPROM write wrapped in unlock/relock. The lock is restored even
+    // if the write itself fails.
+    pub(crate) fn write_eeprom(&self, id: u8, register: ServoRegister, data: &[u8]) -> Result<()> {
+        self.unlock_eeprom(id)?;
+        sleep(EEPROM_WRITE_DELAY);
+        let written = self.write(id, register, data);
+        sleep(EEPROM_WRITE_DELAY);
+        self.lock_eeprom(id)?;
+        written
+    }
+
+    // Soft stop: speed 0 on every servo with a single broadcast write.
+    // Joints in ServoMode::ConstantSpeed stop and hold with torque on;
+    // position mode joints keep their goal, see Robot::hold. Fire-and-forget,
+    // see BROADCAST_ID.
+    pub fn stop_all(&self) -> Result<()> {
+        self.set_speed(BROADCAST_ID, 0, ServoDirection::Clockwise)
+    }
+
+    // Emergency stop: speed 0 and torque off, the robot goes limp instead of
+    // holding and falls if it isn't supported. Both are broadcast first so
+    // every servo stops at once, then torque off is written to each of `ids`
+    // again and ServoRegister::TorqueSwitch read back, since broadcasts are
+    // never confirmed and the host backend doesn't report failed writes.
+    // Carries on past servos that don't answer and reports them at the end.
+    pub fn estop_all(&self, ids: &[u8]) -> Result<()> {
+        let stopped = self.set_speed(BROADCAST_ID, 0, ServoDirection::Clockwise);
+        let released = self.set_torque_mode(BROADCAST_ID, TorqueMode::Disabled);
+        let failed: Vec<u8> = ids.iter()
+            .copied()
+            .filter(|&id| !self.confirm_torque_off(id))
+            .collect();
+        stopped?;
+        released?;
+        if !failed.is_empty() {
+            bail!("Emergency stop could not confirm torque off on servos {:?}", failed);
+        }
+        Ok(())
+    }
+
+    fn confirm_torque_off(&self, id: u8) -> bool {
+        let _ = self.set_torque_mode(id, TorqueMode::Disabled);
+        matches!(self.read_exact(id, ServoRegister::TorqueSwitch, 1), Ok(data) if data[0] == TorqueMode::Disabled as u8)
+    }
+
+    pub fn read_position(&self, id: u8) -> Result<i16> {
+        Ok(self.read_u16(id, ServoRegister::CurrentLocation)? as i16)
+    }
+
+    pub fn read_speed(&self, id: u8) -> Result<i16> {
+        Ok(decode_speed(self.read_u16(id, ServoRegister::CurrentSpeed)?))
+    }
+
+    // Both speeds in a single read, so they describe the same moment
+    pub fn read_speed_tracking(&self, id: u8) -> Result<SpeedTracking> {
+        let length = ServoRegister::CurrentSpeed as u8 + 2 - ServoRegister::RunningSpeed as u8;
+        let data = self.read_exact(id, ServoRegister::RunningSpeed, length)?;
+        Ok(decode_speed_tracking(&data))
+    }
+
+    // The STS series has no separate PWM register: the present load
+    // (ServoRegister::CurrentLoad) is the duty the controller is driving
+    // with. Near full duty at zero speed is a stall, near zero duty while
+    // off target means the controller isn't driving at all.
+    pub fn read_pwm(&self, id: u8) -> Result<i16> {
+        Ok(decode_pwm(self.read_u16(id, ServoRegister::CurrentLoad)?))
+    }
+
+    // Two single-byte registers, main version as the high byte
+    pub fn read_model(&self, id: u8) -> Result<u16> {
+        let data = self.read_exact(id, ServoRegister::ServoMainVersion, 2)?;
+        Ok(read_u16_be(&data, 0))
+    }
+
+    // Firmware (major, minor) version
+    pub fn read_firmware_version(&self, id: u8) -> Result<(u8, u8)> {
+        let data = self.read_exact(id, ServoRegister::FirmwareMajorVersion, 2)?;
+        Ok((data[0], data[1]))
+    }
+
+    // None if this servo's model doesn't store a serial number
+    pub fn read_serial(&self, id: u8) -> Result<Option<u32>> {
+        let model = self.read_model(id)?;
+        let Some(&(_, register, length)) = SERIAL_NUMBER_REGISTERS.iter().find(|(m, _, _)| *m == model) else {
+            return Ok(None);
+        };
+        let data = self.read_exact(id, register, length)?;
+        Ok(Some(decode_serial(&data)))
+    }
+
+    // Present acceleration in ticks/s², None if this servo's model doesn't
+    // report it
+    pub fn read_present_acceleration(&self, id: u8) -> Result<Option<i16>> {
+        let model = self.read_model(id)?;
+        let Some(&(_, register)) = PRESENT_ACCELERATION_REGISTERS.iter().find(|(m, _)| *m == model) else {
+            return Ok(None);
+        };
+        Ok(Some(self.read_u16(id, register)? as i16))
+    }
+
+    pub fn read_scale(&self, id: u8, scaling: &ModelScaling) -> Result<f32> {
+        Ok(scaling.scale(self.read_model(id)?))
+    }
+
+    pub fn read_resolution(&self, id: u8, resolutions: &ModelResolution) -> Result<Resolution> {
+        Ok(resolutions.resolution(self.read_model(id)?))
+    }
+
+    pub fn read_temperature(&self, id: u8, scaling: &ModelScaling) -> Result<f32> {
+        let data = self.read_exact(id, ServoRegister::CurrentTemperature, 1)?;
+        Ok(data[0] as f32 * self.read_scale(id, scaling)?)
+    }
+
+    pub fn read_mode(&self, id: u8) -> Result<ServoMode> {
+        let data = self.read_exact(id, ServoRegister::OperationMode, 1)?;
+        ServoMode::try_from(data[0])
+    }
+
+    // Degrees from the center of the calibrated range
+    pub fn set_position_deg(&self, id: u8, degrees: f32) -> Result<()> {
+        let target = CENTER_POSITION as i32 + deg_to_ticks(degrees);
+        if !(0..=4095).contains(&target) {
+            bail!("{} degrees is outside the position range of servo {}", degrees, id);
+        }
+        self.write_u16(id, ServoRegister::TargetLocation, target as u16)
+    }
+
+    // Constant speed mode only, `from` being the speed the servo runs at now
+    pub fn set_speed_ramped(&self, id: u8, from: u16, to: u16, direction: ServoDirection, ramp: &SpeedRamp) -> Result<()> {
+        let interval = ramp.duration / ramp.steps.max(1) as u32;
+        for speed in ramp_speeds(from, to, ramp.steps) {
+            self.set_speed(id, speed, direction)?;
+            sleep(interval);
+        }
+        Ok(())
+    }
+
+    // Goal, goal time and speed in one write, so the servo never starts
+    // towards the goal with a stale time, see goal_time_ms
+    pub fn move_to_in_time(&self, id: u8, target: i16, time_ms: u16) -> Result<()> {
+        let mut data = [0; 6];
+        data[0..2].copy_from_slice(&write_i16_le(target));
+        data[2..4].copy_from_slice(&write_u16_le(time_ms));
+        data[4..6].copy_from_slice(&write_u16_le(0));
+        self.write(id, ServoRegister::TargetLocation, &data)
+    }
+
+    pub fn read_goal_time(&self, id: u8) -> Result<u16> {
+        self.read_u16(id, ServoRegister::RunningTime)
+    }
+
+    // Commanding and waiting are kept apart: set_position_nowait,
+    // move_to_in_time, set_position_deg and set_speed return as soon as the
+    // servo acknowledges the write, for loops that command at their own
+    // rate. The methods that block poll or sleep until done or timed out:
+    // move_to_and_wait, move_to_and_time, wait_settled, set_speed_ramped and
+    // the calibration sweeps.
+    pub fn set_position_nowait(&self, id: u8, target: i16) -> Result<()> {
+        command_position(target, |register, data| self.write(id, register, data))
+    }
+
+    // Blocks until settled or settle.timeout
+    pub fn move_to_and_wait(&self, id: u8, target: i16, settle: &SettleConfig) -> Result<i16> {
+        self.set_position_nowait(id, target)?;
+        self.wait_settled(&[(id, target)], settle)?;
+        self.read_position(id)
+    }
+
+    // move_to_and_wait, also timing the move and tracking its peak current,
+    // `current_scale` from ModelScaling::current for this servo's model
+    pub fn move_to_and_time(&self, id: u8, target: i16, settle: &SettleConfig, current_scale: f32) -> Result<SettleTiming> {
+        self.set_position_nowait(id, target)?;
+        time_settle(target, settle, || {
+            let info = self.read_info(id)?;
+            Ok((info.current_location, info.scaled_current(current_scale)))
+        })
+    }
+
+    // Poll until every joint is within tolerance of its target
+    pub fn wait_settled(&self, targets: &[(u8, i16)], settle: &SettleConfig) -> Result<()> {
+        let start = Instant::now();
+        loop {
+            let mut unsettled = Vec::new();
+            for &(id, target) in targets {
+                let position = self.read_position(id)?;
+                if !settle.is_settled(position, target) {
+                    unsettled.push((id, position, target));
+                }
+            }
+            if unsettled.is_empty() {
+                return Ok(());
+            }
+            if start.elapsed() >= settle.timeout {
+                let details: Vec<String> = unsettled.iter()
+                    .map(|(id, position, target)| format!("servo {} at {} (target {})", id, position, target))
+                    .collect();
+                bail!("Timed out after {:?} waiting to settle within {} ticks: {}", settle.timeout, settle.tolerance, details.join(", "));
+            }
+            sleep(settle.poll_interval);
+        }
+    }
+
+    // Relative move for servos in ServoMode::Step, e.g. a gripper indexing
+    // in discrete amounts. Refuses to run in any other mode since the same
+    // register is an absolute target in position mode.
+    pub fn step(&self, id: u8, steps: u16, direction: ServoDirection) -> Result<()> {
+        let mode = self.read_mode(id)?;
+        if mode != ServoMode::Step {
+            bail!("Servo {} is in {:?} mode, set ServoMode::Step before sending step commands", id, mode);
+        }
+        self.write(id, ServoRegister::TargetLocation, &encode_step(steps, direction)?)
+    }
+
+    // The servo switches baud as soon as the register is written, so the
+    // relock has to be sent at the new baud before returning the bus to its
+    // previous rate. Afterwards the servo only answers at `baud_rate`.
+    pub fn set_baud(&self, id: u8, baud_rate: u32) -> Result<()> {
+        let value = baud_register_value(baud_rate)?;
+        let bus_baud_rate = self.bus_baud_rate()?;
+
+        self.unlock_eeprom(id)?;
+        sleep(EEPROM_WRITE_DELAY);
+        self.write(id, ServoRegister::BaudRate, &[value])?;
+        sleep(EEPROM_WRITE_DELAY);
+
+        self.set_bus_baud_rate(baud_rate)?;
+        let locked = self.lock_eeprom(id);
+        self.set_bus_baud_rate(bus_baud_rate)?;
+        locked
+    }
+}
+
+// Sends `lock` and checks `read_lock` reads back MemoryLockState::Locked, up
+// to `attempts` times. Returns the attempt it took on; a failed read counts
+// as not locked. Only fails after the last attempt, loudly, since the
+// EEPROM is then left writable.
+pub fn relock_with_retry<L, R>(attempts: usize, mut lock: L, mut read_lock: R) -> Result<usize>
+where
+    L: FnMut() -> Result<()>,
+    R: FnMut() -> Result<u8>,
+{
+    let mut last = None;
+    for attempt in 1..=attempts {
+        let locked = lock().and_then(|_| read_lock());
+        match locked {
+            Ok(state) if state == MemoryLockState::Locked as u8 => return Ok(attempt),
+            Ok(state) => last = Some(format!("lock mark reads {}", state)),
+            Err(e) => last = Some(format!("{:#}", e)),
+        }
+    }
+    bail!(
+        "EEPROM is still UNLOCKED after {} attempts to lock it ({}). Don't run the robot like this, \
+         retry or power cycle the servo",
+        attempts, last.unwrap_or_else(|| "never attempted".to_string())
+    )
+}
+
+pub fn baud_register_value(baud_rate: u32) -> Result<u8> {
+    match BAUD_RATES.iter().position(|&rate| rate == baud_rate) {
+        Some(value) => Ok(value as u8),
+        None => bail!("Unsupported baud rate {}, expected one of {:?}", baud_rate, BAUD_RATES),
+    }
+}
+
+// Speeds are magnitude in bits 0-14 and direction in bit 15, set for
+// counterclockwise. Signed speeds follow Servo::set_speed: clockwise is
+// positive, counterclockwise negative.
+pub fn encode_speed(speed: u16, direction: ServoDirection) -> u16 {
+    let speed = speed & 0x7FFF;
+    if direction == ServoDirection::Clockwise { speed } else { speed | 0x8000 }
+}
+
+pub fn decode_speed(raw: u16) -> i16 {
+    let magnitude = (raw & 0x7FFF) as i16;
+    if raw & 0x8000 != 0 { -magnitude } else { magnitude }
+}
+
+// Arguments to Servo::set_speed for a signed speed
+pub fn speed_direction(speed: i16) -> (u16, ServoDirection) {
+    let direction = if speed < 0 { ServoDirection::Counterclockwise } else { ServoDirection::Clockwise };
+    (speed.unsigned_abs().min(0x7FFF), direction)
+}
+
+// Duty in 0.1% steps (-1000..=1000), magnitude in bits 0-9 and direction in bit 10
+pub fn decode_pwm(raw: u16) -> i16 {
+    let magnitude = (raw & 0x3FF) as i16;
+    if raw & 0x400 != 0 { -magnitude } else { magnitude }
+}
+
+// Little endian like every other multi-byte register, up to 4 bytes
+pub fn decode_serial(data: &[u8]) -> u32 {
+    data.iter().take(4).rev().fold(0, |serial, &byte| (serial << 8) | byte as u32)
+}
+
+pub fn encode_step(steps: u16, direction: ServoDirection) -> Result<[u8; 2]> {
+    if steps > MAX_STEPS {
+        bail!("Step count {} exceeds maximum of {}", steps, MAX_STEPS);
+    }
+    let value = if direction == ServoDirection::Clockwise { steps } else { steps | 0x8000 };
+    Ok(write_u16_le(value))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn step_direction_is_bit_15() {
+        assert_eq!(encode_step(100, ServoDirection::Clockwise).unwrap(), [100, 0x00]);
+        assert_eq!(encode_step(100, ServoDirection::Counterclockwise).unwrap(), [100, 0x80]);
+        assert_eq!(encode_step(MAX_STEPS, ServoDirection::Counterclockwise).unwrap(), [0xFF, 0xFF]);
+    }
+
+    #[test]
+    fn step_count_over_max_is_refused() {
+        assert!(encode_step(MAX_STEPS + 1, ServoDirection::Clockwise).is_err());
+    }
+
+    #[test]
+    fn mode_values_round_trip() {
+        for mode in [ServoMode::Position, ServoMode::ConstantSpeed, ServoMode::PWMOpenLoop, ServoMode::Step] {
+            assert_eq!(ServoMode::try_from(mode as u8).unwrap(), mode);
+        }
+        assert!(ServoMode::try_from(4).is_err());
+    }
+
+    #[test]
+    fn baud_rates_map_to_register_values() {
+        assert_eq!(baud_register_value(1_000_000).unwrap(), 0);
+        assert_eq!(baud_register_value(115_200).unwrap(), 4);
+        assert!(baud_register_value(9600).is_err());
+    }
+
+    #[test]
+    fn current_scale_is_per_model_with_a_default() {
+        let scaling = ModelScaling::current();
+        assert_eq!(scaling.scale(MODEL_STS3215), DEFAULT_CURRENT_SCALE * 6.5);
+        assert_eq!(scaling.scale(0x0A05), DEFAULT_CURRENT_SCALE);
+        let scaling = scaling.with_model(0x0A05, 2.0);
+        assert_eq!(scaling.scale(0x0A05), 2.0);
+    }
+
+    #[test]
+    fn scaled_current_is_in_milliamps() {
+        let info = ServoInfo { current_current: 100, ..ServoInfo::default() };
+        assert_eq!(info.scaled_current(6.5), 650.0);
+    }
+
+    #[test]
+    fn settled_within_tolerance_either_side() {
+        let settle = SettleConfig { tolerance: 4, ..SettleConfig::default() };
+        assert!(settle.is_settled(2048, 2048));
+        assert!(settle.is_settled(2044, 2048));
+        assert!(settle.is_settled(2052, 2048));
+        assert!(!settle.is_settled(2053, 2048));
+    }
+
+    #[test]
+    fn pwm_direction_is_bit_10() {
+        assert_eq!(decode_pwm(500), 500);
+        assert_eq!(decode_pwm(0x400 | 500), -500);
+        assert_eq!(decode_pwm(0x400), 0);
+        // Bits above the direction aren't part of the duty
+        assert_eq!(decode_pwm(0x800 | 1000), 1000);
+    }
+
+    #[test]
+    fn serial_is_little_endian_up_to_four_bytes() {
+        assert_eq!(decode_serial(&[0x78, 0x56, 0x34, 0x12]), 0x1234_5678);
+        assert_eq!(decode_serial(&[0x34, 0x12]), 0x1234);
+        assert_eq!(decode_serial(&[0x78, 0x56, 0x34, 0x12, 0xFF]), 0x1234_5678);
+        assert_eq!(decode_serial(&[]), 0);
+    }
+
+    #[test]
+    fn speed_direction_is_bit_15() {
+        assert_eq!(encode_speed(500, ServoDirection::Clockwise), 500);
+        assert_eq!(encode_speed(500, ServoDirection::Counterclockwise), 0x8000 | 500);
+        // The magnitude can't spill into the direction bit
+        assert_eq!(encode_speed(0xFFFF, ServoDirection::Clockwise), 0x7FFF);
+        assert_eq!(decode_speed(0x8000 | 500), -500);
+        assert_eq!(decode_speed(500), 500);
+    }
+
+    #[test]
+    fn signed_speeds_round_trip() {
+        for speed in [-0x7FFF, -500, 0, 500, 0x7FFF] {
+            let (magnitude, direction) = speed_direction(speed);
+            assert_eq!(decode_speed(encode_speed(magnitude, direction)), speed);
+        }
+        assert_eq!(speed_direction(i16::MIN), (0x7FFF, ServoDirection::Counterclockwise));
+    }
+
+    #[test]
+    fn ramp_steps_evenly_and_ends_on_the_target() {
+        assert_eq!(ramp_speeds(1000, 0, 4), [750, 500, 250, 0]);
+        assert_eq!(ramp_speeds(0, 300, 3), [100, 200, 300]);
+        assert_eq!(ramp_speeds(500, 100, 0), [100]);
+    }
+
+    #[test]
+    fn implausible_frames_are_named() {
+        let info = ServoInfo { current_location: 1000, current_voltage: 120, ..ServoInfo::default() };
+        let checks = ReadingChecks::default();
+        assert_eq!(checks.implausibility(&info), None);
+        let moving = encode_speed(100, ServoDirection::Clockwise) as i16;
+        let zeroed = ServoInfo { current_location: 0, current_speed: moving, ..info };
+        assert_eq!(checks.implausibility(&zeroed).unwrap(), "position 0 while moving at 100 ticks/s");
+        assert_eq!(checks.implausibility(&ServoInfo { current_speed: 0, ..zeroed }), None);
+        assert!(checks.implausibility(&ServoInfo { current_location: 4096, ..info }).is_some());
+        assert!(checks.implausibility(&ServoInfo { torque_switch: 3, ..info }).is_some());
+        assert!(checks.implausibility(&ServoInfo { current_voltage: 0, ..info }).is_some());
+        let fast = encode_speed(6000, ServoDirection::Counterclockwise) as i16;
+        assert!(checks.implausibility(&ServoInfo { current_speed: fast, ..info }).is_some());
+        assert_eq!(ReadingChecks::NONE.implausibility(&ServoInfo { current_voltage: 0, current_speed: fast, ..zeroed }), None);
+    }
+
+    #[test]
+    fn goal_time_is_whole_milliseconds_up_to_u16() {
+        assert_eq!(goal_time_ms(Duration::from_micros(1500)).unwrap(), 1);
+        assert_eq!(goal_time_ms(Duration::from_millis(65535)).unwrap(), 65535);
+        assert!(goal_time_ms(Duration::from_millis(65536)).is_err());
+    }
+
+    #[test]
+    fn tracking_error_is_positive_when_lagging_either_way() {
+        assert_eq!(SpeedTracking { goal: 500, present: 450 }.error(), 50);
+        assert_eq!(SpeedTracking { goal: -500, present: -450 }.error(), 50);
+        assert_eq!(SpeedTracking { goal: -500, present: -550 }.error(), -50);
+        assert_eq!(SpeedTracking { goal: 0, present: 30 }.error(), 0);
+    }
+
+    #[test]
+    fn tracking_decodes_from_running_speed_onwards() {
+        let mut data = vec![0; 14];
+        data[..2].copy_from_slice(&encode_speed(300, ServoDirection::Counterclockwise).to_le_bytes());
+        data[12..].copy_from_slice(&encode_speed(280, ServoDirection::Counterclockwise).to_le_bytes());
+        assert_eq!(decode_speed_tracking(&data), SpeedTracking { goal: -300, present: -280 });
+        let info = ServoInfo { running_speed: 300, current_speed: 290, ..ServoInfo::default() };
+        assert_eq!(info.speed_tracking(), SpeedTracking { goal: 300, present: 290 });
+    }
+
+    #[test]
+    fn relock_retries_until_the_mark_reads_locked() {
+        let mut reads = [0, 0, MemoryLockState::Locked as u8].into_iter();
+        let mut locks = 0;
+        let attempt = relock_with_retry(3, || {
+            locks += 1;
+            Ok(())
+        }, || Ok(reads.next().unwrap())).unwrap();
+        assert_eq!((attempt, locks), (3, 3));
+        assert_eq!(relock_with_retry(3, || Ok(()), || Ok(1)).unwrap(), 1);
+    }
+
+    #[test]
+    fn relock_fails_loudly_with_the_last_reason() {
+        let error = relock_with_retry(2, || Ok(()), || Ok(0)).unwrap_err().to_string();
+        assert!(error.starts_with("EEPROM is still UNLOCKED after 2 attempts to lock it (lock mark reads 0)"), "{}", error);
+        // A failed write counts as not locked, without reading back
+        let error = relock_with_retry(2, || bail!("no reply"), || panic!("read after a failed lock")).unwrap_err();
+        assert!(error.to_string().contains("(no reply)"), "{}", error);
+        assert!(relock_with_retry(0, || Ok(()), || Ok(1)).unwrap_err().to_string().contains("never attempted"));
+    }
+
+    #[test]
+    fn settle_timing_tracks_the_peak_current() {
+        let settle = SettleConfig { tolerance: 4, timeout: Duration::from_secs(1), poll_interval: Duration::ZERO };
+        let mut samples = [(2048, 100.0), (2300, 850.0), (2490, 400.0), (2497, 120.0)].into_iter();
+        let timing = time_settle(2500, &settle, || Ok(samples.next().unwrap())).unwrap();
+        assert_eq!((timing.position, timing.peak_current, timing.samples), (2497, 850.0, 4));
+        assert!(timing.elapsed < Duration::from_secs(1));
+    }
+
+    #[test]
+    fn settle_timing_times_out_or_fails_with_the_sample() {
+        let settle = SettleConfig { tolerance: 4, timeout: Duration::from_millis(20), poll_interval: Duration::from_millis(5) };
+        let error = time_settle(2500, &settle, || Ok((2048, 0.0))).unwrap_err();
+        assert_eq!(error.to_string(), "Timed out after 20ms waiting to settle within 4 ticks of 2500, at 2048");
+        assert!(time_settle(2500, &settle, || bail!("no reply")).is_err());
+    }
+
+    #[test]
+    fn resolution_is_per_model_with_a_default() {
+        let scs = Resolution { ticks_per_turn: 1229, max_tick: 1023 };
+        let resolutions = ModelResolution::default();
+        assert_eq!(resolutions.resolution(MODEL_STS3215), Resolution::STS);
+        assert_eq!(resolutions.resolution(0x0A05), Resolution::STS);
+        let resolutions = resolutions.with_model(0x0A05, scs);
+        assert_eq!(resolutions.resolution(0x0A05), scs);
+        assert_eq!(ModelResolution::new(scs).resolution(0x0101), scs);
+    }
+
+    #[test]
+    fn position_command_is_one_target_write() {
+        let mut written = None;
+        command_position(3000, |register, data| {
+            written = Some((register as u8, data.to_vec()));
+            Ok(())
+        }).unwrap();
+        assert_eq!(written, Some((ServoRegister::TargetLocation as u8, vec![0xB8, 0x0B])));
+        assert!(command_position(4096, |_, _| panic!("out of range position written")).is_err());
+        assert!(command_position(-1, |_, _| panic!("out of range position written")).is_err());
+    }
+
+    #[test]
+    fn model_scales_parse_from_the_command_line() {
+        assert_eq!(parse_model("0x0903").unwrap(), MODEL_STS3215);
+        assert_eq!(parse_model("2307").unwrap(), MODEL_STS3215);
+        assert!(parse_model("sts3215").is_err());
+        assert_eq!(parse_model_scale("0x0903=0.5").unwrap(), (MODEL_STS3215, 0.5));
+        assert!(parse_model_scale("0x0903").is_err());
+        assert!(parse_model_scale("0x0903=-1").is_err());
+        assert!(parse_model_scale("0x0903=nan").is_err());
+    }
+
+    #[test]
+    fn overrides_replace_the_built_in_scales() {
+        let scaling = ModelScaling::current().with_models([(MODEL_STS3215, 0.5), (0x0A01, 0.1)]);
+        assert_eq!(scaling.scale(MODEL_STS3215), 0.5);
+        assert_eq!(scaling.scale(0x0A01), 0.1);
+        assert_eq!(scaling.scale(0x0001), DEFAULT_CURRENT_SCALE);
+        assert_eq!(ModelScaling::current().scale(MODEL_STS3215), STS3215_CURRENT_SCALE);
+    }
+
+    #[cfg(not(feature = "milkv"))]
+    mod bus {
+        use super::*;
+        use crate::hal::mock::{MockBus, MockWrite};
+
+        #[test]
+        fn set_baud_moves_the_servo_and_keeps_the_bus_rate() {
+            let bus = MockBus::new(&[1]);
+            let servo = Servo::mock(&bus);
+            servo.set_baud(1, 500_000).unwrap();
+            assert_eq!(bus.u8(1, ServoRegister::BaudRate), 1);
+            assert_eq!(bus.u8(1, ServoRegister::LockMark), MemoryLockState::Locked as u8);
+            assert_eq!(servo.bus_baud_rate().unwrap(), 1_000_000);
+            assert!(servo.ping(1).is_err());
+            servo.set_bus_baud_rate(500_000).unwrap();
+            assert!(servo.ping(1).is_ok());
+        }
+
+        #[test]
+        fn step_is_refused_outside_step_mode() {
+            let bus = MockBus::new(&[1]);
+            let servo = Servo::mock(&bus);
+            assert!(servo.step(1, 10, ServoDirection::Clockwise).is_err());
+            assert_eq!(bus.u16(1, ServoRegister::TargetLocation), 2048);
+        }
+
+        #[test]
+        fn step_writes_the_relative_count_in_step_mode() {
+            let bus = MockBus::new(&[1]);
+            bus.set_u8(1, ServoRegister::OperationMode, ServoMode::Step as u8);
+            let servo = Servo::mock(&bus);
+            servo.step(1, 10, ServoDirection::Counterclockwise).unwrap();
+            assert_eq!(bus.u16(1, ServoRegister::TargetLocation), 0x800A);
+        }
+
+        #[test]
+        fn model_is_read_big_endian() {
+            let bus = MockBus::new(&[1]);
+            let servo = Servo::mock(&bus);
+            assert_eq!(servo.read_model(1).unwrap(), MODEL_STS3215);
+            assert_eq!(servo.read_scale(1, &ModelScaling::current()).unwrap(), DEFAULT_CURRENT_SCALE * 6.5);
+        }
+
+        // Servos that reach their goal straight away
+        fn follow_targets(bus: &MockBus) {
+            bus.on_packet(|servos| {
+                let ids: Vec<u8> = servos.memory.keys().copied().collect();
+                for id in ids {
+                    let target = servos.u16(id, ServoRegister::TargetLocation);
+                    servos.set_u16(id, ServoRegister::CurrentLocation, target);
+                }
+            });
+        }
+
+        #[test]
+        fn move_to_and_wait_returns_the_settled_position() {
+            let bus = MockBus::new(&[1]);
+            follow_targets(&bus);
+            let servo = Servo::mock(&bus);
+            assert_eq!(servo.move_to_and_wait(1, 1000, &SettleConfig::default()).unwrap(), 1000);
+        }
+
+        #[test]
+        fn wait_settled_times_out_naming_the_stuck_servo() {
+            let bus = MockBus::new(&[1, 2]);
+            bus.set_u16(2, ServoRegister::CurrentLocation, 1000);
+            let servo = Servo::mock(&bus);
+            let settle = SettleConfig { timeout: Duration::from_millis(30), poll_interval: Duration::from_millis(5), ..SettleConfig::default() };
+            let err = servo.wait_settled(&[(1, 2048), (2, 2048)], &settle).unwrap_err().to_string();
+            assert!(err.contains("servo 2 at 1000"), "{}", err);
+            assert!(!err.contains("servo 1 "), "{}", err);
+        }
+
+        #[test]
+        fn pwm_is_read_from_the_load_register() {
+            let bus = MockBus::new(&[1]);
+            bus.set_u16(1, ServoRegister::CurrentLoad, 0x400 | 250);
+            assert_eq!(Servo::mock(&bus).read_pwm(1).unwrap(), -250);
+        }
+
+        #[test]
+        fn firmware_is_read_and_the_sts3215_has_no_serial() {
+            let bus = MockBus::new(&[1]);
+            let servo = Servo::mock(&bus);
+            assert_eq!(servo.read_firmware_version(1).unwrap(), (3, 10));
+            assert_eq!(servo.read_serial(1).unwrap(), None);
+        }
+
+        #[test]
+        fn degrees_are_from_center() {
+            let bus = MockBus::new(&[1]);
+            let servo = Servo::mock(&bus);
+            servo.set_position_deg(1, -90.0).unwrap();
+            assert_eq!(bus.u16(1, ServoRegister::TargetLocation), 1024);
+            assert!(servo.set_position_deg(1, 180.0).is_err());
+            assert_eq!(bus.u16(1, ServoRegister::TargetLocation), 1024);
+        }
+
+        #[test]
+        fn present_speed_is_signed() {
+            let bus = MockBus::new(&[1]);
+            bus.set_u16(1, ServoRegister::CurrentSpeed, encode_speed(300, ServoDirection::Counterclockwise));
+            let servo = Servo::mock(&bus);
+            assert_eq!(servo.read_speed(1).unwrap(), -300);
+            assert_eq!(servo.read_info(1).unwrap().speed(), -300);
+        }
+
+        #[test]
+        fn ramped_speed_is_written_step_by_step() {
+            let bus = MockBus::new(&[1]);
+            let servo = Servo::mock(&bus);
+            let ramp = SpeedRamp { duration: Duration::ZERO, steps: 3 };
+            servo.set_speed_ramped(1, 300, 0, ServoDirection::Counterclockwise, &ramp).unwrap();
+            let speeds: Vec<u16> = bus.writes().iter().map(|write| read_u16_le(&write.data, 0)).collect();
+            assert_eq!(speeds, [encode_speed(200, ServoDirection::Counterclockwise), encode_speed(100, ServoDirection::Counterclockwise), encode_speed(0, ServoDirection::Counterclockwise)]);
+        }
+
+        #[test]
+        fn sts3215_has_no_present_acceleration() {
+            let bus = MockBus::new(&[1]);
+            assert_eq!(Servo::mock(&bus).read_present_acceleration(1).unwrap(), None);
+        }
+
+        #[test]
+        fn checked_read_flags_a_suspect_frame() {
+            let bus = MockBus::new(&[1]);
+            let servo = Servo::mock(&bus);
+            assert_eq!(servo.read_info_checked(1, &ReadingChecks::default()).unwrap().current_location, 2048);
+            bus.set_u8(1, ServoRegister::CurrentVoltage, 0);
+            let error = servo.read_info_checked(1, &ReadingChecks::default()).unwrap_err();
+            assert!(matches!(error.downcast_ref::<ServoError>(), Some(ServoError::SuspectReading { id: 1, .. })), "{}", error);
+            assert!(servo.read_info_checked(1, &ReadingChecks::NONE).is_ok());
+        }
+
+        #[test]
+        fn stop_all_zeroes_every_speed_with_one_broadcast() {
+            let bus = MockBus::new(&[1, 2]);
+            for id in [1, 2] {
+                bus.set_u16(id, ServoRegister::RunningSpeed, 500);
+            }
+            Servo::mock(&bus).stop_all().unwrap();
+            // Split per servo by the mock, from a single packet
+            let writes: Vec<(u8, u8, Vec<u8>)> = bus.writes().into_iter().map(|write| (write.id, write.address, write.data)).collect();
+            assert_eq!(writes, [(1, ServoRegister::RunningSpeed as u8, vec![0, 0]), (2, ServoRegister::RunningSpeed as u8, vec![0, 0])]);
+        }
+
+        #[test]
+        fn timed_move_writes_goal_time_and_zero_speed_together() {
+            let bus = MockBus::new(&[1]);
+            bus.set_u16(1, ServoRegister::RunningSpeed, 500);
+            let servo = Servo::mock(&bus);
+            servo.move_to_in_time(1, 3000, 750).unwrap();
+            assert_eq!(bus.writes().len(), 1);
+            assert_eq!(bus.u16(1, ServoRegister::TargetLocation), 3000);
+            assert_eq!(bus.u16(1, ServoRegister::RunningSpeed), 0);
+            assert_eq!(servo.read_goal_time(1).unwrap(), 750);
+        }
+
+        #[test]
+        fn speed_tracking_is_read_in_one_frame() {
+            let bus = MockBus::new(&[1]);
+            bus.set_u16(1, ServoRegister::RunningSpeed, encode_speed(400, ServoDirection::Clockwise));
+            bus.set_u16(1, ServoRegister::CurrentSpeed, encode_speed(380, ServoDirection::Clockwise));
+            assert_eq!(Servo::mock(&bus).read_speed_tracking(1).unwrap(), SpeedTracking { goal: 400, present: 380 });
+        }
+
+        #[test]
+        fn readout_guard_passes_the_closure_value_through() {
+            let bus = MockBus::new(&[1]);
+            let servo = Servo::mock(&bus);
+            let location = servo.with_readout_disabled(|servo| Ok(servo.read_info(1)?.current_location)).unwrap();
+            assert_eq!(location, 2048);
+            // Nothing of its own goes out on a bus without a readout
+            assert!(bus.writes().is_empty());
+        }
+
+        #[test]
+        fn readout_guard_passes_the_closure_error_through() {
+            let bus = MockBus::new(&[1]);
+            let servo = Servo::mock(&bus);
+            let error = servo.with_readout_disabled(|_| -> Result<()> { bail!("sweep failed") }).unwrap_err();
+            assert_eq!(error.to_string(), "sweep failed");
+            // The servo is still usable afterwards
+            servo.write(1, ServoRegister::TargetLocation, &write_u16_le(3000)).unwrap();
+            assert_eq!(bus.u16(1, ServoRegister::TargetLocation), 3000);
+        }
+
+        #[test]
+        fn lost_lock_write_is_sent_again() {
+            let bus = MockBus::new(&[1]);
+            let mut packets = 0;
+            // Unlocked again right before the first read back
+            bus.on_packet(move |servos| {
+                packets += 1;
+                if packets == 2 {
+                    servos.set_u8(1, ServoRegister::LockMark, 0);
+                }
+            });
+            Servo::mock(&bus).lock_eeprom(1).unwrap();
+            let locks = bus.writes().iter().filter(|write| write.address == ServoRegister::LockMark as u8).count();
+            assert_eq!(locks, 2);
+            assert_eq!(bus.u8(1, ServoRegister::LockMark), 1);
+        }
+
+        #[test]
+        fn eeprom_left_unlocked_is_an_error() {
+            let bus = MockBus::new(&[1]);
+            bus.set_u8(1, ServoRegister::LockMark, 0);
+            bus.refuse(1, ServoRegister::LockMark);
+            let error = Servo::mock(&bus).lock_eeprom(1).unwrap_err();
+            assert_eq!(error.to_string(), "Failed to lock the EEPROM of servo 1");
+            assert!(format!("{:#}", error).contains(&format!("after {} attempts", EEPROM_LOCK_ATTEMPTS)), "{:#}", error);
+            assert_eq!(bus.writes().len(), EEPROM_LOCK_ATTEMPTS);
+        }
+
+        #[test]
+        fn timed_move_follows_the_joint_to_its_goal() {
+            let bus = MockBus::new(&[1]);
+            // 100 ticks per packet towards the goal, drawing 150 raw while moving
+            bus.on_packet(|servos| {
+                let (location, target) = (servos.u16(1, ServoRegister::CurrentLocation) as i32, servos.u16(1, ServoRegister::TargetLocation) as i32);
+                let next = location + (target - location).clamp(-100, 100);
+                servos.set_u16(1, ServoRegister::CurrentLocation, next as u16);
+                let current: u16 = if next == location { 20 } else { 150 };
+                servos.memory.get_mut(&1).unwrap()[0x44..0x46].copy_from_slice(&current.to_le_bytes());
+            });
+            let settle = SettleConfig { poll_interval: Duration::from_millis(1), ..SettleConfig::default() };
+            let timing = Servo::mock(&bus).move_to_and_time(1, 2548, &settle, 6.5).unwrap();
+            assert_eq!(timing.position, 2548);
+            assert_eq!(timing.peak_current, 975.0);
+            assert_eq!(timing.samples, 5);
+        }
+
+        #[test]
+        fn resolution_follows_the_model_read() {
+            let bus = MockBus::new(&[1]);
+            let scs = Resolution { ticks_per_turn: 1229, max_tick: 1023 };
+            let resolutions = ModelResolution::sts().with_model(0x0A05, scs);
+            let servo = Servo::mock(&bus);
+            assert_eq!(servo.read_resolution(1, &resolutions).unwrap(), Resolution::STS);
+            bus.set_u16(1, ServoRegister::ServoMainVersion, 0x050A);
+            assert_eq!(servo.read_resolution(1, &resolutions).unwrap(), scs);
+            assert!(servo.read_resolution(2, &resolutions).is_err());
+        }
+
+        #[test]
+        fn nowait_position_returns_after_the_write() {
+            let bus = MockBus::new(&[1]);
+            let servo = Servo::mock(&bus);
+            // The mock never moves, a blocking move would time out
+            servo.set_position_nowait(1, 3000).unwrap();
+            assert_eq!(bus.writes(), [MockWrite { id: 1, address: ServoRegister::TargetLocation as u8, data: vec![0xB8, 0x0B] }]);
+            assert_eq!(servo.read_position(1).unwrap(), 2048);
+            assert!(servo.set_position_nowait(1, 5000).is_err());
+            assert_eq!(bus.writes().len(), 1);
+        }
+    }
+}
@@ -0,0 +1,154 @@
+use anyhow::{Result, bail};
+use crate::endian::{read_u16_le, write_u16_le};
+use crate::hal::{Servo, ServoRegister};
+use crate::robot::Robot;
+
+// Full output, in the register's unit of 0.1% of stall torque
+pub const MAX_TORQUE: u16 = 1000;
+
+// MaxTorque (EEPROM) caps the motor's output at all times, so a gripper
+// with a low cap closes on an object with at most that force and simply
+// stalls there. It's also what TorqueLimit (RAM) is loaded with at power
+// on. ProtectionCurrent is something else: a fault threshold, the servo
+// drops torque altogether once the current stays above it for
+// OverCurrentProtectionTime, it doesn't limit the force below that.
+pub fn encode_max_torque(value: u16) -> Result<[u8; 2]> {
+    if value > MAX_TORQUE {
+        bail!("Max torque {} is above {} (full stall torque)", value, MAX_TORQUE);
+    }
+    Ok(write_u16_le(value))
+}
+
+// Not validated, so an out of range value already stored can be read
+pub fn decode_max_torque(data: [u8; 2]) -> u16 {
+    read_u16_le(&data, 0)
+}
+
+impl Servo {
+    pub fn read_max_torque(&self, id: u8) -> Result<u16> {
+        let data = self.read_exact(id, ServoRegister::MaxTorque, 2)?;
+        Ok(decode_max_torque([data[0], data[1]]))
+    }
+
+    // Stored in EEPROM, and mirrored into TorqueLimit so it holds right away
+    // instead of from the next power on
+    pub fn write_max_torque(&self, id: u8, value: u16) -> Result<()> {
+        let data = encode_max_torque(value)?;
+        self.write_eeprom(id, ServoRegister::MaxTorque, &data)?;
+        let written = self.read_max_torque(id)?;
+        if written != value {
+            bail!("Servo {} reads back a max torque of {} after writing {}", id, written, value);
+        }
+        self.write_servo_memory(id, ServoRegister::TorqueLimit, value)
+    }
+}
+
+// TorqueLimit for a joint at session start: its configured max torque,
+// lowered further by safe mode's cap. None leaves the servo as it is.
+pub fn session_torque_limit(max_torque: Option<u16>, safe_mode: Option<u16>) -> Option<u16> {
+    match (max_torque, safe_mode) {
+        (Some(max_torque), Some(safe_mode)) => Some(max_torque.min(safe_mode)),
+        (max_torque, safe_mode) => max_torque.or(safe_mode),
+    }
+}
+
+impl Robot {
+    // Called when building a robot. Joints with a configured max_torque get
+    // it in EEPROM, written only if it differs to spare the flash, then
+    // TorqueLimit is set to it or safe mode's cap if lower.
+    pub fn apply_torque_limits(&self) -> Result<()> {
+        let safe_mode = self.safe_mode().map(|safe_mode| safe_mode.torque_limit);
+        for joint in self.joints() {
+            if let Some(max_torque) = joint.max_torque {
+                if self.servo().read_max_torque(joint.id)? != max_torque {
+                    self.servo().write_max_torque(joint.id, max_torque)
+                        .map_err(|e| e.context(format!("Failed to set the max torque of {}", joint.name)))?;
+                }
+            }
+            if let Some(limit) = session_torque_limit(joint.max_torque, safe_mode) {
+                self.servo().write_servo_memory(joint.id, ServoRegister::TorqueLimit, limit)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn max_torque_is_capped_at_full_stall_torque() {
+        assert_eq!(encode_max_torque(400).unwrap(), [0x90, 0x01]);
+        assert_eq!(decode_max_torque([0x90, 0x01]), 400);
+        assert_eq!(encode_max_torque(MAX_TORQUE).unwrap(), write_u16_le(1000));
+        assert!(encode_max_torque(MAX_TORQUE + 1).is_err());
+        // Read back as stored, even if out of range
+        assert_eq!(decode_max_torque(write_u16_le(1500)), 1500);
+    }
+
+    #[test]
+    fn session_limit_takes_the_lower_cap() {
+        assert_eq!(session_torque_limit(Some(400), Some(300)), Some(300));
+        assert_eq!(session_torque_limit(Some(200), Some(300)), Some(200));
+        assert_eq!(session_torque_limit(Some(400), None), Some(400));
+        assert_eq!(session_torque_limit(None, Some(300)), Some(300));
+        assert_eq!(session_torque_limit(None, None), None);
+    }
+
+    #[cfg(not(feature = "milkv"))]
+    mod bus {
+        use super::*;
+        use std::sync::Arc;
+        use crate::hal::mock::MockBus;
+        use crate::robot::Joint;
+        use crate::robot::tests::joint;
+        use crate::safemode::SafeMode;
+
+        fn capped(name: &str, id: u8, max_torque: Option<u16>) -> Joint {
+            Joint { max_torque, ..joint(name, id) }
+        }
+
+        #[test]
+        fn max_torque_holds_right_away() {
+            let bus = MockBus::new(&[1]);
+            let servo = Servo::mock(&bus);
+            servo.write_max_torque(1, 250).unwrap();
+            assert_eq!(servo.read_max_torque(1).unwrap(), 250);
+            assert_eq!(bus.u16(1, ServoRegister::TorqueLimit), 250);
+        }
+
+        #[test]
+        fn out_of_range_max_torque_writes_nothing() {
+            let bus = MockBus::new(&[1]);
+            assert!(Servo::mock(&bus).write_max_torque(1, 1001).is_err());
+            assert!(bus.writes().is_empty());
+        }
+
+        #[test]
+        fn session_start_applies_max_torque_and_safe_mode() {
+            let bus = MockBus::new(&[1, 2, 3]);
+            let joints = vec![capped("gripper", 1, Some(400)), capped("wrist", 2, Some(200)), capped("elbow", 3, None)];
+            let robot = Robot::new(Arc::new(Servo::mock(&bus)), joints)
+                .with_safe_mode(Some(SafeMode { enabled: true, torque_limit: 300, ..SafeMode::default() }));
+            robot.apply_torque_limits().unwrap();
+            let stored = |id| (bus.u16(id, ServoRegister::MaxTorque), bus.u16(id, ServoRegister::TorqueLimit));
+            assert_eq!(stored(1), (400, 300));
+            assert_eq!(stored(2), (200, 200));
+            assert_eq!(stored(3), (1000, 300));
+        }
+
+        #[test]
+        fn matching_max_torque_spares_the_eeprom() {
+            let bus = MockBus::new(&[1, 2]);
+            bus.set_u16(1, ServoRegister::MaxTorque, 400);
+            let robot = Robot::new(Arc::new(Servo::mock(&bus)), vec![capped("gripper", 1, Some(400)), capped("elbow", 2, None)]);
+            robot.apply_torque_limits().unwrap();
+            let writes = bus.writes();
+            assert!(writes.iter().all(|write| write.address != ServoRegister::MaxTorque as u8));
+            // Without safe mode an uncapped joint is left alone
+            assert!(writes.iter().all(|write| write.id == 1));
+            assert_eq!(bus.u16(1, ServoRegister::TorqueLimit), 400);
+        }
+    }
+}
@@ -0,0 +1,362 @@
+use anyhow::{Result, bail};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread::sleep;
+use std::time::{Duration, Instant};
+use crate::hal::Servo;
+
+// In ticks/s and ticks/s²
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MotionLimits {
+    pub max_velocity: f32,
+    pub max_acceleration: f32,
+}
+
+impl MotionLimits {
+    // The lower of both, each limit on its own
+    pub fn min(self, other: MotionLimits) -> Self {
+        Self {
+            max_velocity: self.max_velocity.min(other.max_velocity),
+            max_acceleration: self.max_acceleration.min(other.max_acceleration),
+        }
+    }
+}
+
+// Sine motion around `center`, in the unit of `center` and `amplitude`
+// (joint degrees for Robot::move_group), starting at center heading up
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SineMotion {
+    pub center: f32,
+    pub amplitude: f32,
+    // In Hz
+    pub frequency: f32,
+}
+
+impl SineMotion {
+    pub fn new(center: f32, amplitude: f32, frequency: f32) -> Result<Self> {
+        if !(center.is_finite() && amplitude.is_finite() && amplitude >= 0.0) {
+            bail!("Invalid sine center {} and amplitude {}", center, amplitude);
+        }
+        if !(frequency > 0.0 && frequency.is_finite()) {
+            bail!("Sine frequency must be positive, got {}", frequency);
+        }
+        Ok(Self { center, amplitude, frequency })
+    }
+
+    pub fn position(&self, t: f32) -> f32 {
+        self.center + self.amplitude * (2.0 * std::f32::consts::PI * self.frequency * t).sin()
+    }
+
+    // (t, position) at `rate` Hz for `duration` seconds, from t = 0
+    pub fn samples(&self, duration: f32, rate: f32) -> impl Iterator<Item = (f32, f32)> + '_ {
+        let count = (duration * rate).floor() as usize;
+        (0..=count).map(move |i| {
+            let t = i as f32 / rate;
+            (t, self.position(t))
+        })
+    }
+}
+
+// Trapezoidal velocity profile between two positions, in ticks, ticks/s and
+// ticks/s². Accelerates at max_acceleration up to max_velocity, cruises, then
+// decelerates symmetrically. Moves too short to reach max_velocity become
+// triangular with a lower peak.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TrapezoidalProfile {
+    start: f32,
+    distance: f32,
+    peak_velocity: f32,
+    acceleration: f32,
+    accel_time: f32,
+    cruise_time: f32,
+}
+
+impl TrapezoidalProfile {
+    pub fn new(start: i16, goal: i16, max_velocity: f32, max_acceleration: f32) -> Result<Self> {
+        if !(max_velocity > 0.0 && max_acceleration > 0.0) {
+            bail!("Max velocity and acceleration must be positive, got {} and {}", max_velocity, max_acceleration);
+        }
+        let distance = goal as f32 - start as f32;
+        let length = distance.abs();
+
+        let accel_distance = max_velocity * max_velocity / (2.0 * max_acceleration);
+        let (peak_velocity, cruise_time) = if 2.0 * accel_distance >= length {
+            ((length * max_acceleration).sqrt(), 0.0)
+        } else {
+            (max_velocity, (length - 2.0 * accel_distance) / max_velocity)
+        };
+
+        Ok(Self {
+            start: start as f32,
+            distance,
+            peak_velocity,
+            acceleration: max_acceleration,
+            accel_time: peak_velocity / max_acceleration,
+            cruise_time,
+        })
+    }
+
+    pub fn duration(&self) -> f32 {
+        2.0 * self.accel_time + self.cruise_time
+    }
+
+    pub fn peak_velocity(&self) -> f32 {
+        self.peak_velocity
+    }
+
+    pub fn is_triangular(&self) -> bool {
+        self.cruise_time == 0.0
+    }
+
+    // Position at `t` seconds into the move, clamped to the start and goal
+    pub fn position(&self, t: f32) -> f32 {
+        let t = t.clamp(0.0, self.duration());
+        let decel_start = self.accel_time + self.cruise_time;
+        let travelled = if t < self.accel_time {
+            0.5 * self.acceleration * t * t
+        } else if t < decel_start {
+            let accel_distance = 0.5 * self.acceleration * self.accel_time * self.accel_time;
+            accel_distance + self.peak_velocity * (t - self.accel_time)
+        } else {
+            let remaining = self.duration() - t;
+            self.distance.abs() - 0.5 * self.acceleration * remaining * remaining
+        };
+        self.start + travelled * self.distance.signum()
+    }
+
+    // Same path over a longer `duration`, with velocity and acceleration
+    // scaled down to match
+    fn stretched(&self, duration: f32) -> Self {
+        let own = self.duration();
+        if own <= 0.0 || duration <= own {
+            return *self;
+        }
+        let scale = own / duration;
+        Self {
+            peak_velocity: self.peak_velocity * scale,
+            acceleration: self.acceleration * scale * scale,
+            accel_time: self.accel_time / scale,
+            cruise_time: self.cruise_time / scale,
+            ..*self
+        }
+    }
+}
+
+// Synchronized move of several joints: every profile is stretched to the
+// slowest joint's duration so all joints start and finish together
+#[derive(Debug, Clone)]
+pub struct Trajectory {
+    joints: Vec<(u8, TrapezoidalProfile)>,
+    duration: f32,
+}
+
+impl Trajectory {
+    // `moves` as (id, start, goal)
+    pub fn synchronized(moves: &[(u8, i16, i16)], max_velocity: f32, max_acceleration: f32) -> Result<Self> {
+        let limits = MotionLimits { max_velocity, max_acceleration };
+        let moves: Vec<_> = moves.iter().map(|&(id, start, goal)| (id, start, goal, limits)).collect();
+        Self::synchronized_limited(&moves)
+    }
+
+    // Same with limits per joint, `moves` as (id, start, goal, limits).
+    // Stretching only ever lowers a profile's velocity and acceleration, so
+    // no joint exceeds its own limits.
+    pub fn synchronized_limited(moves: &[(u8, i16, i16, MotionLimits)]) -> Result<Self> {
+        let profiles = moves.iter()
+            .map(|&(id, start, goal, limits)| {
+                let profile = TrapezoidalProfile::new(start, goal, limits.max_velocity, limits.max_acceleration)?;
+                Ok((id, profile))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        let duration = profiles.iter().map(|(_, profile)| profile.duration()).fold(0.0, f32::max);
+        let joints = profiles.into_iter()
+            .map(|(id, profile)| (id, profile.stretched(duration)))
+            .collect();
+        Ok(Self { joints, duration })
+    }
+
+    pub fn duration(&self) -> f32 {
+        self.duration
+    }
+
+    pub fn sample(&self, t: f32) -> Vec<(u8, i16)> {
+        self.joints.iter()
+            .map(|(id, profile)| (*id, profile.position(t).round() as i16))
+            .collect()
+    }
+
+    // (t, positions) at `rate` Hz, the last sample always at the goal
+    pub fn samples(&self, rate: f32) -> impl Iterator<Item = (f32, Vec<(u8, i16)>)> + '_ {
+        let count = (self.duration * rate).ceil() as usize;
+        (0..=count).map(move |i| {
+            let t = (i as f32 / rate).min(self.duration);
+            (t, self.sample(t))
+        })
+    }
+
+    // Feed the samples to the servos at `rate` Hz
+    pub fn execute(&self, servo: &Servo, rate: f32) -> Result<()> {
+        self.execute_while(servo, rate, &AtomicBool::new(true)).map(|_| ())
+    }
+
+    // Same, stopping between samples once `running` is cleared. False if
+    // it was, the joints are then left at the last sample sent.
+    pub fn execute_while(&self, servo: &Servo, rate: f32, running: &AtomicBool) -> Result<bool> {
+        if !(rate > 0.0 && rate.is_finite()) {
+            bail!("Invalid trajectory rate {} Hz", rate);
+        }
+        let start = Instant::now();
+        for (t, positions) in self.samples(rate) {
+            let due = Duration::from_secs_f32(t);
+            if let Some(wait) = due.checked_sub(start.elapsed()) {
+                sleep(wait);
+            }
+            if !running.load(Ordering::SeqCst) {
+                return Ok(false);
+            }
+            servo.sync_write_positions(&positions)?;
+        }
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn close(a: f32, b: f32) -> bool {
+        (a - b).abs() < 1e-3
+    }
+
+    #[test]
+    fn long_moves_cruise_at_max_velocity() {
+        let profile = TrapezoidalProfile::new(1000, 2000, 500.0, 1000.0).unwrap();
+        assert!(!profile.is_triangular());
+        assert_eq!(profile.peak_velocity(), 500.0);
+        // 0.5 s each way to reach 500 ticks/s, 750 ticks of cruise
+        assert!(close(profile.duration(), 2.5));
+        assert!(close(profile.position(0.5), 1125.0));
+        assert!(close(profile.position(1.25), 1500.0));
+    }
+
+    #[test]
+    fn short_moves_are_triangular() {
+        let profile = TrapezoidalProfile::new(2000, 1900, 500.0, 1000.0).unwrap();
+        assert!(profile.is_triangular());
+        assert!(close(profile.peak_velocity(), 100_000f32.sqrt()));
+        assert!(close(profile.position(profile.duration() / 2.0), 1950.0));
+    }
+
+    #[test]
+    fn positions_are_clamped_to_start_and_goal() {
+        let profile = TrapezoidalProfile::new(3000, 1000, 500.0, 1000.0).unwrap();
+        assert_eq!(profile.position(-1.0), 3000.0);
+        assert_eq!(profile.position(profile.duration() + 1.0), 1000.0);
+    }
+
+    #[test]
+    fn limits_must_be_positive() {
+        assert!(TrapezoidalProfile::new(0, 100, 0.0, 100.0).is_err());
+        assert!(TrapezoidalProfile::new(0, 100, 100.0, -1.0).is_err());
+    }
+
+    #[test]
+    fn stretching_keeps_the_path_and_slows_it_down() {
+        let profile = TrapezoidalProfile::new(1000, 2000, 500.0, 1000.0).unwrap();
+        let stretched = profile.stretched(5.0);
+        assert!(close(stretched.duration(), 5.0));
+        assert!(close(stretched.peak_velocity(), 250.0));
+        assert!(close(stretched.position(2.5), 1500.0));
+        assert!(close(stretched.position(5.0), 2000.0));
+        // Never made faster
+        assert_eq!(profile.stretched(1.0), profile);
+    }
+
+    #[test]
+    fn synchronized_joints_finish_together() {
+        let trajectory = Trajectory::synchronized(&[(1, 1000, 2000), (2, 2000, 1900)], 500.0, 1000.0).unwrap();
+        assert!(close(trajectory.duration(), 2.5));
+        assert_eq!(trajectory.sample(1.25), vec![(1, 1500), (2, 1950)]);
+        let samples: Vec<_> = trajectory.samples(10.0).collect();
+        assert_eq!(samples.len(), 26);
+        assert_eq!(samples[0].1, vec![(1, 1000), (2, 2000)]);
+        assert_eq!(samples.last().unwrap().1, vec![(1, 2000), (2, 1900)]);
+    }
+
+    #[test]
+    fn motion_limits_min_takes_each_lower_limit() {
+        let a = MotionLimits { max_velocity: 500.0, max_acceleration: 1000.0 };
+        let b = MotionLimits { max_velocity: 800.0, max_acceleration: 200.0 };
+        assert_eq!(a.min(b), MotionLimits { max_velocity: 500.0, max_acceleration: 200.0 });
+        assert_eq!(a.min(MotionLimits { max_velocity: f32::INFINITY, max_acceleration: f32::INFINITY }), a);
+    }
+
+    #[test]
+    fn slowest_joint_limits_set_the_pace() {
+        let fast = MotionLimits { max_velocity: 500.0, max_acceleration: 1000.0 };
+        let slow = MotionLimits { max_velocity: 250.0, max_acceleration: 1000.0 };
+        let trajectory = Trajectory::synchronized_limited(&[(1, 1000, 2000, fast), (2, 1000, 2000, slow)]).unwrap();
+        // 0.25 s each way to reach 250 ticks/s, 937.5 ticks of cruise
+        assert!(close(trajectory.duration(), 4.25));
+        let (fast_profile, slow_profile) = (&trajectory.joints[0].1, &trajectory.joints[1].1);
+        assert!(close(fast_profile.duration(), 4.25));
+        // Its own 2.5 s profile, stretched to match
+        assert!(close(fast_profile.peak_velocity(), 500.0 * 2.5 / 4.25));
+        assert!(close(slow_profile.peak_velocity(), 250.0));
+        let invalid = MotionLimits { max_velocity: 0.0, ..fast };
+        assert!(Trajectory::synchronized_limited(&[(1, 1000, 2000, fast), (2, 1000, 2000, invalid)]).is_err());
+    }
+
+    #[test]
+    fn sine_starts_at_center_heading_up() {
+        let sine = SineMotion::new(10.0, 5.0, 0.5).unwrap();
+        assert!(close(sine.position(0.0), 10.0));
+        assert!(close(sine.position(0.5), 15.0));
+        assert!(close(sine.position(1.5), 5.0));
+        assert!(close(sine.position(2.0), 10.0));
+    }
+
+    #[test]
+    fn sine_samples_include_both_ends() {
+        let sine = SineMotion::new(0.0, 1.0, 1.0).unwrap();
+        let samples: Vec<(f32, f32)> = sine.samples(1.0, 4.0).collect();
+        assert_eq!(samples.len(), 5);
+        assert!(close(samples[1].0, 0.25) && close(samples[1].1, 1.0));
+        assert!(close(samples[4].0, 1.0));
+    }
+
+    #[test]
+    fn sine_parameters_are_checked() {
+        assert!(SineMotion::new(0.0, -1.0, 1.0).is_err());
+        assert!(SineMotion::new(f32::NAN, 1.0, 1.0).is_err());
+        assert!(SineMotion::new(0.0, 1.0, 0.0).is_err());
+        assert!(SineMotion::new(0.0, 0.0, 1.0).is_ok());
+    }
+
+    #[cfg(not(feature = "milkv"))]
+    mod bus {
+        use super::*;
+        use crate::hal::ServoRegister;
+        use crate::hal::mock::MockBus;
+
+        #[test]
+        fn execute_ends_at_the_goals() {
+            let bus = MockBus::new(&[1, 2]);
+            let servo = Servo::mock(&bus);
+            let trajectory = Trajectory::synchronized(&[(1, 2048, 2148), (2, 2048, 1948)], 2000.0, 20000.0).unwrap();
+            trajectory.execute(&servo, 200.0).unwrap();
+            assert_eq!(bus.u16(1, ServoRegister::TargetLocation), 2148);
+            assert_eq!(bus.u16(2, ServoRegister::TargetLocation), 1948);
+            assert!(trajectory.execute(&servo, 0.0).is_err());
+        }
+
+        #[test]
+        fn cleared_running_stops_before_the_first_sample() {
+            let bus = MockBus::new(&[1]);
+            let servo = Servo::mock(&bus);
+            let trajectory = Trajectory::synchronized(&[(1, 2048, 2148)], 2000.0, 20000.0).unwrap();
+            assert!(!trajectory.execute_while(&servo, 200.0, &AtomicBool::new(false)).unwrap());
+            assert!(bus.writes().is_empty());
+            assert!(trajectory.execute_while(&servo, 200.0, &AtomicBool::new(true)).unwrap());
+        }
+    }
+}
@@ -0,0 +1,187 @@
+use serde::Deserialize;
+use std::f32::consts::PI;
+
+// One full turn of the output shaft on the STS series
+pub const TICKS_PER_TURN: i32 = 4096;
+
+// Position resolution of a servo model: `ticks_per_turn` ticks make a full
+// turn of the output shaft and positions run 0..=max_tick, centered on
+// center(). A full-turn encoder has max_tick = ticks_per_turn - 1 and wraps
+// through 0; a model with reduced range stops short of that and never
+// wraps. Known values, see ModelResolution:
+// - STS3215 and the rest of the STS series: 4096 ticks per turn, 0-4095
+// - SCS series: 1024 ticks over 300°, i.e. 1229 per turn, 0-1023
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Resolution {
+    pub ticks_per_turn: i32,
+    pub max_tick: i32,
+}
+
+impl Resolution {
+    pub const STS: Resolution = Resolution { ticks_per_turn: TICKS_PER_TURN, max_tick: TICKS_PER_TURN - 1 };
+
+    pub fn full_turn(&self) -> bool {
+        self.max_tick + 1 >= self.ticks_per_turn
+    }
+
+    pub fn center(&self) -> i32 {
+        (self.max_tick + 1) / 2
+    }
+
+    pub fn ticks_to_deg(&self, ticks: i32) -> f32 {
+        ticks as f32 * 360.0 / self.ticks_per_turn as f32
+    }
+
+    pub fn deg_to_ticks(&self, degrees: f32) -> i32 {
+        (degrees * self.ticks_per_turn as f32 / 360.0).round() as i32
+    }
+
+    // Into 0..ticks_per_turn
+    pub fn wrap(&self, ticks: i32) -> i32 {
+        ticks.rem_euclid(self.ticks_per_turn)
+    }
+}
+
+// Conversions are linear, a tick count maps to an angle of the same sign
+// with no wrap. Servo positions are centered on CENTER_POSITION, subtract it
+// before converting a position to an angle. These assume Resolution::STS,
+// use the Resolution of the model for others.
+pub fn ticks_to_deg(ticks: i32) -> f32 {
+    Resolution::STS.ticks_to_deg(ticks)
+}
+
+// Rounded to the nearest tick, halves away from zero
+pub fn deg_to_ticks(degrees: f32) -> i32 {
+    Resolution::STS.deg_to_ticks(degrees)
+}
+
+// How deg_to_ticks_within turns a fractional tick count into a tick, e.g.
+// rounding = "inside" under [robot]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Rounding {
+    // As deg_to_ticks
+    #[default]
+    Nearest,
+    Floor,
+    Ceil,
+    // Toward the middle of the range, so a target just short of a limit
+    // never rounds onto or past it
+    Inside,
+}
+
+// Ticks from center for `degrees` when the valid range is `min..=max`
+// ticks from center. With `clamp_inside` the result is clamped into the
+// range: a target exactly at a max angle computed through a float mapping
+// can otherwise land one tick past the limit, where the joint stalls against
+// it. Without, out of range results are left for the caller to reject.
+pub fn deg_to_ticks_within(degrees: f32, min: i32, max: i32, rounding: Rounding, clamp_inside: bool) -> i32 {
+    let exact = degrees * TICKS_PER_TURN as f32 / 360.0;
+    let ticks = match rounding {
+        Rounding::Nearest => exact.round(),
+        Rounding::Floor => exact.floor(),
+        Rounding::Ceil => exact.ceil(),
+        Rounding::Inside if exact * 2.0 > (min + max) as f32 => exact.floor(),
+        Rounding::Inside => exact.ceil(),
+    } as i32;
+    if clamp_inside && min <= max {
+        ticks.clamp(min, max)
+    } else {
+        ticks
+    }
+}
+
+pub fn ticks_to_rad(ticks: i32) -> f32 {
+    ticks as f32 * 2.0 * PI / TICKS_PER_TURN as f32
+}
+
+pub fn rad_to_ticks(radians: f32) -> i32 {
+    (radians * TICKS_PER_TURN as f32 / (2.0 * PI)).round() as i32
+}
+
+// Into 0..4096, the range the servo reports positions in
+pub fn wrap_ticks(ticks: i32) -> i32 {
+    Resolution::STS.wrap(ticks)
+}
+
+// Into -180..180
+pub fn wrap_deg(degrees: f32) -> f32 {
+    (degrees + 180.0).rem_euclid(360.0) - 180.0
+}
+
+// Into -PI..PI
+pub fn wrap_rad(radians: f32) -> f32 {
+    (radians + PI).rem_euclid(2.0 * PI) - PI
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn quarter_turn_is_1024_ticks() {
+        assert_eq!(ticks_to_deg(1024), 90.0);
+        assert_eq!(ticks_to_deg(-2048), -180.0);
+        assert_eq!(deg_to_ticks(90.0), 1024);
+        assert_eq!(deg_to_ticks(-90.0), -1024);
+        assert!((ticks_to_rad(1024) - PI / 2.0).abs() < 1e-6);
+        assert_eq!(rad_to_ticks(PI), 2048);
+    }
+
+    #[test]
+    fn half_ticks_round_away_from_zero() {
+        let half_tick = 360.0 / TICKS_PER_TURN as f32 / 2.0;
+        assert_eq!(deg_to_ticks(half_tick), 1);
+        assert_eq!(deg_to_ticks(-half_tick), -1);
+    }
+
+    #[test]
+    fn wraps_land_in_range() {
+        assert_eq!(wrap_ticks(-1), 4095);
+        assert_eq!(wrap_ticks(4096), 0);
+        assert_eq!(wrap_deg(190.0), -170.0);
+        assert_eq!(wrap_deg(-180.0), -180.0);
+        assert_eq!(wrap_deg(180.0), -180.0);
+        assert!((wrap_rad(3.0 * PI / 2.0) + PI / 2.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn fractional_ticks_round_as_configured() {
+        // 0.13° is 1.48 ticks
+        let within = |degrees, rounding| deg_to_ticks_within(degrees, -100, 100, rounding, false);
+        assert_eq!(within(0.13, Rounding::Nearest), 1);
+        assert_eq!(within(0.13, Rounding::Floor), 1);
+        assert_eq!(within(0.13, Rounding::Ceil), 2);
+        assert_eq!(within(-0.13, Rounding::Floor), -2);
+        // Toward the middle of the range on either side
+        assert_eq!(within(0.13, Rounding::Inside), 1);
+        assert_eq!(within(-0.13, Rounding::Inside), -1);
+        assert_eq!(deg_to_ticks_within(0.13, 10, 100, Rounding::Inside, false), 2);
+    }
+
+    #[test]
+    fn clamp_inside_keeps_the_result_in_range() {
+        // 9° is 102.4 ticks
+        assert_eq!(deg_to_ticks_within(9.0, -100, 100, Rounding::Nearest, false), 102);
+        assert_eq!(deg_to_ticks_within(9.0, -100, 100, Rounding::Nearest, true), 100);
+        assert_eq!(deg_to_ticks_within(-9.0, -100, 100, Rounding::Nearest, true), -100);
+        // An empty range isn't clamped to
+        assert_eq!(deg_to_ticks_within(9.0, 100, -100, Rounding::Nearest, true), 102);
+    }
+
+    const SCS: Resolution = Resolution { ticks_per_turn: 1229, max_tick: 1023 };
+
+    #[test]
+    fn resolution_sets_center_and_scale() {
+        assert!(Resolution::STS.full_turn());
+        assert_eq!(Resolution::STS.center(), 2048);
+        assert!(!SCS.full_turn());
+        assert_eq!(SCS.center(), 512);
+        assert!((SCS.ticks_to_deg(1024) - 299.95).abs() < 0.01);
+        assert_eq!(SCS.deg_to_ticks(90.0), 307);
+        assert_eq!(SCS.wrap(1229), 0);
+        // The STS helpers agree with Resolution::STS
+        assert_eq!(deg_to_ticks(90.0), Resolution::STS.deg_to_ticks(90.0));
+        assert_eq!(wrap_ticks(-1), 4095);
+    }
+}
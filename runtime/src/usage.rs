@@ -0,0 +1,173 @@
+use anyhow::{Result, Context};
+use serde::{Serialize, Deserialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use crate::robot::Robot;
+
+// Usage is kept in a file rather than on the servo. The STS3215 has no spare
+// EEPROM register: the reserved bytes at 0x31-0x36 are RAM and lost on power
+// off, and the EEPROM itself is emulated in flash with a limited number of
+// write cycles, which a counter written on every move would wear out.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct JointUsage {
+    // Seconds since the Unix epoch, None if never calibrated
+    pub calibrated_at: Option<u64>,
+    // Saturates at u32::MAX instead of wrapping, so a worn joint never looks new
+    pub moves: u32,
+}
+
+impl JointUsage {
+    // Time since the last calibration, None if never calibrated or if the
+    // clock is behind the timestamp
+    pub fn since_calibration(&self, now: SystemTime) -> Option<Duration> {
+        let calibrated_at = UNIX_EPOCH + Duration::from_secs(self.calibrated_at?);
+        now.duration_since(calibrated_at).ok()
+    }
+
+    // A joint that was never calibrated is always overdue
+    pub fn is_overdue(&self, max_age: Duration, now: SystemTime) -> bool {
+        self.calibrated_at.is_none() || self.since_calibration(now).is_some_and(|age| age > max_age)
+    }
+}
+
+// JSON usage file, keyed by servo ID
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct UsageFile {
+    pub joints: BTreeMap<u8, JointUsage>,
+}
+
+impl UsageFile {
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let contents = fs::read_to_string(path.as_ref())
+            .with_context(|| format!("Failed to read usage file {:?}", path.as_ref()))?;
+        serde_json::from_str(&contents)
+            .with_context(|| format!("Failed to parse usage file {:?}", path.as_ref()))
+    }
+
+    // No usage recorded yet if the file doesn't exist
+    pub fn load_or_default<P: AsRef<Path>>(path: P) -> Result<Self> {
+        if path.as_ref().exists() {
+            Self::load(path)
+        } else {
+            Ok(Self::default())
+        }
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        let contents = serde_json::to_string_pretty(self)?;
+        fs::write(path.as_ref(), contents)
+            .with_context(|| format!("Failed to write usage file {:?}", path.as_ref()))
+    }
+
+    // Zero moves and never calibrated for servos without an entry
+    pub fn read_usage(&self, id: u8) -> JointUsage {
+        self.joints.get(&id).copied().unwrap_or_default()
+    }
+
+    pub fn record_calibration(&mut self, id: u8, at: SystemTime) {
+        let seconds = at.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+        self.joints.entry(id).or_default().calibrated_at = Some(seconds);
+    }
+
+    // `moved` as returned by Robot::move_group
+    pub fn record_moves(&mut self, moved: &[(u8, i16)]) {
+        for &(id, _) in moved {
+            let usage = self.joints.entry(id).or_default();
+            usage.moves = usage.moves.saturating_add(1);
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct MaintenanceEntry {
+    pub name: String,
+    pub id: u8,
+    pub usage: JointUsage,
+    pub overdue: bool,
+}
+
+impl Robot {
+    // Every configured joint, flagged overdue if it was calibrated longer
+    // than `max_age` ago or never
+    pub fn maintenance_report(&self, usage: &UsageFile, max_age: Duration) -> Vec<MaintenanceEntry> {
+        let now = SystemTime::now();
+        self.joints().iter()
+            .map(|joint| {
+                let joint_usage = usage.read_usage(joint.id);
+                MaintenanceEntry {
+                    name: joint.name.clone(),
+                    id: joint.id,
+                    usage: joint_usage,
+                    overdue: joint_usage.is_overdue(max_age, now),
+                }
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DAY: Duration = Duration::from_secs(24 * 60 * 60);
+
+    #[test]
+    fn age_is_since_the_last_calibration() {
+        let now = UNIX_EPOCH + 10 * DAY;
+        let usage = JointUsage { calibrated_at: Some((7 * DAY).as_secs()), moves: 0 };
+        assert_eq!(usage.since_calibration(now), Some(3 * DAY));
+        assert!(!usage.is_overdue(5 * DAY, now));
+        assert!(usage.is_overdue(2 * DAY, now));
+        // Clock behind the timestamp
+        assert_eq!(usage.since_calibration(UNIX_EPOCH + DAY), None);
+        assert!(!usage.is_overdue(DAY, UNIX_EPOCH + DAY));
+    }
+
+    #[test]
+    fn never_calibrated_is_always_overdue() {
+        assert!(JointUsage::default().is_overdue(Duration::MAX, SystemTime::now()));
+    }
+
+    #[test]
+    fn moves_count_per_joint_and_saturate() {
+        let mut usage = UsageFile::default();
+        usage.record_moves(&[(1, 2048), (2, 1000)]);
+        usage.record_moves(&[(1, 2100)]);
+        assert_eq!((usage.read_usage(1).moves, usage.read_usage(2).moves, usage.read_usage(3).moves), (2, 1, 0));
+        usage.joints.insert(4, JointUsage { calibrated_at: None, moves: u32::MAX });
+        usage.record_moves(&[(4, 0)]);
+        assert_eq!(usage.read_usage(4).moves, u32::MAX);
+    }
+
+    #[test]
+    fn usage_file_round_trips() {
+        let path = std::env::temp_dir().join(format!("usage-{}.json", std::process::id()));
+        assert!(UsageFile::load_or_default(&path).unwrap().joints.is_empty());
+        let mut usage = UsageFile::default();
+        usage.record_calibration(1, UNIX_EPOCH + DAY);
+        usage.record_moves(&[(1, 0)]);
+        usage.save(&path).unwrap();
+        let loaded = UsageFile::load_or_default(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(loaded.read_usage(1), JointUsage { calibrated_at: Some(DAY.as_secs()), moves: 1 });
+    }
+
+    #[cfg(not(feature = "milkv"))]
+    mod bus {
+        use super::*;
+        use crate::robot::tests::mock_robot;
+
+        #[test]
+        fn report_flags_joints_overdue_for_calibration() {
+            let (_bus, robot) = mock_robot(&[("left_hip", 1), ("right_hip", 2)]);
+            let mut usage = UsageFile::default();
+            usage.record_calibration(1, SystemTime::now());
+            let overdue: Vec<(String, bool)> = robot.maintenance_report(&usage, DAY).into_iter()
+                .map(|entry| (entry.name, entry.overdue))
+                .collect();
+            assert_eq!(overdue, [("left_hip".to_string(), false), ("right_hip".to_string(), true)]);
+        }
+    }
+}
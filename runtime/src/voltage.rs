@@ -0,0 +1,143 @@
+use anyhow::{Result, bail};
+use crate::hal::{Servo, ServoRegister};
+
+// Widest window the STS series is rated for, in the registers' unit of
+// 0.1 V. Past either end the servo is out of spec whatever it's told.
+pub const MIN_RATED_VOLTAGE: u8 = 40;
+pub const MAX_RATED_VOLTAGE: u8 = 140;
+
+// Input voltage window outside which the servo shuts down, in 0.1 V as
+// stored. The factory window of the STS3215 is 4.0-8.0 V, which a freshly
+// charged 2S pack (8.4 V) already trips; on a marginal supply a raised
+// minimum shuts down cleanly before the servo browns out.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct VoltageWindow {
+    pub min: u8,
+    pub max: u8,
+}
+
+impl VoltageWindow {
+    pub fn from_volts(min: f32, max: f32) -> Result<Self> {
+        let to_register = |volts: f32| -> Result<u8> {
+            let tenths = (volts * 10.0).round();
+            if !(MIN_RATED_VOLTAGE as f32..=MAX_RATED_VOLTAGE as f32).contains(&tenths) {
+                bail!("{} V is outside the rated {:.1}-{:.1} V", volts, MIN_RATED_VOLTAGE as f32 / 10.0, MAX_RATED_VOLTAGE as f32 / 10.0);
+            }
+            Ok(tenths as u8)
+        };
+        Self::from_bytes([to_register(max)?, to_register(min)?])
+    }
+
+    // As stored from ServoRegister::MaxInputVoltage onwards, max first
+    pub fn from_bytes(data: [u8; 2]) -> Result<Self> {
+        let (max, min) = (data[0], data[1]);
+        for value in [min, max] {
+            if !(MIN_RATED_VOLTAGE..=MAX_RATED_VOLTAGE).contains(&value) {
+                bail!("Voltage limit {} (x0.1 V) is outside the rated {}-{}", value, MIN_RATED_VOLTAGE, MAX_RATED_VOLTAGE);
+            }
+        }
+        if min >= max {
+            bail!("Minimum voltage {} is not below maximum {} (x0.1 V)", min, max);
+        }
+        Ok(Self { min, max })
+    }
+
+    pub fn to_bytes(&self) -> [u8; 2] {
+        [self.max, self.min]
+    }
+
+    pub fn min_volts(&self) -> f32 {
+        self.min as f32 / 10.0
+    }
+
+    pub fn max_volts(&self) -> f32 {
+        self.max as f32 / 10.0
+    }
+
+    pub fn contains(&self, volts: f32) -> bool {
+        (self.min_volts()..=self.max_volts()).contains(&volts)
+    }
+}
+
+impl Servo {
+    // Not validated, so an out of spec window already stored can be read
+    // and fixed
+    pub fn read_voltage_window(&self, id: u8) -> Result<VoltageWindow> {
+        let data = self.read_exact(id, ServoRegister::MaxInputVoltage, 2)?;
+        Ok(VoltageWindow { max: data[0], min: data[1] })
+    }
+
+    pub fn write_voltage_window(&self, id: u8, window: &VoltageWindow) -> Result<()> {
+        // Validate here too, the fields are public
+        let data = VoltageWindow::from_bytes(window.to_bytes())?.to_bytes();
+        self.write_eeprom(id, ServoRegister::MaxInputVoltage, &data)?;
+        let written = self.read_voltage_window(id)?;
+        if written != *window {
+            bail!("Servo {} reads back a {:.1}-{:.1} V window after writing {:.1}-{:.1} V",
+                id, written.min_volts(), written.max_volts(), window.min_volts(), window.max_volts());
+        }
+        Ok(())
+    }
+
+    // Present input voltage in V
+    pub fn read_voltage(&self, id: u8) -> Result<f32> {
+        let data = self.read_exact(id, ServoRegister::CurrentVoltage, 1)?;
+        Ok(data[0] as f32 / 10.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn window_is_built_from_volts_within_the_rating() {
+        let window = VoltageWindow::from_volts(6.0, 8.4).unwrap();
+        assert_eq!(window, VoltageWindow { min: 60, max: 84 });
+        assert_eq!(window.to_bytes(), [84, 60]);
+        assert!(window.contains(7.4));
+        assert!(window.contains(8.4));
+        assert!(!window.contains(5.9));
+        assert_eq!(VoltageWindow::from_volts(3.5, 8.0).unwrap_err().to_string(), "3.5 V is outside the rated 4.0-14.0 V");
+        assert!(VoltageWindow::from_volts(6.0, 14.5).is_err());
+    }
+
+    #[test]
+    fn stored_window_must_be_ordered() {
+        assert_eq!(VoltageWindow::from_bytes([80, 40]).unwrap(), VoltageWindow { min: 40, max: 80 });
+        assert_eq!(VoltageWindow::from_bytes([60, 60]).unwrap_err().to_string(), "Minimum voltage 60 is not below maximum 60 (x0.1 V)");
+        assert!(VoltageWindow::from_bytes([200, 40]).is_err());
+        assert!(VoltageWindow::from_volts(8.0, 6.0).is_err());
+    }
+
+    #[cfg(not(feature = "milkv"))]
+    mod bus {
+        use super::*;
+        use crate::hal::mock::MockBus;
+
+        #[test]
+        fn window_is_written_and_read_back() {
+            let bus = MockBus::new(&[1]);
+            let servo = Servo::mock(&bus);
+            assert_eq!(servo.read_voltage_window(1).unwrap(), VoltageWindow { min: 40, max: 140 });
+            let window = VoltageWindow::from_volts(6.0, 8.4).unwrap();
+            servo.write_voltage_window(1, &window).unwrap();
+            assert_eq!((bus.u8(1, ServoRegister::MaxInputVoltage), bus.u8(1, ServoRegister::MinInputVoltage)), (84, 60));
+            assert_eq!(bus.u8(1, ServoRegister::LockMark), 1);
+            assert_eq!(servo.read_voltage(1).unwrap(), 12.0);
+        }
+
+        #[test]
+        fn invalid_or_unconfirmed_window_is_an_error() {
+            let bus = MockBus::new(&[1]);
+            let servo = Servo::mock(&bus);
+            assert!(servo.write_voltage_window(1, &VoltageWindow { min: 90, max: 80 }).is_err());
+            assert!(bus.writes().is_empty());
+
+            bus.refuse(1, ServoRegister::MaxInputVoltage);
+            let window = VoltageWindow::from_volts(6.0, 8.4).unwrap();
+            assert!(servo.write_voltage_window(1, &window).is_err());
+            assert_eq!(servo.read_voltage_window(1).unwrap(), VoltageWindow { min: 40, max: 140 });
+        }
+    }
+}
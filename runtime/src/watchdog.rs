@@ -0,0 +1,128 @@
+use anyhow::{Result, bail};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+#[derive(Debug, Default)]
+struct WatchdogState {
+    // None until the first feed, so nothing trips before a controller connects
+    last_fed: Option<Instant>,
+    expired: bool,
+    stopped: bool,
+}
+
+// Calls `on_expire` once when `feed` hasn't been called for `timeout`, on a
+// background thread. The next feed re-arms it. Dropping the watchdog stops
+// the thread without calling `on_expire`.
+#[derive(Debug)]
+pub struct Watchdog {
+    timeout: Duration,
+    state: Arc<(Mutex<WatchdogState>, Condvar)>,
+}
+
+impl Watchdog {
+    pub fn new<F: Fn() + Send + 'static>(timeout: Duration, on_expire: F) -> Result<Self> {
+        if timeout.is_zero() {
+            bail!("Watchdog timeout must be positive");
+        }
+        let state = Arc::new((Mutex::new(WatchdogState::default()), Condvar::new()));
+
+        let shared = state.clone();
+        thread::spawn(move || {
+            let (lock, wakeup) = &*shared;
+            let mut state = lock.lock().unwrap();
+            while !state.stopped {
+                match state.last_fed {
+                    Some(fed) if !state.expired => {
+                        let elapsed = fed.elapsed();
+                        if elapsed >= timeout {
+                            state.expired = true;
+                            drop(state);
+                            on_expire();
+                            state = lock.lock().unwrap();
+                        } else {
+                            state = wakeup.wait_timeout(state, timeout - elapsed).unwrap().0;
+                        }
+                    }
+                    _ => state = wakeup.wait(state).unwrap(),
+                }
+            }
+        });
+
+        Ok(Self { timeout, state })
+    }
+
+    pub fn feed(&self) {
+        let (lock, wakeup) = &*self.state;
+        let mut state = lock.lock().unwrap();
+        state.last_fed = Some(Instant::now());
+        state.expired = false;
+        wakeup.notify_one();
+    }
+
+    // True from expiry until the next feed
+    pub fn is_expired(&self) -> bool {
+        self.state.0.lock().unwrap().expired
+    }
+
+    pub fn timeout(&self) -> Duration {
+        self.timeout
+    }
+}
+
+impl Drop for Watchdog {
+    fn drop(&mut self) {
+        let (lock, wakeup) = &*self.state;
+        lock.lock().unwrap().stopped = true;
+        wakeup.notify_one();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::thread::sleep;
+
+    fn counting(timeout: Duration) -> (Watchdog, Arc<AtomicUsize>) {
+        let expiries = Arc::new(AtomicUsize::new(0));
+        let counter = expiries.clone();
+        let watchdog = Watchdog::new(timeout, move || {
+            counter.fetch_add(1, Ordering::SeqCst);
+        }).unwrap();
+        (watchdog, expiries)
+    }
+
+    #[test]
+    fn nothing_trips_before_the_first_feed() {
+        let (watchdog, expiries) = counting(Duration::from_millis(10));
+        sleep(Duration::from_millis(40));
+        assert!(!watchdog.is_expired());
+        assert_eq!(expiries.load(Ordering::SeqCst), 0);
+    }
+
+    #[test]
+    fn expires_once_and_rearms_on_the_next_feed() {
+        let (watchdog, expiries) = counting(Duration::from_millis(20));
+        watchdog.feed();
+        sleep(Duration::from_millis(80));
+        assert!(watchdog.is_expired());
+        assert_eq!(expiries.load(Ordering::SeqCst), 1);
+
+        watchdog.feed();
+        assert!(!watchdog.is_expired());
+        sleep(Duration::from_millis(80));
+        assert_eq!(expiries.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn feeding_keeps_it_from_expiring() {
+        let (watchdog, expiries) = counting(Duration::from_millis(60));
+        for _ in 0..6 {
+            watchdog.feed();
+            sleep(Duration::from_millis(10));
+        }
+        assert_eq!(expiries.load(Ordering::SeqCst), 0);
+        assert!(Watchdog::new(Duration::ZERO, || ()).is_err());
+    }
+}